@@ -0,0 +1,63 @@
+use crate::cpu::Hp16cCpu;
+use crate::snapshot::Snapshot;
+use crate::storage::Storage;
+use std::io;
+
+/// Periodically persists the session and detects unclean exits.
+///
+/// A marker key is written when a session starts and removed on a clean
+/// shutdown, so finding it at startup means the last session crashed.
+pub struct Autosave<S: Storage> {
+    storage: S,
+    interval: usize,
+    pending: usize,
+}
+
+impl<S: Storage> Autosave<S> {
+    pub const SNAPSHOT_KEY: &'static str = "hp16c_autosave.json";
+    pub const MARKER_KEY: &'static str = "hp16c_session.lock";
+
+    /// Save after every `interval` recorded commands.
+    pub fn new(storage: S, interval: usize) -> Self {
+        Autosave {
+            storage,
+            interval: interval.max(1),
+            pending: 0,
+        }
+    }
+
+    /// Begin a session, returning the autosaved state if the previous one
+    /// did not shut down cleanly.
+    pub fn start(&mut self) -> io::Result<Option<Snapshot>> {
+        let recovered = if self.storage.exists(Self::MARKER_KEY)? {
+            self.storage.load_snapshot(Self::SNAPSHOT_KEY)?
+        } else {
+            None
+        };
+        self.storage.write(Self::MARKER_KEY, "")?;
+        Ok(recovered)
+    }
+
+    pub fn record(&mut self, cpu: &Hp16cCpu) -> io::Result<()> {
+        self.pending += 1;
+        if self.pending >= self.interval {
+            self.save(cpu)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self, cpu: &Hp16cCpu) -> io::Result<()> {
+        self.pending = 0;
+        self.storage.save_snapshot(Self::SNAPSHOT_KEY, &Snapshot::capture(cpu))
+    }
+
+    /// Clean shutdown: nothing needs recovering next time.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.storage.remove(Self::SNAPSHOT_KEY)?;
+        self.storage.remove(Self::MARKER_KEY)
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+}
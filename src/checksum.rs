@@ -0,0 +1,76 @@
+use crate::scratch::Endian;
+
+/// Checksums found in common protocols, for checking packets and frames
+/// by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// The Internet checksum of IP, UDP, TCP and ICMP (RFC 1071).
+    Internet,
+    Fletcher16,
+    Fletcher32,
+}
+
+impl Checksum {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "IP" | "INET" => Some(Checksum::Internet),
+            "F16" | "FLETCHER16" => Some(Checksum::Fletcher16),
+            "F32" | "FLETCHER32" => Some(Checksum::Fletcher32),
+            _ => None,
+        }
+    }
+
+    /// Checksum of `bytes`. Fletcher-32 reads 16-bit words in `endian`
+    /// order; the Internet checksum always uses network (big-endian) order.
+    pub fn compute(&self, bytes: &[u8], endian: Endian) -> u128 {
+        match self {
+            Checksum::Internet => internet(bytes) as u128,
+            Checksum::Fletcher16 => fletcher16(bytes) as u128,
+            Checksum::Fletcher32 => fletcher32(bytes, endian) as u128,
+        }
+    }
+}
+
+/// 16-bit words of `bytes`, with an odd last byte padded with zero.
+fn words(bytes: &[u8], endian: Endian) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks(2).map(move |pair| {
+        let pair = [pair[0], pair.get(1).copied().unwrap_or(0)];
+        match endian {
+            Endian::Little => u16::from_le_bytes(pair) as u32,
+            Endian::Big => u16::from_be_bytes(pair) as u32,
+        }
+    })
+}
+
+/// The ones' complement of the ones' complement sum of the big-endian
+/// 16-bit words. Summing data that includes its checksum gives 0.
+pub fn internet(bytes: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for word in words(bytes, Endian::Big) {
+        sum += word;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Fletcher-16: two running sums of the bytes modulo 255, the second in
+/// the high byte.
+pub fn fletcher16(bytes: &[u8]) -> u16 {
+    let (mut low, mut high) = (0u16, 0u16);
+    for byte in bytes {
+        low = (low + *byte as u16) % 255;
+        high = (high + low) % 255;
+    }
+    (high << 8) | low
+}
+
+/// Fletcher-32: two running sums of the 16-bit words modulo 65535, the
+/// second in the high half.
+pub fn fletcher32(bytes: &[u8], endian: Endian) -> u32 {
+    let (mut low, mut high) = (0u32, 0u32);
+    for word in words(bytes, endian) {
+        low = (low + word) % 65535;
+        high = (high + low) % 65535;
+    }
+    (high << 16) | low
+}
@@ -0,0 +1,1769 @@
+use crate::cancel::CancelToken;
+use crate::checksum::Checksum;
+use crate::constants::BUILTIN;
+use crate::cpu::{
+    parse_register, register_name, Base, BitField, ComplementMode, Hp16cCpu, ResultsBank, StoreOperation, WordSize,
+    WordSizePolicy, MAX_FLOAT_DIGITS, MEMORY_BYTES,
+};
+use crate::optimizer;
+use crate::plugin::{Plugin, Plugins};
+use crate::program::{Control, Program, Test, HP16C_MAX_WORD_SIZE};
+use crate::random::Generator;
+use crate::scratch::{decode_base64, decode_hex, decode_utf8, encode_base64, hex_bytes, Endian};
+#[cfg(feature = "scripting")]
+use crate::script::{Script, SearchPath};
+use crate::shorthand;
+use crate::snapshot::{Difference, Snapshot};
+use crate::storage::Storage;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/// What a front-end should do after a command line has run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The command ran; redraw the display.
+    Done,
+    /// The command produced text to show the user.
+    Message(Vec<String>),
+    /// The user asked to leave the calculator.
+    Quit,
+}
+
+impl Outcome {
+    fn message(text: impl Into<String>) -> Self {
+        Outcome::Message(vec![text.into()])
+    }
+}
+
+/// Protection applied to input from untrusted front-ends (servers, bots).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest accepted command line, in bytes after sanitizing.
+    pub max_line_len: usize,
+    /// Commands accepted per `window`.
+    pub max_commands: usize,
+    pub window: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_line_len: 256,
+            max_commands: 20,
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What a command line runs under, passed down to every command it leads
+/// to so that nothing it runs escapes the policy it came in with.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    /// Stops long-running work such as scripts and programs once set
+    pub cancel: CancelToken,
+    /// Refuse the commands that touch files or run scripts
+    pub restricted: bool,
+}
+
+impl Context {
+    /// Whether `line` may run under this context.
+    pub fn check_policy(&self, line: &str) -> Result<(), String> {
+        match restricted_command(line) {
+            Some(command) if self.restricted => Err(format!("{} is disabled in restricted mode", command)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Per-session command dispatcher. All front-ends go through this so that
+/// input policies are enforced in one place.
+#[derive(Debug, Clone, Default)]
+pub struct Dispatcher {
+    limits: Option<Limits>,
+    restricted: bool,
+    recent: VecDeque<Instant>,
+    observers: Vec<Sender<Snapshot>>,
+    elapsed: Option<Duration>,
+    cancel: CancelToken,
+    plugins: Plugins,
+}
+
+impl Dispatcher {
+    /// A dispatcher for trusted, local input.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A dispatcher that sanitizes input and enforces `limits`.
+    pub fn with_limits(limits: Limits) -> Self {
+        Dispatcher {
+            limits: Some(limits),
+            ..Self::default()
+        }
+    }
+
+    /// Attach a read-only observer. It receives a snapshot of the state after
+    /// every successful command until the receiver is dropped.
+    pub fn subscribe(&mut self) -> Receiver<Snapshot> {
+        let (sender, receiver) = mpsc::channel();
+        self.observers.push(sender);
+        receiver
+    }
+
+    /// Refuse commands that read or write files or run scripts, for
+    /// classroom and lab installs.
+    pub fn set_restricted(&mut self, restricted: bool) {
+        self.restricted = restricted;
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.restricted
+    }
+
+    /// Whether this dispatcher's policy allows `line`. Front-ends that
+    /// handle some commands themselves check here first.
+    pub fn check_policy(&self, line: &str) -> Result<(), String> {
+        self.context().check_policy(line)
+    }
+
+    /// The context commands run under: this dispatcher's cancel token and
+    /// policy.
+    pub fn context(&self) -> Context {
+        Context {
+            cancel: self.cancel.clone(),
+            restricted: self.restricted,
+        }
+    }
+
+    /// Add a plugin. It sees every command this dispatcher accepts, after
+    /// the plugins added before it.
+    pub fn add_plugin(&mut self, plugin: impl Plugin + 'static) {
+        self.plugins.add(plugin);
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    pub fn observer_count(&self) -> usize {
+        self.observers.len()
+    }
+
+    /// A token that cancels the command currently running, for example from
+    /// a Ctrl-C handler. It is reset when the next command starts.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// A copy for running commands on another thread, such as a background
+    /// job: the same limits, policy, plugins and observers, with a cancel
+    /// token of its own.
+    pub fn fork(&self) -> Dispatcher {
+        Dispatcher {
+            cancel: CancelToken::new(),
+            ..self.clone()
+        }
+    }
+
+    /// How long the most recent command took to run.
+    pub fn last_elapsed(&self) -> Option<Duration> {
+        self.elapsed
+    }
+
+    pub fn execute(&mut self, cpu: &mut Hp16cCpu, storage: &mut dyn Storage, line: &str) -> Result<Outcome, String> {
+        self.execute_at(cpu, storage, line, Instant::now())
+    }
+
+    pub fn execute_at(
+        &mut self,
+        cpu: &mut Hp16cCpu,
+        storage: &mut dyn Storage,
+        line: &str,
+        now: Instant,
+    ) -> Result<Outcome, String> {
+        self.cancel.reset();
+        self.run(cpu, storage, line, now)
+    }
+
+    /// Like `execute`, but keeps a cancellation requested before the command
+    /// starts. A background job runs this way, so a KILL sent before its
+    /// thread gets going is not lost.
+    pub fn execute_uncancelled(
+        &mut self,
+        cpu: &mut Hp16cCpu,
+        storage: &mut dyn Storage,
+        line: &str,
+    ) -> Result<Outcome, String> {
+        self.run(cpu, storage, line, Instant::now())
+    }
+
+    fn run(&mut self, cpu: &mut Hp16cCpu, storage: &mut dyn Storage, line: &str, now: Instant) -> Result<Outcome, String> {
+        let started = Instant::now();
+        let outcome = self.dispatch(cpu, storage, line, now);
+        self.elapsed = Some(started.elapsed());
+        let outcome = outcome?;
+        if !self.observers.is_empty() {
+            let snapshot = Snapshot::capture(cpu);
+            self.observers.retain(|observer| observer.send(snapshot.clone()).is_ok());
+        }
+        Ok(outcome)
+    }
+
+    fn dispatch(
+        &mut self,
+        cpu: &mut Hp16cCpu,
+        storage: &mut dyn Storage,
+        line: &str,
+        now: Instant,
+    ) -> Result<Outcome, String> {
+        self.check_policy(line)?;
+        let Some(limits) = self.limits else {
+            return self.run_plugins_then(cpu, storage, line);
+        };
+
+        let line = sanitize(line);
+        if line.len() > limits.max_line_len {
+            return Err(format!("Command too long (limit {} characters)", limits.max_line_len));
+        }
+
+        while let Some(&oldest) = self.recent.front() {
+            if now.duration_since(oldest) < limits.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= limits.max_commands {
+            return Err("Too many commands; slow down".to_string());
+        }
+        self.recent.push_back(now);
+
+        self.run_plugins_then(cpu, storage, &line)
+    }
+
+    /// Run `line` on the calculator unless a plugin handles it, and tell
+    /// the plugins how it went.
+    fn run_plugins_then(
+        &mut self,
+        cpu: &mut Hp16cCpu,
+        storage: &mut dyn Storage,
+        line: &str,
+    ) -> Result<Outcome, String> {
+        let outcome = match self.plugins.on_command(cpu, line) {
+            Some(outcome) => outcome,
+            None => execute_in(cpu, storage, line, &self.context()),
+        };
+        self.plugins.on_result(cpu, line, &outcome);
+        outcome
+    }
+
+    /// Run commands separated by `;` or new lines, as given to `hp16c -e`.
+    /// Returns the lines the commands showed, or the first error with the
+    /// command that caused it. QUIT stops early.
+    pub fn execute_batch(&mut self, cpu: &mut Hp16cCpu, storage: &mut dyn Storage, commands: &str) -> Result<Vec<String>, String> {
+        let mut output = Vec::new();
+        for command in commands.split([';', '\n']).map(str::trim).filter(|command| !command.is_empty()) {
+            match self.execute(cpu, storage, command) {
+                Ok(Outcome::Quit) => break,
+                Ok(Outcome::Message(lines)) => output.extend(lines),
+                Ok(Outcome::Done) => {},
+                Err(e) => return Err(format!("{}: {}", command, e)),
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Commands refused in restricted mode: everything that touches files,
+/// runs a script or starts a program. There are no shell or network
+/// commands to refuse.
+const RESTRICTED_COMMANDS: &[&str] =
+    &["SAVE", "LOAD", "DIFF", "SOURCE", "TAPE SAVE", "TRAIL SAVE", "EXPORT", "IMPORT", "COMPILE",
+    "NOTIFY"];
+
+/// The restricted command `line` would run, including one inside MAPR.
+fn restricted_command(line: &str) -> Option<&'static str> {
+    let line = sanitize(line).to_uppercase();
+    if let Some(rest) = line.strip_prefix("MAPR ") {
+        let (_, commands) = rest.split_once(' ')?;
+        return commands.split(';').find_map(restricted_command);
+    }
+    RESTRICTED_COMMANDS.iter().copied().find(|restricted| {
+        line.strip_prefix(restricted).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+    })
+}
+
+/// Strip control characters and collapse runs of whitespace.
+pub fn sanitize(line: &str) -> String {
+    line.split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Rows of 16 bytes shown by SCRATCH addr.
+const SCRATCH_VIEW_ROWS: usize = 4;
+
+/// Commands whose result in X goes to the results bank.
+const RESULT_OPERATIONS: &[&str] = &[
+    "+", "-", "*", "/", "RMD", "DBL*", "DBL/", "DBLR", "BCD+", "BCD-", "MIN", "MAX", "CMP",
+    "&", "|", "^", "~", "CHS", "ABS", "SL", "SR", "RL", "RR", "RLN", "RRN", "RLC", "RRC", "SB", "CB",
+    "SUMR", "XORR", "REDUCE",
+];
+
+/// Commands that only make sense on integers, refused in FLOAT mode.
+const INTEGER_COMMANDS: &[&str] = &[
+    "RMD", ">BCD", "BCD>", "BCD+", "BCD-", "MIN", "MAX", "CMP", "DBL*", "DBL/", "DBLR", "&", "|", "^", "~",
+    "SL", "SR", "RL", "RR", "RLN", "RRN", "RLC", "RRC", "MASKL", "MASKR", "SB", "CB", "B?", "FIELD", "WS",
+    "CONST", "DEFCONST", "PUSHN", "RAND", "LFSR", "UTF8", "CKSUM", "BYTES", "ST8", "ST16", "ST32", "ST64",
+    "LD8", "LD16", "LD32", "LD64",
+];
+
+/// Commands that act on program memory from the keyboard and run even in
+/// program mode rather than being stored as steps. Those starting with
+/// one of `KEYBOARD_PREFIXES` are too.
+const KEYBOARD_COMMANDS: &[&str] = &[
+    "P/R", "SST", "BST", "DEL", "PRGM", "PRGM LIST", "DECOMPILE", "OPTIMIZE", "CLR PRGM", "CLEAR PRGM", "CHECK",
+    "STRICT", "STRICT ON", "STRICT OFF", "STATUS", "MEM", "PRESET", "BATTERY LOW", "BATTERY OK", "QUIT", "Q", "EXIT",
+];
+
+const KEYBOARD_PREFIXES: &[&str] = &["GTO .", "ANNOTATE ", "EXPORT ", "IMPORT ", "COMPILE "];
+
+/// Two-operand commands accepted between numbers when AUTOENTER is on.
+const CHAIN_OPERATORS: &[&str] = &["+", "-", "*", "/", "RMD", "&", "|", "^", "MIN", "MAX"];
+
+/// Execute one command line against the calculator.
+///
+/// Commands are case-insensitive; file name arguments keep their case.
+/// Errors are returned as the message to show the user.
+pub fn execute(cpu: &mut Hp16cCpu, storage: &mut dyn Storage, line: &str) -> Result<Outcome, String> {
+    execute_with(cpu, storage, line, &CancelToken::new())
+}
+
+/// Execute one command line, stopping long-running work such as scripts
+/// once `cancel` is set.
+pub fn execute_with(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    line: &str,
+    cancel: &CancelToken,
+) -> Result<Outcome, String> {
+    let context = Context {
+        cancel: cancel.clone(),
+        restricted: false,
+    };
+    execute_in(cpu, storage, line, &context)
+}
+
+/// Execute one command line under `context`. Its policy holds for every
+/// command the line leads to: program steps, script lines and the commands
+/// REDUCE, RADD and MAPR run for it.
+pub fn execute_in(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    line: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    if cpu.program_mode && !is_keyboard_command(line) {
+        let step = sanitize(line);
+        if step.is_empty() {
+            return Ok(Outcome::Done);
+        }
+        // Files, scripts and notifications are for the keyboard only
+        if let Some(command) = restricted_command(&step) {
+            return Err(format!("{} cannot be a program step", command));
+        }
+        let number = cpu.insert_step(&step)?;
+        return Ok(Outcome::message(format!("{:03}- {}", number, step)));
+    }
+    if cpu.auto_enter {
+        if let Some(commands) = chain_commands(cpu, line) {
+            for command in commands {
+                run_and_record(cpu, storage, &command, context).map_err(|e| format!("{}: {}", command, e))?;
+            }
+            return Ok(Outcome::Done);
+        }
+    }
+    run_and_record(cpu, storage, line, context)
+}
+
+fn run_and_record(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    line: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    let outcome = run_command(cpu, storage, line, context);
+    // In auto mode the window follows X after anything but a scroll
+    if cpu.window_follow && !matches!(line.trim(), "<" | ">") {
+        cpu.follow_window();
+    }
+    let outcome = outcome?;
+    let command = line.split_whitespace().next().unwrap_or("").to_uppercase();
+    if RESULT_OPERATIONS.contains(&command.as_str()) {
+        cpu.record_result();
+    }
+    Ok(outcome)
+}
+
+/// Whether `line` runs in program mode instead of being stored as a step.
+fn is_keyboard_command(line: &str) -> bool {
+    let input = sanitize(line).to_uppercase();
+    KEYBOARD_COMMANDS.contains(&input.as_str()) || KEYBOARD_PREFIXES.iter().any(|prefix| input.starts_with(prefix))
+}
+
+/// Split a calculator-style chain such as `10 + 5 * 2` or `+ 5` into the
+/// RPN commands it implies: each number is entered, then the operator
+/// before it applied. Chains run left to right with no precedence, and a
+/// chain starting with an operator continues from X. Returns None for
+/// lines that are not chains.
+fn chain_commands(cpu: &Hp16cCpu, line: &str) -> Option<Vec<String>> {
+    let words: Vec<String> = line.split_whitespace().map(str::to_uppercase).collect();
+    let is_number = |word: &String| parse_entry(cpu, word).is_some();
+    let is_operator = |word: &String| CHAIN_OPERATORS.contains(&word.as_str());
+    let (first, pairs) = match words.first() {
+        Some(word) if is_number(word) => (Some(word), &words[1..]),
+        _ => (None, &words[..]),
+    };
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut commands: Vec<String> = first.into_iter().cloned().collect();
+    for pair in pairs.chunks(2) {
+        if !is_operator(&pair[0]) || !is_number(&pair[1]) {
+            return None;
+        }
+        commands.push(pair[1].clone());
+        commands.push(pair[0].clone());
+    }
+    Some(commands)
+}
+
+fn run_command(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    line: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    // Checked here as well as by the dispatcher, for the commands a
+    // program, REDUCE or MAPR runs
+    context.check_policy(line)?;
+    let line = line.trim();
+    // ASCII only, so the arguments can be cut from `line` at the offsets
+    // of their prefix in `input`
+    let input = line.to_ascii_uppercase();
+
+    if cpu.is_float() {
+        let command = input.split_whitespace().next().unwrap_or("");
+        if INTEGER_COMMANDS.contains(&command) || command.starts_with('[') {
+            return Err(format!("{} is not available in FLOAT mode", command));
+        }
+    }
+
+    // Any command but a scroll goes back to the lowest display window
+    if input != "<" && input != ">" {
+        cpu.window = 0;
+    }
+
+    match input.as_str() {
+        "QUIT" | "Q" | "EXIT" => return Ok(Outcome::Quit),
+        "<" => cpu.window_left(),
+        ">" => cpu.window_right(),
+        "CLR" | "CLEAR" => return Err("Use CLX, CLR STK, CLEAR REG or CLEAR PRGM".to_string()),
+        "CLR STK" | "CLEAR STK" => cpu.clear_stack(),
+        "CLR REG" | "CLEAR REG" => cpu.clear_registers(),
+        "CLR PRGM" | "CLEAR PRGM" => cpu.clear_program(),
+        "P/R" => {
+            cpu.program_mode = !cpu.program_mode;
+            if cpu.program_mode {
+                return Ok(Outcome::message(format!("PRGM mode, line {:03}", cpu.pc)));
+            }
+            return Ok(Outcome::message("RUN mode"));
+        },
+        "DEL" => {
+            if !cpu.program_mode {
+                return Err("DEL deletes program steps; use P/R to enter program mode".to_string());
+            }
+            cpu.delete_step()?;
+            return Ok(Outcome::message(program_line(cpu)));
+        },
+        "PRGM" => {
+            return Ok(Outcome::Message(cpu.program().listing()));
+        },
+        "PRGM LIST" => {
+            return Ok(Outcome::Message(cpu.program().keycode_listing()));
+        },
+        "DECOMPILE" => {
+            return decompile_program(cpu);
+        },
+        "OPTIMIZE" => {
+            if cpu.program().is_empty() {
+                return Err("Program memory is empty".to_string());
+            }
+            let optimized = optimizer::optimize(cpu.program());
+            if optimized.changes.is_empty() {
+                return Ok(Outcome::message("Nothing to optimize"));
+            }
+            let mut lines = optimized.changes;
+            lines.push(format!(
+                "Saved {} bytes: the program takes {} of {} bytes",
+                optimized.saved,
+                optimized.program.bytes(),
+                MEMORY_BYTES
+            ));
+            cpu.shrink_program(optimized.program);
+            return Ok(Outcome::Message(lines));
+        },
+        "R/S" => {
+            return run_program(cpu, storage, cpu.pc as usize, context);
+        },
+        "SST" => {
+            if cpu.program_mode {
+                cpu.next_line();
+                return Ok(Outcome::message(cpu.program().keycode_line(cpu.pc as usize)));
+            }
+            return single_step(cpu, storage, context);
+        },
+        "BST" => {
+            cpu.previous_line();
+            return Ok(Outcome::message(cpu.program().keycode_line(cpu.pc as usize)));
+        },
+        "CHECK" => {
+            return Ok(Outcome::Message(check_program(cpu)));
+        },
+        "PRESET" => {
+            cpu.preset();
+            return Ok(Outcome::message("Pr Error (continuous memory reset)"));
+        },
+        "BATTERY LOW" => {
+            cpu.low_battery = true;
+        },
+        "BATTERY OK" => {
+            cpu.low_battery = false;
+        },
+        "STRICT" => {
+            return Ok(Outcome::message(format!("Strict mode is {}", if cpu.strict { "on" } else { "off" })));
+        },
+        "STRICT ON" => {
+            cpu.strict = true;
+            let problems = cpu.program().check(cpu.base).len();
+            if problems > 0 {
+                let message = format!("{} stored steps would not run on an HP-16C; CHECK lists them", problems);
+                return Ok(Outcome::message(message));
+            }
+        },
+        "STRICT OFF" => {
+            cpu.strict = false;
+        },
+        "RTN" => {
+            cpu.pc = 0;
+        },
+        "ENTER" => {
+            cpu.enter();
+        },
+        "UTF8" => {
+            let point = u32::try_from(cpu.x).ok().and_then(char::from_u32).ok_or_else(|| {
+                format!("{:X} is not a Unicode scalar value (0-10FFFF, excluding D800-DFFF)", cpu.x)
+            })?;
+            let mut buffer = [0u8; 4];
+            return Ok(Outcome::message(format!(
+                "U+{:04X} {:?}: {}",
+                point as u32,
+                point,
+                hex_bytes(point.encode_utf8(&mut buffer).as_bytes())
+            )));
+        },
+        "CLX" => {
+            cpu.clear_x();
+        },
+        "DROP" => {
+            cpu.drop();
+        },
+        "SWAP" => {
+            cpu.swap_xy();
+        },
+        "RV" => {
+            cpu.roll_down();
+        },
+        "R^" => {
+            cpu.roll_up();
+        },
+        "+" => {
+            cpu.in_field(true, Hp16cCpu::add);
+        },
+        "-" => {
+            cpu.in_field(true, Hp16cCpu::subtract);
+        },
+        "*" => {
+            cpu.in_field(true, Hp16cCpu::multiply);
+        },
+        "/" => {
+            cpu.in_field(true, Hp16cCpu::divide);
+        },
+        "RMD" => {
+            cpu.in_field(true, Hp16cCpu::remainder);
+        },
+        ">BCD" => {
+            cpu.to_bcd()?;
+        },
+        "BCD>" => {
+            cpu.from_bcd()?;
+        },
+        "BCD+" => {
+            cpu.bcd_add()?;
+        },
+        "BCD-" => {
+            cpu.bcd_subtract()?;
+        },
+        "MIN" => {
+            cpu.in_field(true, Hp16cCpu::min);
+        },
+        "MAX" => {
+            cpu.in_field(true, Hp16cCpu::max);
+        },
+        "CMP" => {
+            cpu.in_field(true, Hp16cCpu::cmp);
+        },
+        "DBL*" => {
+            cpu.double_multiply();
+        },
+        "DBL/" => {
+            cpu.double_divide();
+        },
+        "DBLR" => {
+            cpu.double_remainder();
+        },
+        "&" => {
+            cpu.in_field(true, Hp16cCpu::and);
+        },
+        "|" => {
+            cpu.in_field(true, Hp16cCpu::or);
+        },
+        "^" => {
+            cpu.in_field(true, Hp16cCpu::xor);
+        },
+        "~" => {
+            cpu.in_field(false, Hp16cCpu::not);
+        },
+        "CHS" => {
+            cpu.in_field(false, Hp16cCpu::chs);
+        },
+        "ABS" => {
+            cpu.in_field(false, Hp16cCpu::abs);
+        },
+        "RL" => {
+            cpu.in_field(false, Hp16cCpu::rotate_left);
+        },
+        "RR" => {
+            cpu.in_field(false, Hp16cCpu::rotate_right);
+        },
+        "RLN" | "RRN" => {
+            let width = cpu.operand_width();
+            if cpu.x > width as u128 {
+                return Err(format!("Rotate count must be 0-{}", width));
+            }
+            let rotate = if input == "RLN" { Hp16cCpu::rotate_left_n } else { Hp16cCpu::rotate_right_n };
+            cpu.in_field(true, rotate);
+        },
+        "MASKL" | "MASKR" => {
+            make_mask(cpu, &input, None)?;
+        },
+        "SB" | "CB" => {
+            // Checked before the field is cut out of X
+            cpu.check_bit_number()?;
+            let op = if input == "SB" { Hp16cCpu::set_bit } else { Hp16cCpu::clear_bit };
+            cpu.in_field(true, op)?;
+        },
+        "RLC" => {
+            cpu.in_field(false, Hp16cCpu::rotate_left_carry);
+        },
+        "RRC" => {
+            cpu.in_field(false, Hp16cCpu::rotate_right_carry);
+        },
+        "PRINT X" | "PRX" => {
+            cpu.print_x();
+        },
+        "TAPE" => {
+            if cpu.printer.tape().is_empty() {
+                return Ok(Outcome::message("(printer tape is empty)"));
+            }
+            return Ok(Outcome::Message(cpu.printer.tape().to_vec()));
+        },
+        "TAPE CLEAR" => {
+            cpu.printer.clear();
+        },
+        "CONST" => {
+            return Ok(Outcome::Message(list_constants(cpu)));
+        },
+        "MEMVIEW" => {
+            return Ok(Outcome::Message(memory_view(cpu)));
+        },
+        "RESULTS" => {
+            let bank = cpu.results_bank.ok_or("Results bank is off; use RESULTS ON")?;
+            let lines = bank
+                .newest_first()
+                .into_iter()
+                .map(|reg| {
+                    let value = cpu.memory.get(reg).ok_or_else(|| format!("Results bank register R{} is gone", reg))?;
+                    Ok(format!("R{:<2} {:>24}", reg, cpu.format_value(*value)))
+                })
+                .collect::<Result<_, String>>()?;
+            return Ok(Outcome::Message(lines));
+        },
+        "AUTOENTER" => {
+            let state = if cpu.auto_enter { "on" } else { "off" };
+            return Ok(Outcome::message(format!("Auto-ENTER is {}", state)));
+        },
+        "AUTOENTER ON" => {
+            cpu.auto_enter = true;
+        },
+        "AUTOENTER OFF" => {
+            cpu.auto_enter = false;
+        },
+        "WINDOW" => {
+            let mode = if cpu.window_follow { "follows the highest set bit of X" } else { "manual" };
+            return Ok(Outcome::message(format!("Binary display window is {}", mode)));
+        },
+        "WINDOW AUTO" => {
+            cpu.window_follow = true;
+            cpu.follow_window();
+        },
+        "WINDOW MANUAL" => {
+            cpu.window_follow = false;
+        },
+        "RESULTS ON" => {
+            let bank = ResultsBank::DEFAULT;
+            cpu.results_bank = Some(ResultsBank::new(bank.first, bank.last, cpu.memory.len())?);
+        },
+        "RESULTS OFF" => {
+            cpu.results_bank = None;
+        },
+        "FIELD" => {
+            return Ok(Outcome::message(match cpu.field {
+                Some(field) => format!("Field: bits {}", field.label()),
+                None => "Field: off (whole word)".to_string(),
+            }));
+        },
+        "SCRATCH" => {
+            // Up to the last byte in use, at least one row
+            let used = cpu.scratch.to_hex().len() / 2;
+            return Ok(Outcome::Message(cpu.scratch.dump(0, used.div_ceil(16).max(1))));
+        },
+        "ENDIAN" => {
+            return Ok(Outcome::message(format!("Byte order: {}", cpu.endian.label())));
+        },
+        "ENDIAN LITTLE" | "ENDIAN LE" => {
+            cpu.endian = Endian::Little;
+        },
+        "ENDIAN BIG" | "ENDIAN BE" => {
+            cpu.endian = Endian::Big;
+        },
+        "BYTES" => {
+            return Ok(Outcome::message(format!("X bytes ({}): {}", cpu.endian.label(), hex_bytes(&cpu.bytes_of_x()))));
+        },
+        "RAND" => {
+            let value = cpu.random.next_word(cpu.word_size.bits() as u32);
+            cpu.enter_value(value);
+        },
+        "RAND XORSHIFT" => {
+            cpu.random = Generator::xorshift();
+        },
+        "RAND PCG" => {
+            cpu.random = Generator::pcg();
+        },
+        "RAND SEED" => {
+            cpu.random.seed(cpu.x)?;
+        },
+        "RAND STATE" => {
+            return Ok(Outcome::message(cpu.random.describe()));
+        },
+        "STATUS" => {
+            return Ok(Outcome::Message(status(cpu)));
+        },
+        "MEM" => {
+            let mem = cpu.memory_status();
+            return Ok(Outcome::Message(vec![
+                mem.to_string(),
+                format!(
+                    "Program steps: {} ({} bytes, {} allocated)  Registers: {} ({} bytes each)  Unused bytes: {}",
+                    cpu.program().len(),
+                    cpu.program().bytes(),
+                    cpu.program_bytes(),
+                    mem.registers,
+                    mem.register_bytes,
+                    mem.unused_bytes
+                ),
+            ]));
+        },
+        "STO I" => {
+            cpu.set_i(cpu.x);
+            cpu.stack_lift = true;
+        },
+        "RCL I" => {
+            cpu.enter_value(cpu.i);
+        },
+        "STO (I)" => {
+            let register = cpu.indirect_register()?;
+            cpu.store(register);
+        },
+        "RCL (I)" => {
+            let register = cpu.indirect_register()?;
+            cpu.recall(register);
+        },
+        "X<>I" => {
+            cpu.swap_xi();
+        },
+        "X<>(I)" => {
+            cpu.swap_x_indirect()?;
+        },
+        "SCRATCH CLEAR" => {
+            cpu.scratch.clear();
+        },
+        "FIELD OFF" => {
+            cpu.set_field(None)?;
+        },
+        "BIN" => {
+            cpu.set_base(Base::Binary);
+        },
+        "OCT" => {
+            cpu.set_base(Base::Octal);
+        },
+        "DEC" => {
+            cpu.set_base(Base::Decimal);
+        },
+        "HEX" => {
+            cpu.set_base(Base::Hex);
+        },
+        "UNSGN" => {
+            cpu.set_complement_mode(ComplementMode::Unsigned);
+        },
+        "1S" => {
+            cpu.set_complement_mode(ComplementMode::OnesComplement);
+        },
+        "2S" => {
+            cpu.set_complement_mode(ComplementMode::TwosComplement);
+        },
+        _ => {
+            if input.starts_with("SAVE ") {
+                let name = line[5..].trim();
+                let snapshot = Snapshot::capture(cpu);
+                return match storage.save_snapshot(name, &snapshot) {
+                    Ok(()) => Ok(Outcome::message(format!("Session saved to {}", name))),
+                    Err(e) => Err(format!("Could not save session: {}", e)),
+                };
+            } else if input.starts_with("LOAD ") {
+                let name = line[5..].trim();
+                match storage.load_snapshot(name) {
+                    Ok(Some(snapshot)) => snapshot.restore(cpu),
+                    Ok(None) => return Err(format!("No saved session: {}", name)),
+                    Err(e) => return Err(format!("Could not load session: {}", e)),
+                }
+            } else if input.starts_with("EXPORT MD ") {
+                let name = line[10..].trim();
+                if cpu.program().is_empty() {
+                    return Err("Program memory is empty".to_string());
+                }
+                return match storage.write(name, &cpu.program().markdown()) {
+                    Ok(()) => Ok(Outcome::message(format!("Program exported to {}", name))),
+                    Err(e) => Err(format!("Could not export program: {}", e)),
+                };
+            } else if input.starts_with("EXPORT KEYS ") {
+                let name = line[12..].trim();
+                if cpu.program().is_empty() {
+                    return Err("Program memory is empty".to_string());
+                }
+                let mut text = cpu.program().keystrokes()?.join("\n");
+                text.push('\n');
+                return match storage.write(name, &text) {
+                    Ok(()) => Ok(Outcome::message(format!("Keystrokes exported to {}", name))),
+                    Err(e) => Err(format!("Could not export keystrokes: {}", e)),
+                };
+            } else if input.starts_with("IMPORT ") {
+                let name = line[7..].trim();
+                let text = storage
+                    .read(name)
+                    .map_err(|e| format!("Could not read {}: {}", name, e))?
+                    .ok_or_else(|| format!("No such file: {}", name))?;
+                let program = import_program(cpu, storage, &text)?;
+                let steps = program.len();
+                cpu.load_program(program)?;
+                return Ok(Outcome::message(format!("{} steps imported from {}", steps, name)));
+            } else if input.starts_with("COMPILE ") {
+                return compile_program(cpu, storage, line[8..].trim());
+            } else if input.starts_with("ANNOTATE ") {
+                // From the line as typed, so the text keeps its case
+                let arg = line[9..].trim();
+                let (number, text) = arg.split_once(' ').unwrap_or((arg, ""));
+                let number = number.parse::<usize>().map_err(|_| "Usage: ANNOTATE <line> <text>".to_string())?;
+                cpu.annotate_step(number, text)?;
+            } else if input.starts_with("TAPE SAVE ") {
+                let name = line[10..].trim();
+                return match storage.write(name, &cpu.printer.contents()) {
+                    Ok(()) => Ok(Outcome::message(format!("Tape saved to {}", name))),
+                    Err(e) => Err(format!("Could not save tape: {}", e)),
+                };
+            } else if let Some(range) = input.strip_prefix("SUMR ") {
+                reduce_registers(cpu, storage, "+", range, context)?;
+            } else if let Some(range) = input.strip_prefix("XORR ") {
+                reduce_registers(cpu, storage, "^", range, context)?;
+            } else if let Some(arg) = input.strip_prefix("SF ") {
+                cpu.set_flag(flag_number(arg)?)?;
+            } else if let Some(arg) = input.strip_prefix("CF ") {
+                cpu.clear_flag(flag_number(arg)?)?;
+            } else if let Some(arg) = input.strip_prefix("F? ") {
+                let number = flag_number(arg)?;
+                let state = if cpu.test_flag(number)? { "set" } else { "clear" };
+                return Ok(Outcome::message(format!("Flag {} is {}", number, state)));
+            } else if let Some(arg) = input.strip_prefix("RADD ") {
+                combine_registers(cpu, storage, "RADD", "+", arg, context)?;
+            } else if let Some(arg) = input.strip_prefix("RXOR ") {
+                combine_registers(cpu, storage, "RXOR", "^", arg, context)?;
+            } else if let Some(arg) = input.strip_prefix("REDUCE ") {
+                let (op, range) = arg.trim().split_once(' ').ok_or("Usage: REDUCE <op> <first>-<last>")?;
+                reduce_registers(cpu, storage, op, range, context)?;
+            } else if input.starts_with("MAPR ") {
+                // The commands keep the case they were typed in, for file names
+                let (range, commands) = line[5..].trim().split_once(' ').ok_or("Usage: MAPR <first>-<last> <cmd>; <cmd>...")?;
+                return map_registers(cpu, storage, range, commands, context);
+            } else if input.starts_with("SOURCE ") {
+                let name = line[7..].trim();
+                return run_script(cpu, storage, name, context);
+            } else if input.starts_with("DIFF ") {
+                let names: Vec<&str> = line[5..].split_whitespace().collect();
+                if names.len() != 2 {
+                    return Err("Usage: DIFF <file1> <file2>".to_string());
+                }
+                return diff_sessions(storage, names[0], names[1]).map(Outcome::Message);
+            } else if let Some(name) = input.strip_prefix("CHECKPOINT ") {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err("Usage: CHECKPOINT <name>".to_string());
+                }
+                cpu.checkpoints.insert(name.to_string(), Snapshot::capture(cpu));
+            } else if let Some(name) = input.strip_prefix("VERIFY ") {
+                return verify_checkpoint(cpu, name.trim());
+            } else if let Some(name) = input.strip_prefix("CONST ") {
+                let value = cpu
+                    .constants
+                    .lookup(name.trim())
+                    .ok_or_else(|| format!("Unknown constant: {}", name.trim()))?;
+                cpu.enter_value(value);
+            } else if let Some(arg) = input.strip_prefix("DEFCONST ") {
+                let mut words = arg.split_whitespace();
+                let name = words.next().unwrap_or("");
+                let value = match words.next() {
+                    Some(digits) => parse_number(digits, cpu.base)
+                        .ok_or_else(|| format!("Invalid number: {}", digits))?,
+                    None => cpu.x,
+                };
+                cpu.constants.define(name, value)?;
+            } else if let Some(width) = narrow_width(input.split_whitespace().next().unwrap_or(""), "ST") {
+                let words: Vec<&str> = input.split_whitespace().collect();
+                let (address, endian, saturate) = narrow_arguments(cpu, words[0], &words[1..])?;
+                cpu.store_narrow(address, width, saturate, endian)?;
+            } else if let Some(width) = narrow_width(input.split_whitespace().next().unwrap_or(""), "LD") {
+                let words: Vec<&str> = input.split_whitespace().collect();
+                let (address, endian, saturate) = narrow_arguments(cpu, words[0], &words[1..])?;
+                if saturate {
+                    return Err(format!("Usage: {} <address> [LE|BE]", words[0]));
+                }
+                cpu.load_narrow(address, width, endian)?;
+            } else if let Some(taps) = input.strip_prefix("RAND LFSR ") {
+                let taps = parse_number(taps.trim(), cpu.base).ok_or_else(|| number_error(taps.trim(), cpu.base))?;
+                cpu.random = Generator::lfsr(taps)?;
+            } else if let Some(count) = input.strip_prefix("LFSR ") {
+                let count = count.trim().parse::<u64>().map_err(|_| "Invalid step count")?;
+                if !matches!(cpu.random, Generator::Lfsr { .. }) {
+                    return Err("Select an LFSR first with RAND LFSR <taps>".to_string());
+                }
+                for step in 0..count {
+                    // The count is not bounded, so a KILL or Ctrl-C must reach it
+                    if context.cancel.is_cancelled() {
+                        return Err(format!("Cancelled after {} steps", step));
+                    }
+                    cpu.random.step();
+                }
+                if let Generator::Lfsr { state, .. } = cpu.random {
+                    cpu.enter_value(state);
+                }
+            } else if input.starts_with("ENDIAN ") {
+                return Err("Usage: ENDIAN LITTLE|BIG".to_string());
+            } else if let Some(arg) = input.strip_prefix("SCRATCH ") {
+                let address = parse_number(arg.trim(), cpu.base).ok_or_else(|| number_error(arg.trim(), cpu.base))?;
+                let address = usize::try_from(address).unwrap_or(usize::MAX);
+                if address >= cpu.scratch.bytes().len() {
+                    return Err(format!("Address {:X} is outside scratch memory", address));
+                }
+                return Ok(Outcome::Message(cpu.scratch.dump(address, SCRATCH_VIEW_ROWS)));
+            } else if input.starts_with("DECODE ") {
+                // Base64 is case-sensitive, so decode the line as typed
+                return decode_into_scratch(cpu, &line[7..]);
+            } else if let Some(arg) = input.strip_prefix("ENCODE ") {
+                return encode_scratch(cpu, arg);
+            } else if let Some(arg) = input.strip_prefix("UTF8 ") {
+                let address = parse_number(arg.trim(), cpu.base).ok_or_else(|| number_error(arg.trim(), cpu.base))?;
+                let available = cpu.scratch.bytes().len().saturating_sub(usize::try_from(address).unwrap_or(usize::MAX));
+                let bytes = cpu.scratch.read(address, available.min(4))?.to_vec();
+                let (point, length) = decode_utf8(&bytes).map_err(|e| format!("At {:X}: {}", address, e))?;
+                cpu.enter_value(point as u128);
+                return Ok(Outcome::message(format!(
+                    "U+{:04X} {:?} from {} bytes: {}",
+                    point as u32,
+                    point,
+                    length,
+                    hex_bytes(&bytes[..length])
+                )));
+            } else if let Some(arg) = input.strip_prefix("CKSUM ") {
+                let value = checksum(cpu, arg)?;
+                cpu.enter_value(value);
+            } else if input.starts_with("MASKL ") || input.starts_with("MASKR ") {
+                let size = input[6..].trim().parse::<u8>().map_err(|_| "Invalid mask size")?;
+                make_mask(cpu, &input[..5], Some(size))?;
+            } else if let Some(values) = input.strip_prefix("PUSHN ") {
+                return push_values(cpu, values);
+            } else if let Some(values) = input.strip_prefix('[') {
+                let (values, rest) = values.split_once(']').ok_or("Missing ] after values")?;
+                return push_values(cpu, &format!("{} {}", values, rest));
+            } else if let Some((operation, arg)) = store_operation(&input) {
+                let reg = match arg {
+                    "(I)" => cpu.indirect_register()?,
+                    name => cpu.find_register(name)?,
+                };
+                cpu.store_operation(reg, operation)?;
+            } else if let Some(arg) = input.strip_prefix("STO ") {
+                let reg = cpu.find_register(arg.trim())?;
+                cpu.store(reg);
+            } else if let Some(arg) = input.strip_prefix("RCL ") {
+                let reg = cpu.find_register(arg.trim())?;
+                cpu.recall(reg);
+            } else if input.starts_with("TAG ") {
+                // The label keeps the case it was typed in
+                let mut words = line[4..].split_whitespace();
+                let reg = words.next().unwrap_or("");
+                let reg = parse_register(reg).ok_or("Usage: TAG <register> [label]")?;
+                let label = words.next().unwrap_or("");
+                if words.next().is_some() {
+                    return Err("Tags are one word of up to 16 characters".to_string());
+                }
+                cpu.tag(reg, label)?;
+            } else if let Some(arg) = input.strip_prefix("WS ") {
+                let size = arg.parse::<u8>().map_err(|_| "Invalid word size (1-128)")?;
+                let truncated = cpu.change_word_size(size)?;
+                if !truncated.is_empty() {
+                    match cpu.word_size_policy {
+                        WordSizePolicy::Warn => {
+                            return Ok(Outcome::message(format!("Warning: set bits truncated in {}", truncated.join(", "))));
+                        },
+                        WordSizePolicy::Preserve => {
+                            return Ok(Outcome::message(format!(
+                                "Full values of {} kept for a larger word size",
+                                truncated.join(", ")
+                            )));
+                        },
+                        _ => {},
+                    }
+                }
+            } else if let Some(arg) = input.strip_prefix("RESULTS ") {
+                let range: Vec<usize> = arg
+                    .split_whitespace()
+                    .map(|n| n.parse::<usize>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| "Usage: RESULTS ON|OFF|<first> <last>")?;
+                let [first, last] = range[..] else {
+                    return Err("Usage: RESULTS ON|OFF|<first> <last>".to_string());
+                };
+                cpu.results_bank = Some(ResultsBank::new(first, last, cpu.memory.len())?);
+            } else if let Some(arg) = input.strip_prefix("WSPOLICY ") {
+                cpu.word_size_policy = match arg.trim() {
+                    "TRUNCATE" => WordSizePolicy::Truncate,
+                    "WARN" => WordSizePolicy::Warn,
+                    "PRESERVE" => WordSizePolicy::Preserve,
+                    "ERROR" => WordSizePolicy::Error,
+                    _ => return Err("Usage: WSPOLICY TRUNCATE|WARN|PRESERVE|ERROR".to_string()),
+                };
+            } else if let Some(arg) = input.strip_prefix("FLOAT ") {
+                let digits = arg.trim().parse::<u8>().map_err(|_| format!("Usage: FLOAT <0-{}>", MAX_FLOAT_DIGITS))?;
+                cpu.set_float(digits)?;
+            } else if let Some(arg) = input.strip_prefix("FIELD ") {
+                cpu.set_field(Some(BitField::parse(arg)?))?;
+            } else if let Some(arg) = input.strip_prefix("GTO .") {
+                let number = arg.trim().parse::<usize>().map_err(|_| "Usage: GTO .nnn".to_string())?;
+                cpu.go_to_line(number)?;
+                if cpu.program_mode {
+                    return Ok(Outcome::message(program_line(cpu)));
+                }
+            } else if let Some(test) = Test::parse(&input) {
+                let held = test.evaluate(cpu)?;
+                return Ok(Outcome::message(if held { "TRUE" } else { "SKIP" }));
+            } else if let Some(control) = Control::parse(&input)? {
+                match control {
+                    Control::Label(_) => return Err("LBL only works in a program; use P/R to enter one".to_string()),
+                    Control::Goto(label) => cpu.go_to_line(cpu.program().label_line(label)?)?,
+                    Control::Gosub(label) => {
+                        let line = cpu.program().label_line(label)?;
+                        return run_program(cpu, storage, line, context);
+                    },
+                    Control::Return | Control::RunStop => unreachable!("RTN and R/S are matched above"),
+                }
+            } else if let Some(arg) = input.strip_prefix("SL ") {
+                let positions = shift_count(arg)?;
+                cpu.in_field(false, |cpu| cpu.shift_left(positions));
+            } else if let Some(arg) = input.strip_prefix("SR ") {
+                let positions = shift_count(arg)?;
+                cpu.in_field(false, |cpu| cpu.shift_right(positions));
+            } else {
+                match parse_entry(cpu, &input) {
+                    Some(value) => cpu.enter_value(value),
+                    None => return Err(number_error(&input, cpu.base)),
+                }
+            }
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// Parse a number in the given base.
+pub fn parse_number(text: &str, base: Base) -> Option<u128> {
+    u128::from_str_radix(text, base.radix() as u32).ok()
+}
+
+/// A typed number as register bits: in the current base, or a decimal
+/// float such as 1.5 or -2E-3 in FLOAT mode.
+fn parse_entry(cpu: &Hp16cCpu, text: &str) -> Option<u128> {
+    if !cpu.is_float() {
+        return parse_number(text, cpu.base);
+    }
+    // Rust also accepts INF and NAN, which the calculator cannot hold
+    let value = text.parse::<f64>().ok().filter(|value| value.is_finite())?;
+    Some(value.to_bits() as u128)
+}
+
+/// For text made only of hex digits, the position and character of the
+/// first digit that is not valid in `base`. Other text is not treated as a
+/// number attempt and yields None.
+pub fn invalid_digit(text: &str, base: Base) -> Option<(usize, char)> {
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    text.char_indices().find(|(_, c)| !c.is_digit(base.radix() as u32))
+}
+
+/// Preview of a number being typed, as it would be pushed: masked to the
+/// word size and shown in the other bases. None if `text` is not a number.
+pub fn number_preview(text: &str, base: Base, word_size: WordSize) -> Option<String> {
+    let mut value = parse_number(text, base)?;
+    if word_size < WordSize::MAX {
+        value &= (1u128 << word_size.bits()) - 1;
+    }
+    let mut preview = format!("= {} dec / {:X} hex / {:o} oct", value, value, value);
+    if word_size.bits() <= 16 {
+        preview.push_str(&format!(" / {:b} bin", value));
+    }
+    Some(preview)
+}
+
+fn number_error(input: &str, base: Base) -> String {
+    if let Some((_, digit)) = invalid_digit(input, base) {
+        format!("Invalid digit '{}' in {} (valid digits: {})", digit, base.name(), base.digits())
+    } else if input.chars().all(|c| c.is_ascii_hexdigit()) {
+        "Number too large for 128 bits".to_string()
+    } else {
+        format!("Unknown command or invalid number: {}", input)
+    }
+}
+
+fn list_constants(cpu: &Hp16cCpu) -> Vec<String> {
+    let mut lines: Vec<String> = BUILTIN
+        .iter()
+        .map(|c| format!("{:14} {:>24X}  {}", c.name, c.value, c.description))
+        .collect();
+    for (name, value) in cpu.constants.user() {
+        lines.push(format!("{:14} {:>24X}  (user defined)", name, value));
+    }
+    lines
+}
+
+/// PUSHN: push each value in order. With a trailing `STO n` they are
+/// stored in consecutive registers from Rn instead, and with `ST8 addr`
+/// (or ST16, ST32, ST64) one after another in scratch memory.
+fn push_values(cpu: &mut Hp16cCpu, text: &str) -> Result<Outcome, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let destination = words.iter().position(|word| *word == "STO" || narrow_width(word, "ST").is_some());
+    let (values, destination) = match destination {
+        Some(at) => (&words[..at], Some(&words[at..])),
+        None => (&words[..], None),
+    };
+    let values = values
+        .iter()
+        .map(|value| parse_number(value, cpu.base).ok_or_else(|| number_error(value, cpu.base)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.is_empty() {
+        return Err("No values to push".to_string());
+    }
+
+    match destination {
+        Some(["STO", register]) => {
+            let first = cpu.find_register(register)?;
+            let last = first + values.len() - 1;
+            if last >= cpu.memory.len() {
+                return Err(format!(
+                    "{} values do not fit in registers R{}-R{}",
+                    values.len(),
+                    first,
+                    cpu.memory.len() - 1
+                ));
+            }
+            for (register, value) in (first..).zip(&values) {
+                cpu.store_value(register, *value);
+            }
+            Ok(Outcome::message(format!("Stored {} values in R{}-R{}", values.len(), first, last)))
+        },
+        Some([store, arguments @ ..]) if *store != "STO" => {
+            let width = narrow_width(store, "ST").unwrap_or(1);
+            let (address, endian, _) = narrow_arguments(cpu, store, arguments)?;
+            for (i, value) in values.iter().enumerate() {
+                let mask = (1u128 << (width * 8)) - 1;
+                cpu.scratch.store(address + (i * width) as u128, width, value & mask, endian)?;
+            }
+            Ok(Outcome::message(format!(
+                "Stored {} values at {:X}-{:X}",
+                values.len(),
+                address,
+                address + (values.len() * width) as u128 - 1
+            )))
+        },
+        Some(_) => Err("Usage: [values] STO <register> or [values] ST8 <address>".to_string()),
+        None => {
+            for value in &values {
+                cpu.enter_value(*value);
+            }
+            if values.len() > 4 {
+                return Ok(Outcome::message(format!(
+                    "Pushed {} values; only the last 4 are on the stack",
+                    values.len()
+                )));
+            }
+            Ok(Outcome::Done)
+        },
+    }
+}
+
+/// CKSUM: a checksum over typed bytes, `<kind> [b ...]`, or over scratch
+/// memory, `<kind> <address> <length>`. Bytes and the address are in the
+/// current base and the length in decimal.
+fn checksum(cpu: &Hp16cCpu, arg: &str) -> Result<u128, String> {
+    let usage = "Usage: CKSUM IP|F16|F32 [bytes] or CKSUM IP|F16|F32 <address> <length>";
+    let (kind, source) = arg.trim().split_once(' ').ok_or(usage)?;
+    let kind = Checksum::parse(kind).ok_or(usage)?;
+    let source = source.trim();
+    let bytes = if let Some(values) = source.strip_prefix('[') {
+        let values = values.strip_suffix(']').ok_or("Missing ] after bytes")?;
+        values
+            .split_whitespace()
+            .map(|value| match parse_number(value, cpu.base) {
+                Some(byte) if byte <= 0xFF => Ok(byte as u8),
+                Some(_) => Err(format!("Not a byte: {}", value)),
+                None => Err(number_error(value, cpu.base)),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let (address, length) = source.split_once(' ').ok_or(usage)?;
+        let (address, length) = scratch_range(cpu, address, length)?;
+        cpu.scratch.read(address, length)?.to_vec()
+    };
+    Ok(kind.compute(&bytes, cpu.endian))
+}
+
+/// A scratch memory range written as an address in the current base and
+/// a decimal length.
+fn scratch_range(cpu: &Hp16cCpu, address: &str, length: &str) -> Result<(u128, usize), String> {
+    let address = address.trim();
+    let address = parse_number(address, cpu.base).ok_or_else(|| number_error(address, cpu.base))?;
+    let length = length.trim().parse::<usize>().map_err(|_| "Invalid length")?;
+    Ok((address, length))
+}
+
+/// DECODE HEX|B64 <address> <text>: write the decoded bytes to scratch
+/// memory. The text keeps the case it was typed in.
+fn decode_into_scratch(cpu: &mut Hp16cCpu, arg: &str) -> Result<Outcome, String> {
+    let usage = "Usage: DECODE HEX|B64 <address> <text>";
+    let mut words = arg.trim().splitn(3, ' ');
+    let (format, address, text) = match (words.next(), words.next(), words.next()) {
+        (Some(format), Some(address), Some(text)) => (format.to_uppercase(), address, text),
+        _ => return Err(usage.to_string()),
+    };
+    let bytes = match format.as_str() {
+        "HEX" => decode_hex(text)?,
+        "B64" | "BASE64" => decode_base64(text)?,
+        _ => return Err(usage.to_string()),
+    };
+    let address = parse_number(address, cpu.base).ok_or_else(|| number_error(address, cpu.base))?;
+    if bytes.is_empty() {
+        return Err("Nothing to decode".to_string());
+    }
+    cpu.scratch.write(address, &bytes)?;
+    Ok(Outcome::message(format!(
+        "Decoded {} bytes at {:X}-{:X}",
+        bytes.len(),
+        address,
+        address + bytes.len() as u128 - 1
+    )))
+}
+
+/// ENCODE HEX|B64 <address> <length>: show a scratch memory range as text.
+fn encode_scratch(cpu: &Hp16cCpu, arg: &str) -> Result<Outcome, String> {
+    let usage = "Usage: ENCODE HEX|B64 <address> <length>";
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    let [format, address, length] = words[..] else {
+        return Err(usage.to_string());
+    };
+    let (address, length) = scratch_range(cpu, address, length)?;
+    let bytes = cpu.scratch.read(address, length)?;
+    match format {
+        "HEX" => Ok(Outcome::message(bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())),
+        "B64" | "BASE64" => Ok(Outcome::message(encode_base64(bytes))),
+        _ => Err(usage.to_string()),
+    }
+}
+
+/// MASKL/MASKR: replace X, the mask size, with the mask. A size given
+/// with the command is entered first, as if typed.
+fn make_mask(cpu: &mut Hp16cCpu, command: &str, size: Option<u8>) -> Result<(), String> {
+    let count = size.map_or(cpu.x, u128::from);
+    if count > cpu.word_size.bits() as u128 {
+        return Err(format!("Mask size must be 0-{}", cpu.word_size));
+    }
+    if let Some(size) = size {
+        cpu.enter_value(size as u128);
+    }
+    if command == "MASKL" {
+        cpu.mask_left();
+    } else {
+        cpu.mask_right();
+    }
+    Ok(())
+}
+
+/// Byte width of a narrow scratch access such as ST16 or LD32, given its
+/// `prefix`.
+fn narrow_width(command: &str, prefix: &str) -> Option<usize> {
+    match command.strip_prefix(prefix)? {
+        "8" => Some(1),
+        "16" => Some(2),
+        "32" => Some(4),
+        "64" => Some(8),
+        _ => None,
+    }
+}
+
+/// The address, byte order and saturation option after a narrow access:
+/// `<address> [LE|BE] [SAT]`, with the address in the current base. The
+/// byte order defaults to the ENDIAN setting.
+fn narrow_arguments(cpu: &Hp16cCpu, command: &str, arguments: &[&str]) -> Result<(u128, Endian, bool), String> {
+    let usage = || format!("Usage: {} <address> [LE|BE] [SAT]", command);
+    let (address, options) = arguments.split_first().ok_or_else(usage)?;
+    let address = parse_number(address, cpu.base).ok_or_else(|| number_error(address, cpu.base))?;
+    let (mut endian, mut saturate) = (cpu.endian, false);
+    for option in options {
+        match *option {
+            "LE" => endian = Endian::Little,
+            "BE" => endian = Endian::Big,
+            "SAT" => saturate = true,
+            _ => return Err(usage()),
+        }
+    }
+    Ok((address, endian, saturate))
+}
+
+/// A decimal shift count, 0 to the largest word size.
+fn shift_count(text: &str) -> Result<u8, String> {
+    text.trim()
+        .parse::<u8>()
+        .ok()
+        .filter(|count| *count <= 128)
+        .ok_or_else(|| "Shift count must be 0-128".to_string())
+}
+
+fn flag_number(text: &str) -> Result<u8, String> {
+    text.trim().parse::<u8>().map_err(|_| "Invalid flag number (0-5)".to_string())
+}
+
+/// Flags 0-5 that are set, as a list of numbers.
+fn set_flags(cpu: &Hp16cCpu) -> String {
+    let set: Vec<String> = (0..).zip(cpu.flags()).filter(|(_, on)| *on).map(|(n, _)| n.to_string()).collect();
+    if set.is_empty() { "none".to_string() } else { set.join(" ") }
+}
+
+/// How / and RMD treat negative operands in signed modes.
+pub const DIVISION_CONVENTION: &str = "truncates toward zero; RMD takes the dividend's sign";
+
+/// Settings and flags for STATUS, one per line.
+fn status(cpu: &Hp16cCpu) -> Vec<String> {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    vec![
+        format!("Base:        {}", cpu.base.name()),
+        format!("Word size:   {} bits ({:?} on change)", cpu.word_size, cpu.word_size_policy),
+        format!("Mode:        {}", cpu.complement_mode.label()),
+        format!("Float:       {}", cpu.float_digits.map_or("off".to_string(), |digits| format!("{} digits", digits))),
+        format!("Byte order:  {}", cpu.endian.label()),
+        format!("Division:    {}", DIVISION_CONVENTION),
+        format!("Field:       {}", cpu.field.map_or("off".to_string(), |field| field.label())),
+        format!("I:           {}", cpu.format_value(cpu.i)),
+        format!(
+            "Results:     {}",
+            cpu.results_bank.map_or("off".to_string(), |bank| format!("R{}-R{}", bank.first, bank.last))
+        ),
+        format!("Random:      {}", cpu.random.describe()),
+        format!("Auto-ENTER:  {}", on_off(cpu.auto_enter)),
+        format!("Window:      {}", if cpu.window_follow { "auto" } else { "manual" }),
+        format!("Strict:      {}", on_off(cpu.strict)),
+        format!("Battery:     {}", if cpu.low_battery { "low" } else { "ok" }),
+        format!(
+            "Program:     {} steps, line {:03}{}",
+            cpu.program().len(),
+            cpu.pc,
+            if cpu.program_mode { " (PRGM mode)" } else { "" }
+        ),
+        format!("Carry:       {}", on_off(cpu.carry)),
+        format!("Overflow:    {}", on_off(cpu.overflow)),
+        format!("Flags set:   {}", set_flags(cpu)),
+    ]
+}
+
+/// Memory registers with their values and tags.
+fn memory_view(cpu: &Hp16cCpu) -> Vec<String> {
+    cpu.memory
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let tag = cpu.tags.get(&i).map(String::as_str).unwrap_or("");
+            format!("R{:<3} {:>24}  {}", register_name(i), cpu.format_value(*value), tag).trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Run a startup config script. Every line is checked against `context`,
+/// so in restricted mode the config can set the calculator up but cannot
+/// reach files any more than typed commands can.
+pub fn apply_config(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    name: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    run_script(cpu, storage, name, context)
+}
+
+#[cfg(feature = "scripting")]
+fn run_script(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    name: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    let mut read = |key: &str| storage.read(key).map_err(|e| format!("Could not read script {}: {}", key, e));
+    let script = Script::load(name, &SearchPath::from_env(), &mut read)?;
+    let mut execute = |cpu: &mut Hp16cCpu, line: &str| execute_in(cpu, storage, line, context);
+    let messages = script.run_cancellable(cpu, &context.cancel, &mut execute)?;
+
+    if messages.is_empty() {
+        Ok(Outcome::Done)
+    } else {
+        Ok(Outcome::Message(messages))
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn run_script(_: &mut Hp16cCpu, _: &mut dyn Storage, _: &str, _: &Context) -> Result<Outcome, String> {
+    Err("SOURCE is not available: built without the scripting feature".to_string())
+}
+
+/// Run the stored program from `line` until it stops.
+fn run_program(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    line: usize,
+    context: &Context,
+) -> Result<Outcome, String> {
+    let program = cpu.program().clone();
+    let messages = program.run(cpu, line, &context.cancel, &mut |cpu, step| execute_in(cpu, storage, step, context))?;
+    if messages.is_empty() {
+        Ok(Outcome::Done)
+    } else {
+        Ok(Outcome::Message(messages))
+    }
+}
+
+/// IMPORT: a program written in shorthand, each step checked. A step is
+/// tried on a copy of the calculator, with files and scripts refused, so
+/// one the command parser cannot read is an error giving its line and
+/// column; steps that only fail on the values they meet still import.
+fn import_program(cpu: &Hp16cCpu, storage: &mut dyn Storage, text: &str) -> Result<Program, String> {
+    let steps = shorthand::parse(text)?;
+    if steps.is_empty() {
+        return Err("No program steps to import".to_string());
+    }
+    let mut trial = cpu.clone();
+    trial.program_mode = false;
+    let context = Context {
+        restricted: true,
+        ..Context::default()
+    };
+    for source in &steps {
+        let step = &source.step;
+        if is_keyboard_command(step) || restricted_command(step).is_some() {
+            return Err(source.error(format!("{} cannot be a program step", step)));
+        }
+        if Control::parse(step)?.is_some() || Test::parse(step).is_some() {
+            continue;
+        }
+        match run_command(&mut trial, storage, step, &context) {
+            Err(e) if e == number_error(step, trial.base) => return Err(source.error(e)),
+            _ => {},
+        }
+    }
+    Ok(Program::from(steps.into_iter().map(|source| source.step).collect::<Vec<_>>()))
+}
+
+/// COMPILE: replace program memory with the program compiled from the
+/// file `name`, and list the register each variable was given.
+#[cfg(feature = "compiler")]
+fn compile_program(cpu: &mut Hp16cCpu, storage: &mut dyn Storage, name: &str) -> Result<Outcome, String> {
+    if cpu.is_float() {
+        return Err("COMPILE works on integers: leave FLOAT mode first".to_string());
+    }
+    let source = storage
+        .read(name)
+        .map_err(|e| format!("Could not read {}: {}", name, e))?
+        .ok_or_else(|| format!("No such file: {}", name))?;
+    let compiled = crate::compiler::compile(&source, cpu.base)?;
+    let steps = compiled.steps.len();
+    cpu.load_program(Program::from(compiled.steps))?;
+    let mut lines = vec![format!("{} steps compiled from {}", steps, name)];
+    lines.extend(compiled.registers.iter().map(|(variable, register)| {
+        format!("{} in R{}", variable, register_name(*register))
+    }));
+    Ok(Outcome::Message(lines))
+}
+
+#[cfg(not(feature = "compiler"))]
+fn compile_program(_: &mut Hp16cCpu, _: &mut dyn Storage, _: &str) -> Result<Outcome, String> {
+    Err("COMPILE is not available: built without the compiler feature".to_string())
+}
+
+/// DECOMPILE: program memory as structured pseudocode.
+#[cfg(feature = "compiler")]
+fn decompile_program(cpu: &Hp16cCpu) -> Result<Outcome, String> {
+    if cpu.program().is_empty() {
+        return Err("Program memory is empty".to_string());
+    }
+    Ok(Outcome::Message(crate::decompiler::decompile(cpu.program())))
+}
+
+#[cfg(not(feature = "compiler"))]
+fn decompile_program(_: &Hp16cCpu) -> Result<Outcome, String> {
+    Err("DECOMPILE is not available: built without the compiler feature".to_string())
+}
+
+/// SST in run mode: run one program step and show it with the stack after
+/// it, such as `005- 40  +` then `T:` down to `X:`.
+fn single_step(cpu: &mut Hp16cCpu, storage: &mut dyn Storage, context: &Context) -> Result<Outcome, String> {
+    let program = cpu.program().clone();
+    let line = program.keycode_line((cpu.pc as usize).max(1));
+    let mut lines = vec![line];
+    lines.extend(program.single_step(cpu, &mut |cpu, step| execute_in(cpu, storage, step, context))?);
+    lines.extend(cpu.get_stack_display());
+    Ok(Outcome::Message(lines))
+}
+
+/// CHECK: the program steps and settings a real HP-16C could not run.
+fn check_program(cpu: &Hp16cCpu) -> Vec<String> {
+    let mut lines = cpu.program().check(cpu.base);
+    if cpu.word_size.bits() > HP16C_MAX_WORD_SIZE {
+        lines.push(format!("Word size {} is above the HP-16C's {} bits", cpu.word_size, HP16C_MAX_WORD_SIZE));
+    }
+    if lines.is_empty() {
+        lines.push(match cpu.program().len() {
+            0 => "Program memory is empty".to_string(),
+            steps => format!("All {} steps run on an HP-16C", steps),
+        });
+    }
+    lines
+}
+
+/// The current program line as shown in program mode, such as `005- LBL A`.
+fn program_line(cpu: &Hp16cCpu) -> String {
+    match cpu.program().step(cpu.pc as usize) {
+        Some(step) => format!("{:03}- {}", cpu.pc, step),
+        None => format!("{:03}-", cpu.pc),
+    }
+}
+
+/// Parse a register range such as `0-7`, or a single register.
+fn register_range(cpu: &Hp16cCpu, text: &str) -> Result<std::ops::RangeInclusive<usize>, String> {
+    let text = text.trim();
+    let (first, last) = text.split_once('-').unwrap_or((text, text));
+    let register = |name: &str| {
+        cpu.register_for(name.trim())
+            .filter(|register| *register < cpu.memory.len())
+            .ok_or_else(|| format!("Invalid register range: {}", text))
+    };
+    let (first, last) = (register(first)?, register(last)?);
+    if first > last {
+        return Err(format!("Invalid register range: {} (write the lower register first)", text));
+    }
+    Ok(first..=last)
+}
+
+/// Fold a two-operand command across a register range and push the
+/// result. The rest of the stack is left as it was before the fold.
+fn reduce_registers(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    op: &str,
+    range: &str,
+    context: &Context,
+) -> Result<(), String> {
+    let range = register_range(cpu, range)?;
+    let stack = (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift);
+    let mut accumulator = cpu.memory[*range.start()];
+    for register in range.skip(1) {
+        if context.cancel.is_cancelled() {
+            (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift) = stack;
+            return Err(format!("Cancelled at R{}", register));
+        }
+        (cpu.y, cpu.x) = (accumulator, cpu.memory[register]);
+        if let Err(e) = run_command(cpu, storage, op, context) {
+            (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift) = stack;
+            return Err(format!("R{}: {}", register, e));
+        }
+        accumulator = cpu.x;
+    }
+    (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift) = stack;
+    cpu.enter_value(accumulator);
+    Ok(())
+}
+
+/// Split `STO+ n` and the like into the operation and the register name.
+fn store_operation(input: &str) -> Option<(StoreOperation, &str)> {
+    let mut chars = input.strip_prefix("STO")?.chars();
+    let operation = StoreOperation::from_symbol(chars.next()?)?;
+    let arg = chars.as_str();
+    arg.starts_with(' ').then(|| (operation, arg.trim()))
+}
+
+/// RADD/RXOR: combine register `dst` with `src` using `op` and store the
+/// result in `dst`. Flags are set as by `op`; the stack is untouched.
+fn combine_registers(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    command: &str,
+    op: &str,
+    arg: &str,
+    context: &Context,
+) -> Result<(), String> {
+    let usage = || format!("Usage: {} <destination> <source>", command);
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    let [destination, source] = words[..] else {
+        return Err(usage());
+    };
+    let register = |name: &str| {
+        cpu.register_for(name)
+            .filter(|register| *register < cpu.memory.len())
+            .ok_or_else(|| format!("Invalid register: {}", name))
+    };
+    let (destination, source) = (register(destination)?, register(source)?);
+
+    let stack = (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift);
+    (cpu.y, cpu.x) = (cpu.memory[destination], cpu.memory[source]);
+    let result = run_command(cpu, storage, op, context).map(|_| cpu.x);
+    (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift) = stack;
+    cpu.store_value(destination, result?);
+    Ok(())
+}
+
+/// Run `;`-separated commands on each register of a range in turn: the
+/// register is recalled, the commands run and X is stored back. The stack
+/// is restored afterwards.
+fn map_registers(
+    cpu: &mut Hp16cCpu,
+    storage: &mut dyn Storage,
+    range: &str,
+    commands: &str,
+    context: &Context,
+) -> Result<Outcome, String> {
+    let range = register_range(cpu, range)?;
+    let commands: Vec<&str> = commands.split(';').map(str::trim).filter(|c| !c.is_empty()).collect();
+    if commands.is_empty() {
+        return Err("Usage: MAPR <first>-<last> <cmd>; <cmd>...".to_string());
+    }
+
+    let stack = (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift);
+    let mut result = Ok(Outcome::Done);
+    'registers: for register in range {
+        if context.cancel.is_cancelled() {
+            result = Err(format!("Cancelled at R{}", register));
+            break;
+        }
+        cpu.recall(register);
+        for command in &commands {
+            if let Err(e) = run_command(cpu, storage, command, context) {
+                result = Err(format!("R{}: {}: {}", register, command, e));
+                break 'registers;
+            }
+        }
+        cpu.store_value(register, cpu.x);
+    }
+    (cpu.x, cpu.y, cpu.z, cpu.t, cpu.stack_lift) = stack;
+    result
+}
+
+fn diff_sessions(storage: &dyn Storage, left: &str, right: &str) -> Result<Vec<String>, String> {
+    let load = |name: &str| match storage.load_snapshot(name) {
+        Ok(Some(snapshot)) => Ok(snapshot),
+        Ok(None) => Err(format!("No saved session: {}", name)),
+        Err(e) => Err(format!("Could not load session {}: {}", name, e)),
+    };
+    let a = load(left)?;
+    let b = load(right)?;
+
+    let differences = a.diff(&b);
+    if differences.is_empty() {
+        return Ok(vec!["Sessions are identical".to_string()]);
+    }
+    Ok(render_differences(&differences, left, right))
+}
+
+fn render_differences(differences: &[Difference], left: &str, right: &str) -> Vec<String> {
+    let mut lines = vec![format!("{:10} {:>20} {:>20}", "Field", left, right)];
+    for d in differences {
+        lines.push(format!("{:10} {:>20} {:>20}", d.field, d.left, d.right));
+    }
+    lines
+}
+
+/// Start of the error VERIFY gives when the state has changed.
+const VERIFY_FAILED: &str = "State differs from checkpoint";
+
+/// Whether an error message reports a failed VERIFY, also from inside a
+/// script.
+pub fn is_verify_failure(message: &str) -> bool {
+    message.contains(VERIFY_FAILED)
+}
+
+/// Compare the state against a checkpoint. A mismatch is an error so that
+/// VERIFY stops a script at the line where the state diverged.
+fn verify_checkpoint(cpu: &Hp16cCpu, name: &str) -> Result<Outcome, String> {
+    let checkpoint = cpu
+        .checkpoints
+        .get(name)
+        .ok_or_else(|| format!("No checkpoint: {}", name))?;
+    let differences = checkpoint.diff(&Snapshot::capture(cpu));
+    if differences.is_empty() {
+        return Ok(Outcome::message(format!("State matches checkpoint {}", name)));
+    }
+    let mut lines = vec![format!("{} {}:", VERIFY_FAILED, name)];
+    lines.extend(render_differences(&differences, name, "now"));
+    Err(lines.join("\n"))
+}
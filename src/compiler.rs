@@ -0,0 +1,666 @@
+use crate::cpu::{register_name, Base, NAMED_REGISTERS};
+use crate::program::RETURN_STACK_DEPTH;
+use std::collections::HashMap;
+
+/// Stack levels an expression can use.
+const STACK_DEPTH: usize = 4;
+
+/// Labels in the order the compiler hands them out; A is the entry point.
+const LABELS: &str = "0123456789BCDEF";
+
+/// What COMPILE produces: the keystroke program and where each variable
+/// lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compiled {
+    pub steps: Vec<String>,
+    /// Each variable with its register, in the order they first appear
+    pub registers: Vec<(String, usize)>,
+}
+
+/// Compile a program in a small structured language to HP-16C steps, with
+/// numbers written in `base`, the base the program will run in.
+///
+/// The program starts at `LBL A`. Statements go one per line (or are
+/// separated by `;`), and `#` comments out the rest of a line:
+///
+/// ```text
+/// input n                 # n is X when the program starts
+/// total = 0
+/// while n > 0 {
+///     total = total + n
+///     n = n - 1
+/// }
+/// return total
+/// ```
+///
+/// Variables are given registers from R0 up, in the order they are set. `input a, b` takes a from Y
+/// and b from X; `if`/`else` and `while` take a comparison (`==`, `!=`,
+/// `<`, `<=`, `>`, `>=`); `sub name { ... }` defines a subroutine run by
+/// `call name`, nested no deeper than the HP-16C's four returns. Arithmetic
+/// is `+ - * / %` and `& | ^ ~`, within the four stack levels. Errors give
+/// the line they are on.
+pub fn compile(source: &str, base: Base) -> Result<Compiled, String> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let (main, subs) = parser.program()?;
+
+    let mut labels = LABELS.chars();
+    let mut sub_labels = HashMap::new();
+    for sub in &subs {
+        if sub_labels.contains_key(&sub.name) {
+            return Err(at(sub.line, format!("sub {} is defined twice", sub.name)));
+        }
+        let label = labels.next().ok_or_else(|| at(sub.line, out_of_labels()))?;
+        sub_labels.insert(sub.name.clone(), label);
+    }
+    check_nesting(&main, &subs)?;
+
+    let mut generator = Generator {
+        steps: vec!["LBL A".to_string()],
+        registers: Vec::new(),
+        labels,
+        sub_labels,
+        base,
+    };
+    for line in main.iter().chain(subs.iter().flat_map(|sub| &sub.body)) {
+        generator.allocate(line)?;
+    }
+    generator.block(&main)?;
+    generator.steps.push("RTN".to_string());
+    for sub in &subs {
+        generator.steps.push(format!("LBL {}", generator.sub_labels[&sub.name]));
+        generator.block(&sub.body)?;
+        generator.steps.push("RTN".to_string());
+    }
+    Ok(Compiled {
+        steps: generator.steps,
+        registers: generator.registers.into_iter().enumerate().map(|(register, name)| (name, register)).collect(),
+    })
+}
+
+// An error on a source line
+fn at(line: usize, message: impl std::fmt::Display) -> String {
+    format!("Line {}: {}", line, message)
+}
+
+fn out_of_labels() -> String {
+    "out of labels: the HP-16C has 16 (0-9, A-F)".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u128),
+    Name(String),
+    Symbol(&'static str),
+    // A new line or `;`
+    End,
+}
+
+// Longer symbols first, so `<=` is not read as `<`
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "=", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "{", "}", ",",
+];
+
+const KEYWORDS: &[&str] = &["input", "if", "else", "while", "sub", "call", "return"];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut rest = line.split('#').next().unwrap_or_default().trim_start();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix(';') {
+                tokens.push((number, Token::End));
+                rest = after.trim_start();
+                continue;
+            }
+            let word_len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let token = if word_len > 0 {
+                let word = &rest[..word_len];
+                if word.starts_with(|c: char| c.is_ascii_digit()) {
+                    Token::Number(parse_literal(word).ok_or_else(|| at(number, format!("invalid number {}", word)))?)
+                } else {
+                    Token::Name(word.to_string())
+                }
+            } else {
+                let symbol = SYMBOLS
+                    .iter()
+                    .find(|symbol| rest.starts_with(**symbol))
+                    .ok_or_else(|| at(number, format!("unexpected '{}'", rest.chars().next().unwrap_or(' '))))?;
+                Token::Symbol(symbol)
+            };
+            let len = match &token {
+                Token::Symbol(symbol) => symbol.len(),
+                _ => word_len,
+            };
+            tokens.push((number, token));
+            rest = rest[len..].trim_start();
+        }
+        tokens.push((number, Token::End));
+    }
+    Ok(tokens)
+}
+
+// A decimal number, or hex, octal or binary with 0x, 0o or 0b
+fn parse_literal(word: &str) -> Option<u128> {
+    let lower = word.to_lowercase();
+    let (digits, radix) = match lower.get(..2) {
+        Some("0x") => (&lower[2..], 16),
+        Some("0o") => (&lower[2..], 8),
+        Some("0b") => (&lower[2..], 2),
+        _ => (lower.as_str(), 10),
+    };
+    u128::from_str_radix(digits, radix).ok()
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(u128),
+    Variable(String),
+    // `-` or `~`
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    left: Expr,
+    comparison: &'static str,
+    right: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Input(Vec<String>),
+    Assign(String, Expr),
+    If(Condition, Vec<Line>, Vec<Line>),
+    While(Condition, Vec<Line>),
+    Call(String),
+    Return(Option<Expr>),
+}
+
+// A statement with the source line it starts on
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    statement: Statement,
+}
+
+#[derive(Debug, Clone)]
+struct Sub {
+    name: String,
+    line: usize,
+    body: Vec<Line>,
+}
+
+// Binary operators from the loosest to the tightest, with their steps
+const PRECEDENCE: &[&[(&str, &str)]] = &[
+    &[("|", "|"), ("^", "^")],
+    &[("&", "&")],
+    &[("+", "+"), ("-", "-")],
+    &[("*", "*"), ("/", "/"), ("%", "RMD")],
+];
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.position).map_or(&Token::End, |(_, token)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.position).or(self.tokens.last()).map_or(1, |(line, _)| *line)
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        self.position += 1;
+        token
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn skip_ends(&mut self) {
+        while !self.at_end() && *self.peek() == Token::End {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if *self.peek() == Token::Symbol(known_symbol(symbol)) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        Err(at(self.line(), format!("expected '{}'", symbol)))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let line = self.line();
+        match self.next() {
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            _ => Err(at(line, "expected a name")),
+        }
+    }
+
+    // The main statements and the subroutines
+    fn program(&mut self) -> Result<(Vec<Line>, Vec<Sub>), String> {
+        let mut main = Vec::new();
+        let mut subs = Vec::new();
+        loop {
+            self.skip_ends();
+            if self.at_end() {
+                return Ok((main, subs));
+            }
+            if *self.peek() == Token::Name("sub".to_string()) {
+                let line = self.line();
+                self.position += 1;
+                let name = self.name()?;
+                let body = self.block()?;
+                subs.push(Sub { name, line, body });
+            } else {
+                main.push(self.statement()?);
+            }
+        }
+    }
+
+    // `{ statements }`
+    fn block(&mut self) -> Result<Vec<Line>, String> {
+        self.expect("{")?;
+        let mut lines = Vec::new();
+        loop {
+            self.skip_ends();
+            if self.at_end() {
+                return Err(at(self.line(), "expected '}'"));
+            }
+            if self.eat("}") {
+                return Ok(lines);
+            }
+            lines.push(self.statement()?);
+        }
+    }
+
+    fn statement(&mut self) -> Result<Line, String> {
+        let number = self.line();
+        let Token::Name(word) = self.next() else {
+            return Err(at(number, "expected a statement"));
+        };
+        let statement = match word.as_str() {
+            "input" => {
+                let mut names = vec![self.name()?];
+                while self.eat(",") {
+                    names.push(self.name()?);
+                }
+                if names.len() > STACK_DEPTH {
+                    return Err(at(number, format!("input takes at most {} values, one per stack level", STACK_DEPTH)));
+                }
+                Statement::Input(names)
+            },
+            "if" => {
+                let condition = self.condition()?;
+                let then = self.block()?;
+                let otherwise = if self.eat_word("else") {
+                    if *self.peek() == Token::Name("if".to_string()) {
+                        vec![self.statement()?]
+                    } else {
+                        self.block()?
+                    }
+                } else {
+                    Vec::new()
+                };
+                Statement::If(condition, then, otherwise)
+            },
+            "while" => {
+                let condition = self.condition()?;
+                Statement::While(condition, self.block()?)
+            },
+            "call" => Statement::Call(self.name()?),
+            "return" => match self.peek() {
+                Token::End | Token::Symbol("}") => Statement::Return(None),
+                _ => Statement::Return(Some(self.expression(0)?)),
+            },
+            "else" | "sub" => return Err(at(number, format!("unexpected {}", word))),
+            _ => {
+                self.expect("=")?;
+                Statement::Assign(word, self.expression(0)?)
+            },
+        };
+        match self.peek() {
+            Token::End | Token::Symbol("}") => Ok(Line { number, statement }),
+            _ => Err(at(number, "expected the end of the statement")),
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        // `else` may follow the closing brace on the next line
+        let start = self.position;
+        self.skip_ends();
+        if *self.peek() == Token::Name(word.to_string()) {
+            self.position += 1;
+            return true;
+        }
+        self.position = start;
+        false
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let left = self.expression(0)?;
+        let line = self.line();
+        let comparison = match self.next() {
+            Token::Symbol(symbol @ ("==" | "!=" | "<" | "<=" | ">" | ">=")) => symbol,
+            _ => return Err(at(line, "expected a comparison (==, !=, <, <=, >, >=)")),
+        };
+        let right = self.expression(0)?;
+        Ok(Condition { left, comparison, right })
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.primary();
+        };
+        let mut left = self.expression(level + 1)?;
+        while let Some((_, step)) = operators.iter().find(|(symbol, _)| *self.peek() == Token::Symbol(known_symbol(symbol))) {
+            self.position += 1;
+            let right = self.expression(level + 1)?;
+            left = Expr::Binary(step, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        match self.next() {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => Ok(Expr::Variable(name)),
+            Token::Symbol("(") => {
+                let inner = self.expression(0)?;
+                self.expect(")")?;
+                Ok(inner)
+            },
+            Token::Symbol("-") => Ok(Expr::Unary("CHS", Box::new(self.primary()?))),
+            Token::Symbol("~") => Ok(Expr::Unary("~", Box::new(self.primary()?))),
+            _ => Err(at(line, "expected a number, a variable or '('")),
+        }
+    }
+}
+
+// The symbol from SYMBOLS spelled `text`, so tokens compare by value
+fn known_symbol(text: &str) -> &'static str {
+    SYMBOLS.iter().find(|symbol| **symbol == text).copied().unwrap_or("")
+}
+
+// Each subroutine's calls, to check they nest within the return stack
+fn calls(lines: &[Line], found: &mut Vec<(usize, String)>) {
+    for line in lines {
+        match &line.statement {
+            Statement::Call(name) => found.push((line.number, name.clone())),
+            Statement::If(_, then, otherwise) => {
+                calls(then, found);
+                calls(otherwise, found);
+            },
+            Statement::While(_, body) => calls(body, found),
+            _ => {},
+        }
+    }
+}
+
+fn check_nesting(main: &[Line], subs: &[Sub]) -> Result<(), String> {
+    // Returns a sub needs below it, or an error for recursion
+    fn depth(name: &str, subs: &[Sub], path: &mut Vec<String>) -> Result<usize, String> {
+        let sub = subs.iter().find(|sub| sub.name == name).expect("calls are checked first");
+        if path.iter().any(|called| called == name) {
+            return Err(at(sub.line, format!("sub {} calls itself; the HP-16C cannot recurse", name)));
+        }
+        path.push(name.to_string());
+        let mut found = Vec::new();
+        calls(&sub.body, &mut found);
+        let mut deepest = 0;
+        for (_, callee) in &found {
+            deepest = deepest.max(depth(callee, subs, path)?);
+        }
+        path.pop();
+        Ok(deepest + 1)
+    }
+
+    let mut all = Vec::new();
+    calls(main, &mut all);
+    for sub in subs {
+        calls(&sub.body, &mut all);
+    }
+    if let Some((line, name)) = all.iter().find(|(_, name)| !subs.iter().any(|sub| sub.name == *name)) {
+        return Err(at(*line, format!("no sub {}", name)));
+    }
+    let mut found = Vec::new();
+    calls(main, &mut found);
+    for (line, name) in found {
+        let levels = depth(&name, subs, &mut Vec::new())?;
+        if levels > RETURN_STACK_DEPTH {
+            return Err(at(
+                line,
+                format!("call {} nests subroutines {} deep; the HP-16C returns from {}", name, levels, RETURN_STACK_DEPTH),
+            ));
+        }
+    }
+    Ok(())
+}
+
+struct Generator {
+    steps: Vec<String>,
+    // Variable names by register
+    registers: Vec<String>,
+    labels: std::str::Chars<'static>,
+    sub_labels: HashMap<String, char>,
+    base: Base,
+}
+
+impl Generator {
+    fn label(&mut self, line: usize) -> Result<char, String> {
+        self.labels.next().ok_or_else(|| at(line, out_of_labels()))
+    }
+
+    // Give each variable set in `line` a register, in the order they are
+    // written
+    fn allocate(&mut self, line: &Line) -> Result<(), String> {
+        let names = match &line.statement {
+            Statement::Input(names) => names.clone(),
+            Statement::Assign(name, _) => vec![name.clone()],
+            Statement::If(_, then, otherwise) => {
+                for inner in then.iter().chain(otherwise) {
+                    self.allocate(inner)?;
+                }
+                Vec::new()
+            },
+            Statement::While(_, body) => {
+                for inner in body {
+                    self.allocate(inner)?;
+                }
+                Vec::new()
+            },
+            Statement::Call(_) | Statement::Return(_) => Vec::new(),
+        };
+        for name in names {
+            if self.registers.contains(&name) {
+                continue;
+            }
+            if self.registers.len() == NAMED_REGISTERS {
+                return Err(at(line.number, format!("out of registers: a program can name {}", NAMED_REGISTERS)));
+            }
+            self.registers.push(name);
+        }
+        Ok(())
+    }
+
+    fn register(&self, name: &str) -> String {
+        register_name(self.registers.iter().position(|known| known == name).unwrap_or_default())
+    }
+
+    fn block(&mut self, lines: &[Line]) -> Result<(), String> {
+        for line in lines {
+            self.statement(line)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, line: &Line) -> Result<(), String> {
+        let number = line.number;
+        match &line.statement {
+            Statement::Input(names) => {
+                // The last name is X; rolling down brings up Y, then Z
+                for (index, name) in names.iter().rev().enumerate() {
+                    if index > 0 {
+                        self.steps.push("RV".to_string());
+                    }
+                    self.steps.push(format!("STO {}", self.register(name)));
+                }
+            },
+            Statement::Assign(name, value) => {
+                self.expression(number, value)?;
+                self.steps.push(format!("STO {}", self.register(name)));
+            },
+            Statement::If(condition, then, otherwise) => {
+                let skip = self.label(number)?;
+                self.branch_unless(number, condition, skip)?;
+                self.block(then)?;
+                if otherwise.is_empty() {
+                    self.steps.push(format!("LBL {}", skip));
+                } else {
+                    let end = self.label(number)?;
+                    self.steps.push(format!("GTO {}", end));
+                    self.steps.push(format!("LBL {}", skip));
+                    self.block(otherwise)?;
+                    self.steps.push(format!("LBL {}", end));
+                }
+            },
+            Statement::While(condition, body) => {
+                let (top, end) = (self.label(number)?, self.label(number)?);
+                self.steps.push(format!("LBL {}", top));
+                self.branch_unless(number, condition, end)?;
+                self.block(body)?;
+                self.steps.push(format!("GTO {}", top));
+                self.steps.push(format!("LBL {}", end));
+            },
+            Statement::Call(name) => self.steps.push(format!("GSB {}", self.sub_labels[name])),
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(number, value)?;
+                }
+                self.steps.push("RTN".to_string());
+            },
+        }
+        Ok(())
+    }
+
+    // Go to `label` when `condition` is false. The HP-16C's tests skip the
+    // next step when false, so each comparison uses the test for its
+    // opposite ahead of a GTO.
+    fn branch_unless(&mut self, line: usize, condition: &Condition, label: char) -> Result<(), String> {
+        let Condition { left, comparison, right } = condition;
+        let test = match (*comparison, right) {
+            ("==", Expr::Number(0)) | ("!=", Expr::Number(0)) => {
+                self.expression(line, left)?;
+                if *comparison == "==" { "X!=0?" } else { "X=0?" }
+            },
+            _ => {
+                // With a in Y and b in X, a < b is x > y and a >= b is x <= y
+                let (first, second, test) = match *comparison {
+                    "==" => (left, right, "X!=Y?"),
+                    "!=" => (left, right, "X=Y?"),
+                    "<" => (left, right, "X<=Y?"),
+                    ">=" => (left, right, "X>Y?"),
+                    ">" => (right, left, "X<=Y?"),
+                    _ => (right, left, "X>Y?"),
+                };
+                let needed = pair_depth(first, second);
+                if needed > STACK_DEPTH {
+                    return Err(at(line, too_deep(needed)));
+                }
+                self.check_variables(line, first)?;
+                self.check_variables(line, second)?;
+                self.push(first);
+                self.push(second);
+                test
+            },
+        };
+        self.steps.push(test.to_string());
+        self.steps.push(format!("GTO {}", label));
+        Ok(())
+    }
+
+    fn expression(&mut self, line: usize, expr: &Expr) -> Result<(), String> {
+        let needed = depth(expr);
+        if needed > STACK_DEPTH {
+            return Err(at(line, too_deep(needed)));
+        }
+        self.check_variables(line, expr)?;
+        self.push(expr);
+        Ok(())
+    }
+
+    fn check_variables(&self, line: usize, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Number(_) => Ok(()),
+            Expr::Variable(name) if self.registers.contains(name) => Ok(()),
+            Expr::Variable(name) => Err(at(line, format!("{} is never set", name))),
+            Expr::Unary(_, inner) => self.check_variables(line, inner),
+            Expr::Binary(_, left, right) => {
+                self.check_variables(line, left)?;
+                self.check_variables(line, right)
+            },
+        }
+    }
+
+    // The steps leaving `expr` in X, with what was in X lifted into Y
+    fn push(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(value) => self.steps.push(format_number(*value, self.base)),
+            Expr::Variable(name) => self.steps.push(format!("RCL {}", self.register(name))),
+            Expr::Unary(step, inner) => {
+                self.push(inner);
+                self.steps.push(step.to_string());
+            },
+            Expr::Binary(step, left, right) => {
+                self.push(left);
+                self.push(right);
+                self.steps.push(step.to_string());
+            },
+        }
+    }
+}
+
+// Stack levels `expr` needs: the left operand waits in Y while the right
+// one is worked out
+fn depth(expr: &Expr) -> usize {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => 1,
+        Expr::Unary(_, inner) => depth(inner),
+        Expr::Binary(_, left, right) => pair_depth(left, right),
+    }
+}
+
+fn pair_depth(first: &Expr, second: &Expr) -> usize {
+    depth(first).max(depth(second) + 1)
+}
+
+fn too_deep(needed: usize) -> String {
+    format!("expression needs {} stack levels and the HP-16C has {}; set part of it to a variable first", needed, STACK_DEPTH)
+}
+
+fn format_number(value: u128, base: Base) -> String {
+    match base {
+        Base::Binary => format!("{:b}", value),
+        Base::Octal => format!("{:o}", value),
+        Base::Decimal => value.to_string(),
+        Base::Hex => format!("{:X}", value),
+    }
+}
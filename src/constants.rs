@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+/// A named value that can be pushed with `CONST name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constant {
+    pub name: &'static str,
+    pub value: u128,
+    pub description: &'static str,
+}
+
+const fn constant(name: &'static str, value: u128, description: &'static str) -> Constant {
+    Constant { name, value, description }
+}
+
+/// Bundled constants: sizes, limits, masks, CRC polynomials and hash magic.
+/// Signed minimums are given as their two's complement bit patterns.
+pub const BUILTIN: &[Constant] = &[
+    // Sizes
+    constant("KIB", 1 << 10, "Kibibyte"),
+    constant("MIB", 1 << 20, "Mebibyte"),
+    constant("GIB", 1 << 30, "Gibibyte"),
+    constant("PAGE_SIZE", 4096, "Common MMU page size"),
+    constant("SECTOR_SIZE", 512, "Classic disk sector size"),
+    // Limits
+    constant("U8_MAX", u8::MAX as u128, "Largest unsigned 8-bit value"),
+    constant("U16_MAX", u16::MAX as u128, "Largest unsigned 16-bit value"),
+    constant("U32_MAX", u32::MAX as u128, "Largest unsigned 32-bit value"),
+    constant("U64_MAX", u64::MAX as u128, "Largest unsigned 64-bit value"),
+    constant("I8_MAX", i8::MAX as u128, "Largest signed 8-bit value"),
+    constant("I16_MAX", i16::MAX as u128, "Largest signed 16-bit value"),
+    constant("I32_MAX", i32::MAX as u128, "Largest signed 32-bit value"),
+    constant("I64_MAX", i64::MAX as u128, "Largest signed 64-bit value"),
+    constant("I8_MIN", 0x80, "Smallest signed 8-bit value"),
+    constant("I16_MIN", 0x8000, "Smallest signed 16-bit value"),
+    constant("I32_MIN", 0x8000_0000, "Smallest signed 32-bit value"),
+    constant("I64_MIN", 0x8000_0000_0000_0000, "Smallest signed 64-bit value"),
+    // Masks
+    constant("NIBBLE_MASK", 0xF, "Low nibble"),
+    constant("BYTE_MASK", 0xFF, "Low byte"),
+    constant("WORD_MASK", 0xFFFF, "Low 16 bits"),
+    constant("DWORD_MASK", 0xFFFF_FFFF, "Low 32 bits"),
+    // CRC polynomials (normal form unless noted)
+    constant("CRC8", 0x07, "CRC-8 (SMBus) polynomial"),
+    constant("CRC16_CCITT", 0x1021, "CRC-16/CCITT polynomial"),
+    constant("CRC16_IBM", 0x8005, "CRC-16/IBM (ARC, Modbus) polynomial"),
+    constant("CRC32", 0x04C1_1DB7, "CRC-32 (Ethernet, zlib) polynomial"),
+    constant("CRC32_REV", 0xEDB8_8320, "CRC-32 polynomial, reflected"),
+    constant("CRC32C", 0x1EDC_6F41, "CRC-32C (Castagnoli) polynomial"),
+    constant("CRC64_ECMA", 0x42F0_E1EB_A9EA_3693, "CRC-64/ECMA-182 polynomial"),
+    // Hashing magic numbers
+    constant("FNV32_PRIME", 0x0100_0193, "FNV-1 32-bit prime"),
+    constant("FNV32_OFFSET", 0x811C_9DC5, "FNV-1 32-bit offset basis"),
+    constant("FNV64_PRIME", 0x0100_0000_01B3, "FNV-1 64-bit prime"),
+    constant("FNV64_OFFSET", 0xCBF2_9CE4_8422_2325, "FNV-1 64-bit offset basis"),
+    constant("GOLDEN32", 0x9E37_79B9, "32-bit golden ratio (TEA, hashing)"),
+    constant("GOLDEN64", 0x9E37_79B9_7F4A_7C15, "64-bit golden ratio"),
+];
+
+/// Bundled constants plus any the user defines during the session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Constants {
+    user: BTreeMap<String, u128>,
+}
+
+impl Constants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define or redefine a user constant. Names are letters, digits and
+    /// underscores starting with a letter, and cannot replace bundled ones.
+    pub fn define(&mut self, name: &str, value: u128) -> Result<(), String> {
+        let name = name.to_uppercase();
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("Invalid constant name: {}", name));
+        }
+        if BUILTIN.iter().any(|c| c.name == name) {
+            return Err(format!("{} is a bundled constant", name));
+        }
+        self.user.insert(name, value);
+        Ok(())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u128> {
+        let name = name.to_uppercase();
+        BUILTIN
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.value)
+            .or_else(|| self.user.get(&name).copied())
+    }
+
+    pub fn user(&self) -> &BTreeMap<String, u128> {
+        &self.user
+    }
+
+    pub fn set_user(&mut self, user: BTreeMap<String, u128>) {
+        self.user = user;
+    }
+}
@@ -0,0 +1,192 @@
+use crate::keypad::{is_key_code, Keypad};
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+/// A button, note or controller of an input device that can be mapped to
+/// a calculator key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// A gamepad or joystick button, by number
+    Button(u8),
+    /// A MIDI note, on any channel
+    Note(u8),
+    /// A MIDI control change, pressed at 64 and above
+    ControlChange(u8),
+}
+
+/// A control going down or coming back up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerEvent {
+    pub control: Control,
+    pub pressed: bool,
+}
+
+/// Bindings from device controls to HP-16C key codes.
+///
+/// The file format is one `CONTROL = KEY` binding per line, where CONTROL
+/// is `button n`, `note n` or `cc n` and KEY is a key code as the
+/// [`Keypad`] takes it: `button 0 = 36` or `note 60 = 11`. Lines starting
+/// with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct ControllerMap {
+    bindings: Vec<(Control, u8)>,
+}
+
+impl ControllerMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_from_file(filename: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = ControllerMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            // Skip comments and empty lines
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (control, key) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected CONTROL = KEY", number + 1))?;
+            let control = parse_control(control.trim())
+                .ok_or_else(|| format!("line {}: unknown control '{}'", number + 1, control.trim()))?;
+            let key = key
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|key| is_key_code(*key))
+                .ok_or_else(|| format!("line {}: no HP-16C key code '{}'", number + 1, key.trim()))?;
+            map.bind(control, key);
+        }
+
+        Ok(map)
+    }
+
+    /// Bind a control, replacing any earlier binding for it.
+    pub fn bind(&mut self, control: Control, key: u8) {
+        self.bindings.retain(|(existing, _)| *existing != control);
+        self.bindings.push((control, key));
+    }
+
+    pub fn key_for(&self, control: Control) -> Option<u8> {
+        self.bindings.iter().find(|(existing, _)| *existing == control).map(|(_, key)| *key)
+    }
+
+    pub fn bindings(&self) -> &[(Control, u8)] {
+        &self.bindings
+    }
+}
+
+fn parse_control(text: &str) -> Option<Control> {
+    let (kind, number) = text.split_once(char::is_whitespace)?;
+    let number = number.trim().parse::<u8>().ok()?;
+    match kind.to_lowercase().as_str() {
+        "button" => Some(Control::Button(number)),
+        "note" => Some(Control::Note(number)),
+        "cc" => Some(Control::ControlChange(number)),
+        _ => None,
+    }
+}
+
+/// Decodes a raw MIDI byte stream, as read from a device such as
+/// `/dev/snd/midiC1D0`, into note and control change events. Running
+/// status is followed; other messages are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct MidiParser {
+    status: Option<u8>,
+    data: Vec<u8>,
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the next byte, returning the event it completes.
+    pub fn feed(&mut self, byte: u8) -> Option<ControllerEvent> {
+        match byte {
+            // Real-time messages may come between the bytes of any other
+            0xF8..=0xFF => return None,
+            // System messages and SysEx carry nothing mapped
+            0xF0..=0xF7 => {
+                self.status = None;
+                return None;
+            },
+            0x80..=0xEF => {
+                self.status = Some(byte);
+                self.data.clear();
+                return None;
+            },
+            _ => {},
+        }
+        let status = self.status?;
+        self.data.push(byte);
+        let length = match status >> 4 {
+            0xC | 0xD => 1,
+            _ => 2,
+        };
+        if self.data.len() < length {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data);
+        let (control, pressed) = match status >> 4 {
+            0x8 => (Control::Note(data[0]), false),
+            0x9 => (Control::Note(data[0]), data[1] > 0),
+            0xB => (Control::ControlChange(data[0]), data[1] >= 64),
+            _ => return None,
+        };
+        Some(ControllerEvent { control, pressed })
+    }
+}
+
+/// Size of an event from the Linux joystick interface, `/dev/input/js0`.
+pub const JOYSTICK_EVENT_SIZE: usize = 8;
+
+/// The button event in one joystick interface event, or None for axis
+/// movement and the initial state the driver reports on opening.
+pub fn joystick_event(event: &[u8; JOYSTICK_EVENT_SIZE]) -> Option<ControllerEvent> {
+    // A 32-bit timestamp, a 16-bit value, then the type and the number
+    const BUTTON: u8 = 0x01;
+    let value = i16::from_ne_bytes([event[4], event[5]]);
+    (event[6] == BUTTON).then_some(ControllerEvent {
+        control: Control::Button(event[7]),
+        pressed: value != 0,
+    })
+}
+
+/// A device's events played on the calculator's keys: each mapped control
+/// presses and releases its key on a [`Keypad`], which gives the command
+/// lines to run.
+#[derive(Debug, Clone, Default)]
+pub struct Controller {
+    pub map: ControllerMap,
+    pub keypad: Keypad,
+}
+
+impl Controller {
+    pub fn new(map: ControllerMap, keypad: Keypad) -> Self {
+        Controller { map, keypad }
+    }
+
+    /// Handle `event`, which happened at `at`. Controls without a binding
+    /// are ignored.
+    pub fn handle(&mut self, event: ControllerEvent, at: Instant) -> Result<Vec<String>, String> {
+        let Some(key) = self.map.key_for(event.control) else {
+            return Ok(Vec::new());
+        };
+        if !event.pressed {
+            self.keypad.release(key);
+            return Ok(Vec::new());
+        }
+        self.keypad.press(key, at)
+    }
+}
@@ -1,145 +1,1640 @@
+use crate::constants::Constants;
+use crate::display::StackView;
+use crate::printer::Printer;
+use crate::program::{self, Control, Program};
+#[cfg(feature = "rom")]
 use crate::rom::Rom;
+use crate::random::Generator;
+use crate::scratch::{Endian, Scratch};
+use crate::snapshot::Snapshot;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Index;
+
+/// How register contents are interpreted by arithmetic, comparisons and
+/// the decimal display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ComplementMode {
+    #[default]
+    Unsigned,
+    /// Signed values in 1's complement form, with a negative zero
+    OnesComplement,
+    /// Signed values in 2's complement form within the word size
+    TwosComplement,
+}
+
+/// How the memory pool is split, as the HP-16C's MEM shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStatus {
+    /// Program steps that still fit in the allocated program memory
+    pub free_steps: usize,
+    /// Data registers at the current word size
+    pub registers: usize,
+    /// Bytes each register takes
+    pub register_bytes: usize,
+    /// Bytes of the pool too few for another register
+    pub unused_bytes: usize,
+}
+
+/// `P-0 r-101`, as on the calculator's display.
+impl fmt::Display for MemoryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "P-{} r-{}", self.free_steps, self.registers)
+    }
+}
+
+/// Memory registers that receive each operation result in turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultsBank {
+    pub first: usize,
+    pub last: usize,
+    /// Register the next result goes to
+    pub next: usize,
+}
+
+impl ResultsBank {
+    /// Registers R10-R15.
+    pub const DEFAULT: ResultsBank = ResultsBank { first: 10, last: 15, next: 10 };
+
+    /// Registers `first` to `last` of a memory of `registers` registers.
+    pub fn new(first: usize, last: usize, registers: usize) -> Result<Self, String> {
+        if first > last || last >= registers {
+            return Err(format!("Results bank must be a register range within 0-{}", registers.saturating_sub(1)));
+        }
+        Ok(ResultsBank { first, last, next: first })
+    }
+
+    /// Bank registers from the newest result to the oldest.
+    pub fn newest_first(&self) -> Vec<usize> {
+        let len = self.last - self.first + 1;
+        (1..=len)
+            .map(|back| self.first + (self.next - self.first + len - back) % len)
+            .collect()
+    }
+}
+
+/// One of the four stack registers, for indexing: `cpu[StackRegister::Y]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackRegister {
+    X,
+    Y,
+    Z,
+    T,
+}
+
+/// Binary digits the display shows at a time. Longer binary values are
+/// shown one window of this many digits at a time.
+pub const WINDOW_DIGITS: usize = 8;
+
+/// User flag that shows leading zeros, as on the HP-16C.
+pub const LEADING_ZEROS_FLAG: usize = 3;
+
+/// Bytes of memory shared by program steps and data registers, as on the
+/// HP-16C.
+pub const MEMORY_BYTES: usize = 203;
+
+/// Program memory is taken from the pool seven one-byte steps at a time.
+pub const PROGRAM_BLOCK_STEPS: usize = 7;
+
+/// Bytes a data register takes at `word_size`: one per started byte.
+pub fn register_bytes(word_size: u8) -> usize {
+    (word_size as usize).div_ceil(8)
+}
+
+/// Registers with a name on the keyboard: 0-9, A-F, .0-.9 and .A-.F.
+pub const NAMED_REGISTERS: usize = 32;
+
+/// The keyboard name of `register`: R10 is A and R19 is .3. Registers
+/// past .F only have their decimal number.
+pub fn register_name(register: usize) -> String {
+    match register {
+        0..=15 => format!("{:X}", register),
+        16..=31 => format!(".{:X}", register - 16),
+        _ => register.to_string(),
+    }
+}
+
+/// The register a name stands for: a decimal number, a hex digit A-F, or
+/// a point and one hex digit. Case is ignored.
+pub fn parse_register(name: &str) -> Option<usize> {
+    if let Ok(register) = name.parse::<usize>() {
+        return Some(register);
+    }
+    let (offset, digit) = match name.strip_prefix('.') {
+        Some(digit) => (16, digit),
+        None => (0, name),
+    };
+    let mut chars = digit.chars();
+    match (chars.next().and_then(|c| c.to_digit(16)), chars.next()) {
+        (Some(value), None) if offset > 0 || value >= 10 => Some(offset + value as usize),
+        _ => None,
+    }
+}
+
+/// Number base for entering and showing values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Base {
+    Binary,
+    Octal,
+    Decimal,
+    Hex,
+}
+
+impl Base {
+    pub fn radix(&self) -> u8 {
+        match self {
+            Base::Binary => 2,
+            Base::Octal => 8,
+            Base::Decimal => 10,
+            Base::Hex => 16,
+        }
+    }
+
+    /// Name of the base as on the calculator's keyboard.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Base::Binary => "BIN",
+            Base::Octal => "OCT",
+            Base::Decimal => "DEC",
+            Base::Hex => "HEX",
+        }
+    }
+
+    /// Name of the base for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Base::Binary => "binary",
+            Base::Octal => "octal",
+            Base::Decimal => "decimal",
+            Base::Hex => "hexadecimal",
+        }
+    }
+
+    /// The digits a number in this base may use, for error messages.
+    pub fn digits(&self) -> &'static str {
+        match self {
+            Base::Binary => "0-1",
+            Base::Octal => "0-7",
+            Base::Decimal => "0-9",
+            Base::Hex => "0-9, A-F",
+        }
+    }
+
+    /// Annunciator the printer and faceplate show after a value.
+    pub fn suffix(&self) -> char {
+        match self {
+            Base::Binary => 'b',
+            Base::Octal => 'o',
+            Base::Decimal => 'd',
+            Base::Hex => 'h',
+        }
+    }
+
+    /// Bits each digit stands for, or None for decimal.
+    pub fn bits_per_digit(&self) -> Option<usize> {
+        match self {
+            Base::Binary => Some(1),
+            Base::Octal => Some(3),
+            Base::Decimal => None,
+            Base::Hex => Some(4),
+        }
+    }
+}
+
+impl TryFrom<u8> for Base {
+    type Error = String;
+
+    fn try_from(radix: u8) -> Result<Self, String> {
+        match radix {
+            2 => Ok(Base::Binary),
+            8 => Ok(Base::Octal),
+            10 => Ok(Base::Decimal),
+            16 => Ok(Base::Hex),
+            _ => Err(format!("Invalid base {} (2, 8, 10 or 16)", radix)),
+        }
+    }
+}
+
+impl From<Base> for u8 {
+    fn from(base: Base) -> u8 {
+        base.radix()
+    }
+}
+
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.label())
+    }
+}
+
+/// A word size of 1 to 128 bits. Other sizes cannot be made: use
+/// `WordSize::try_from(bits)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct WordSize(u8);
+
+impl WordSize {
+    pub const MIN: WordSize = WordSize(1);
+    pub const MAX: WordSize = WordSize(128);
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for WordSize {
+    /// 16 bits, the HP-16C's power-on word size.
+    fn default() -> Self {
+        WordSize(16)
+    }
+}
+
+impl TryFrom<u8> for WordSize {
+    type Error = String;
+
+    fn try_from(bits: u8) -> Result<Self, String> {
+        if (Self::MIN.0..=Self::MAX.0).contains(&bits) {
+            Ok(WordSize(bits))
+        } else {
+            Err("Invalid word size (1-128)".to_string())
+        }
+    }
+}
+
+impl From<WordSize> for u8 {
+    fn from(size: WordSize) -> u8 {
+        size.0
+    }
+}
+
+impl fmt::Display for WordSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Most digits FLOAT shows after the decimal point.
+pub const MAX_FLOAT_DIGITS: u8 = 9;
+
+/// Word size used in FLOAT mode, to hold an f64.
+const FLOAT_WORD_SIZE: u8 = 64;
+
+/// Word size set on leaving FLOAT mode, as on the HP-16C.
+const FLOAT_EXIT_WORD_SIZE: u8 = 56;
+
+/// Bits in the mantissa FLOAT mode leaves in Y.
+const MANTISSA_BITS: u32 = 32;
+
+/// What WS does with set bits that do not fit a smaller word size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WordSizePolicy {
+    /// Drop them silently
+    #[default]
+    Truncate,
+    /// Drop them and report which registers lost bits
+    Warn,
+    /// Keep the full values aside and bring them back when the word size
+    /// is raised again, for registers not changed in between
+    Preserve,
+    /// Refuse the change
+    Error,
+}
+
+/// Arithmetic STO+, STO-, STO× and STO÷ do into a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOperation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl StoreOperation {
+    /// The operation for `+`, `-`, `*` or `/`, or their `−`, `×` and `÷`
+    /// spellings.
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        match symbol {
+            '+' => Some(StoreOperation::Add),
+            '-' | '−' => Some(StoreOperation::Subtract),
+            '*' | '×' => Some(StoreOperation::Multiply),
+            '/' | '÷' => Some(StoreOperation::Divide),
+            _ => None,
+        }
+    }
+}
+
+/// A window of bits, such as 15:8, that arithmetic and logic work on
+/// instead of the whole word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitField {
+    pub high: u8,
+    pub low: u8,
+}
+
+impl BitField {
+    /// Parse `high:low`, or a single bit number.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (high, low) = text.split_once(':').unwrap_or((text, text));
+        let bit = |part: &str| part.trim().parse::<u8>().map_err(|_| format!("Invalid bit field: {}", text));
+        let (high, low) = (bit(high)?, bit(low)?);
+        if low > high {
+            return Err(format!("Invalid bit field: {} (write the high bit first)", text));
+        }
+        Ok(BitField { high, low })
+    }
+
+    pub fn width(&self) -> u8 {
+        self.high - self.low + 1
+    }
+
+    /// Mask covering the field's width, not yet shifted into place.
+    fn mask(&self) -> u128 {
+        if self.width() == 128 { u128::MAX } else { (1u128 << self.width()) - 1 }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}:{}", self.high, self.low)
+    }
+}
+
+impl ComplementMode {
+    /// Name of the mode as on the calculator's keyboard.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ComplementMode::Unsigned => "UNSGN",
+            ComplementMode::OnesComplement => "1'S",
+            ComplementMode::TwosComplement => "2'S",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Hp16cCpu {
-    // RPN Stack (X, Y, Z, T registers)
-    pub x: u128,  // Display register
-    pub y: u128,  // First operand
-    pub z: u128,  // Second operand  
-    pub t: u128,  // Third operand
-
-    // Program counter and ROM
-    pub pc: u16,
-    pub rom: Rom,
-    
-    // Word size (1-128 bits)
-    pub word_size: u8,
+    // RPN Stack (X, Y, Z, T registers). Outside the crate these are read
+    // and written through x() and set_x() and friends, which keep values
+    // within the word size; the compat feature still exposes the fields
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use x() and set_x()")]
+    pub x: u128,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) x: u128,  // Display register
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use y() and set_y()")]
+    pub y: u128,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) y: u128,  // First operand
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use z() and set_z()")]
+    pub z: u128,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) z: u128,  // Second operand
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use t() and set_t()")]
+    pub t: u128,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) t: u128,  // Third operand
+
+    // Program counter: the current line of program memory, 0 for the top
+    pub(crate) pc: u16,
+    // Whether typed lines are stored as program steps, toggled by P/R
+    pub(crate) program_mode: bool,
+    // Whether program mode refuses steps a real HP-16C cannot run, set
+    // with STRICT ON
+    pub(crate) strict: bool,
+    // Simulated low battery, shown as the HP-16C's * indicator
+    pub(crate) low_battery: bool,
+    #[cfg(feature = "rom")]
+    pub(crate) rom: Rom,
     
-    // Number base (2, 8, 10, 16)
-    pub base: u8,
+    // Word size, read with word_size() and changed with set_word_size()
+    // or change_word_size()
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use word_size() and set_word_size()")]
+    pub word_size: WordSize,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) word_size: WordSize,
+
+    // Number base, read with base() and changed with set_base()
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use base() and set_base()")]
+    pub base: Base,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) base: Base,
     
     // Flags
-    pub carry: bool,
-    pub overflow: bool,
-    
-    // Memory
-    pub memory: [u128; 16],  // HP-16C has 16 memory registers
+    pub(crate) carry: bool,
+    pub(crate) overflow: bool,
+    // User flags 0-3; flag 3 shows leading zeros, flags 4 and 5 are
+    // carry and overflow
+    pub(crate) user_flags: [bool; 4],
+
+    // Unsigned or signed interpretation of the registers
+    pub(crate) complement_mode: ComplementMode,
+
+    // How WS treats values that do not fit the new word size
+    pub(crate) word_size_policy: WordSizePolicy,
+    // Full-width stack (X, Y, Z, T) kept by the Preserve policy
+    preserved_stack: Option<[u128; 4]>,
+
+    // Whether the next entered value lifts the stack (cleared by ENTER)
+    pub(crate) stack_lift: bool,
+
+    // Whether `a + b` style lines are accepted, set with AUTOENTER
+    pub(crate) auto_enter: bool,
+
+    // Digits after the point in FLOAT mode, None in the integer modes.
+    // In FLOAT mode the stack holds f64 bit patterns
+    pub(crate) float_digits: Option<u8>,
+    // Word size the data registers stay laid out at in FLOAT mode, so
+    // entering it does not move or drop any of them
+    pub(crate) float_layout: Option<WordSize>,
+
+    // Display window of a long binary X, 0 for the lowest digits
+    pub(crate) window: usize,
+    // Whether the window follows the highest set bit of X after each
+    // command, set with WINDOW AUTO
+    pub(crate) window_follow: bool,
     
-    pub running: bool,
+    // Data registers: whatever the word size lets fit in the memory pool
+    // after program memory. Read with memory() outside the crate
+    #[cfg(feature = "compat")]
+    #[deprecated(note = "use memory() and store_value()")]
+    pub memory: Vec<u128>,
+    #[cfg(not(feature = "compat"))]
+    pub(crate) memory: Vec<u128>,
+    // Program memory allocated from the pool, in bytes (one-byte steps),
+    // at least as many as the program takes
+    pub(crate) program_steps: usize,
+    // Program memory, read with program() and changed in program mode
+    pub(crate) program: Program,
+    // Lines RTN goes back to for subroutines entered by SST
+    pub(crate) returns: Vec<usize>,
+    // Index register I, outside the pool; addresses registers for STO (i)
+    pub(crate) i: u128,
+
+    // Virtual printer tape
+    pub(crate) printer: Printer,
+
+    // Named constants for CONST
+    pub(crate) constants: Constants,
+
+    // Byte-addressed memory for narrow loads and stores
+    pub(crate) scratch: Scratch,
+
+    // Pseudo-random generator behind RAND
+    pub(crate) random: Generator,
+
+    // Byte order for scratch memory and byte-grouped display, set with ENDIAN
+    pub(crate) endian: Endian,
+
+    // Bit window used as the operand by arithmetic and logic, set with FIELD
+    pub(crate) field: Option<BitField>,
+
+    // Optional rolling bank that keeps recent operation results
+    pub(crate) results_bank: Option<ResultsBank>,
+
+    // User labels for memory registers, set with TAG
+    pub(crate) tags: BTreeMap<usize, String>,
+
+    // States recorded by CHECKPOINT for VERIFY
+    pub(crate) checkpoints: BTreeMap<String, Snapshot>,
+}
+
+/// `cpu[n]` reads memory register n and panics when there is no such
+/// register. Registers are only read this way: stores go through STO or
+/// `store_value`, which mask to the word size.
+impl Index<usize> for Hp16cCpu {
+    type Output = u128;
+
+    fn index(&self, register: usize) -> &u128 {
+        &self.memory[register]
+    }
+}
+
+impl Index<StackRegister> for Hp16cCpu {
+    type Output = u128;
+
+    fn index(&self, register: StackRegister) -> &u128 {
+        match register {
+            StackRegister::X => &self.x,
+            StackRegister::Y => &self.y,
+            StackRegister::Z => &self.z,
+            StackRegister::T => &self.t,
+        }
+    }
+}
+
+/// Settings for a new calculator, from [`Hp16cCpu::builder`]. Anything
+/// not set keeps the power-on default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuBuilder {
+    word_size: u8,
+    base: Base,
+    complement: ComplementMode,
+    registers: usize,
+    program_steps: usize,
+}
+
+impl Default for CpuBuilder {
+    fn default() -> Self {
+        CpuBuilder {
+            word_size: 16,
+            base: Base::Hex,
+            complement: ComplementMode::Unsigned,
+            registers: 0,
+            program_steps: 0,
+        }
+    }
+}
+
+impl CpuBuilder {
+    pub fn word_size(mut self, bits: u8) -> Self {
+        self.word_size = bits;
+        self
+    }
+
+    pub fn base(mut self, base: Base) -> Self {
+        self.base = base;
+        self
+    }
+
+    pub fn complement(mut self, mode: ComplementMode) -> Self {
+        self.complement = mode;
+        self
+    }
+
+    /// Data registers the caller needs. The count comes from the memory
+    /// pool; `build` fails if fewer than `count` fit.
+    pub fn registers(mut self, count: usize) -> Self {
+        self.registers = count;
+        self
+    }
+
+    /// Program steps to allocate from the memory pool.
+    pub fn program_steps(mut self, steps: usize) -> Self {
+        self.program_steps = steps;
+        self
+    }
+
+    /// The calculator, or an error naming the first invalid setting.
+    pub fn build(self) -> Result<Hp16cCpu, String> {
+        let word_size = WordSize::try_from(self.word_size)?;
+        let mut cpu = Hp16cCpu::new();
+        cpu.set_word_size(word_size.bits());
+        cpu.set_base(self.base);
+        cpu.set_complement_mode(self.complement);
+        cpu.allocate_program(self.program_steps)?;
+        if cpu.available_registers() < self.registers {
+            return Err(format!(
+                "Out of memory: {} registers needed, {} fit at word size {}",
+                self.registers,
+                cpu.available_registers(),
+                word_size
+            ));
+        }
+        Ok(cpu)
+    }
 }
 
-impl Hp16cCpu {
-    pub fn new() -> Self {
-        Hp16cCpu {
-            x: 0,
-            y: 0,
-            z: 0,
-            t: 0,
-            pc: 0,
-            rom: Rom::new(),
-            word_size: 16,
-            base: 16,
-            carry: false,
-            overflow: false,
-            memory: [0; 16],
-            running: true,
+impl Default for Hp16cCpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hp16cCpu {
+    pub fn new() -> Self {
+        let mut cpu = Hp16cCpu {
+            x: 0,
+            y: 0,
+            z: 0,
+            t: 0,
+            pc: 0,
+            program_mode: false,
+            strict: false,
+            low_battery: false,
+            #[cfg(feature = "rom")]
+            rom: Rom::new(),
+            word_size: WordSize::default(),
+            base: Base::Hex,
+            carry: false,
+            overflow: false,
+            user_flags: [false; 4],
+            complement_mode: ComplementMode::Unsigned,
+            word_size_policy: WordSizePolicy::Truncate,
+            preserved_stack: None,
+            stack_lift: true,
+            auto_enter: false,
+            float_digits: None,
+            float_layout: None,
+            window: 0,
+            window_follow: false,
+            memory: Vec::new(),
+            program_steps: 0,
+            program: Program::new(),
+            returns: Vec::new(),
+            i: 0,
+            printer: Printer::new(),
+            constants: Constants::new(),
+            scratch: Scratch::new(),
+            endian: Endian::Little,
+            random: Generator::default(),
+            field: None,
+            results_bank: None,
+            tags: BTreeMap::new(),
+            checkpoints: BTreeMap::new(),
+        };
+        cpu.resize_memory();
+        cpu
+    }
+
+    /// The state the HP-16C comes up in after its continuous memory is
+    /// reset: 16-bit words, hex, 2's complement, flags clear, the stack,
+    /// I and all 101 registers zero, and no program. [`Hp16cCpu::new`]
+    /// starts the same way but unsigned.
+    pub fn power_on() -> Self {
+        let mut cpu = Self::new();
+        cpu.complement_mode = ComplementMode::TwosComplement;
+        cpu
+    }
+
+    /// PRESET: a continuous memory reset, which brings back the
+    /// [`Hp16cCpu::power_on`] state. The emulator's extensions (tags,
+    /// constants, scratch memory and so on) are cleared too; the loaded
+    /// ROM, the printer tape and the battery are not part of memory and
+    /// are kept.
+    pub fn preset(&mut self) {
+        let mut reset = Self::power_on();
+        #[cfg(feature = "rom")]
+        std::mem::swap(&mut reset.rom, &mut self.rom);
+        std::mem::swap(&mut reset.printer, &mut self.printer);
+        reset.low_battery = self.low_battery;
+        *self = reset;
+    }
+
+    pub fn x(&self) -> u128 {
+        self.x
+    }
+
+    pub fn y(&self) -> u128 {
+        self.y
+    }
+
+    pub fn z(&self) -> u128 {
+        self.z
+    }
+
+    pub fn t(&self) -> u128 {
+        self.t
+    }
+
+    /// The stack registers from X up to T.
+    pub fn stack_iter(&self) -> impl Iterator<Item = u128> {
+        [self.x, self.y, self.z, self.t].into_iter()
+    }
+
+    /// The memory registers, R0 first.
+    pub fn memory(&self) -> &[u128] {
+        &self.memory
+    }
+
+    /// Data registers that fit in the memory pool at the current word size
+    /// next to the allocated program memory.
+    pub fn available_registers(&self) -> usize {
+        (MEMORY_BYTES - self.program_bytes()) / register_bytes(self.register_word_size().bits())
+    }
+
+    /// The word size data registers are laid out at: the current one, or in
+    /// FLOAT mode the one before it.
+    pub fn register_word_size(&self) -> WordSize {
+        self.float_layout.unwrap_or(self.word_size)
+    }
+
+    /// Bytes allocated to program memory: one per step, or two for the
+    /// steps that take two on the HP-16C.
+    pub fn program_steps(&self) -> usize {
+        self.program_steps
+    }
+
+    /// Bytes of the pool taken by program memory, in whole blocks.
+    pub fn program_bytes(&self) -> usize {
+        self.program_steps.div_ceil(PROGRAM_BLOCK_STEPS) * PROGRAM_BLOCK_STEPS
+    }
+
+    /// Free program steps and data registers at the current word size.
+    pub fn memory_status(&self) -> MemoryStatus {
+        let registers = self.available_registers();
+        let register_bytes = register_bytes(self.register_word_size().bits());
+        MemoryStatus {
+            free_steps: self.program_bytes() - self.program_steps,
+            registers,
+            register_bytes,
+            unused_bytes: MEMORY_BYTES - self.program_bytes() - registers * register_bytes,
+        }
+    }
+
+    /// Allocate `steps` bytes of program memory, a step each for one-byte
+    /// steps. Registers at the top of memory give up their bytes and their
+    /// contents, as on the HP-16C.
+    pub fn allocate_program(&mut self, steps: usize) -> Result<(), String> {
+        if steps < self.program.bytes() {
+            return Err(format!("The program needs {} bytes; use CLEAR PRGM first", self.program.bytes()));
+        }
+        if steps > MEMORY_BYTES {
+            return Err(format!("Out of memory: {} bytes of program memory, more than {}", steps, MEMORY_BYTES));
+        }
+        self.program_steps = steps;
+        self.resize_memory();
+        Ok(())
+    }
+
+    /// Fit the data registers to the memory pool after the word size or
+    /// program memory changed. Registers beyond the new count are lost,
+    /// with their tags; a results bank is cut to the registers left, or
+    /// turned off if none are.
+    pub(crate) fn resize_memory(&mut self) {
+        let count = self.available_registers();
+        self.memory.resize(count, 0);
+        self.tags.retain(|register, _| *register < count);
+        if let Some(bank) = self.results_bank {
+            let last = bank.last.min(count.saturating_sub(1));
+            self.results_bank = ResultsBank::new(bank.first, last, count).ok().map(|shrunk| ResultsBank {
+                next: if bank.next > last { bank.first } else { bank.next },
+                ..shrunk
+            });
+        }
+    }
+
+    /// `register`, or an error if it does not fit in the memory pool.
+    fn check_register(&self, register: usize) -> Result<usize, String> {
+        if register >= self.memory.len() {
+            return Err(format!(
+                "R{} does not exist: {} registers fit at word size {}",
+                register,
+                self.memory.len(),
+                self.register_word_size()
+            ));
+        }
+        Ok(register)
+    }
+
+    /// The register named by a number, a keyboard name such as A or .3,
+    /// or a tag, or an error saying why there is no such register.
+    pub fn find_register(&self, name: &str) -> Result<usize, String> {
+        if let Some(register) = parse_register(name) {
+            return self.check_register(register);
+        }
+        self.tags
+            .iter()
+            .find(|(_, label)| label.eq_ignore_ascii_case(name))
+            .map(|(register, _)| *register)
+            .ok_or_else(|| "Invalid register number".to_string())
+    }
+
+    /// The index register I.
+    pub fn i(&self) -> u128 {
+        self.i
+    }
+
+    /// Set I, masked to the word size.
+    pub fn set_i(&mut self, value: u128) {
+        self.i = self.mask_value(value);
+    }
+
+    /// The register I points at for STO (i) and RCL (i): the magnitude of
+    /// I, or of its integer part in FLOAT mode.
+    pub fn indirect_register(&self) -> Result<usize, String> {
+        let index = if self.is_float() {
+            Self::float_value(self.i).abs().trunc() as u128
+        } else {
+            self.signed_value(self.i).unsigned_abs()
+        };
+        let register = usize::try_from(index).unwrap_or(usize::MAX);
+        self.check_register(register).map_err(|e| format!("I = {}: {}", self.format_value(self.i), e))
+    }
+
+    /// Set X, masked to the word size. Unlike entering a number this does
+    /// not lift the stack.
+    pub fn set_x(&mut self, value: u128) {
+        self.x = self.mask_value(value);
+    }
+
+    pub fn set_y(&mut self, value: u128) {
+        self.y = self.mask_value(value);
+    }
+
+    pub fn set_z(&mut self, value: u128) {
+        self.z = self.mask_value(value);
+    }
+
+    pub fn set_t(&mut self, value: u128) {
+        self.t = self.mask_value(value);
+    }
+
+    /// Set all four stack registers to zero.
+    pub fn clear_stack(&mut self) {
+        (self.x, self.y, self.z, self.t) = (0, 0, 0, 0);
+    }
+
+    /// CLEAR REG: zero the data registers and I. The stack, tags and the
+    /// program are kept.
+    pub fn clear_registers(&mut self) {
+        self.memory.fill(0);
+        self.i = 0;
+    }
+
+    /// CLEAR PRGM: erase program memory and give its bytes back to the
+    /// data registers.
+    pub fn clear_program(&mut self) {
+        self.program.clear();
+        self.pc = 0;
+        self.returns.clear();
+        self.program_steps = 0;
+        self.resize_memory();
+    }
+
+    /// The stored program.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Store `step` after the current line and move to it, taking the
+    /// bytes it needs from the registers when the allocation is full.
+    /// Returns the new line. In strict mode steps the HP-16C lacks are
+    /// refused.
+    pub fn insert_step(&mut self, step: &str) -> Result<usize, String> {
+        Control::parse(step)?;
+        if self.strict {
+            if let Some(reason) = program::extension(step, self.base) {
+                return Err(format!("{}: {} (strict mode)", step, reason));
+            }
+        }
+        let needed = self.program.bytes() + program::step_bytes(step);
+        if needed > self.program_steps {
+            self.allocate_program(needed)?;
+        }
+        let line = self.program.insert(self.pc as usize, step);
+        self.pc = line as u16;
+        Ok(line)
+    }
+
+    /// Delete the step on the current line and move to the line before it.
+    /// Its memory goes back to the registers.
+    pub fn delete_step(&mut self) -> Result<(), String> {
+        let bytes = self.program.step(self.pc as usize).map(program::step_bytes);
+        if !self.program.delete(self.pc as usize) {
+            return Err("No step to delete at line 000".to_string());
+        }
+        self.pc -= 1;
+        self.program_steps = self.program_steps.saturating_sub(bytes.unwrap_or_default());
+        self.resize_memory();
+        Ok(())
+    }
+
+    /// ANNOTATE: attach a comment to the step on `line`, for EXPORT MD; an
+    /// empty `text` removes it. Comments take no program memory.
+    pub fn annotate_step(&mut self, line: usize, text: &str) -> Result<(), String> {
+        self.program.annotate(line, text)
+    }
+
+    /// Move to `line` of program memory, 0 for the top. Pending
+    /// subroutine returns are forgotten.
+    pub fn go_to_line(&mut self, line: usize) -> Result<(), String> {
+        if line > self.program.len() {
+            return Err(format!("Line {:03} is past the end of the program ({:03})", line, self.program.len()));
+        }
+        self.pc = line as u16;
+        self.returns.clear();
+        Ok(())
+    }
+
+    /// SST in program mode: move to the next line, from the last step
+    /// round to 000.
+    pub fn next_line(&mut self) {
+        self.pc = if self.pc as usize >= self.program.len() { 0 } else { self.pc + 1 };
+    }
+
+    /// BST: move to the previous line, from 000 round to the last step.
+    pub fn previous_line(&mut self) {
+        self.pc = match self.pc {
+            0 => self.program.len() as u16,
+            line => line - 1,
+        };
+    }
+
+    /// Replace program memory with `program`, taking the memory it needs
+    /// from the registers, as IMPORT does. The calculator is left alone if
+    /// it does not fit.
+    pub fn load_program(&mut self, program: Program) -> Result<(), String> {
+        if program.bytes() > MEMORY_BYTES {
+            let bytes = program.bytes();
+            return Err(format!("Out of memory: the program takes {} bytes, more than {}", bytes, MEMORY_BYTES));
+        }
+        self.program_steps = 0;
+        self.set_program(program);
+        Ok(())
+    }
+
+    /// Replace program memory with a shorter form of the program, as
+    /// OPTIMIZE makes. The bytes it no longer needs go back to the
+    /// registers, as if its steps had been deleted.
+    pub fn shrink_program(&mut self, program: Program) {
+        let freed = self.program.bytes().saturating_sub(program.bytes());
+        self.program_steps = self.program_steps.saturating_sub(freed);
+        self.set_program(program);
+    }
+
+    /// Replace program memory, for restoring a session.
+    pub(crate) fn set_program(&mut self, program: Program) {
+        self.program = program;
+        self.pc = 0;
+        self.returns.clear();
+        self.program_steps = self.program_steps.max(self.program.bytes()).min(MEMORY_BYTES);
+        self.resize_memory();
+    }
+
+    pub fn word_size(&self) -> WordSize {
+        self.word_size
+    }
+
+    pub fn base(&self) -> Base {
+        self.base
+    }
+
+    pub fn complement_mode(&self) -> ComplementMode {
+        self.complement_mode
+    }
+
+    pub fn word_size_policy(&self) -> WordSizePolicy {
+        self.word_size_policy
+    }
+
+    /// Digits after the point in FLOAT mode, None in the integer modes.
+    pub fn float_digits(&self) -> Option<u8> {
+        self.float_digits
+    }
+
+    pub fn carry(&self) -> bool {
+        self.carry
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.overflow
+    }
+
+    /// The current line of program memory, 0 for the top.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn program_mode(&self) -> bool {
+        self.program_mode
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn stack_lift(&self) -> bool {
+        self.stack_lift
+    }
+
+    pub fn auto_enter(&self) -> bool {
+        self.auto_enter
+    }
+
+    /// The bit window operations work on, set with FIELD.
+    pub fn field(&self) -> Option<BitField> {
+        self.field
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn results_bank(&self) -> Option<ResultsBank> {
+        self.results_bank
+    }
+
+    /// Register labels set with TAG.
+    pub fn tags(&self) -> &BTreeMap<usize, String> {
+        &self.tags
+    }
+
+    pub fn constants(&self) -> &Constants {
+        &self.constants
+    }
+
+    pub fn printer(&self) -> &Printer {
+        &self.printer
+    }
+
+    pub fn scratch(&self) -> &Scratch {
+        &self.scratch
+    }
+
+    /// The stack registers, for formatting with `{}`.
+    pub fn stack_view(&self) -> StackView<'_> {
+        StackView::new(self)
+    }
+
+    /// A builder for a calculator configured up front:
+    /// `Hp16cCpu::builder().word_size(32).base(Base::Hex).build()`.
+    pub fn builder() -> CpuBuilder {
+        CpuBuilder::default()
+    }
+
+    #[cfg(feature = "rom")]
+    pub fn load_rom(&mut self, filename: &str) -> Result<(), std::io::Error> {
+        self.rom.load_from_file(filename)
+    }
+
+    // RPN Stack operations
+    pub fn push(&mut self, value: u128) {
+        self.t = self.z;
+        self.z = self.y;
+        self.y = self.x;
+        self.x = self.mask_value(value);
+    }
+
+    /// ENTER: copy X into Y. The next value entered replaces the copy in X
+    /// instead of lifting the stack again.
+    pub fn enter(&mut self) {
+        self.push(self.x);
+        self.stack_lift = false;
+    }
+
+    /// CLX: zero X. Like ENTER it disables stack lift, so the next value
+    /// entered replaces the zero.
+    pub fn clear_x(&mut self) {
+        self.x = 0;
+        self.stack_lift = false;
+    }
+
+    /// Put a typed or recalled value in X, lifting the stack unless the
+    /// previous operation was ENTER.
+    pub fn enter_value(&mut self, value: u128) {
+        if self.stack_lift {
+            self.push(value);
+        } else {
+            self.x = self.mask_value(value);
+        }
+        self.stack_lift = true;
+    }
+
+    pub fn pop(&mut self) -> u128 {
+        let result = self.x;
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.t;
+        // T register duplicates when stack drops
+        result
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn drop(&mut self) {
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.t;
+        self.stack_lift = true;
+    }
+
+    pub fn swap_xy(&mut self) {
+        std::mem::swap(&mut self.x, &mut self.y);
+        self.stack_lift = true;
+    }
+
+    /// Exchange X and the index register I.
+    pub fn swap_xi(&mut self) {
+        std::mem::swap(&mut self.x, &mut self.i);
+        self.stack_lift = true;
+    }
+
+    /// Exchange X and the register I points at.
+    pub fn swap_x_indirect(&mut self) -> Result<(), String> {
+        let register = self.indirect_register()?;
+        let value = std::mem::replace(&mut self.memory[register], self.x);
+        self.x = self.mask_value(value);
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    pub fn roll_down(&mut self) {
+        self.stack_lift = true;
+        let temp = self.x;
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.t;
+        self.t = temp;
+    }
+
+    pub fn roll_up(&mut self) {
+        self.stack_lift = true;
+        let temp = self.t;
+        self.t = self.z;
+        self.z = self.y;
+        self.y = self.x;
+        self.x = temp;
+    }
+
+    // Apply word size mask
+    fn mask_value(&self, value: u128) -> u128 {
+        if self.word_size.bits() == 128 {
+            value
+        } else if self.word_size.bits() == 64 {
+            value & u64::MAX as u128
+        } else {
+            value & ((1u128 << self.word_size.bits()) - 1)
+        }
+    }
+
+    fn sign_bit(&self) -> u128 {
+        1u128 << (self.word_size.bits() - 1)
+    }
+
+    /// Whether an exact unsigned result fits the word size.
+    fn fits_unsigned(&self, value: u128) -> bool {
+        value == self.mask_value(value)
+    }
+
+    fn is_signed(&self) -> bool {
+        self.complement_mode != ComplementMode::Unsigned
+    }
+
+    /// The value a register holds under the current complement mode.
+    /// In unsigned mode values of 128-bit words above `i128::MAX` wrap.
+    pub fn signed_value(&self, value: u128) -> i128 {
+        let value = self.mask_value(value);
+        if !self.is_signed() || value & self.sign_bit() == 0 {
+            return value as i128;
+        }
+        let twos = (value | !self.mask_value(u128::MAX)) as i128;
+        match self.complement_mode {
+            // All ones is negative zero
+            ComplementMode::OnesComplement => twos + 1,
+            _ => twos,
+        }
+    }
+
+    /// The register bits for a signed value in the current complement mode.
+    fn encode_signed(&self, value: i128) -> u128 {
+        if value < 0 && self.complement_mode == ComplementMode::OnesComplement {
+            self.mask_value(!value.unsigned_abs())
+        } else {
+            self.mask_value(value as u128)
+        }
+    }
+
+    /// Whether a signed result fits the word size
+    fn in_signed_range(&self, value: i128) -> bool {
+        let max = if self.word_size.bits() == 128 { i128::MAX } else { (1i128 << (self.word_size.bits() - 1)) - 1 };
+        let min = match self.complement_mode {
+            ComplementMode::OnesComplement => -max,
+            _ => -max - 1,
+        };
+        (min..=max).contains(&value)
+    }
+
+    /// True for the all-ones negative zero of 1's complement mode.
+    pub fn is_negative_zero(&self, value: u128) -> bool {
+        self.complement_mode == ComplementMode::OnesComplement && self.mask_value(value) == self.mask_value(u128::MAX)
+    }
+
+    /// Compare two register values under the current complement mode.
+    pub fn compare(&self, a: u128, b: u128) -> Ordering {
+        if self.is_signed() {
+            self.signed_value(a).cmp(&self.signed_value(b))
+        } else {
+            a.cmp(&b)
+        }
+    }
+
+    pub fn set_complement_mode(&mut self, mode: ComplementMode) {
+        self.complement_mode = mode;
+    }
+
+    /// 1's complement addition: a carry out of the word is added back in.
+    /// Returns the sum and whether that end-around carry happened. A
+    /// negative zero sum is normalized to zero, so x - x gives 0.
+    fn ones_complement_add(&self, a: u128, b: u128) -> (u128, bool) {
+        let mask = self.mask_value(u128::MAX);
+        let (mut sum, mut carried) = a.overflowing_add(b);
+        if self.word_size.bits() < 128 {
+            carried = sum > mask;
+            sum &= mask;
+        }
+        if carried {
+            sum = self.mask_value(sum.wrapping_add(1));
+        }
+        if sum == mask {
+            sum = 0;
+        }
+        (sum, carried)
+    }
+
+    // Arithmetic operations
+
+    /// Y + X. Carry is the carry out of the word size (the end-around
+    /// carry in 1's complement).
+    pub fn add(&mut self) {
+        if self.is_float() {
+            return self.float_operation(|y, x| y + x);
+        }
+        let mut result = self.x.wrapping_add(self.y);
+        // Carry out of the top bit of the word, not of the u128
+        self.carry = !self.y.checked_add(self.x).is_some_and(|sum| self.fits_unsigned(sum));
+        if self.complement_mode == ComplementMode::OnesComplement {
+            (result, self.carry) = self.ones_complement_add(self.y, self.x);
+        }
+        if self.is_signed() {
+            // Operands of equal sign giving a result of the other sign
+            let masked = self.mask_value(result);
+            self.overflow = (self.x ^ masked) & (self.y ^ masked) & self.sign_bit() != 0;
+        } else {
+            self.overflow = !self.y.checked_add(self.x).is_some_and(|sum| self.fits_unsigned(sum));
+        }
+        self.drop();
+        self.x = self.mask_value(result);
+    }
+
+    /// Y - X. Carry reports a borrow.
+    pub fn subtract(&mut self) {
+        if self.is_float() {
+            return self.float_operation(|y, x| y - x);
+        }
+        let mut result = self.y.wrapping_sub(self.x);
+        self.carry = self.y < self.x;
+        if self.complement_mode == ComplementMode::OnesComplement {
+            // Add the complement; no end-around carry means a borrow
+            let (difference, carried) = self.ones_complement_add(self.y, self.mask_value(!self.x));
+            result = difference;
+            self.carry = !carried;
+        }
+        if self.is_signed() {
+            let masked = self.mask_value(result);
+            self.overflow = (self.y ^ self.x) & (self.y ^ masked) & self.sign_bit() != 0;
+        } else {
+            // Negative differences have no unsigned representation
+            self.overflow = self.y < self.x;
+        }
+        self.drop();
+        self.x = self.mask_value(result);
+    }
+
+    pub fn multiply(&mut self) {
+        if self.is_float() {
+            return self.float_operation(|y, x| y * x);
+        }
+        // As on the HP-16C, multiplication leaves carry alone
+        let mut result = self.x.wrapping_mul(self.y);
+        if self.is_signed() {
+            let product = self.signed_value(self.x).checked_mul(self.signed_value(self.y));
+            self.overflow = !product.is_some_and(|p| self.in_signed_range(p));
+            if let Some(product) = product {
+                result = self.encode_signed(product);
+            }
+        } else {
+            self.overflow = !self.y.checked_mul(self.x).is_some_and(|product| self.fits_unsigned(product));
+        }
+        self.drop();
+        self.x = self.mask_value(result);
+    }
+
+    /// Quotient and remainder of Y ÷ X, and whether the quotient is out of
+    /// range, or None for division by zero. Signed modes follow the HP-16C:
+    /// the quotient truncates toward zero and the remainder takes the sign
+    /// of the dividend, so -7 ÷ 2 is -3 remainder -1.
+    fn divide_parts(&self) -> Option<(u128, u128, bool)> {
+        if self.is_signed() {
+            let (y, x) = (self.signed_value(self.y), self.signed_value(self.x));
+            if x == 0 {
+                return None;
+            }
+            // Only MIN / -1 is out of range
+            let (quotient, overflow) = y.overflowing_div(x);
+            let remainder = y.wrapping_rem(x);
+            Some((
+                self.encode_signed(quotient),
+                self.encode_signed(remainder),
+                overflow || !self.in_signed_range(quotient),
+            ))
+        } else {
+            let (y, x) = (self.mask_value(self.y), self.mask_value(self.x));
+            Some((y.checked_div(x)?, y % x, false))
         }
     }
 
-    pub fn load_rom(&mut self, filename: &str) -> Result<(), std::io::Error> {
-        self.rom.load_from_file(filename)
+    /// Y ÷ X. Carry is set when the division leaves a remainder; division
+    /// by zero sets overflow and leaves the stack alone.
+    pub fn divide(&mut self) {
+        if self.is_float() {
+            if Self::float_value(self.x) == 0.0 {
+                self.overflow = true;
+                return;
+            }
+            return self.float_operation(|y, x| y / x);
+        }
+        match self.divide_parts() {
+            Some((quotient, remainder, overflow)) => {
+                self.drop();
+                self.x = self.mask_value(quotient);
+                self.carry = remainder != 0;
+                self.overflow = overflow;
+            },
+            None => self.overflow = true,
+        }
     }
 
-    // RPN Stack operations
-    pub fn push(&mut self, value: u128) {
-        self.t = self.z;
-        self.z = self.y;
-        self.y = self.x;
-        self.x = self.mask_value(value);
+    /// RMD: the remainder of Y ÷ X, with the sign of Y in signed modes.
+    pub fn remainder(&mut self) {
+        match self.divide_parts() {
+            Some((_, remainder, _)) => {
+                self.drop();
+                self.x = self.mask_value(remainder);
+                self.overflow = false;
+            },
+            None => self.overflow = true,
+        }
     }
 
-    pub fn pop(&mut self) -> u128 {
-        let result = self.x;
-        self.x = self.y;
-        self.y = self.z;
-        self.z = self.t;
-        // T register duplicates when stack drops
-        result
+    /// A double word (high, low) is negative if the high word's sign bit is set.
+    fn double_is_negative(&self, high: u128) -> bool {
+        self.is_signed() && high & self.sign_bit() != 0
     }
 
-    pub fn drop(&mut self) {
-        self.x = self.y;
-        self.y = self.z;
-        self.z = self.t;
+    /// Negate a double word under the current complement mode.
+    fn negate_double(&self, high: u128, low: u128) -> (u128, u128) {
+        let (high, low) = (self.mask_value(!high), self.mask_value(!low));
+        if self.complement_mode == ComplementMode::OnesComplement {
+            return (high, low);
+        }
+        let low = self.mask_value(low.wrapping_add(1));
+        let high = if low == 0 { self.mask_value(high.wrapping_add(1)) } else { high };
+        (high, low)
     }
 
-    pub fn swap_xy(&mut self) {
-        let temp = self.x;
-        self.x = self.y;
-        self.y = temp;
+    /// Magnitude of a word as an unsigned value, and whether it was negative.
+    fn magnitude(&self, value: u128) -> (u128, bool) {
+        if self.is_signed() && value & self.sign_bit() != 0 {
+            let (_, magnitude) = self.negate_double(0, value);
+            (magnitude, true)
+        } else {
+            (value, false)
+        }
     }
 
-    pub fn roll_down(&mut self) {
-        let temp = self.x;
-        self.x = self.y;
-        self.y = self.z;
-        self.z = self.t;
-        self.t = temp;
+    /// DBL×: multiply Y by X into a double word, with the high word in X
+    /// and the low word in Y. Z and T are unchanged.
+    pub fn double_multiply(&mut self) {
+        let (a, a_negative) = self.magnitude(self.y);
+        let (b, b_negative) = self.magnitude(self.x);
+
+        // 128×128 bit product from 64-bit halves
+        let half = u64::MAX as u128;
+        let (a_high, a_low, b_high, b_low) = (a >> 64, a & half, b >> 64, b & half);
+        let low_low = a_low * b_low;
+        let (cross1, cross2) = (a_high * b_low, a_low * b_high);
+        let middle = (low_low >> 64) + (cross1 & half) + (cross2 & half);
+        let low = (low_low & half) | (middle << 64);
+        let high = a_high * b_high + (cross1 >> 64) + (cross2 >> 64) + (middle >> 64);
+
+        // Split the product at the word size
+        let (mut high, mut low) = match self.word_size.bits() {
+            128 => (high, low),
+            size => (self.mask_value((low >> size) | (high << (128 - size))), self.mask_value(low)),
+        };
+        if a_negative != b_negative {
+            (high, low) = self.negate_double(high, low);
+        }
+        self.x = high;
+        self.y = low;
+        self.stack_lift = true;
+        self.carry = false;
+        self.overflow = false;
     }
 
-    pub fn roll_up(&mut self) {
-        let temp = self.t;
-        self.t = self.z;
-        self.z = self.y;
-        self.y = self.x;
-        self.x = temp;
+    /// Divide the double word in Y (high) and Z (low) by X. Returns the
+    /// quotient and remainder, or None when X is zero or the quotient
+    /// does not fit in a word.
+    fn double_divide_parts(&self) -> Option<(u128, u128)> {
+        let (mut high, mut low) = (self.mask_value(self.y), self.mask_value(self.z));
+        let dividend_negative = self.double_is_negative(high);
+        if dividend_negative {
+            (high, low) = self.negate_double(high, low);
+        }
+        let (divisor, divisor_negative) = self.magnitude(self.x);
+        if divisor == 0 {
+            return None;
+        }
+
+        // Long division one bit at a time; the remainder stays below the
+        // divisor, but shifting it may briefly need one bit more than a word
+        let size = self.word_size.bits() as u32;
+        let top_bit = self.sign_bit();
+        let (mut quotient, mut remainder, mut too_large) = (0u128, 0u128, false);
+        for position in (0..2 * size).rev() {
+            let bit = if position >= size { high >> (position - size) & 1 } else { low >> position & 1 };
+            let shifted_out = remainder & top_bit != 0;
+            remainder = self.mask_value(remainder << 1 | bit);
+            too_large |= quotient & top_bit != 0;
+            quotient = self.mask_value(quotient << 1);
+            if shifted_out || remainder >= divisor {
+                remainder = self.mask_value(remainder.wrapping_sub(divisor));
+                quotient |= 1;
+            }
+        }
+        if too_large {
+            return None;
+        }
+
+        // Truncate toward zero: the remainder takes the dividend's sign
+        if self.is_signed() {
+            let limit = if dividend_negative != divisor_negative && self.complement_mode == ComplementMode::TwosComplement {
+                top_bit
+            } else {
+                top_bit - 1
+            };
+            if quotient > limit {
+                return None;
+            }
+            if dividend_negative != divisor_negative {
+                quotient = self.negate_double(0, quotient).1;
+            }
+            if dividend_negative {
+                remainder = self.negate_double(0, remainder).1;
+            }
+        }
+        Some((quotient, remainder))
     }
 
-    // Apply word size mask
-    fn mask_value(&self, value: u128) -> u128 {
-        if self.word_size == 128 {
-            value
-        } else if self.word_size == 64 {
-            value & u64::MAX as u128
-        } else {
-            value & ((1u128 << self.word_size) - 1)
+    /// DBL÷: the quotient of the double word in Y and Z by X. Division by
+    /// zero or a quotient too large for a word sets overflow instead.
+    pub fn double_divide(&mut self) {
+        self.finish_double_division(|(quotient, _)| quotient);
+    }
+
+    /// DBLR: the remainder of the double word in Y and Z by X.
+    pub fn double_remainder(&mut self) {
+        self.finish_double_division(|(_, remainder)| remainder);
+    }
+
+    fn finish_double_division(&mut self, pick: fn((u128, u128)) -> u128) {
+        match self.double_divide_parts() {
+            Some(parts) => {
+                self.drop();
+                self.drop();
+                self.x = pick(parts);
+                self.carry = false;
+                self.overflow = false;
+            },
+            None => self.overflow = true,
         }
     }
 
-    // Arithmetic operations
-    pub fn add(&mut self) {
-        let result = self.x.wrapping_add(self.y);
-        self.carry = result < self.x || result < self.y;
+    /// Replace Y and X with the smaller of the two under the complement mode.
+    pub fn min(&mut self) {
+        let result = if self.compare(self.y, self.x) == Ordering::Greater { self.x } else { self.y };
         self.drop();
-        self.x = self.mask_value(result);
+        self.x = result;
     }
 
-    pub fn subtract(&mut self) {
-        let result = self.y.wrapping_sub(self.x);
-        self.carry = self.y < self.x;
+    /// Replace Y and X with the larger of the two under the complement mode.
+    pub fn max(&mut self) {
+        let result = if self.compare(self.y, self.x) == Ordering::Less { self.x } else { self.y };
         self.drop();
-        self.x = self.mask_value(result);
+        self.x = result;
     }
 
-    pub fn multiply(&mut self) {
-        let (result, overflow) = self.x.overflowing_mul(self.y);
-        self.carry = overflow;
+    /// Replace Y and X with -1, 0 or 1 as Y is less than, equal to or
+    /// greater than X under the complement mode.
+    pub fn cmp(&mut self) {
+        let result = match self.compare(self.y, self.x) {
+            Ordering::Less => self.encode_signed(-1),
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        };
         self.drop();
-        self.x = self.mask_value(result);
+        self.x = result;
     }
 
-    pub fn divide(&mut self) {
-        if self.x != 0 {
-            let result = self.y / self.x;
-            self.drop();
-            self.x = self.mask_value(result);
-            self.carry = false;
-        } else {
-            // Division by zero - set overflow
-            self.overflow = true;
+    /// Decimal digits a packed BCD value can hold in the word size.
+    fn bcd_digits(&self) -> Result<u32, String> {
+        match self.word_size.bits() / 4 {
+            0 => Err("Word size too small for BCD (at least 4 bits)".to_string()),
+            digits => Ok(digits as u32),
+        }
+    }
+
+    /// The number a packed BCD value stands for, or an error naming the
+    /// first nibble that is not a decimal digit.
+    fn decode_bcd(&self, value: u128) -> Result<u128, String> {
+        let value = self.mask_value(value);
+        let mut number = 0u128;
+        for nibble in (0..32).rev() {
+            let digit = value >> (nibble * 4) & 0xF;
+            if digit > 9 {
+                return Err(format!("{:X} is not valid BCD (digit {} is {:X})", value, nibble, digit));
+            }
+            number = number * 10 + digit;
+        }
+        Ok(number)
+    }
+
+    /// Packed BCD for `number`, which must have at most `bcd_digits` digits.
+    fn encode_bcd(number: u128) -> u128 {
+        let (mut number, mut value, mut shift) = (number, 0u128, 0);
+        while number > 0 {
+            value |= (number % 10) << shift;
+            number /= 10;
+            shift += 4;
+        }
+        value
+    }
+
+    /// `>BCD`: convert X to packed BCD, one decimal digit per nibble. Digits
+    /// beyond the word size are lost and set overflow.
+    pub fn to_bcd(&mut self) -> Result<(), String> {
+        let modulus = 10u128.pow(self.bcd_digits()?);
+        let value = self.mask_value(self.x);
+        self.overflow = value >= modulus;
+        self.x = self.mask_value(Self::encode_bcd(value % modulus));
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    /// BCD>: convert packed BCD in X to binary.
+    pub fn from_bcd(&mut self) -> Result<(), String> {
+        self.x = self.mask_value(self.decode_bcd(self.x)?);
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    /// BCD+: add packed BCD X to Y. Carry is set when the decimal sum does
+    /// not fit the word size; the result keeps its low digits.
+    pub fn bcd_add(&mut self) -> Result<(), String> {
+        let modulus = 10u128.pow(self.bcd_digits()?);
+        let sum = self.decode_bcd(self.y)? + self.decode_bcd(self.x)?;
+        self.drop();
+        self.x = self.mask_value(Self::encode_bcd(sum % modulus));
+        self.carry = sum >= modulus;
+        Ok(())
+    }
+
+    /// BCD-: subtract packed BCD X from Y. A negative difference borrows:
+    /// carry is set and the result is its ten's complement.
+    pub fn bcd_subtract(&mut self) -> Result<(), String> {
+        let modulus = 10u128.pow(self.bcd_digits()?);
+        let (y, x) = (self.decode_bcd(self.y)?, self.decode_bcd(self.x)?);
+        let borrow = y < x;
+        let difference = if borrow { y + modulus - x } else { y - x };
+        self.drop();
+        self.x = self.mask_value(Self::encode_bcd(difference % modulus));
+        self.carry = borrow;
+        Ok(())
+    }
+
+    /// Absolute value of X in signed modes; unsigned values are left as
+    /// they are. The most negative 2's complement value has no positive
+    /// counterpart: it is kept and sets overflow. 1's complement -0 gives 0.
+    pub fn abs(&mut self) {
+        self.stack_lift = true;
+        if self.is_float() {
+            self.x = Self::float_bits(Self::float_value(self.x).abs());
+            self.overflow = false;
+            return;
+        }
+        if !self.is_signed() {
+            self.overflow = false;
+            return;
+        }
+        let magnitude = self.signed_value(self.x).abs();
+        self.overflow = !self.in_signed_range(magnitude);
+        if !self.overflow {
+            self.x = self.encode_signed(magnitude);
         }
     }
 
+    /// Change the sign of X: the 2's complement in unsigned and 2's
+    /// complement modes, the inverted bits in 1's complement mode. Only
+    /// the most negative 2's complement value is out of range; it is
+    /// returned unchanged with overflow set.
+    pub fn chs(&mut self) {
+        self.stack_lift = true;
+        if self.is_float() {
+            self.x = Self::float_bits(-Self::float_value(self.x));
+            self.overflow = false;
+            return;
+        }
+        self.overflow = self.complement_mode == ComplementMode::TwosComplement && self.x == self.sign_bit();
+        self.x = match self.complement_mode {
+            ComplementMode::OnesComplement => self.mask_value(!self.x),
+            _ => self.mask_value(self.x.wrapping_neg()),
+        };
+    }
+
     // Bitwise operations
     pub fn and(&mut self) {
         let result = self.x & self.y;
@@ -160,93 +1655,677 @@ impl Hp16cCpu {
     }
 
     pub fn not(&mut self) {
+        self.stack_lift = true;
         self.x = self.mask_value(!self.x);
     }
 
     // Shift operations
+    /// Shift X left. Counts of the word size or more give 0, with carry
+    /// from the last bit shifted out (clear beyond the word size). Overflow
+    /// is set when the shifted value is out of range: set bits were lost,
+    /// or in signed modes the bits shifted past the sign bit differ from it.
     pub fn shift_left(&mut self, positions: u8) {
-        let result = self.x << positions;
-        self.carry = (self.x >> (self.word_size - positions)) != 0;
-        self.x = self.mask_value(result);
+        self.stack_lift = true;
+        let value = self.mask_value(self.x);
+        let kept = if self.is_signed() { self.word_size.bits() - 1 } else { self.word_size.bits() };
+        self.overflow = if positions > kept {
+            value != 0
+        } else {
+            // The bits shifted out, plus the new sign bit in signed modes
+            let top = value.checked_shr((kept - positions) as u32).unwrap_or(0);
+            let negative = self.is_signed() && value & self.sign_bit() != 0;
+            top != if negative { u128::MAX >> (127 - positions) } else { 0 }
+        };
+        // Carry is the last bit shifted out, or clear for no shift
+        self.carry = positions != 0
+            && positions <= self.word_size.bits()
+            && value >> (self.word_size.bits() - positions) & 1 != 0;
+        self.x = self.mask_value(value.checked_shl(positions as u32).unwrap_or(0));
     }
 
+    /// Logical shift of X right, with the same count rules as
+    /// [`Hp16cCpu::shift_left`]. The result always fits, so overflow is cleared.
     pub fn shift_right(&mut self, positions: u8) {
-        self.carry = (self.x & ((1 << positions) - 1)) != 0;
-        self.x = self.x >> positions;
+        self.stack_lift = true;
+        self.overflow = false;
+        let value = self.mask_value(self.x);
+        self.carry = positions != 0 && value.checked_shr(positions as u32 - 1).unwrap_or(0) & 1 != 0;
+        self.x = value.checked_shr(positions as u32).unwrap_or(0);
+    }
+
+    /// Rotate X left one bit within the word size; carry gets the bit
+    /// that moved from the top to the bottom.
+    pub fn rotate_left(&mut self) {
+        self.stack_lift = true;
+        let x = self.mask_value(self.x);
+        let top = x & self.sign_bit() != 0;
+        self.x = self.mask_value(x << 1) | top as u128;
+        self.carry = top;
+    }
+
+    /// Rotate X right one bit within the word size; carry gets the bit
+    /// that moved from the bottom to the top.
+    pub fn rotate_right(&mut self) {
+        self.stack_lift = true;
+        let x = self.mask_value(self.x);
+        let bottom = x & 1 != 0;
+        self.x = x >> 1 | if bottom { self.sign_bit() } else { 0 };
+        self.carry = bottom;
+    }
+
+    /// RLn: rotate Y left by the count in X and drop the stack. Carry gets
+    /// the last bit rotated out, which is cleared for a count of zero.
+    pub fn rotate_left_n(&mut self) {
+        let count = (self.x % self.word_size.bits() as u128) as u32;
+        let value = self.mask_value(self.y);
+        let rotated = if count == 0 {
+            value
+        } else {
+            self.mask_value(value << count) | value >> (self.word_size.bits() as u32 - count)
+        };
+        self.carry = self.x != 0 && rotated & 1 != 0;
+        self.drop();
+        self.x = rotated;
+    }
+
+    /// RRn: rotate Y right by the count in X and drop the stack. Carry gets
+    /// the last bit rotated out, which is cleared for a count of zero.
+    pub fn rotate_right_n(&mut self) {
+        let count = (self.x % self.word_size.bits() as u128) as u32;
+        let value = self.mask_value(self.y);
+        let rotated = if count == 0 {
+            value
+        } else {
+            value >> count | self.mask_value(value << (self.word_size.bits() as u32 - count))
+        };
+        self.carry = self.x != 0 && rotated & self.sign_bit() != 0;
+        self.drop();
+        self.x = rotated;
+    }
+
+    /// MASKR: replace the count in X with a mask of that many bits at the
+    /// bottom of the word.
+    pub fn mask_right(&mut self) {
+        self.stack_lift = true;
+        let count = self.x.min(self.word_size.bits() as u128) as u32;
+        self.x = self.mask_value(1u128.checked_shl(count).map_or(u128::MAX, |bit| bit - 1));
+    }
+
+    /// MASKL: replace the count in X with a mask of that many bits at the
+    /// top of the word.
+    pub fn mask_left(&mut self) {
+        self.mask_right();
+        let count = self.x.count_ones();
+        self.x = self.mask_value(self.x.checked_shl(self.word_size.bits() as u32 - count).unwrap_or(0));
+    }
+
+    /// SB: set the bit of Y numbered by X and drop the stack. An error if
+    /// X is not a bit number of the word.
+    pub fn set_bit(&mut self) -> Result<(), String> {
+        self.check_bit_number()?;
+        let bit = 1u128 << self.x;
+        let result = self.y | bit;
+        self.drop();
+        self.x = result;
+        Ok(())
+    }
+
+    /// CB: clear the bit of Y numbered by X and drop the stack. An error if
+    /// X is not a bit number of the word.
+    pub fn clear_bit(&mut self) -> Result<(), String> {
+        self.check_bit_number()?;
+        let bit = 1u128 << self.x;
+        let result = self.y & !bit;
+        self.drop();
+        self.x = result;
+        Ok(())
+    }
+
+    /// B?: drop the bit number in X, leaving Y, and set carry to that bit.
+    /// An error if X is not a bit number of the word.
+    pub fn test_bit(&mut self) -> Result<(), String> {
+        self.check_bit_number()?;
+        self.carry = self.y >> self.x & 1 == 1;
+        self.drop();
+        Ok(())
+    }
+
+    /// An error unless X is a bit number for SB, CB or B?.
+    pub fn check_bit_number(&self) -> Result<(), String> {
+        let width = self.operand_width();
+        if self.x >= width as u128 {
+            return Err(format!("Bit number must be 0-{}", width - 1));
+        }
+        Ok(())
+    }
+
+    /// B? as a test instruction: [`Hp16cCpu::test_bit`] within the field,
+    /// returning whether the bit was set.
+    pub fn bit_test(&mut self) -> Result<bool, String> {
+        self.check_bit_number()?;
+        self.in_field(true, Hp16cCpu::test_bit)?;
+        Ok(self.carry)
+    }
+
+    /// Rotate X left through carry: the old carry enters at the bottom and
+    /// the top bit becomes the new carry.
+    pub fn rotate_left_carry(&mut self) {
+        self.stack_lift = true;
+        let x = self.mask_value(self.x);
+        let top = x & self.sign_bit() != 0;
+        self.x = self.mask_value(x << 1) | self.carry as u128;
+        self.carry = top;
+    }
+
+    /// Rotate X right through carry: the old carry enters at the top and
+    /// the bottom bit becomes the new carry.
+    pub fn rotate_right_carry(&mut self) {
+        self.stack_lift = true;
+        let x = self.mask_value(self.x);
+        let bottom = x & 1 != 0;
+        self.x = x >> 1 | if self.carry { self.sign_bit() } else { 0 };
+        self.carry = bottom;
     }
 
     // Memory operations
     pub fn store(&mut self, register: usize) {
-        if register < 16 {
+        if register < self.memory.len() {
+            self.memory[register] = self.x;
+            self.stack_lift = true;
+        }
+    }
+
+    /// STO+ and friends: Rn = Rn op X. Carry and overflow are set as by
+    /// the operation on the stack, and the stack is left alone. Dividing
+    /// by zero sets overflow and leaves the register unchanged.
+    pub fn store_operation(&mut self, register: usize, operation: StoreOperation) -> Result<(), String> {
+        let register = self.check_register(register)?;
+        let stack = (self.x, self.y, self.z, self.t);
+        self.y = self.memory[register];
+        let by_zero = if self.is_float() {
+            Self::float_value(self.x) == 0.0
+        } else {
+            self.divide_parts().is_none()
+        };
+        match operation {
+            StoreOperation::Add => self.add(),
+            StoreOperation::Subtract => self.subtract(),
+            StoreOperation::Multiply => self.multiply(),
+            StoreOperation::Divide if by_zero => self.overflow = true,
+            StoreOperation::Divide => self.divide(),
+        }
+        if !(operation == StoreOperation::Divide && by_zero) {
             self.memory[register] = self.x;
         }
+        (self.x, self.y, self.z, self.t) = stack;
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    /// Store a value in a register without going through X.
+    pub fn store_value(&mut self, register: usize, value: u128) {
+        if register < self.memory.len() {
+            self.memory[register] = self.mask_value(value);
+        }
+    }
+
+    /// Store X as a `width`-byte value in scratch memory. A value that does
+    /// not fit sets overflow and is truncated, or with `saturate` clamped
+    /// to the largest value of its sign. Signed modes store the width's
+    /// own complement form. Widths are 1 to 8 bytes.
+    pub fn store_narrow(&mut self, address: u128, width: usize, saturate: bool, endian: Endian) -> Result<(), String> {
+        let bits = width as u32 * 8;
+        let width_mask = (1u128 << bits) - 1;
+        let (stored, fits) = if self.is_signed() {
+            let value = self.signed_value(self.x);
+            let ones = self.complement_mode == ComplementMode::OnesComplement;
+            let max = (1i128 << (bits - 1)) - 1;
+            let min = if ones { -max } else { -max - 1 };
+            let clamped = if saturate { value.clamp(min, max) } else { value };
+            let stored = if clamped < 0 && ones { !clamped.unsigned_abs() } else { clamped as u128 };
+            (stored & width_mask, (min..=max).contains(&value))
+        } else {
+            let value = self.mask_value(self.x);
+            let fits = value <= width_mask;
+            (if saturate && !fits { width_mask } else { value & width_mask }, fits)
+        };
+        self.scratch.store(address, width, stored, endian)?;
+        self.overflow = !fits;
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    /// The bytes of X within the word size, in memory order for the
+    /// current byte order.
+    pub fn bytes_of_x(&self) -> Vec<u8> {
+        let width = (self.word_size.bits() as usize).div_ceil(8);
+        let mut bytes = self.mask_value(self.x).to_le_bytes()[..width].to_vec();
+        if self.endian == Endian::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    /// Push a `width`-byte value from scratch memory, sign-extended to the
+    /// word size in signed modes. Widths are 1 to 8 bytes.
+    pub fn load_narrow(&mut self, address: u128, width: usize, endian: Endian) -> Result<(), String> {
+        let mut value = self.scratch.load(address, width, endian)?;
+        let bits = width as u32 * 8;
+        if self.is_signed() && value >> (bits - 1) & 1 == 1 {
+            value |= u128::MAX << bits;
+        }
+        self.enter_value(value);
+        Ok(())
     }
 
     pub fn recall(&mut self, register: usize) {
-        if register < 16 {
-            self.push(self.memory[register]);
+        if register < self.memory.len() {
+            self.enter_value(self.memory[register]);
+        }
+    }
+
+    /// Flag `number` (0-5). Flags 4 and 5 are the carry and out-of-range
+    /// (overflow) flags, as on the HP-16C.
+    fn flag_mut(&mut self, number: u8) -> Result<&mut bool, String> {
+        match number {
+            0..=3 => Ok(&mut self.user_flags[number as usize]),
+            4 => Ok(&mut self.carry),
+            5 => Ok(&mut self.overflow),
+            _ => Err(format!("Invalid flag: {} (0-5)", number)),
+        }
+    }
+
+    pub fn set_flag(&mut self, number: u8) -> Result<(), String> {
+        *self.flag_mut(number)? = true;
+        Ok(())
+    }
+
+    pub fn clear_flag(&mut self, number: u8) -> Result<(), String> {
+        *self.flag_mut(number)? = false;
+        Ok(())
+    }
+
+    pub fn test_flag(&self, number: u8) -> Result<bool, String> {
+        self.flags()
+            .get(number as usize)
+            .copied()
+            .ok_or_else(|| format!("Invalid flag: {} (0-5)", number))
+    }
+
+    /// Flags 0-5 in order.
+    pub fn flags(&self) -> [bool; 6] {
+        let [f0, f1, f2, f3] = self.user_flags;
+        [f0, f1, f2, f3, self.carry, self.overflow]
+    }
+
+    /// Select the bit window arithmetic and logic work on, or None for
+    /// the whole word.
+    pub fn set_field(&mut self, field: Option<BitField>) -> Result<(), String> {
+        if let Some(field) = field {
+            if field.high >= self.word_size.bits() {
+                return Err(format!("Bit {} is outside the {}-bit word", field.high, self.word_size.bits()));
+            }
+        }
+        self.field = field;
+        Ok(())
+    }
+
+    /// Width of the operands arithmetic works on: the bit field if one is
+    /// selected, otherwise the word size.
+    pub fn operand_width(&self) -> u8 {
+        self.field.map_or(self.word_size.bits(), |field| field.width())
+    }
+
+    /// Run an operation on the selected bit field. A binary operation
+    /// combines the field of Y with X and puts the result back into Y's
+    /// field; a unary one works on the field of X in place. The other bits
+    /// are kept, and the operation sees the field's width as the word size,
+    /// so results wrap and overflow within the field.
+    pub fn in_field<R>(&mut self, binary: bool, op: impl FnOnce(&mut Self) -> R) -> R {
+        let Some(field) = self.field else {
+            return op(self);
+        };
+        let (word_size, mask) = (self.word_size, field.mask());
+        let packed = if binary { self.y } else { self.x };
+
+        self.word_size = WordSize(field.width());
+        if binary {
+            self.y = packed >> field.low & mask;
+            self.x &= mask;
+        } else {
+            self.x = packed >> field.low & mask;
+        }
+        let result = op(self);
+        self.word_size = word_size;
+        self.x = packed & !(mask << field.low) | (self.x & mask) << field.low;
+        result
+    }
+
+    /// Copy X into the results bank, if one is enabled.
+    pub fn record_result(&mut self) {
+        if let Some(bank) = &mut self.results_bank {
+            if let Some(cell) = self.memory.get_mut(bank.next) {
+                *cell = self.x;
+            }
+            bank.next = if bank.next == bank.last { bank.first } else { bank.next + 1 };
+        }
+    }
+
+    /// Label a memory register, or remove its label when `label` is empty.
+    pub fn tag(&mut self, register: usize, label: &str) -> Result<(), String> {
+        if register >= self.memory.len() {
+            return Err("Invalid register number".to_string());
+        }
+        if label.is_empty() {
+            self.tags.remove(&register);
+            return Ok(());
+        }
+        if label.len() > 16 || label.contains(char::is_whitespace) {
+            return Err("Tags are one word of up to 16 characters".to_string());
+        }
+        if parse_register(label).is_some() || label.eq_ignore_ascii_case("I") {
+            return Err("Tags cannot be register names or I".to_string());
         }
+        if let Some(other) = self.register_for(label).filter(|other| *other != register) {
+            return Err(format!("{} already tags R{}", label, other));
+        }
+        self.tags.insert(register, label.to_string());
+        Ok(())
+    }
+
+    /// The register named by a number, a keyboard name or a tag (ignoring
+    /// case).
+    pub fn register_for(&self, name: &str) -> Option<usize> {
+        self.find_register(name).ok()
+    }
+
+    // Number base conversion; a base also leaves FLOAT mode
+    pub fn set_base(&mut self, base: Base) {
+        if self.is_float() {
+            self.leave_float();
+        }
+        self.base = base;
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.float_digits.is_some()
     }
 
-    // Number base conversion
-    pub fn set_base(&mut self, base: u8) {
-        if base == 2 || base == 8 || base == 10 || base == 16 {
-            self.base = base;
+    /// The float a register holds in FLOAT mode.
+    pub fn float_value(value: u128) -> f64 {
+        f64::from_bits(value as u64)
+    }
+
+    fn float_bits(value: f64) -> u128 {
+        value.to_bits() as u128
+    }
+
+    /// FLOAT n: decimal floating point showing `digits` after the point.
+    /// Coming from an integer mode, Y is taken as a mantissa and X as an
+    /// exponent of 2, as on the HP-16C: X becomes Y × 2^X and Y, Z and T
+    /// are cleared. Both are read in the current complement mode. A result
+    /// beyond the float range is clamped and sets overflow. The word size
+    /// becomes 64 bits; memory registers keep their bits and the layout of
+    /// the integer word size, so none are lost.
+    pub fn set_float(&mut self, digits: u8) -> Result<(), String> {
+        if digits > MAX_FLOAT_DIGITS {
+            return Err(format!("FLOAT digits must be 0-{}", MAX_FLOAT_DIGITS));
         }
+        if !self.is_float() {
+            let mantissa = if self.is_signed() { self.signed_value(self.y) as f64 } else { self.mask_value(self.y) as f64 };
+            let exponent = if self.is_signed() { self.signed_value(self.x) } else { self.mask_value(self.x) as i128 };
+            let value = scale_by_power_of_two(mantissa, exponent);
+            self.overflow = !value.is_finite();
+            self.field = None;
+            self.preserved_stack = None;
+            self.float_layout = Some(self.word_size);
+            self.set_word_size(FLOAT_WORD_SIZE);
+            self.x = Self::float_bits(value.clamp(f64::MIN, f64::MAX));
+            (self.y, self.z, self.t) = (0, 0, 0);
+            self.base = Base::Decimal;
+        }
+        self.float_digits = Some(digits);
+        self.stack_lift = true;
+        Ok(())
+    }
+
+    /// Back to an integer mode, as on the HP-16C: X is split into a 32-bit
+    /// mantissa in Y and an exponent of 2 in X, with X = Y × 2^X, in a
+    /// 56-bit word in 2's complement mode. Z and T are cleared. Memory is
+    /// laid out again from the word size before FLOAT to 56 bits.
+    fn leave_float(&mut self) {
+        let (mantissa, exponent) = float_parts(Self::float_value(self.x));
+        self.float_digits = None;
+        self.float_layout = None;
+        self.set_word_size(FLOAT_EXIT_WORD_SIZE);
+        self.complement_mode = ComplementMode::TwosComplement;
+        self.y = self.encode_signed(mantissa as i128);
+        self.x = self.encode_signed(exponent as i128);
+        (self.z, self.t) = (0, 0);
+        self.overflow = false;
+    }
+
+    /// Y op X on the floats in FLOAT mode. A result beyond the f64 range
+    /// sets overflow and is clamped to the largest float of its sign.
+    fn float_operation(&mut self, op: fn(f64, f64) -> f64) {
+        let result = op(Self::float_value(self.y), Self::float_value(self.x));
+        self.overflow = !result.is_finite();
+        self.drop();
+        self.x = Self::float_bits(result.clamp(f64::MIN, f64::MAX));
     }
 
+    /// Change the word size, ignoring sizes outside 1-128 bits.
     pub fn set_word_size(&mut self, size: u8) {
-        if size >= 1 && size <= 128 {
+        if let Ok(size) = WordSize::try_from(size) {
             self.word_size = size;
             // Re-mask current values
             self.x = self.mask_value(self.x);
             self.y = self.mask_value(self.y);
             self.z = self.mask_value(self.z);
             self.t = self.mask_value(self.t);
+            self.i = self.mask_value(self.i);
+            self.resize_memory();
+            if self.field.is_some_and(|field| field.high >= size.bits()) {
+                self.field = None;
+            }
+        }
+    }
+
+    /// Stack registers holding set bits above `size`.
+    pub fn truncated_registers(&self, size: u8) -> Vec<&'static str> {
+        let fits = |value: u128| size >= 128 || value >> size == 0;
+        [("X", self.x), ("Y", self.y), ("Z", self.z), ("T", self.t)]
+            .into_iter()
+            .filter(|(_, value)| !fits(*value))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Change the word size following `word_size_policy`. Returns the
+    /// registers that lost set bits; only the Error policy fails.
+    pub fn change_word_size(&mut self, size: u8) -> Result<Vec<&'static str>, String> {
+        let size = WordSize::try_from(size)?.bits();
+        let truncated = self.truncated_registers(size);
+        match self.word_size_policy {
+            WordSizePolicy::Error if !truncated.is_empty() => {
+                return Err(format!(
+                    "Word size {} would lose set bits in {}",
+                    size,
+                    truncated.join(", ")
+                ));
+            },
+            WordSizePolicy::Preserve => {
+                let current = [self.x, self.y, self.z, self.t];
+                // Use a kept value while the register still holds it
+                let mut full = current;
+                if let Some(kept) = self.preserved_stack {
+                    for (value, kept) in full.iter_mut().zip(kept) {
+                        if self.mask_value(kept) == *value {
+                            *value = kept;
+                        }
+                    }
+                }
+                self.set_word_size(size);
+                [self.x, self.y, self.z, self.t] = full.map(|value| self.mask_value(value));
+                self.preserved_stack = if full.iter().any(|value| self.mask_value(*value) != *value) {
+                    Some(full)
+                } else {
+                    None
+                };
+            },
+            _ => self.set_word_size(size),
         }
+        Ok(truncated)
     }
 
     // Display formatting
     pub fn format_display(&self) -> String {
-        match self.base {
-            2 => format!("{:b}", self.x),
-            8 => format!("{:o}", self.x),
-            10 => format!("{}", self.x),
-            16 => format!("{:X}", self.x),
-            _ => format!("{:X}", self.x),
+        self.format_digits(self.x)
+    }
+
+    /// Whether flag 3 pads the display with leading zeros.
+    pub fn leading_zeros(&self) -> bool {
+        self.user_flags[LEADING_ZEROS_FLAG]
+    }
+
+    /// A value as the display shows it, with leading zeros while flag 3
+    /// is set. Decimal and FLOAT are never padded.
+    fn format_digits(&self, value: u128) -> String {
+        self.format_in(value, self.base, self.leading_zeros())
+    }
+
+    /// Format a register value in the current base. Decimal shows the sign
+    /// in signed modes; other bases show the bit pattern.
+    pub fn format_value(&self, value: u128) -> String {
+        self.format_in(value, self.base, false)
+    }
+
+    /// Format a register value in `base`, padded with leading zeros to the
+    /// whole word size in binary, octal and hex if `leading_zeros` is set.
+    /// FLOAT mode values are always shown as floats.
+    pub(crate) fn format_in(&self, value: u128, base: Base, leading_zeros: bool) -> String {
+        if let Some(digits) = self.float_digits {
+            return format_float(Self::float_value(value), digits);
+        }
+        let digits = match base {
+            Base::Binary => format!("{:b}", value),
+            Base::Octal => format!("{:o}", value),
+            Base::Decimal if self.is_negative_zero(value) => "-0".to_string(),
+            Base::Decimal if self.is_signed() => format!("{}", self.signed_value(value)),
+            Base::Decimal => format!("{}", value),
+            Base::Hex => format!("{:X}", value),
+        };
+        let Some(bits_per_digit) = base.bits_per_digit() else {
+            return digits;
+        };
+        if !leading_zeros {
+            return digits;
+        }
+        let width = (self.word_size.bits() as usize).div_ceil(bits_per_digit);
+        format!("{:0>width$}", digits, width = width)
+    }
+
+    // Printer output: X in the current base with a base annunciator
+    pub fn print_x(&mut self) {
+        let suffix = if self.is_float() { 'f' } else { self.base.suffix() };
+        let line = format!("{:>40} {}", self.format_display(), suffix);
+        self.printer.print(line);
+    }
+
+    /// Number of display windows X takes: more than one only for binary
+    /// values longer than [`WINDOW_DIGITS`].
+    pub fn window_count(&self) -> usize {
+        if self.base != Base::Binary || self.is_float() {
+            return 1;
+        }
+        self.format_digits(self.x).len().div_ceil(WINDOW_DIGITS)
+    }
+
+    /// Show the window of more significant digits (`<`), if there is one.
+    pub fn window_left(&mut self) {
+        if self.window + 1 < self.window_count() {
+            self.window += 1;
         }
     }
 
+    /// Show the window of less significant digits (`>`).
+    pub fn window_right(&mut self) {
+        self.window = self.window.saturating_sub(1);
+    }
+
+    /// Show the window holding the highest set bit of X, or the lowest
+    /// window if X is zero.
+    pub fn follow_window(&mut self) {
+        let bits = 128 - self.mask_value(self.x).leading_zeros() as usize;
+        self.window = bits.saturating_sub(1) / WINDOW_DIGITS;
+    }
+
+    /// A register in the current display window, with `.` on the side
+    /// where more digits are hidden. Only binary is windowed.
+    fn format_windowed(&self, value: u128) -> String {
+        let digits = self.format_digits(value);
+        if self.base != Base::Binary || self.is_float() {
+            return digits;
+        }
+        let window = self.window.min(self.window_count() - 1);
+        let end = digits.len().saturating_sub(window * WINDOW_DIGITS);
+        let start = end.saturating_sub(WINDOW_DIGITS);
+        format!(
+            "{}{}{}",
+            if start > 0 { "." } else { "" },
+            &digits[start..end],
+            if end < digits.len() { "." } else { "" }
+        )
+    }
+
     pub fn get_stack_display(&self) -> [String; 4] {
         [
-            format!("T: {}", match self.base {
-                2 => format!("{:b}", self.t),
-                8 => format!("{:o}", self.t),
-                10 => format!("{}", self.t),
-                16 => format!("{:X}", self.t),
-                _ => format!("{:X}", self.t),
-            }),
-            format!("Z: {}", match self.base {
-                2 => format!("{:b}", self.z),
-                8 => format!("{:o}", self.z),
-                10 => format!("{}", self.z),
-                16 => format!("{:X}", self.z),
-                _ => format!("{:X}", self.z),
-            }),
-            format!("Y: {}", match self.base {
-                2 => format!("{:b}", self.y),
-                8 => format!("{:o}", self.y),
-                10 => format!("{}", self.y),
-                16 => format!("{:X}", self.y),
-                _ => format!("{:X}", self.y),
-            }),
-            format!("X: {}", match self.base {
-                2 => format!("{:b}", self.x),
-                8 => format!("{:o}", self.x),
-                10 => format!("{}", self.x),
-                16 => format!("{:X}", self.x),
-                _ => format!("{:X}", self.x),
-            }),
+            format!("T: {}", self.format_windowed(self.t)),
+            format!("Z: {}", self.format_windowed(self.z)),
+            format!("Y: {}", self.format_windowed(self.y)),
+            format!("X: {}", self.format_windowed(self.x)),
         ]
     }
-}
\ No newline at end of file
+}
+
+/// A float as FLOAT mode shows it: `digits` after the point, or in
+/// scientific notation when that would need more than ten digits before
+/// the point or would show a nonzero value as zero.
+fn format_float(value: f64, digits: u8) -> String {
+    let digits = digits as usize;
+    // No negative zero
+    let value = if value == 0.0 { 0.0 } else { value };
+    let smallest = 0.5 * 10f64.powi(-(digits as i32));
+    if value.abs() >= 1e10 || (value != 0.0 && value.abs() < smallest) {
+        format!("{:.*e}", digits, value)
+    } else {
+        format!("{:.*}", digits, value)
+    }
+}
+
+/// `value` × 2^`exponent`, in two steps so that a large mantissa with a
+/// very negative exponent (or the reverse) does not round to 0 or infinity
+/// on the way.
+fn scale_by_power_of_two(value: f64, exponent: i128) -> f64 {
+    let exponent = exponent.clamp(-4096, 4096) as i32;
+    let half = exponent / 2;
+    value * 2f64.powi(half) * 2f64.powi(exponent - half)
+}
+
+/// `value` as mantissa × 2^exponent with the mantissa's magnitude
+/// normalized to 32 bits (its top bit set), as FLOAT mode leaves it when
+/// returning to integers. Mantissa bits below the 32 are dropped. Zero is
+/// 0 × 2^0.
+pub fn float_parts(value: f64) -> (i64, i32) {
+    if value == 0.0 || !value.is_finite() {
+        return (0, 0);
+    }
+    let bits = value.abs().to_bits();
+    let (biased, fraction) = ((bits >> 52) as i32, bits & ((1 << 52) - 1));
+    // Subnormals have no implicit leading 1
+    let (mantissa, exponent) = if biased == 0 { (fraction, -1074) } else { (fraction | 1 << 52, biased - 1075) };
+    let shift = (u64::BITS - mantissa.leading_zeros()) as i32 - MANTISSA_BITS as i32;
+    let mantissa = if shift >= 0 { mantissa >> shift } else { mantissa << -shift };
+    let mantissa = if value < 0.0 { -(mantissa as i64) } else { mantissa as i64 };
+    (mantissa, exponent + shift)
+}
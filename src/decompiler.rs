@@ -0,0 +1,232 @@
+use crate::program::{Control, Program, Test};
+use std::collections::BTreeSet;
+
+/// A piece of the program once its GTOs and tests are read as structure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    /// Any other step, or a test or GTO that fits no pattern
+    Step(String),
+    Label(u8),
+    Goto(u8),
+    Break,
+    Continue,
+    /// `LBL t ... GTO t`, left by `break` to the step after the GTO
+    Loop(u8, Vec<Node>),
+    If(String, Vec<Node>, Vec<Node>),
+}
+
+/// DECOMPILE: the program as indented pseudocode, for reading published
+/// listings. A `LBL t` whose only GTOs come back to it from further on is
+/// a `loop`, with a GTO to the step after it as `break`, and a test ahead
+/// of the last GTO as a `break` when it fails. A test ahead of
+/// `GTO t` that jumps forward past some steps to `LBL t` is an `if` around
+/// them, the other way round as the steps run when the test is false, and
+/// an `else` when they end by jumping over more; a test ahead of any other
+/// step is an `if` around that step. Labels still gone to stay as `label`
+/// and `goto`, and so do A-F, which are run from the keyboard.
+pub fn decompile(program: &Program) -> Vec<String> {
+    let steps = program.steps();
+    let mut decompiler = Decompiler { steps, loops: Vec::new() };
+    let nodes = decompiler.block(0, steps.len());
+    let mut targets = BTreeSet::new();
+    gone_to(&nodes, &mut targets);
+    let mut lines = Vec::new();
+    render(&nodes, 0, &targets, &mut lines);
+    lines
+}
+
+struct Decompiler<'a> {
+    steps: &'a [String],
+    // The top and exit of each loop around the steps being read
+    loops: Vec<(u8, usize)>,
+}
+
+impl Decompiler<'_> {
+    fn control(&self, index: usize) -> Option<Control> {
+        self.steps.get(index).and_then(|step| Control::parse(step).ok().flatten())
+    }
+
+    fn test(&self, index: usize) -> Option<Test> {
+        self.steps.get(index).and_then(|step| Test::parse(step))
+    }
+
+    // Where GTO `label` steps are
+    fn gotos(&self, label: u8) -> Vec<usize> {
+        (0..self.steps.len()).filter(|index| self.control(*index) == Some(Control::Goto(label))).collect()
+    }
+
+    // Where LBL `label` is, if it is only there once
+    fn label_at(&self, label: u8) -> Option<usize> {
+        let found: Vec<usize> =
+            (0..self.steps.len()).filter(|index| self.control(*index) == Some(Control::Label(label))).collect();
+        (found.len() == 1).then(|| found[0])
+    }
+
+    fn is_called(&self, label: u8) -> bool {
+        (0..self.steps.len()).any(|index| self.control(index) == Some(Control::Gosub(label)))
+    }
+
+    // The steps from `start` up to `end` as nodes
+    fn block(&mut self, start: usize, end: usize) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut index = start;
+        while index < end {
+            let (node, next) = self.node(index, end);
+            nodes.push(node);
+            index = next;
+        }
+        nodes
+    }
+
+    // The node starting at `index`, and where the next one starts
+    fn node(&mut self, index: usize, end: usize) -> (Node, usize) {
+        match (self.control(index), self.test(index)) {
+            (Some(Control::Label(label)), _) => {
+                let gotos = self.gotos(label);
+                let last = gotos.last().copied().unwrap_or(index);
+                let is_loop = !gotos.is_empty()
+                    && gotos.iter().all(|goto| *goto > index && *goto < end)
+                    && self.label_at(label) == Some(index)
+                    && !self.is_called(label);
+                if !is_loop {
+                    return (Node::Label(label), index + 1);
+                }
+                self.loops.push((label, last + 1));
+                // A test ahead of the GTO back loops only while it holds
+                let test = self.test(last - 1).filter(|_| last - 1 > index);
+                let mut body = self.block(index + 1, if test.is_some() { last - 1 } else { last });
+                if let Some(test) = test {
+                    body.push(Node::If(condition(test, true), vec![Node::Break], Vec::new()));
+                }
+                self.loops.pop();
+                (Node::Loop(label, body), last + 1)
+            },
+            (Some(Control::Goto(label)), _) => (self.jump(label), index + 1),
+            (_, Some(test)) => self.branch(test, index, end),
+            _ => (Node::Step(self.steps[index].clone()), index + 1),
+        }
+    }
+
+    // A GTO on its own: out of or round the innermost loop, or elsewhere
+    fn jump(&self, label: u8) -> Node {
+        match self.loops.last() {
+            Some((top, _)) if *top == label => Node::Continue,
+            Some((_, exit)) if self.label_at(label) == Some(*exit) => Node::Break,
+            _ => Node::Goto(label),
+        }
+    }
+
+    // A test at `index` and the steps it decides on
+    fn branch(&mut self, test: Test, index: usize, end: usize) -> (Node, usize) {
+        if index + 1 >= end {
+            return (Node::Step(self.steps[index].clone()), index + 1);
+        }
+        let Some(Control::Goto(label)) = self.control(index + 1) else {
+            // A false test skips the one step after it
+            let (then, next) = self.node(index + 1, (index + 2).min(end));
+            return (Node::If(condition(test, false), vec![then], Vec::new()), next);
+        };
+        let skipped = match self.label_at(label) {
+            Some(target) if target > index + 1 && target < end && self.gotos(label) == [index + 1] => target,
+            _ => return (Node::If(condition(test, false), vec![self.jump(label)], Vec::new()), index + 2),
+        };
+        // Jumping over the steps up to LBL: they run when the test is false
+        let condition = condition(test, true);
+        if let Some(Control::Goto(over)) = self.control(skipped - 1) {
+            if let Some(after) = self.label_at(over) {
+                if after > skipped && after < end && self.gotos(over) == [skipped - 1] {
+                    let then = self.block(index + 2, skipped - 1);
+                    let otherwise = self.block(skipped + 1, after);
+                    return (Node::If(condition, then, otherwise), after + 1);
+                }
+            }
+        }
+        let then = self.block(index + 2, skipped);
+        (Node::If(condition, then, Vec::new()), skipped + 1)
+    }
+}
+
+// A test as a condition, or what makes it false
+fn condition(test: Test, negated: bool) -> String {
+    let (holds, fails) = match test {
+        Test::XEqualsY => ("x == y", "x != y"),
+        Test::XNotEqualY => ("x != y", "x == y"),
+        Test::XLessOrEqualY => ("x <= y", "x > y"),
+        Test::XGreaterThanY => ("x > y", "x <= y"),
+        Test::XEqualsZero => ("x == 0", "x != 0"),
+        Test::XNotZero => ("x != 0", "x == 0"),
+        Test::XLessThanZero => ("x < 0", "x >= 0"),
+        Test::XGreaterThanZero => ("x > 0", "x <= 0"),
+        Test::Bit => ("bit x of y is set", "bit x of y is clear"),
+        Test::Flag(number) => {
+            let state = if negated { "clear" } else { "set" };
+            return format!("flag {} is {}", number, state);
+        },
+    };
+    if negated { fails } else { holds }.to_string()
+}
+
+// The labels a goto is left for
+fn gone_to(nodes: &[Node], targets: &mut BTreeSet<u8>) {
+    for node in nodes {
+        match node {
+            Node::Goto(label) => {
+                targets.insert(*label);
+            },
+            Node::Loop(_, body) => gone_to(body, targets),
+            Node::If(_, then, otherwise) => {
+                gone_to(then, targets);
+                gone_to(otherwise, targets);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn render(nodes: &[Node], depth: usize, targets: &BTreeSet<u8>, lines: &mut Vec<String>) {
+    let indent = "    ".repeat(depth);
+    // Labels A-F are started from the keyboard, so always show them
+    let shown = |label: &u8| *label >= 10 || targets.contains(label);
+    for node in nodes {
+        match node {
+            Node::Step(step) => lines.push(format!("{}{}", indent, statement(step))),
+            Node::Label(label) if shown(label) => lines.push(format!("{}label {:X}:", indent, label)),
+            Node::Label(_) => {},
+            Node::Goto(label) => lines.push(format!("{}goto {:X}", indent, label)),
+            Node::Break => lines.push(format!("{}break", indent)),
+            Node::Continue => lines.push(format!("{}continue", indent)),
+            Node::Loop(label, body) => {
+                if shown(label) {
+                    lines.push(format!("{}label {:X}:", indent, label));
+                }
+                lines.push(format!("{}loop {{", indent));
+                render(body, depth + 1, targets, lines);
+                lines.push(format!("{}}}", indent));
+            },
+            Node::If(condition, then, otherwise) => {
+                lines.push(format!("{}if {} {{", indent, condition));
+                render(then, depth + 1, targets, lines);
+                if !otherwise.is_empty() {
+                    lines.push(format!("{}}} else {{", indent));
+                    render(otherwise, depth + 1, targets, lines);
+                }
+                lines.push(format!("{}}}", indent));
+            },
+        }
+    }
+}
+
+// A step as a statement: register use spelled out, other steps as typed
+fn statement(step: &str) -> String {
+    let (word, argument) = step.split_once(' ').unwrap_or((step, ""));
+    match (word, argument) {
+        ("STO", register) if !register.is_empty() => format!("R{} = x", register),
+        ("RCL", register) if !register.is_empty() => format!("push R{}", register),
+        ("STO+" | "STO-" | "STO*" | "STO/", register) => format!("R{} {}= x", register, &word[3..]),
+        ("GSB", label) => format!("call {}", label),
+        ("RTN", "") => "return".to_string(),
+        ("R/S", "") => "stop".to_string(),
+        _ if step.starts_with(|c: char| c.is_ascii_digit()) => format!("push {}", step),
+        _ => step.to_string(),
+    }
+}
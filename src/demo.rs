@@ -0,0 +1,23 @@
+/// The bundled demo script, one command per line with `#` narration.
+pub const SCRIPT: &str = include_str!("demo.rpn");
+
+/// One step of a demo script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemoStep<'a> {
+    /// Narration shown to the audience.
+    Say(&'a str),
+    /// A command typed at the prompt.
+    Key(&'a str),
+}
+
+pub fn steps(script: &str) -> Vec<DemoStep<'_>> {
+    script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.strip_prefix('#') {
+            Some(text) => DemoStep::Say(text.trim()),
+            None => DemoStep::Key(line),
+        })
+        .collect()
+}
@@ -0,0 +1,204 @@
+use crate::command;
+use crate::cpu::{Base, Hp16cCpu};
+use crate::storage::MemoryStorage;
+use std::fmt;
+
+/// Render the calculator display box as text, one line per row.
+pub fn render_lines(calc: &Hp16cCpu) -> Vec<String> {
+    render_lines_with_flags(calc, &[])
+}
+
+/// A strip of `▁` for clear and `█` for set, oldest first.
+pub fn sparkline(values: impl IntoIterator<Item = bool>) -> String {
+    values.into_iter().map(|set| if set { '█' } else { '▁' }).collect()
+}
+
+/// [`render_lines`] with a row under the flags showing carry and overflow
+/// after each of the recent commands in `history`, oldest first, such as
+/// `C: ▁▁█▁█  V: ▁▁▁▁▁`. An empty history leaves the row out.
+pub fn render_lines_with_flags(calc: &Hp16cCpu, history: &[(bool, bool)]) -> Vec<String> {
+    // Calculate the required width based on the longest stack display
+    let stack = calc.get_stack_display();
+    let title = "HP-16C Calculator";
+    let mode = match calc.float_digits {
+        Some(digits) => format!("FLOAT {}", digits),
+        None => format!("Base: {:2}", calc.base.radix()),
+    };
+    let mut status_line = format!("{}  Word Size: {:2}  {}", mode, calc.word_size, calc.complement_mode.label());
+    if let Some(field) = calc.field {
+        status_line.push_str(&format!("  Field {}", field.label()));
+    }
+    if calc.program_mode {
+        status_line.push_str(&format!("  PRGM {:03}", calc.pc));
+    }
+    // The HP-16C's low-power indicator
+    if calc.low_battery {
+        status_line.push_str("  *");
+    }
+    let mut flags_line = format!("Carry: {}  Overflow: {}",
+                            if calc.carry { "1" } else { "0" },
+                            if calc.overflow { "1" } else { "0" });
+    let user_flags: Vec<String> = (0..4).filter(|n| calc.user_flags[*n]).map(|n| n.to_string()).collect();
+    if !user_flags.is_empty() {
+        flags_line.push_str(&format!("  Flags: {}", user_flags.join(" ")));
+    }
+
+    let history_line = (!history.is_empty()).then(|| {
+        format!(
+            "C: {}  V: {}",
+            sparkline(history.iter().map(|flags| flags.0)),
+            sparkline(history.iter().map(|flags| flags.1))
+        )
+    });
+
+    // Find the maximum width needed
+    let mut max_width = title.len().max(status_line.len()).max(flags_line.len());
+    for line in stack.iter().chain(&history_line) {
+        max_width = max_width.max(line.chars().count());
+    }
+
+    // Ensure minimum width and add padding for borders
+    let display_width = max_width.max(29) + 2; // +2 for left and right padding
+    let row = |text: &str| format!("│ {:width$} │", text, width = display_width - 2);
+
+    let mut lines = vec![
+        format!("┌{}┐", "─".repeat(display_width)),
+        row(title),
+        format!("├{}┤", "─".repeat(display_width)),
+        row(&status_line),
+        row(&flags_line),
+    ];
+    if let Some(history_line) = &history_line {
+        lines.push(row(history_line));
+    }
+    lines.push(format!("├{}┤", "─".repeat(display_width)));
+    for line in &stack {
+        lines.push(row(line));
+    }
+    lines.push(format!("└{}┘", "─".repeat(display_width)));
+    lines
+}
+
+/// The stack registers for `{}`: `X: 3F  Y: 0  Z: 0  T: 0` on one line,
+/// or with `{:#}` one row per register from T down to X, as in the display.
+/// Values are in the calculator's base unless another is chosen.
+#[derive(Debug, Clone, Copy)]
+pub struct StackView<'a> {
+    calc: &'a Hp16cCpu,
+    base: Base,
+    leading_zeros: bool,
+}
+
+impl<'a> StackView<'a> {
+    pub fn new(calc: &'a Hp16cCpu) -> Self {
+        StackView {
+            calc,
+            base: calc.base(),
+            leading_zeros: calc.leading_zeros(),
+        }
+    }
+
+    /// Show the values in `base`. FLOAT mode values stay floats.
+    pub fn base(mut self, base: Base) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Pad binary, octal and hex to the word size; flag 3 by default.
+    pub fn leading_zeros(mut self, on: bool) -> Self {
+        self.leading_zeros = on;
+        self
+    }
+}
+
+impl fmt::Display for StackView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let calc = self.calc;
+        let registers = [("X", calc.x()), ("Y", calc.y()), ("Z", calc.z()), ("T", calc.t())];
+        let rows: Vec<String> = registers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, calc.format_in(*value, self.base, self.leading_zeros)))
+            .collect();
+        if f.alternate() {
+            let rows: Vec<&str> = rows.iter().rev().map(String::as_str).collect();
+            write!(f, "{}", rows.join("\n"))
+        } else {
+            write!(f, "{}", rows.join("  "))
+        }
+    }
+}
+
+/// `{}` shows the stack and the modes on one line, for logging;
+/// `{:#}` shows the whole display box.
+impl fmt::Display for Hp16cCpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write!(f, "{}", render_lines(self).join("\n"));
+        }
+        let mode = match self.float_digits {
+            Some(digits) => format!("FLOAT {}", digits),
+            None => self.base().to_string(),
+        };
+        write!(f, "{}  [{} WS {} {}", StackView::new(self), mode, self.word_size(), self.complement_mode.label())?;
+        if self.carry {
+            write!(f, " CARRY")?;
+        }
+        if self.overflow {
+            write!(f, " OVERFLOW")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Render the display box as a single text frame.
+pub fn render_frame(calc: &Hp16cCpu) -> String {
+    let mut frame = render_lines(calc).join("\n");
+    frame.push('\n');
+    frame
+}
+
+/// Render the display box as a standalone SVG image of monospaced text.
+pub fn render_svg(calc: &Hp16cCpu) -> String {
+    const CHAR_WIDTH: usize = 9;
+    const LINE_HEIGHT: usize = 18;
+
+    let lines = render_lines(calc);
+    let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let width = columns * CHAR_WIDTH + 2 * CHAR_WIDTH;
+    let height = lines.len() * LINE_HEIGHT + LINE_HEIGHT;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        width, height
+    );
+    svg.push_str("  <rect width=\"100%\" height=\"100%\" fill=\"#1e1e1e\"/>\n");
+    svg.push_str("  <g font-family=\"monospace\" font-size=\"15\" fill=\"#e0e0e0\" xml:space=\"preserve\">\n");
+    for (i, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            "    <text x=\"{}\" y=\"{}\">{}</text>\n",
+            CHAR_WIDTH,
+            (i + 1) * LINE_HEIGHT,
+            escape_xml(line)
+        ));
+    }
+    svg.push_str("  </g>\n</svg>\n");
+    svg
+}
+
+/// Run a command sequence on a fresh calculator and return the final state,
+/// for generating documentation frames without a terminal.
+pub fn run_headless(commands: &[&str]) -> Result<Hp16cCpu, String> {
+    let mut calc = Hp16cCpu::new();
+    let mut storage = MemoryStorage::new();
+    for line in commands {
+        command::execute(&mut calc, &mut storage, line)?;
+    }
+    Ok(calc)
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,120 @@
+use crate::cpu::Hp16cCpu;
+use crate::display::escape_xml;
+
+/// Key labels of the HP-16C keyboard, row by row. ENTER occupies the sixth
+/// column of the third and fourth rows.
+const KEY_ROWS: [[&str; 10]; 4] = [
+    ["A", "B", "C", "D", "E", "F", "7", "8", "9", "÷"],
+    ["GSB", "GTO", "HEX", "DEC", "OCT", "BIN", "4", "5", "6", "×"],
+    ["R/S", "SST", "R↓", "x≷y", "BSP", "ENTER", "1", "2", "3", "−"],
+    ["ON", "f", "g", "STO", "RCL", "ENTER", "0", "·", "CHS", "+"],
+];
+
+// Number of characters the LCD can show
+const LCD_DIGITS: usize = 16;
+
+const KEY_WIDTH: usize = 52;
+const KEY_HEIGHT: usize = 40;
+const KEY_PITCH_X: usize = 62;
+const KEY_PITCH_Y: usize = 58;
+const KEYBOARD_LEFT: usize = 30;
+const KEYBOARD_TOP: usize = 150;
+
+/// Map a REPL command to the faceplate key that performs it.
+fn key_label(command: &str) -> Option<&'static str> {
+    let command = command.trim().to_uppercase();
+    let label = match command.as_str() {
+        "/" => "÷",
+        "*" => "×",
+        "-" => "−",
+        "+" => "+",
+        "SWAP" => "x≷y",
+        "RV" => "R↓",
+        "ENTER" => "ENTER",
+        _ => {
+            let word = command.split_whitespace().next().unwrap_or("");
+            return KEY_ROWS
+                .iter()
+                .flatten()
+                .copied()
+                .find(|label| label.eq_ignore_ascii_case(word));
+        }
+    };
+    Some(label)
+}
+
+/// Render the calculator faceplate as SVG with the LCD showing X, the
+/// annunciators for the current flags, and `pressed` highlighted.
+pub fn render_faceplate(calc: &Hp16cCpu, pressed: Option<&str>) -> String {
+    let pressed = pressed.and_then(key_label);
+    let width = KEYBOARD_LEFT * 2 + KEY_PITCH_X * 9 + KEY_WIDTH;
+    let height = KEYBOARD_TOP + KEY_PITCH_Y * 4 + 10;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\">\n",
+        width, height
+    );
+    svg.push_str(&format!(
+        "  <rect width=\"{}\" height=\"{}\" rx=\"12\" fill=\"#2b2b2b\"/>\n",
+        width, height
+    ));
+
+    // LCD with the X register right-aligned and a base annunciator
+    let mut digits = calc.format_display();
+    if digits.chars().count() > LCD_DIGITS {
+        let tail: String = digits.chars().skip(digits.chars().count() - (LCD_DIGITS - 1)).collect();
+        digits = format!("…{}", tail);
+    }
+    let base = calc.base.suffix();
+    svg.push_str(&format!(
+        "  <rect x=\"{}\" y=\"20\" width=\"{}\" height=\"90\" rx=\"4\" fill=\"#9fa88f\"/>\n",
+        KEYBOARD_LEFT,
+        width - KEYBOARD_LEFT * 2
+    ));
+    svg.push_str(&format!(
+        "  <text x=\"{}\" y=\"78\" font-family=\"monospace\" font-size=\"40\" text-anchor=\"end\" fill=\"#222\">{} {}</text>\n",
+        width - KEYBOARD_LEFT - 16,
+        escape_xml(&digits),
+        base
+    ));
+
+    let mut annunciators = Vec::new();
+    if calc.carry {
+        annunciators.push("C");
+    }
+    if calc.overflow {
+        annunciators.push("G");
+    }
+    svg.push_str(&format!(
+        "  <text x=\"{}\" y=\"102\" font-size=\"14\" fill=\"#222\">{}</text>\n",
+        KEYBOARD_LEFT + 16,
+        annunciators.join("  ")
+    ));
+
+    // Keyboard
+    for (row, labels) in KEY_ROWS.iter().enumerate() {
+        for (col, label) in labels.iter().enumerate() {
+            // The lower half of ENTER is drawn with the upper half
+            if row == 3 && *label == "ENTER" {
+                continue;
+            }
+            let x = KEYBOARD_LEFT + col * KEY_PITCH_X;
+            let y = KEYBOARD_TOP + row * KEY_PITCH_Y;
+            let key_height = if *label == "ENTER" { KEY_HEIGHT + KEY_PITCH_Y } else { KEY_HEIGHT };
+            let fill = if pressed == Some(*label) { "#d9a441" } else { "#444" };
+            svg.push_str(&format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"{}\"/>\n",
+                x, y, KEY_WIDTH, key_height, fill
+            ));
+            svg.push_str(&format!(
+                "  <text x=\"{}\" y=\"{}\" font-size=\"14\" text-anchor=\"middle\" fill=\"#fff\">{}</text>\n",
+                x + KEY_WIDTH / 2,
+                y + key_height / 2 + 5,
+                escape_xml(label)
+            ));
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,537 @@
+/// Text of the full HELP screen.
+pub const TEXT: &str = include_str!("help.txt");
+
+/// What a command's arguments are, for completing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    None,
+    /// File paths, completed from the filesystem
+    File,
+}
+
+/// One command in the help registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelpEntry {
+    pub command: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+    pub argument: Argument,
+}
+
+impl HelpEntry {
+    /// The command's own words, without argument placeholders such as
+    /// `file` or `[n]`.
+    pub fn keyword(&self) -> String {
+        self.command
+            .split_whitespace()
+            .take_while(|word| !word.starts_with('[') && !word.chars().any(char::is_lowercase))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A "how do I ...?" recipe in the task index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Task {
+    pub question: &'static str,
+    pub steps: &'static str,
+}
+
+pub const COMMANDS: &[HelpEntry] = &[
+    entry("[number]", "Enter number in current base", "FF (hex), 255 (dec)"),
+    entry("ENTER", "Push X to stack (duplicate)", "10 ENTER → stack: [10,10]"),
+    entry("CLX", "Clear X; the next number replaces it", "5 ENTER 9 CLX 3 + → 8"),
+    entry("PUSHN v...", "Push several values in order", "PUSHN 1 2 3 → X: 3"),
+    entry("[v ...]", "Same as PUSHN", "[DE AD BE EF]"),
+    entry("[v ...] STO n", "Store the values from Rn on", "[DE AD BE EF] STO 4"),
+    entry("AUTOENTER ON", "Accept left-to-right chains such as 10 + 5 (AUTOENTER OFF for pure RPN)", "10 + 5 * 2 → 30"),
+    entry("+", "Add Y + X", "10 ENTER 5 + → 15"),
+    entry("-", "Subtract Y - X", "10 ENTER 3 - → 7"),
+    entry("*", "Multiply Y × X", "6 ENTER 7 * → 42"),
+    entry("/", "Divide Y ÷ X (carry: remainder)", "20 ENTER 4 / → 5"),
+    entry("RMD", "Remainder of Y ÷ X (sign of Y)", "20 ENTER 6 RMD → 2"),
+    entry("CHS", "Change the sign of X (negate)", "2S DEC 5 CHS → -5"),
+    entry("ABS", "Absolute value of X; overflow for the most negative 2's complement value", "2S DEC 5 CHS ABS → 5"),
+    entry(">BCD", "Binary X to packed BCD", "DEC 42 >BCD HEX → 42"),
+    entry("BCD>", "Packed BCD X to binary", "HEX 42 BCD> DEC → 42"),
+    entry("BCD+", "Add packed BCD (carry = decimal carry)", "0199 ENTER 1 BCD+ → 200"),
+    entry("BCD-", "Subtract packed BCD (carry = borrow)", "0100 ENTER 1 BCD- → 99"),
+    entry("MIN", "Smaller of Y and X (signed in 1S/2S)", "3 ENTER 5 MIN → 3"),
+    entry("MAX", "Larger of Y and X (signed in 1S/2S)", "3 ENTER 5 MAX → 5"),
+    entry("CMP", "-1, 0 or 1 as Y <, =, > X", "3 ENTER 5 CMP → -1 (all ones)"),
+    entry("DBL*", "Y × X as a double word (X high)", "FF ENTER FF DBL* → FE, Y: 1"),
+    entry("DBL/", "Double word Y:Z ÷ X", "34 ENTER 12 ENTER 56 DBL/ → 36"),
+    entry("DBLR", "Remainder of double word Y:Z ÷ X", "34 ENTER 12 ENTER 56 DBLR → 10"),
+    entry("&", "Bitwise AND of Y & X (masking)", "F0 ENTER 0F & → 0"),
+    entry("|", "Bitwise OR of Y | X", "F0 ENTER 0F | → FF"),
+    entry("^", "Bitwise XOR of Y ^ X", "FF ENTER AA ^ → 55"),
+    entry("~", "Bitwise NOT of X", "FF ~ → 0 (in 8-bit mode)"),
+    entry("MASKL [n]", "Mask of n (or X) ones at the top", "WS 16 4 MASKL → F000"),
+    entry("MASKR [n]", "Mask of n (or X) ones at the bottom", "WS 16 MASKR 4 → F"),
+    entry("SB", "Set bit X of Y", "10 ENTER 3 SB → 18"),
+    entry("CB", "Clear bit X of Y", "18 ENTER 4 CB → 8"),
+    entry("B?", "Test bit X of Y into carry, keep Y; a test in programs", "8 ENTER 3 B? → TRUE, carry set"),
+    entry("DROP", "Remove X, lift stack up", "[4,3,2,1] DROP → [3,2,1,1]"),
+    entry("SWAP", "Exchange X and Y", "[4,3,2,1] SWAP → [3,4,2,1]"),
+    entry("RV", "Roll stack down", "[4,3,2,1] RV → [3,2,1,4]"),
+    entry("R^", "Roll stack up", "[4,3,2,1] R^ → [1,4,3,2]"),
+    entry("FIELD h:l", "Operate on bits h-l only", "FIELD 15:8"),
+    entry("FIELD OFF", "Operate on whole words again", "FIELD OFF"),
+    entry("HEX", "Switch to hexadecimal", "255 HEX → displays as FF"),
+    entry("DEC", "Switch to decimal", "FF DEC → displays as 255"),
+    entry("OCT", "Switch to octal", "255 OCT → displays as 377"),
+    entry("BIN", "Switch to binary", "255 BIN → displays as 11111111"),
+    entry("<", "Show higher binary digits", "WS 16 FFFF BIN < → shows 11111111."),
+    entry(">", "Show lower binary digits", "> → back to .11111111"),
+    entry("WINDOW AUTO", "Keep the highest set bit of X in view (WINDOW MANUAL to stop)", "WINDOW AUTO 1 SL 9"),
+    entry("FLOAT n", "Decimal floats, n digits (a base leaves)", "FLOAT 4 1.5 ENTER 2 / → 0.7500"),
+    entry("WS [n]", "Set word size (1-128 bits)", "WS 8 → 8-bit arithmetic"),
+    entry("WSPOLICY p", "TRUNCATE, WARN, PRESERVE or ERROR on WS", "WSPOLICY PRESERVE"),
+    entry("1S", "Signed 1's complement mode", "WS 8 1S FE DEC → shows -1"),
+    entry("2S", "Signed 2's complement mode", "WS 8 2S FF DEC → shows -1"),
+    entry("UNSGN", "Unsigned mode (default)", "UNSGN → FF shows 255"),
+    entry("SL [n]", "Shift left n positions (0-128; carry = last bit out)", "5 SL 1 → A (5<<1 = 10)"),
+    entry("SR [n]", "Shift right n positions (0-128; carry = last bit out)", "A SR 1 → 5 (10>>1 = 5)"),
+    entry("RL", "Rotate left one bit (carry = bit out)", "WS 8 81 RL → 3, carry set"),
+    entry("RR", "Rotate right one bit (carry = bit out)", "WS 8 81 RR → C0, carry set"),
+    entry("RLn", "Rotate Y left by X bits", "WS 8 81 ENTER 4 RLn → 18"),
+    entry("RRn", "Rotate Y right by X bits", "WS 8 81 ENTER 4 RRn → 18"),
+    entry("RLC", "Rotate left through carry", "WS 8 80 RLC → 0 (+carry), RLC → 1"),
+    entry("RRC", "Rotate right through carry", "WS 8 1 RRC → 0 (+carry), RRC → 80"),
+    entry("CONST", "List bundled and user constants", "CONST"),
+    entry("CONST name", "Push a named constant", "CONST CRC32 → 4C11DB7"),
+    entry("DEFCONST n [v]", "Define a constant (X or v)", "DEFCONST UART 4000C000"),
+    entry("STO [n]", "Store X in register n (101 at 16 bits)", "42 STO 5 → saves 42 to R5"),
+    entry("RCL [n]", "Recall register n, 0-9, A-F or .0-.F", "RCL .3 → pushes R19 to stack"),
+    entry("STO I", "Store X in the index register I", "5 STO I"),
+    entry("RCL I", "Recall I", "RCL I"),
+    entry("STO (i)", "Store X in the register I points at", "5 STO I 42 STO (i) → R5 = 42"),
+    entry("RCL (i)", "Recall the register I points at", "5 STO I RCL (i) → pushes R5"),
+    entry("STO+ n", "Add X into register n", "5 STO+ 3 → R3 = R3 + 5"),
+    entry("STO- n", "Subtract X from register n", "1 STO- 3 → R3 = R3 - 1"),
+    entry("STO* n", "Multiply register n by X (or STO×)", "2 STO* 3 → R3 = R3 × 2"),
+    entry("STO/ n", "Divide register n by X (or STO÷)", "2 STO/ 3 → R3 = R3 ÷ 2"),
+    entry("X<>I", "Exchange X and I", "7 X<>I → I = 7, X = old I"),
+    entry("X<>(i)", "Exchange X and the register I points at", "5 STO I 9 X<>(i) → R5 = 9"),
+    entry("TAG n label", "Label a register for STO/RCL", "TAG 3 crc_seed"),
+    entry("MEMVIEW", "List registers with their tags", "MEMVIEW"),
+    entry("MEM", "Free program steps and registers", "MEM → P-0 r-101"),
+    entry("SUMR a-b", "Push the sum of registers a-b", "SUMR 0-7"),
+    entry("XORR a-b", "Push the XOR of registers a-b", "XORR 0-7"),
+    entry("REDUCE op a-b", "Fold op across registers a-b", "REDUCE * 1-3"),
+    entry("MAPR a-b c;c", "Run commands on each register", "MAPR 0-3 F0; &"),
+    entry("RADD d s", "Add register s into register d; the stack is unchanged", "RADD 4 0"),
+    entry("RXOR d s", "XOR register s into register d; the stack is unchanged", "RXOR 4 0"),
+    entry("ST8 a", "Store the low byte of X at a", "FF ST8 10 (also ST16/ST32/ST64)"),
+    entry("LD8 a", "Push the byte at a", "LD8 10 (also LD16/LD32/LD64)"),
+    entry("ST32 a BE SAT", "Big-endian; SAT clamps, not truncates", "DEADBEEF ST32 0 BE"),
+    entry("SCRATCH [a]", "Dump scratch memory", "SCRATCH 10 / SCRATCH CLEAR"),
+    entry("DECODE B64 a t", "Decode base64 (or HEX) text into scratch memory at a", "DECODE B64 0 3q2+7w=="),
+    entry("ENCODE HEX a n", "Show n bytes of scratch memory as hex (or B64)", "ENCODE HEX 0 4 → DEADBEEF"),
+    entry("UTF8", "Show the UTF-8 bytes of the code point in X", "20AC UTF8 → E2 82 AC"),
+    entry("UTF8 a", "Decode the UTF-8 character at a and push its code point", "UTF8 10 → 20AC"),
+    entry("CKSUM k [b]", "Internet (IP) or Fletcher (F16/F32) checksum of bytes or scratch memory", "CKSUM F16 [61 62 63] / CKSUM IP 0 20"),
+    entry("RAND", "Push a random word", "RAND"),
+    entry("RAND LFSR t", "Use a Galois LFSR with taps t", "RAND LFSR B400"),
+    entry("RAND PCG", "Use PCG32 (or RAND XORSHIFT)", "RAND PCG"),
+    entry("RAND SEED", "Restart the generator from X", "2A RAND SEED"),
+    entry("RAND STATE", "Show the generator's state", "RAND STATE"),
+    entry("LFSR n", "Step the LFSR n times and push it", "LFSR 100"),
+    entry("ENDIAN b", "Byte order: LITTLE or BIG", "ENDIAN BIG"),
+    entry("BYTES", "Show X as bytes in that order", "1234 BYTES → 34 12"),
+    entry("RESULTS ON", "Keep results in R10-R15 in turn", "RESULTS ON / RESULTS OFF"),
+    entry("RESULTS a b", "Use registers a-b for results", "RESULTS 12 15"),
+    entry("RESULTS", "List recent results, newest first", "RESULTS"),
+    entry("PRINT X", "Print X to the printer tape", "FF PRINT X → tape: FF h"),
+    entry("TAPE", "Show the printer tape", "TAPE"),
+    entry("TAPE CLEAR", "Clear the printer tape", "TAPE CLEAR"),
+    file_entry("TAPE SAVE", "Write the tape to a file", "TAPE SAVE out.txt"),
+    entry("TRAIL", "List every input and result", "TRAIL"),
+    entry("TRAIL ON", "Show recent entries below X", "TRAIL ON / TRAIL OFF"),
+    file_entry("TRAIL SAVE", "Write the trail to a file", "TRAIL SAVE calc.txt"),
+    entry("TRAIL CLEAR", "Start a fresh trail", "TRAIL CLEAR"),
+    entry("TIMER ON", "Show each command's run time", "TIMER ON / TIMER OFF"),
+    entry("SPARKLINE OFF", "Hide the carry/overflow history row", "SPARKLINE OFF / SPARKLINE ON"),
+    entry("PANES ON", "Show stack, registers, program and tape panes", "PANES ON / PANES OFF"),
+    entry("PANES NEXT", "Move the focus to the next pane", "F6 = PANES NEXT in the keymap"),
+    entry("PANES FOCUS p", "Focus the stack, registers, program or tape pane", "PANES FOCUS program"),
+    entry("USAGE", "Commands and errors so far", "hp16c --usage me.json"),
+    entry("BACK [n]", "View the state n steps back", "BACK 3"),
+    entry("FORWARD [n]", "Move toward the present", "FORWARD"),
+    entry("RESUME", "Return to the live state", "RESUME"),
+    entry("P/R", "Switch between program and run mode", "P/R → PRGM mode, line 000"),
+    entry("LBL x", "Label a program step, 0-9 or A-F", "LBL A (in program mode)"),
+    entry("GTO x", "Go to a label, or to a line with GTO .nnn", "GTO A / GTO .005"),
+    entry("GSB x", "Run the program from a label until RTN", "5 GSB A"),
+    entry("RTN", "Return from a subroutine; ends a program", "RTN (in program mode)"),
+    entry("R/S", "Run from the current line; stops a program", "R/S"),
+    entry("SST", "Run one program step, showing its keys and the stack", "SST → 001- 43 22 A  LBL A"),
+    entry("BST", "Back up one program line without running it", "BST → 004- 43 21    RTN"),
+    entry("DEL", "Delete the current program step", "DEL (in program mode)"),
+    entry("PRGM", "List program memory", "PRGM → 001- LBL A ..."),
+    entry("PRGM LIST", "List program memory with each step's HP-16C key codes", "PRGM LIST → 001- 43 22 A  LBL A"),
+    file_entry("IMPORT", "Replace program memory with a program written in shorthand", "IMPORT count.txt"),
+    file_entry("COMPILE", "Replace program memory with one compiled from variables, if and while", "COMPILE sum.txt"),
+    entry("DECOMPILE", "Show the program as pseudocode with loops and ifs", "DECOMPILE → loop { ..."),
+    entry("ANNOTATE n text", "Attach a comment to program line n, for EXPORT MD", "ANNOTATE 1 Doubles X"),
+    file_entry("EXPORT MD", "Write the program as a Markdown table with keys and comments", "EXPORT MD double.md"),
+    file_entry("EXPORT KEYS", "Write a checklist of keys to enter the program on an HP-16C", "EXPORT KEYS double.txt"),
+    entry("CHECK", "List program steps a real HP-16C cannot run", "CHECK → 003- MIN: not an HP-16C instruction"),
+    entry("STRICT ON", "Refuse steps a real HP-16C cannot run in program mode", "STRICT ON / STRICT OFF"),
+    entry("OPTIMIZE", "Thread GTO chains and remove unreachable and redundant steps", "OPTIMIZE → Saved 3 bytes..."),
+    entry("X=Y?", "Test X = Y; in a program a false test skips the next step", "5 ENTER 5 X=Y? → TRUE"),
+    entry("X≠Y?", "Test X ≠ Y (also X!=Y?)", "5 ENTER 6 X≠Y? → TRUE"),
+    entry("X≤Y?", "Test X ≤ Y (also X<=Y?)", "6 ENTER 5 X≤Y? → TRUE"),
+    entry("X>Y?", "Test X > Y", "5 ENTER 6 X>Y? → TRUE"),
+    entry("X=0?", "Test X = 0", "0 X=0? → TRUE"),
+    entry("X≠0?", "Test X ≠ 0 (also X!=0?)", "1 X≠0? → TRUE"),
+    entry("X<0?", "Test X < 0 in a signed mode", "2S FFFF X<0? → TRUE"),
+    entry("X>0?", "Test X > 0", "0 X>0? → SKIP"),
+    entry("STATUS", "Show modes, settings and flags", "STATUS"),
+    entry("SF n", "Set flag n (0-3 user, 4 carry, 5 overflow)", "SF 0"),
+    entry("CF n", "Clear flag n", "CF 4 → carry cleared"),
+    entry("F? n", "Show whether flag n is set; IF F? n in scripts", "F? 0 → Flag 0 is set"),
+    entry("CLR STK", "Clear X, Y, Z and T", "CLR STK → all stack registers = 0"),
+    entry("CLEAR REG", "Zero the data registers and I", "CLEAR REG (also CLR REG)"),
+    entry("CLEAR PRGM", "Erase program memory, freeing it for registers", "CLEAR PRGM (also CLR PRGM)"),
+    entry("PRESET", "Reset continuous memory to the power-on state: WS 16, HEX, 2S", "PRESET → Pr Error"),
+    entry("BATTERY LOW", "Show the low-battery * indicator", "BATTERY LOW / BATTERY OK"),
+    entry("HELP", "Show the full help (also H, ?)", "HELP"),
+    entry("HELP ?text", "Search commands and tasks", "HELP ?mask"),
+    entry("HELP TASKS", "List the how-do-I task index", "HELP TASKS"),
+    entry("DEMO", "Replay a guided demonstration", "DEMO → tour of features"),
+    entry("QUIT", "Exit calculator (also Q)", "QUIT → exits program"),
+    file_entry("SAVE file", "Save stack, flags and memory", "SAVE work.json"),
+    file_entry("LOAD file", "Restore a saved session", "LOAD work.json"),
+    file_entry("DIFF f1 f2", "Compare two saved sessions", "DIFF mine.json yours.json"),
+    file_entry("SOURCE f", "Run a script of commands", "SOURCE setup.rpn"),
+    entry("CHECKPOINT n", "Record the state under a name", "CHECKPOINT before"),
+    entry("VERIFY n", "Report what changed since a checkpoint", "VERIFY before"),
+    entry("cmd &", "Run a command in the background", "SOURCE long.rpn &"),
+    entry("JOBS", "List background jobs", "JOBS"),
+    entry("FG [n]", "Wait for a job and take its state", "FG 1"),
+    entry("KILL [n]", "Stop and discard a background job", "KILL 1"),
+    entry("BELL ON", "Ring when a job ends or VERIFY fails", "BELL ON / BELL OFF"),
+    entry("NOTIFY p", "Run program p when a job ends or VERIFY fails", "NOTIFY notify-send HP-16C"),
+];
+
+pub const TASKS: &[Task] = &[
+    Task {
+        question: "How do I extract a bitfield?",
+        steps: "Shift the field down, then mask it: A5 SR 4 MASKR 4 & → A (bits 4-7 of A5)",
+    },
+    Task {
+        question: "How do I set a bit?",
+        steps: "SB with the bit number in X: 10 ENTER 3 SB → 18 (sets bit 3)",
+    },
+    Task {
+        question: "How do I clear a bit?",
+        steps: "CB with the bit number in X: 1F ENTER 3 CB → 17 (clears bit 3)",
+    },
+    Task {
+        question: "How do I test a bit?",
+        steps: "B? with the bit number in X: 8 ENTER 3 B? sets carry because bit 3 is 1",
+    },
+    Task {
+        question: "How do I test for a power of two?",
+        steps: "AND with one less: 8 ENTER 8 ENTER 1 - & → 0 means a power of two",
+    },
+    Task {
+        question: "How do I convert a number to another base?",
+        steps: "Enter it in the current base, then switch: FF DEC → 255",
+    },
+    Task {
+        question: "How do I change one byte inside a packed word?",
+        steps: "Select it as a bit field: FIELD 15:8, then 12FF ENTER 1 + → 13FF",
+    },
+    Task {
+        question: "How do I shift a value that spans two registers?",
+        steps: "Shift the low word, then rotate the high word through carry: RCL 0 SL 1 STO 0 RCL 1 RLC STO 1",
+    },
+    Task {
+        question: "How do I multiply or divide by a power of two?",
+        steps: "Shift instead: 7 SL 2 → 1C (×4), 1C SR 2 → 7 (÷4)",
+    },
+    Task {
+        question: "How do I work with 8-bit or 16-bit values?",
+        steps: "Set the word size first: WS 8, then FF 1 + → 0 with overflow",
+    },
+    Task {
+        question: "How do I work with negative (signed) numbers?",
+        steps: "Switch to 2's complement: 2S, then DEC shows FF as -1 in 8-bit words",
+    },
+    Task {
+        question: "How do I multiply or divide beyond the word size?",
+        steps: "Use double words: DBL* leaves the high word in X, then divisor DBL/ or DBLR",
+    },
+    Task {
+        question: "How do I pick the larger of two values?",
+        steps: "Enter both and use MAX (or MIN); 2S or 1S first to compare them as signed",
+    },
+    Task {
+        question: "How do I read or set a BCD register, like an RTC's?",
+        steps: "BCD> turns packed BCD into a number, >BCD turns it back; BCD+ and BCD- count in BCD",
+    },
+    Task {
+        question: "How do I keep a value for later?",
+        steps: "Store and recall it: 42 STO 1 ... RCL 1; TAG 1 name lets you use RCL name",
+    },
+    Task {
+        question: "How do I get back an answer that scrolled off the stack?",
+        steps: "Turn on the results bank with RESULTS ON, then RESULTS lists the last six answers",
+    },
+    Task {
+        question: "How do I load a test vector?",
+        steps: "Enter it in one line: [DE AD BE EF] STO 4 fills R4-R7",
+    },
+    Task {
+        question: "How do I add up or combine a block of registers?",
+        steps: "SUMR 0-7 or XORR 0-7, or REDUCE op 0-7 for any two-operand command",
+    },
+    Task {
+        question: "How do I change every register in a range?",
+        steps: "MAPR 0-7 followed by ;-separated commands, e.g. MAPR 0-7 F; & masks each one",
+    },
+    Task {
+        question: "How do I keep a running total in a register?",
+        steps: "STO+ n adds X into register n, e.g. 5 STO+ 4; RADD total value adds one register into another, and RXOR works the same for checksums",
+    },
+    Task {
+        question: "How do I lay values out like target memory?",
+        steps: "Store them in scratch memory: 1234 ST16 0 BE, then LD8 0 → 12; SCRATCH shows the bytes",
+    },
+    Task {
+        question: "How do I look at a base64 or hex blob from a log?",
+        steps: "DECODE B64 0 text (or DECODE HEX 0 text), then SCRATCH or LD32 0 to read it; ENCODE B64 0 length turns it back",
+    },
+    Task {
+        question: "How is a character encoded in UTF-8?",
+        steps: "Type its code point in hex and UTF8: 20AC UTF8 → E2 82 AC; for bytes, DECODE HEX 0 bytes then UTF8 0",
+    },
+    Task {
+        question: "How do I check an IP or UDP header checksum?",
+        steps: "Store the header in scratch memory with [words] ST16 0 BE, then CKSUM IP 0 length; 0 means the checksum is right",
+    },
+    Task {
+        question: "How do I reproduce my hardware's LFSR sequence?",
+        steps: "RAND LFSR taps, seed with value RAND SEED, then RAND or LFSR n to step it",
+    },
+    Task {
+        question: "How do I use a mask or CRC polynomial without typing it?",
+        steps: "Push a named constant: CONST CRC32, or define one with DEFCONST name value",
+    },
+    Task {
+        question: "How do I save my work and continue later?",
+        steps: "SAVE work.json, then LOAD work.json in a later session",
+    },
+    Task {
+        question: "How do I see what changed between two saved sessions?",
+        steps: "DIFF before.json after.json",
+    },
+    Task {
+        question: "How do I undo or look at an earlier result?",
+        steps: "BACK n shows the state n steps back; RESUME returns to the present",
+    },
+    Task {
+        question: "How do I keep working while a long script runs?",
+        steps: "Start it in the background: SOURCE long.rpn &, then FG when you want the result",
+    },
+    Task {
+        question: "How do I hear when a background job is done?",
+        steps: "BELL ON rings the terminal bell; NOTIFY notify-send HP-16C also pops up a desktop message",
+    },
+    Task {
+        question: "How do I check that a script leaves the state as expected?",
+        steps: "CHECKPOINT name before, VERIFY name after: differences stop the script",
+    },
+    Task {
+        question: "How do I make a script remember a yes/no choice?",
+        steps: "SF 0 or CF 0 to record it, then IF F? 0 ... END to act on it; flags 0-3 are free",
+    },
+    Task {
+        question: "Can I type 10 + 5 instead of 10 ENTER 5 +?",
+        steps: "Yes, after AUTOENTER ON: chains run left to right, and + 5 on its own adds 5 to X",
+    },
+    Task {
+        question: "How can an instructor see what a student practised?",
+        steps: "Start hp16c --usage name.json: the commands and errors are written there on QUIT",
+    },
+    Task {
+        question: "How do I work with fractions?",
+        steps: "FLOAT 4 switches to decimal floating point with 4 digits; DEC (or any base) returns to integers",
+    },
+    Task {
+        question: "How do I see all the digits of a long binary number?",
+        steps: "The display shows 8 binary digits; a . marks hidden digits, < shows higher ones and > lower ones",
+    },
+    Task {
+        question: "How do I see leading zeros?",
+        steps: "SF 3 pads binary, octal and hex to the whole word size; CF 3 hides them again",
+    },
+    Task {
+        question: "Why does STO say a register does not exist?",
+        steps: "Registers share 203 bytes with programs: a smaller WS gives more of them (101 at 16 bits, 25 at 64)",
+    },
+    Task {
+        question: "How do I loop over a block of registers?",
+        steps: "Put the first register number in I with STO I, then STO (i) or RCL (i) use the register I points at",
+    },
+    Task {
+        question: "How do I keep separate settings for each project?",
+        steps: "Create a .rpn_rust folder in the project: its session, registers.txt labels and config.rpn load on start",
+    },
+    Task {
+        question: "How much memory is left?",
+        steps: "MEM shows free program steps and data registers as P-0 r-101; a larger word size leaves fewer registers",
+    },
+    Task {
+        question: "How do I store a program?",
+        steps: "P/R, then LBL A and the steps, RTN, and P/R again; 5 GSB A runs them on X, and PRGM lists them",
+    },
+    Task {
+        question: "How do I debug a program one step at a time?",
+        steps: "GTO A, then SST runs one step and shows its keys and the stack; BST backs up a line, and R/S runs on",
+    },
+    Task {
+        question: "How do I branch in a program?",
+        steps: "A test such as X>Y?, X=0? or B? skips the next step when false; follow it with GTO x to branch",
+    },
+    Task {
+        question: "Will my program run on a real HP-16C?",
+        steps: "CHECK lists steps that use emulator extensions, such as WS 128 or MIN; STRICT ON refuses them as you type",
+    },
+    Task {
+        question: "How do I write a program in my editor?",
+        steps: "Write steps separated by ; or new lines, such as LBL A; RCL 1; x<>y; RTN with # comments, then IMPORT file",
+    },
+    Task {
+        question: "Can I write a program with variables and loops?",
+        steps: "With the compiler feature, write input n; while n > 0 { n = n - 1 } and the like in a file, then COMPILE file",
+    },
+    Task {
+        question: "How do I understand an old program listing?",
+        steps: "IMPORT it, then DECOMPILE (compiler feature) shows it as pseudocode with its loops and ifs",
+    },
+    Task {
+        question: "How do I publish a program listing?",
+        steps: "ANNOTATE n text adds a comment to line n; EXPORT MD file writes a table of lines, keys, steps and comments",
+    },
+    Task {
+        question: "How do I make my program smaller?",
+        steps: "OPTIMIZE removes steps that cannot run or change nothing and lists each one with the bytes saved",
+    },
+    Task {
+        question: "How do I put my program on a real HP-16C?",
+        steps: "CHECK for steps it lacks, then EXPORT KEYS file writes each key to press, with f and g, line by line",
+    },
+    Task {
+        question: "How do I watch registers and steps while debugging?",
+        steps: "PANES ON shows the stack, registers, program around the current step and tape; PANES NEXT moves the focus",
+    },
+    Task {
+        question: "How do I get the calculator's power-on state?",
+        steps: "PRESET resets all memory as the HP-16C does: word size 16, HEX, 2's complement, flags clear, no program",
+    },
+    Task {
+        question: "How do I run the same steps again?",
+        steps: "Put the commands in a file, one per line, and run SOURCE file",
+    },
+];
+
+const fn entry(command: &'static str, description: &'static str, example: &'static str) -> HelpEntry {
+    HelpEntry {
+        command,
+        description,
+        example,
+        argument: Argument::None,
+    }
+}
+
+const fn file_entry(command: &'static str, description: &'static str, example: &'static str) -> HelpEntry {
+    HelpEntry {
+        argument: Argument::File,
+        ..entry(command, description, example)
+    }
+}
+
+/// True if `line` is a command taking file paths, typed up to an argument,
+/// so completion should offer files rather than commands.
+pub fn completes_file(line: &str) -> bool {
+    let line = line.trim_start().to_uppercase();
+    COMMANDS
+        .iter()
+        .filter(|entry| entry.argument == Argument::File)
+        .any(|entry| line.starts_with(&format!("{} ", entry.keyword())))
+}
+
+/// True if every word of `query` occurs in one of `fields`, ignoring case.
+fn matches(query: &str, fields: &[&str]) -> bool {
+    let text = fields.join(" ").to_lowercase();
+    query.split_whitespace().all(|word| text.contains(&word.to_lowercase()))
+}
+
+/// Commands whose name, description or example mention every word of `query`.
+pub fn search(query: &str) -> Vec<&'static HelpEntry> {
+    COMMANDS
+        .iter()
+        .filter(|entry| matches(query, &[entry.command, entry.description, entry.example]))
+        .collect()
+}
+
+/// Tasks whose question or steps mention every word of `query`.
+pub fn search_tasks(query: &str) -> Vec<&'static Task> {
+    TASKS
+        .iter()
+        .filter(|task| matches(query, &[task.question, task.steps]))
+        .collect()
+}
+
+/// Format commands as rows of the help screen's tables.
+pub fn render_entries(entries: &[&HelpEntry]) -> Vec<String> {
+    let mut lines = vec![
+        "  Command         Description                      Example".to_string(),
+        "  ──────────────  ───────────────────────────────  ───────────────────────".to_string(),
+    ];
+    for entry in entries {
+        lines.push(format!("  {:<14}  {:<31}  {}", entry.command, entry.description, entry.example));
+    }
+    lines
+}
+
+pub fn render_tasks(tasks: &[&Task]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for task in tasks {
+        lines.push(format!("  {}", task.question));
+        lines.push(format!("      {}", task.steps));
+    }
+    lines
+}
+
+/// Search results for HELP ?query, commands first and then tasks.
+pub fn search_results(query: &str) -> Vec<String> {
+    let entries = search(query);
+    let tasks = search_tasks(query);
+    if entries.is_empty() && tasks.is_empty() {
+        return vec![format!("No help found for '{}'", query.trim())];
+    }
+
+    let mut lines = Vec::new();
+    if !entries.is_empty() {
+        lines.extend(render_entries(&entries));
+    }
+    if !tasks.is_empty() {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push("Tasks:".to_string());
+        lines.extend(render_tasks(&tasks));
+    }
+    lines
+}
+
+/// The whole task index for HELP TASKS.
+pub fn task_index() -> Vec<String> {
+    let mut lines = vec!["How do I ...?".to_string(), String::new()];
+    lines.extend(render_tasks(&TASKS.iter().collect::<Vec<_>>()));
+    lines
+}
@@ -0,0 +1,84 @@
+use crate::snapshot::Snapshot;
+use std::collections::VecDeque;
+
+/// Journal of states after each command, navigable without changing the
+/// live calculator.
+#[derive(Debug, Clone)]
+pub struct History {
+    states: VecDeque<Snapshot>,
+    limit: usize,
+    // Index into `states` while viewing history; None at the live head
+    cursor: Option<usize>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        History {
+            states: VecDeque::new(),
+            limit: limit.max(1),
+            cursor: None,
+        }
+    }
+
+    /// Append the latest live state, dropping the oldest beyond the limit.
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if self.states.len() == self.limit {
+            self.states.pop_front();
+        }
+        self.states.push_back(snapshot);
+        self.cursor = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Step `n` states back from the current view (or the live head).
+    pub fn back(&mut self, n: usize) -> Option<&Snapshot> {
+        let head = self.states.len().checked_sub(1)?;
+        let index = self.cursor.unwrap_or(head).saturating_sub(n);
+        self.cursor = (index < head).then_some(index);
+        self.viewing()
+    }
+
+    /// Step `n` states forward; reaching the live head ends viewing.
+    pub fn forward(&mut self, n: usize) -> Option<&Snapshot> {
+        let head = self.states.len().checked_sub(1)?;
+        if let Some(index) = self.cursor {
+            let index = (index + n).min(head);
+            self.cursor = (index < head).then_some(index);
+        }
+        self.viewing()
+    }
+
+    pub fn resume(&mut self) {
+        self.cursor = None;
+    }
+
+    /// The state being viewed, or None when at the live head.
+    pub fn viewing(&self) -> Option<&Snapshot> {
+        self.cursor.map(|index| &self.states[index])
+    }
+
+    /// Carry and overflow in the last `count` states up to the one being
+    /// viewed (or the live head), oldest first.
+    pub fn flags(&self, count: usize) -> Vec<(bool, bool)> {
+        let end = self.cursor.map_or(self.states.len(), |index| index + 1);
+        self.states
+            .range(end.saturating_sub(count)..end)
+            .map(|state| (state.carry, state.overflow))
+            .collect()
+    }
+
+    /// How many steps behind the live head the current view is.
+    pub fn steps_back(&self) -> usize {
+        match self.cursor {
+            Some(index) => self.states.len() - 1 - index,
+            None => 0,
+        }
+    }
+}
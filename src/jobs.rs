@@ -0,0 +1,151 @@
+use crate::cancel::CancelToken;
+use crate::command::{Dispatcher, Outcome};
+use crate::cpu::Hp16cCpu;
+use crate::notify::Notifier;
+use crate::snapshot::Snapshot;
+use crate::storage::Storage;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// What a finished background job left behind: the state of its copy of
+/// the calculator and the command's result.
+#[derive(Debug)]
+pub struct JobResult {
+    pub cpu: Hp16cCpu,
+    pub outcome: Result<Outcome, String>,
+    // The calculator the job was started from, as it was then
+    started: Snapshot,
+}
+
+impl JobResult {
+    /// Take the job's work into `cpu`, the calculator it was started from.
+    /// If `cpu` has not changed since, it takes over the job's whole state
+    /// and this returns true. Otherwise what was done meanwhile is kept and
+    /// only the job's X is entered, which needs the same word size and
+    /// FLOAT mode as the job finished in.
+    pub fn merge_into(&self, cpu: &mut Hp16cCpu) -> Result<bool, String> {
+        if Snapshot::capture(cpu) == self.started {
+            *cpu = self.cpu.clone();
+            return Ok(true);
+        }
+        if cpu.word_size != self.cpu.word_size || cpu.is_float() != self.cpu.is_float() {
+            return Err("The calculator changed mode while the job ran; its result was not taken".to_string());
+        }
+        cpu.enter_value(self.cpu.x);
+        Ok(false)
+    }
+}
+
+/// A command running on its own thread.
+#[derive(Debug)]
+struct Job {
+    id: usize,
+    command: String,
+    cancel: CancelToken,
+    handle: JoinHandle<JobResult>,
+}
+
+/// Commands run in the background on a copy of the calculator, so the
+/// REPL stays usable while a long script works.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    next_id: usize,
+    jobs: Vec<Job>,
+    // Told when a job finishes, shared with the running jobs
+    notifier: Arc<Mutex<Notifier>>,
+}
+
+/// One line of the JOBS listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub id: usize,
+    pub command: String,
+    pub finished: bool,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How to announce finished jobs, including those already running.
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        *self.notifier.lock().unwrap() = notifier;
+    }
+
+    /// Start `line` on a copy of `cpu`, through a copy of `dispatcher` so
+    /// its limits, policy, plugins and observers apply. Returns the job
+    /// number.
+    pub fn spawn<S>(&mut self, dispatcher: &Dispatcher, cpu: &Hp16cCpu, mut storage: S, line: &str) -> usize
+    where
+        S: Storage + Send + 'static,
+    {
+        self.next_id += 1;
+        let mut dispatcher = dispatcher.fork();
+        let cancel = dispatcher.cancel_token();
+        let started = Snapshot::capture(cpu);
+        let mut cpu = cpu.clone();
+        let command = line.to_string();
+        let notifier = Arc::clone(&self.notifier);
+        let id = self.next_id;
+        let handle = thread::spawn(move || {
+            // The token is fresh from `fork`, and a KILL may already be on it
+            let outcome = dispatcher.execute_uncancelled(&mut cpu, &mut storage, &command);
+            let state = if outcome.is_ok() { "done" } else { "failed" };
+            let notifier = notifier.lock().unwrap().clone();
+            // A notifier that cannot start has nobody to tell from here
+            let _ = notifier.notify(&format!("[{}] {}: {}", id, state, command));
+            JobResult { cpu, outcome, started }
+        });
+        self.jobs.push(Job {
+            id: self.next_id,
+            command: line.to_string(),
+            cancel,
+            handle,
+        });
+        self.next_id
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.jobs
+            .iter()
+            .map(|job| JobStatus {
+                id: job.id,
+                command: job.command.clone(),
+                finished: job.handle.is_finished(),
+            })
+            .collect()
+    }
+
+    /// The most recently started job still being tracked.
+    pub fn latest(&self) -> Option<usize> {
+        self.jobs.last().map(|job| job.id)
+    }
+
+    pub fn is_finished(&self, id: usize) -> Option<bool> {
+        self.jobs.iter().find(|job| job.id == id).map(|job| job.handle.is_finished())
+    }
+
+    /// Wait for a job to finish and stop tracking it.
+    pub fn wait(&mut self, id: usize) -> Result<JobResult, String> {
+        let index = self
+            .jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| format!("No such job: {}", id))?;
+        let job = self.jobs.remove(index);
+        job.handle.join().map_err(|_| format!("Job {} crashed", id))
+    }
+
+    /// Ask a job to stop. It finishes at its next cancellation check and
+    /// can then be collected with [`Jobs::wait`].
+    pub fn kill(&mut self, id: usize) -> bool {
+        match self.jobs.iter().find(|job| job.id == id) {
+            Some(job) => {
+                job.cancel.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+
+/// A physical key that can be bound in a keymap file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    F(u8),
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Insert,
+    Delete,
+}
+
+/// A key together with its modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyChord {
+    pub key: Key,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+/// Bindings from keyboard keys to calculator commands.
+///
+/// The file format is one `KEY = COMMAND` binding per line, for example
+/// `F1 = HEX` or `Ctrl-E = ENTER`. Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: Vec<(KeyChord, String)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_from_file(filename: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut keymap = Keymap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            // Skip comments and empty lines
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, command) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected KEY = COMMAND", number + 1))?;
+            let chord = parse_chord(key.trim())
+                .ok_or_else(|| format!("line {}: unknown key '{}'", number + 1, key.trim()))?;
+            let command = command.trim();
+            if command.is_empty() {
+                return Err(format!("line {}: missing command", number + 1));
+            }
+            keymap.bind(chord, command);
+        }
+
+        Ok(keymap)
+    }
+
+    /// Bind a key, replacing any earlier binding for it.
+    pub fn bind(&mut self, chord: KeyChord, command: &str) {
+        self.bindings.retain(|(existing, _)| *existing != chord);
+        self.bindings.push((chord, command.to_string()));
+    }
+
+    pub fn command_for(&self, chord: KeyChord) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|(existing, _)| *existing == chord)
+            .map(|(_, command)| command.as_str())
+    }
+
+    pub fn bindings(&self) -> &[(KeyChord, String)] {
+        &self.bindings
+    }
+}
+
+fn parse_chord(text: &str) -> Option<KeyChord> {
+    let mut chord = KeyChord {
+        key: Key::Char(' '),
+        ctrl: false,
+        alt: false,
+    };
+    let mut rest = text;
+    loop {
+        let upper = rest.to_uppercase();
+        if upper.starts_with("CTRL-") || upper.starts_with("CTRL+") {
+            chord.ctrl = true;
+            rest = &rest[5..];
+        } else if upper.starts_with("ALT-") || upper.starts_with("ALT+") {
+            chord.alt = true;
+            rest = &rest[4..];
+        } else {
+            break;
+        }
+    }
+
+    let upper = rest.to_uppercase();
+    chord.key = match upper.as_str() {
+        "PAGEUP" | "PGUP" => Key::PageUp,
+        "PAGEDOWN" | "PGDN" => Key::PageDown,
+        "HOME" => Key::Home,
+        "END" => Key::End,
+        "INSERT" | "INS" => Key::Insert,
+        "DELETE" | "DEL" => Key::Delete,
+        _ => {
+            if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+                if !(1..=12).contains(&n) {
+                    return None;
+                }
+                Key::F(n)
+            } else {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Key::Char(c.to_ascii_lowercase()),
+                    _ => return None,
+                }
+            }
+        }
+    };
+
+    // Plain printable characters are needed for typing numbers and commands
+    if let Key::Char(_) = chord.key {
+        if !chord.ctrl && !chord.alt {
+            return None;
+        }
+    }
+
+    Some(chord)
+}
@@ -0,0 +1,192 @@
+use crate::program::keyed_steps;
+use std::time::{Duration, Instant};
+
+// Key codes of the keys with a meaning of their own here
+const BACKSPACE: u8 = 35;
+const SHIFTS: [&str; 2] = ["42", "43"];
+
+/// Keys that act on the calculator rather than being program steps.
+const KEYBOARD_KEYS: &[(&str, &str)] = &[("31", "R/S"), ("32", "SST"), ("43 31", "P/R"), ("43 32", "BST")];
+
+/// What happens to a key pressed while another one is still held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollover {
+    /// It is ignored, as on a keyboard scanned one key at a time
+    Lockout,
+    /// It counts, so fast presses that overlap all register in order
+    Queue,
+}
+
+/// How a [`Keypad`] treats fast or hesitant input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyConfig {
+    pub rollover: Rollover,
+    /// How long a prefix such as f, g or STO waits for the rest of its
+    /// instruction before it is dropped. None waits for ever, as the
+    /// HP-16C does.
+    pub prefix_timeout: Option<Duration>,
+    /// A second press of the same key sooner than this is taken as the
+    /// first bouncing and ignored.
+    pub debounce: Duration,
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        KeyConfig {
+            rollover: Rollover::Lockout,
+            prefix_timeout: None,
+            debounce: Duration::ZERO,
+        }
+    }
+}
+
+/// Turns HP-16C key presses into command lines, for front-ends driven by a
+/// touchscreen, pads or anything else with the calculator's keys. Keys are
+/// given by key code, the row then the column (`11` for A, `43` for g,
+/// `36` for ENTER), with the digit keys as the digit itself.
+///
+/// Prefix keys collect until their instruction is complete, so f then A
+/// gives `SL 1` and STO . 3 gives `STO .3`. Digits collect into a number,
+/// which comes out ahead of the next instruction: 1 2 + gives `12` then
+/// `+`. Backspace cancels a prefix, drops the last digit typed, or clears
+/// X.
+#[derive(Debug, Clone)]
+pub struct Keypad {
+    pub config: KeyConfig,
+    // Key codes to steps, from the program's key code table
+    sequences: Vec<(String, String)>,
+    // The keys of an unfinished instruction
+    pending: Vec<String>,
+    // Digits of a number being typed
+    entry: String,
+    held: Vec<u8>,
+    last_press: Option<(u8, Instant)>,
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self::with_config(KeyConfig::default())
+    }
+
+    pub fn with_config(config: KeyConfig) -> Self {
+        let mut sequences = keyed_steps();
+        sequences.extend(KEYBOARD_KEYS.iter().map(|(keys, command)| (keys.to_string(), command.to_string())));
+        Keypad {
+            config,
+            sequences,
+            pending: Vec::new(),
+            entry: String::new(),
+            held: Vec::new(),
+            last_press: None,
+        }
+    }
+
+    /// Press `key` at time `at`. Returns the command lines it completes,
+    /// often none; an impossible sequence such as STO then + is an error,
+    /// and starts over.
+    pub fn press(&mut self, key: u8, at: Instant) -> Result<Vec<String>, String> {
+        let token = token(key).ok_or_else(|| format!("No key {} on the HP-16C", key))?;
+        if let Some((last, when)) = self.last_press {
+            if last == key && at.saturating_duration_since(when) < self.config.debounce {
+                return Ok(Vec::new());
+            }
+        }
+        if self.config.rollover == Rollover::Lockout && self.held.iter().any(|held| *held != key) {
+            return Ok(Vec::new());
+        }
+        if !self.held.contains(&key) {
+            self.held.push(key);
+        }
+        let timed_out = match (self.last_press, self.config.prefix_timeout) {
+            (Some((_, when)), Some(timeout)) => at.saturating_duration_since(when) > timeout,
+            _ => false,
+        };
+        if timed_out {
+            self.pending.clear();
+        }
+        self.last_press = Some((key, at));
+
+        if key == BACKSPACE {
+            // Cancels a prefix, then takes back digits, then clears X
+            if self.pending.is_empty() && self.entry.pop().is_none() {
+                return Ok(vec!["CLX".to_string()]);
+            }
+            self.pending.clear();
+            return Ok(Vec::new());
+        }
+        // A second shift replaces the first
+        if SHIFTS.contains(&token.as_str()) && self.pending.len() == 1 && SHIFTS.contains(&self.pending[0].as_str()) {
+            self.pending.clear();
+        }
+        self.pending.push(token);
+        let keys = self.pending.join(" ");
+        if let Some((_, step)) = self.sequences.iter().find(|(sequence, _)| *sequence == keys) {
+            let step = step.clone();
+            self.pending.clear();
+            if step == keys && step.len() == 1 {
+                self.entry.push_str(&step);
+                return Ok(Vec::new());
+            }
+            let mut commands: Vec<String> = self.flush().into_iter().collect();
+            commands.push(step);
+            return Ok(commands);
+        }
+        let prefix = format!("{} ", keys);
+        if self.sequences.iter().any(|(sequence, _)| sequence.starts_with(&prefix)) {
+            return Ok(Vec::new());
+        }
+        self.pending.clear();
+        Err(format!("Invalid key sequence {}", keys))
+    }
+
+    /// Release `key`, letting other keys through again under
+    /// [`Rollover::Lockout`].
+    pub fn release(&mut self, key: u8) {
+        self.held.retain(|held| *held != key);
+    }
+
+    /// Press and release `key`, for front-ends that only see taps.
+    pub fn tap(&mut self, key: u8, at: Instant) -> Result<Vec<String>, String> {
+        let commands = self.press(key, at);
+        self.release(key);
+        commands
+    }
+
+    /// The number being typed, taken so it can be entered; for the end of
+    /// input.
+    pub fn flush(&mut self) -> Option<String> {
+        (!self.entry.is_empty()).then(|| std::mem::take(&mut self.entry))
+    }
+
+    /// The keys of the unfinished instruction, such as `44 48`, for
+    /// showing the prefix that is waiting.
+    pub fn pending(&self) -> String {
+        self.pending.join(" ")
+    }
+
+    /// The digits of the number being typed.
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+}
+
+/// Whether `key` is the code of a key a [`Keypad`] takes. ON is not one.
+pub fn is_key_code(key: u8) -> bool {
+    token(key).is_some()
+}
+
+// A key code as it appears in a step's key codes: A-F for the top-left
+// keys, or the code itself. None for codes of no key.
+fn token(key: u8) -> Option<String> {
+    match key {
+        0..=10 | 20..=26 | 30..=36 | 40 | 42..=45 | 48 | 49 => Some(key.to_string()),
+        11..=16 => Some(format!("{:X}", key - 1)),
+        _ => None,
+    }
+}
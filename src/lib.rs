@@ -1,10 +1,72 @@
+// The deprecated fields of the compat feature are still used inside the crate
+#![cfg_attr(feature = "compat", allow(deprecated))]
+
+#[cfg(feature = "rom")]
 pub mod rom;
 pub mod cpu;
+pub mod autosave;
+pub mod cancel;
+pub mod checksum;
+pub mod command;
+pub mod constants;
+#[cfg(feature = "compiler")]
+pub mod compiler;
+#[cfg(feature = "controller")]
+pub mod controller;
+#[cfg(feature = "compiler")]
+pub mod decompiler;
+pub mod demo;
+pub mod display;
+#[cfg(feature = "faceplate")]
+pub mod faceplate;
+pub mod help;
+pub mod history;
+pub mod jobs;
+pub mod keymap;
+pub mod keypad;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod notify;
+pub mod optimizer;
+pub mod pager;
+pub mod panes;
+pub mod plugin;
+pub mod printer;
+pub mod program;
+pub mod project;
+pub mod random;
+pub mod scratch;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod shorthand;
+pub mod snapshot;
+pub mod storage;
+pub mod trail;
+pub mod usage;
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cpu::Hp16cCpu;
+    use crate::cpu::WordSize;
+    use autosave::Autosave;
+    #[cfg(feature = "scripting")]
+    use cancel::CancelToken;
+    #[cfg(feature = "scripting")]
+    use command::Context;
+    use command::{Dispatcher, Limits, Outcome};
+    use demo::DemoStep;
+    use history::History;
+    #[cfg(feature = "scripting")]
+    use jobs::Jobs;
+    #[cfg(feature = "scripting")]
+    use script::{Script, SearchPath};
+    use cpu::{Base, ComplementMode, Hp16cCpu, StackRegister, WordSizePolicy};
+    use keymap::{Key, KeyChord, Keymap};
+    use pager::Pager;
+    use panes::Pane;
+    use snapshot::Snapshot;
+    use storage::{FileStorage, MemoryStorage, Storage};
+    use trail::PaperTrail;
 
     #[test]
     fn test_rpn_stack_push_pop() {
@@ -124,12 +186,3428 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "rom")]
     fn test_rom_loading() {
-        let mut rom = rom::Rom::new();
+        let rom = rom::Rom::new();
         
         // Test with a mock ROM file (this would normally load from 16c.obj)
         // For now, just test the basic functionality
         assert_eq!(rom.size(), 0);
         assert_eq!(rom.read(0x1000), 0); // Should return 0 for uninitialized memory
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut calc = Hp16cCpu::new();
+        calc.set_word_size(32);
+        calc.set_base(Base::Binary);
+        calc.push(0xCAFE);
+        calc.push(0xBEEF);
+        calc.store(3);
+        calc.carry = true;
+
+        let mut storage = MemoryStorage::new();
+        storage.save_snapshot("session", &Snapshot::capture(&calc)).unwrap();
+
+        let mut restored = Hp16cCpu::new();
+        storage.load_snapshot("session").unwrap().unwrap().restore(&mut restored);
+        assert_eq!(Snapshot::capture(&restored), Snapshot::capture(&calc));
+        assert_eq!(restored.memory[3], 0xBEEF);
+        assert!(storage.load_snapshot("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let dir = std::env::temp_dir().join(format!("hp16c_storage_{}", std::process::id()));
+        let mut storage = FileStorage::new(&dir);
+
+        storage.write("state.json", "{}").unwrap();
+        assert_eq!(storage.read("state.json").unwrap().as_deref(), Some("{}"));
+        storage.remove("state.json").unwrap();
+        assert!(!storage.exists("state.json").unwrap());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_autosave_recovery() {
+        let mut calc = Hp16cCpu::new();
+        let mut autosave = Autosave::new(MemoryStorage::new(), 2);
+        assert!(autosave.start().unwrap().is_none());
+
+        calc.push(7);
+        autosave.record(&calc).unwrap();
+        calc.push(9);
+        autosave.record(&calc).unwrap();
+
+        // Simulate a crash: the next session starts without finish()
+        let mut next = Autosave::new(autosave.into_storage(), 2);
+        let recovered = next.start().unwrap().expect("unclean exit should be detected");
+        assert_eq!(recovered.x, 9);
+        assert_eq!(recovered.y, 7);
+
+        next.finish().unwrap();
+        let mut after_clean = Autosave::new(next.into_storage(), 2);
+        assert!(after_clean.start().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut calc = Hp16cCpu::new();
+        calc.push(0x10);
+        let before = Snapshot::capture(&calc);
+
+        calc.push(0x20);
+        calc.store(2);
+        calc.carry = true;
+        let after = Snapshot::capture(&calc);
+
+        let fields: Vec<_> = before.diff(&after).into_iter().map(|d| d.field).collect();
+        assert_eq!(fields, ["Carry", "Y", "X", "R2"]);
+
+        let x = before.diff(&after).into_iter().find(|d| d.field == "X").unwrap();
+        assert_eq!((x.left.as_str(), x.right.as_str()), ("10", "20"));
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_printer_tape() {
+        let mut calc = Hp16cCpu::new();
+        calc.push(0xFF);
+        calc.print_x();
+        calc.set_base(Base::Decimal);
+        calc.print_x();
+
+        let tape = calc.printer.tape();
+        assert_eq!(tape.len(), 2);
+        assert!(tape[0].ends_with("FF h"));
+        assert!(tape[1].ends_with("255 d"));
+
+        assert_eq!(calc.printer.contents().lines().count(), 2);
+        calc.printer.clear();
+        assert!(calc.printer.tape().is_empty());
+    }
+
+    #[test]
+    fn test_paper_trail() {
+        let mut trail = PaperTrail::new();
+        trail.record("10", "A");
+        trail.record("ENTER", "A");
+        trail.record("5", "5");
+        trail.record("+", "F");
+
+        assert_eq!(trail.entries().len(), 4);
+        assert_eq!(trail.recent(2)[0].input, "5");
+        assert_eq!(trail.recent(10).len(), 4);
+        assert_eq!(PaperTrail::render(trail.recent(1)), ["+  = F"]);
+        assert!(trail.contents().starts_with("10     = A\n"));
+    }
+
+    #[test]
+    fn test_command_execute() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+
+        for line in ["10", "enter", "5", "+"] {
+            assert_eq!(command::execute(&mut calc, &mut storage, line), Ok(Outcome::Done));
+        }
+        assert_eq!(calc.x, 0x15);
+
+        assert!(command::execute(&mut calc, &mut storage, "STO X").is_err());
+        assert!(command::execute(&mut calc, &mut storage, "XYZZY").is_err());
+        assert_eq!(command::execute(&mut calc, &mut storage, "quit"), Ok(Outcome::Quit));
+    }
+
+    #[test]
+    fn test_keymap_parsing() {
+        let keymap = Keymap::parse("# bindings\nF1 = HEX\nCtrl-E = ENTER\nalt+s = SWAP\n").unwrap();
+        assert_eq!(keymap.bindings().len(), 3);
+
+        let f1 = KeyChord { key: Key::F(1), ctrl: false, alt: false };
+        let ctrl_e = KeyChord { key: Key::Char('e'), ctrl: true, alt: false };
+        let alt_s = KeyChord { key: Key::Char('s'), ctrl: false, alt: true };
+        assert_eq!(keymap.command_for(f1), Some("HEX"));
+        assert_eq!(keymap.command_for(ctrl_e), Some("ENTER"));
+        assert_eq!(keymap.command_for(alt_s), Some("SWAP"));
+
+        assert!(Keymap::parse("F13 = HEX").is_err());
+        assert!(Keymap::parse("A = HEX").is_err());
+        assert!(Keymap::parse("F2 HEX").unwrap_err().starts_with("line 1"));
+    }
+
+    #[test]
+    fn test_demo_script_runs() {
+        let steps = demo::steps(demo::SCRIPT);
+        assert!(matches!(steps[0], DemoStep::Say(_)));
+
+        // Every command in the bundled script must be accepted
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for step in steps {
+            if let DemoStep::Key(line) = step {
+                assert_eq!(command::execute(&mut calc, &mut storage, line), Ok(Outcome::Done), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_headless_frame_capture() {
+        let calc = display::run_headless(&["DEC", "10", "ENTER", "5", "+"]).unwrap();
+        let frame = display::render_frame(&calc);
+        assert!(frame.starts_with("┌"));
+        assert!(frame.contains("│ X: 15 "));
+        assert!(frame.contains("Base: 10"));
+        assert!(frame.contains("UNSGN"));
+        assert_eq!(frame.lines().count(), 11);
+
+        let svg = display::render_svg(&calc);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("X: 15"));
+
+        assert!(display::run_headless(&["NOPE"]).is_err());
+
+        let calc = display::run_headless(&["WS 8", "2S", "FF", "DEC"]).unwrap();
+        let frame = display::render_frame(&calc);
+        assert!(frame.contains("2'S"));
+        assert!(frame.contains("│ X: -1 "));
+    }
+
+    #[cfg(feature = "faceplate")]
+    #[test]
+    fn test_faceplate_render() {
+        let mut calc = Hp16cCpu::new();
+        calc.push(0xFF);
+        calc.carry = true;
+
+        let svg = faceplate::render_faceplate(&calc, Some("hex"));
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">FF h</text>"));
+        assert!(svg.contains(">C</text>"));
+        assert_eq!(svg.matches("#d9a441").count(), 1);
+        assert_eq!(faceplate::render_faceplate(&calc, None).matches("#d9a441").count(), 0);
+    }
+
+    #[test]
+    fn test_dispatcher_limits() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let limits = Limits {
+            max_line_len: 16,
+            max_commands: 3,
+            window: std::time::Duration::from_secs(1),
+        };
+        let mut dispatcher = Dispatcher::with_limits(limits);
+        let start = std::time::Instant::now();
+
+        assert_eq!(command::sanitize("  STO\t\u{7}3 \n"), "STO 3");
+        assert!(dispatcher.execute_at(&mut calc, &mut storage, &"1".repeat(17), start).is_err());
+
+        for _ in 0..3 {
+            assert!(dispatcher.execute_at(&mut calc, &mut storage, "1", start).is_ok());
+        }
+        assert!(dispatcher.execute_at(&mut calc, &mut storage, "1", start).is_err());
+
+        let later = start + std::time::Duration::from_secs(1);
+        assert!(dispatcher.execute_at(&mut calc, &mut storage, "1", later).is_ok());
+    }
+
+    #[test]
+    fn test_dispatcher_restricted() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        storage.write("setup.rpn", "5\n").unwrap();
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.set_restricted(true);
+
+        for line in ["SAVE a.json", "load a.json", "DIFF a b", "SOURCE setup.rpn", "TAPE  SAVE t", "MAPR 0-1 1; SOURCE setup.rpn", "MAPR 0-1 MAPR 2-3 SAVE x"] {
+            assert!(dispatcher.execute(&mut calc, &mut storage, line).unwrap_err().contains("restricted mode"), "{}", line);
+        }
+        assert_eq!(calc.x, 0);
+        assert!(dispatcher.check_policy("TRAIL SAVE t.txt").is_err());
+        assert!(dispatcher.check_policy("SOURCE setup.rpn &").is_err());
+        // Other commands, including ones that merely start the same way, run
+        for line in ["7", "SAVEX", "MAPR 0-1 1; +"] {
+            assert!(dispatcher.check_policy(line).is_ok(), "{}", line);
+        }
+        dispatcher.execute(&mut calc, &mut storage, "7").unwrap();
+        assert_eq!(calc.x, 7);
+
+        dispatcher.set_restricted(false);
+        #[cfg(feature = "scripting")]
+        {
+            dispatcher.execute(&mut calc, &mut storage, "SOURCE setup.rpn").unwrap();
+            assert_eq!(calc.x, 5);
+        }
+    }
+
+    #[test]
+    fn test_dispatcher_observers() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut dispatcher = Dispatcher::new();
+
+        let observer = dispatcher.subscribe();
+        let watcher = std::thread::spawn(move || observer.iter().map(|s| s.x).collect::<Vec<_>>());
+
+        dispatcher.execute(&mut calc, &mut storage, "7").unwrap();
+        assert!(dispatcher.execute(&mut calc, &mut storage, "bogus").is_err());
+        dispatcher.execute(&mut calc, &mut storage, "ENTER").unwrap();
+        dispatcher.execute(&mut calc, &mut storage, "+").unwrap();
+        drop(dispatcher);
+
+        assert_eq!(watcher.join().unwrap(), [7, 7, 14]);
+
+        let mut dispatcher = Dispatcher::new();
+        drop(dispatcher.subscribe());
+        dispatcher.execute(&mut calc, &mut storage, "1").unwrap();
+        assert_eq!(dispatcher.observer_count(), 0);
+    }
+
+    #[test]
+    fn test_history_navigation() {
+        let mut calc = Hp16cCpu::new();
+        let mut history = History::new(3);
+        for value in 1..=4 {
+            calc.push(value);
+            history.record(Snapshot::capture(&calc));
+        }
+        assert_eq!(history.len(), 3);
+        assert!(history.viewing().is_none());
+
+        assert_eq!(history.back(1).unwrap().x, 3);
+        assert_eq!(history.back(5).unwrap().x, 2);
+        assert_eq!(history.steps_back(), 2);
+        assert_eq!(history.forward(1).unwrap().x, 3);
+        assert!(history.forward(1).is_none());
+
+        history.back(1);
+        history.resume();
+        assert!(history.viewing().is_none());
+        assert_eq!(history.steps_back(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_script_conditionals() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        storage.write("count.rpn", "
+            # count down from 5, adding into R0
+            5
+            STO 1
+            WHILE X!=0
+              RCL 0
+              RCL 1
+              +
+              STO 0
+              RCL 1
+              1
+              -
+              STO 1
+            END
+            # dividing by zero sets overflow
+            1
+            0
+            /
+            IF OVERFLOW
+              PRINT X
+            ELSE
+              1
+            END
+            REPEAT 3
+              RCL 0
+            END
+        ").unwrap();
+
+        assert_eq!(command::execute(&mut calc, &mut storage, "SOURCE count.rpn"), Ok(Outcome::Done));
+        assert_eq!(calc.memory[0], 15);
+        assert_eq!(calc.printer.tape().len(), 1);
+        assert_eq!((calc.x, calc.y, calc.z), (15, 15, 15));
+
+        assert_eq!(Script::parse("IF CARRY\n1\n").unwrap_err(), "line 1: block is missing END");
+        assert_eq!(Script::parse("ELSE\n").unwrap_err(), "line 1: ELSE without IF");
+        assert_eq!(Script::parse("END\n").unwrap_err(), "line 1: END without a block");
+        assert!(Script::parse("WHILE SOMETIMES\nEND").is_err());
+
+        storage.write("bad.rpn", "1\nNOPE\n").unwrap();
+        let error = command::execute(&mut calc, &mut storage, "SOURCE bad.rpn").unwrap_err();
+        assert!(error.starts_with("bad.rpn: line 2:"));
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_script_include() {
+        let mut storage = MemoryStorage::new();
+        storage.write("main.rpn", "1\nINCLUDE masks.rpn\n+").unwrap();
+        storage.write("lib/masks.rpn", "INCLUDE byte.rpn").unwrap();
+        storage.write("lib/byte.rpn", "FF").unwrap();
+        storage.write("loop.rpn", "INCLUDE loop.rpn").unwrap();
+
+        let search = SearchPath::new(vec!["lib".into()]);
+        let mut read = |key: &str| Ok(storage.read(key).unwrap());
+        let script = Script::load("main.rpn", &search, &mut read).unwrap();
+
+        let mut calc = Hp16cCpu::new();
+        let mut scratch = MemoryStorage::new();
+        script.run(&mut calc, &mut |cpu, line| command::execute(cpu, &mut scratch, line)).unwrap();
+        assert_eq!(calc.x, 0x100);
+
+        let error = Script::load("loop.rpn", &search, &mut read).unwrap_err();
+        assert!(error.contains("INCLUDE cycle: loop.rpn -> loop.rpn"), "{}", error);
+        assert!(Script::load("main.rpn", &SearchPath::default(), &mut read).is_err());
+        assert!(Script::parse("INCLUDE x.rpn").is_err());
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        calc.set_word_size(32);
+
+        command::execute(&mut calc, &mut storage, "const crc32").unwrap();
+        assert_eq!(calc.x, 0x04C1_1DB7);
+        assert!(command::execute(&mut calc, &mut storage, "CONST NOPE").is_err());
+
+        command::execute(&mut calc, &mut storage, "DEFCONST UART_BASE 4000C000").unwrap();
+        command::execute(&mut calc, &mut storage, "1234").unwrap();
+        command::execute(&mut calc, &mut storage, "DEFCONST seed").unwrap();
+        command::execute(&mut calc, &mut storage, "CONST UART_BASE").unwrap();
+        assert_eq!(calc.x, 0x4000_C000);
+        assert_eq!(calc.constants.lookup("SEED"), Some(0x1234));
+
+        assert!(command::execute(&mut calc, &mut storage, "DEFCONST CRC32 1").is_err());
+        assert!(command::execute(&mut calc, &mut storage, "DEFCONST 9LIVES 1").is_err());
+
+        let mut restored = Hp16cCpu::new();
+        Snapshot::capture(&calc).restore(&mut restored);
+        assert_eq!(restored.constants.lookup("UART_BASE"), Some(0x4000_C000));
+    }
+
+    #[test]
+    fn test_digit_validation() {
+        assert_eq!(command::invalid_digit("1019", Base::Octal), Some((3, '9')));
+        assert_eq!(command::invalid_digit("FF", Base::Decimal), Some((0, 'F')));
+        assert_eq!(command::invalid_digit("102", Base::Binary), Some((2, '2')));
+        assert_eq!(command::invalid_digit("FF", Base::Hex), None);
+        assert_eq!(command::invalid_digit("SWAP", Base::Decimal), None);
+
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        calc.set_base(Base::Octal);
+        assert_eq!(
+            command::execute(&mut calc, &mut storage, "178"),
+            Err("Invalid digit '8' in octal (valid digits: 0-7)".to_string())
+        );
+        calc.set_base(Base::Hex);
+        let error = command::execute(&mut calc, &mut storage, &"F".repeat(33)).unwrap_err();
+        assert_eq!(error, "Number too large for 128 bits");
+        assert!(command::execute(&mut calc, &mut storage, "FROB").unwrap_err().starts_with("Unknown command"));
+    }
+
+    #[test]
+    fn test_number_preview() {
+        assert_eq!(
+            command::number_preview("FF", Base::Hex, WordSize::try_from(16).unwrap()).as_deref(),
+            Some("= 255 dec / FF hex / 377 oct / 11111111 bin")
+        );
+        assert_eq!(command::number_preview("300", Base::Decimal, WordSize::try_from(8).unwrap()).as_deref(), Some("= 44 dec / 2C hex / 54 oct / 101100 bin"));
+        assert_eq!(command::number_preview("10", Base::Decimal, WordSize::try_from(32).unwrap()).as_deref(), Some("= 10 dec / A hex / 12 oct"));
+        assert_eq!(command::number_preview("19", Base::Octal, WordSize::try_from(16).unwrap()), None);
+        assert_eq!(command::number_preview("SWAP", Base::Hex, WordSize::try_from(16).unwrap()), None);
+    }
+
+    #[test]
+    fn test_pager() {
+        let lines: Vec<String> = (1..=5).map(|n| format!("line {}", n)).collect();
+        let mut pager = Pager::new(lines, 2);
+        assert_eq!(pager.screen(), ["line 1", "line 2"]);
+        assert_eq!(pager.status(), "-- lines 1-2 of 5 -- Enter: next page, b Enter: back, q Enter: quit");
+
+        assert!(pager.key(' '));
+        assert_eq!(pager.screen(), ["line 3", "line 4"]);
+        assert!(pager.key('b'));
+        assert_eq!(pager.screen(), ["line 1", "line 2"]);
+        assert!(pager.key('x'));
+        assert!(pager.key('\n'));
+        assert!(pager.key(' '));
+        assert_eq!(pager.screen(), ["line 5"]);
+        assert!(!pager.key(' '));
+
+        let mut pager = Pager::new(vec!["a".to_string(); 10], 3);
+        assert!(!pager.key('Q'));
+    }
+
+    #[test]
+    fn test_help_search() {
+        let found = help::search("mask");
+        assert!(found.iter().any(|entry| entry.command == "&"));
+        let found: Vec<_> = help::search("store REGISTER").iter().map(|entry| entry.command).collect();
+        assert_eq!(found, ["STO [n]", "STO I", "STO (i)"]);
+        assert!(help::search("no such thing").is_empty());
+
+        let tasks = help::search_tasks("BITFIELD");
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].question, "How do I extract a bitfield?");
+
+        let results = help::search_results("bitfield");
+        assert_eq!(results[0], "Tasks:");
+        assert_eq!(help::search_results("zzz"), ["No help found for 'zzz'"]);
+        assert_eq!(help::task_index().len(), 2 + help::TASKS.len() * 2);
+    }
+
+    #[test]
+    fn test_enter_stack_lift() {
+        let mut calc = Hp16cCpu::new();
+        calc.set_base(Base::Decimal);
+        calc.enter_value(10);
+        calc.enter();
+        calc.enter_value(5);
+        assert_eq!((calc.x, calc.y, calc.z), (5, 10, 0));
+        calc.add();
+        assert_eq!((calc.x, calc.y), (15, 0));
+
+        // Squaring and cubing with repeated ENTER
+        let mut calc = Hp16cCpu::new();
+        calc.enter_value(5);
+        calc.enter();
+        calc.enter();
+        calc.enter();
+        assert_eq!([calc.x, calc.y, calc.z, calc.t], [5, 5, 5, 5]);
+        calc.multiply();
+        assert_eq!([calc.x, calc.y, calc.z, calc.t], [25, 5, 5, 5]);
+        calc.multiply();
+        assert_eq!(calc.x, 125);
+
+        // RCL after ENTER replaces the copy in X
+        let mut calc = Hp16cCpu::new();
+        calc.memory[1] = 7;
+        calc.enter_value(3);
+        calc.enter();
+        calc.recall(1);
+        assert_eq!((calc.x, calc.y, calc.z), (7, 3, 0));
+
+        // An operation re-enables lift for the next number
+        let mut storage = MemoryStorage::new();
+        let mut calc = Hp16cCpu::new();
+        for line in ["2", "ENTER", "3", "*", "4"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        assert_eq!((calc.x, calc.y, calc.z), (4, 6, 0));
+
+        // CLX also disables lift: a mistyped number is replaced, not pushed
+        let mut calc = Hp16cCpu::new();
+        for line in ["5", "ENTER", "9", "CLX", "3", "+"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        assert_eq!((calc.x, calc.y), (8, 0));
+        let mut calc = Hp16cCpu::new();
+        for line in ["5", "CLX", "CLX", "4"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        assert_eq!((calc.x, calc.y), (4, 0));
+    }
+
+    #[test]
+    fn test_twos_complement() {
+        let mut calc = Hp16cCpu::new();
+        calc.set_word_size(8);
+        calc.set_base(Base::Decimal);
+        calc.set_complement_mode(ComplementMode::TwosComplement);
+
+        calc.enter_value(0xFF);
+        assert_eq!(calc.format_display(), "-1");
+        assert_eq!(calc.signed_value(calc.x), -1);
+        calc.set_base(Base::Hex);
+        assert_eq!(calc.format_display(), "FF");
+        calc.set_base(Base::Decimal);
+
+        // 127 + 1 leaves the signed range
+        calc.enter_value(0x7F);
+        calc.enter_value(1);
+        calc.add();
+        assert!(calc.overflow);
+        assert_eq!(calc.format_display(), "-128");
+
+        // -1 + -1 = -2 is fine
+        calc.enter_value(0xFF);
+        calc.enter_value(0xFF);
+        calc.add();
+        assert!(!calc.overflow);
+        assert_eq!(calc.x, 0xFE);
+
+        // -128 - 1 overflows
+        calc.enter_value(0x80);
+        calc.enter_value(1);
+        calc.subtract();
+        assert!(calc.overflow);
+
+        calc.enter_value(0xFE);
+        calc.enter_value(3);
+        calc.multiply();
+        assert!(!calc.overflow);
+        assert_eq!(calc.format_display(), "-6");
+        calc.enter_value(0x40);
+        calc.enter_value(2);
+        calc.multiply();
+        assert!(calc.overflow);
+
+        // Division truncates toward zero
+        calc.enter_value(0xF9);
+        calc.enter_value(2);
+        calc.divide();
+        assert_eq!(calc.format_display(), "-3");
+        calc.enter_value(0x80);
+        calc.enter_value(0xFF);
+        calc.divide();
+        assert!(calc.overflow);
+
+        // Comparisons are signed; unsigned mode compares bit patterns
+        assert!(calc.compare(0xFF, 1).is_lt());
+        calc.set_complement_mode(ComplementMode::Unsigned);
+        assert!(calc.compare(0xFF, 1).is_gt());
+        assert_eq!(calc.format_value(0xFF), "255");
+
+        let mut storage = MemoryStorage::new();
+        command::execute(&mut calc, &mut storage, "2S").unwrap();
+        assert_eq!(calc.complement_mode, ComplementMode::TwosComplement);
+        let json = Snapshot::capture(&calc).to_json().unwrap();
+        let mut restored = Hp16cCpu::new();
+        Snapshot::from_json(&json).unwrap().restore(&mut restored);
+        assert_eq!(restored.complement_mode, ComplementMode::TwosComplement);
+        command::execute(&mut calc, &mut storage, "unsgn").unwrap();
+        assert_eq!(calc.complement_mode, ComplementMode::Unsigned);
+    }
+
+    #[test]
+    fn test_ones_complement() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        calc.set_word_size(8);
+        calc.set_base(Base::Decimal);
+        command::execute(&mut calc, &mut storage, "1S").unwrap();
+        assert_eq!(calc.complement_mode, ComplementMode::OnesComplement);
+
+        assert_eq!(calc.format_value(0xFE), "-1");
+        assert_eq!(calc.format_value(0xFF), "-0");
+        assert_eq!(calc.format_value(0x80), "-127");
+        assert!(calc.compare(0xFF, 0).is_eq());
+
+        // 5 + -1 needs the end-around carry
+        calc.enter_value(5);
+        calc.enter_value(0xFE);
+        calc.add();
+        assert_eq!(calc.x, 4);
+        assert!(calc.carry);
+        assert!(!calc.overflow);
+
+        // 3 - 5 = -2 borrows
+        calc.enter_value(3);
+        calc.enter_value(5);
+        calc.subtract();
+        assert_eq!(calc.x, 0xFD);
+        assert!(calc.carry);
+
+        // x - x gives positive zero
+        calc.enter_value(9);
+        calc.enter_value(9);
+        calc.subtract();
+        assert_eq!(calc.x, 0);
+
+        calc.enter_value(0x7F);
+        calc.enter_value(1);
+        calc.add();
+        assert!(calc.overflow);
+
+        calc.enter_value(0xFD);
+        calc.enter_value(3);
+        calc.multiply();
+        assert_eq!(calc.format_display(), "-6");
+        assert_eq!(calc.x, 0xF9);
+
+        calc.enter_value(0xF8);
+        calc.enter_value(2);
+        calc.divide();
+        assert_eq!(calc.format_display(), "-3");
+        calc.enter_value(1);
+        calc.enter_value(0xFF);
+        calc.divide();
+        assert!(calc.overflow);
+    }
+
+    #[test]
+    fn test_dispatcher_timing() {
+        let mut dispatcher = Dispatcher::new();
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        assert_eq!(dispatcher.last_elapsed(), None);
+
+        dispatcher.execute(&mut calc, &mut storage, "5").unwrap();
+        let first = dispatcher.last_elapsed().unwrap();
+        assert!(first < std::time::Duration::from_secs(1));
+
+        // Failed commands are timed too
+        assert!(dispatcher.execute(&mut calc, &mut storage, "XYZZY").is_err());
+        assert!(dispatcher.last_elapsed().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_script_cancellation() {
+        let script = Script::parse("1\nWHILE X!=0\n1\nEND\n").unwrap();
+        let cancel = CancelToken::new();
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+
+        // Cancel from inside the run, as a Ctrl-C handler would
+        let mut count = 0;
+        let result = script.run_cancellable(&mut calc, &cancel, &mut |cpu, line| {
+            count += 1;
+            if count == 50 {
+                cancel.cancel();
+            }
+            command::execute(cpu, &mut storage, line)
+        });
+        assert_eq!(result, Err("line 2: cancelled after 50 commands".to_string()));
+
+        // The dispatcher resets its token for every command
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.cancel_token().cancel();
+        assert!(dispatcher.execute(&mut calc, &mut storage, "5").is_ok());
+        assert!(!dispatcher.cancel_token().is_cancelled());
+
+        storage.write("spin.rpn", "0\nWHILE X=0\nEND\n").unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let result = command::execute_with(&mut calc, &mut storage, "SOURCE spin.rpn", &cancel);
+        assert_eq!(result, Err("spin.rpn: line 1: cancelled after 0 commands".to_string()));
+
+        // Unless it is asked to keep a cancellation that came first
+        dispatcher.cancel_token().cancel();
+        let result = dispatcher.execute_uncancelled(&mut calc, &mut storage, "SOURCE spin.rpn");
+        assert_eq!(result, Err("spin.rpn: line 1: cancelled after 0 commands".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_background_jobs() {
+        let mut storage = MemoryStorage::new();
+        storage.write("count.rpn", "0\nREPEAT 100\n1\n+\nEND\n").unwrap();
+        storage.write("spin.rpn", "0\nWHILE X=0\nEND\n").unwrap();
+        let mut calc = Hp16cCpu::new();
+        calc.set_base(Base::Decimal);
+        let mut jobs = Jobs::new();
+
+        let dispatcher = Dispatcher::new();
+        let counter = jobs.spawn(&dispatcher, &calc, storage.clone(), "SOURCE count.rpn");
+        let spinner = jobs.spawn(&dispatcher, &calc, storage.clone(), "SOURCE spin.rpn");
+        assert_eq!((counter, spinner), (1, 2));
+        assert_eq!(jobs.latest(), Some(2));
+        assert_eq!(jobs.list().len(), 2);
+        assert_eq!(jobs.list()[0].command, "SOURCE count.rpn");
+
+        let result = jobs.wait(counter).unwrap();
+        assert_eq!(result.outcome, Ok(Outcome::Done));
+        assert_eq!(result.cpu.x, 100);
+        // The calculator the job was started from is untouched
+        assert_eq!(calc.x, 0);
+
+        assert!(jobs.kill(spinner));
+        let result = jobs.wait(spinner).unwrap();
+        assert!(result.outcome.unwrap_err().contains("cancelled"));
+
+        // A job killed straight away stops, however late its thread starts
+        let spinner = jobs.spawn(&dispatcher, &calc, storage.clone(), "SOURCE spin.rpn");
+        assert!(jobs.kill(spinner));
+        assert!(jobs.wait(spinner).unwrap().outcome.unwrap_err().contains("cancelled"));
+
+        assert!(jobs.list().is_empty());
+        assert!(!jobs.kill(7));
+        assert_eq!(jobs.wait(7).unwrap_err(), "No such job: 7");
+
+        // FG takes over the job's state only if nothing happened meanwhile
+        let job = jobs.spawn(&dispatcher, &calc, storage.clone(), "SOURCE count.rpn");
+        let result = jobs.wait(job).unwrap();
+        let mut changed = calc.clone();
+        changed.enter_value(5);
+        assert_eq!(result.merge_into(&mut changed), Ok(false));
+        assert_eq!((changed.x, changed.y), (100, 5));
+        assert_eq!(result.merge_into(&mut calc), Ok(true));
+        assert_eq!((calc.x, calc.y), (100, 0));
+        changed.set_float(2).unwrap();
+        assert!(result.merge_into(&mut changed).is_err());
+
+        // Jobs run under the dispatcher's policy and limits
+        let mut restricted = Dispatcher::with_limits(Limits::default());
+        restricted.set_restricted(true);
+        let job = jobs.spawn(&restricted, &calc, storage.clone(), "SOURCE count.rpn");
+        let result = jobs.wait(job).unwrap();
+        assert!(result.outcome.unwrap_err().contains("restricted mode"));
+        let long = "1 ".repeat(200);
+        let job = jobs.spawn(&restricted, &calc, storage.clone(), &long);
+        let result = jobs.wait(job).unwrap();
+        assert!(result.outcome.unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn test_change_sign() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["WS 8", "DEC", "5", "CHS"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        // Unsigned mode shows the 2's complement bit pattern
+        assert_eq!(calc.x, 0xFB);
+        assert_eq!(calc.format_display(), "251");
+
+        calc.set_complement_mode(ComplementMode::TwosComplement);
+        assert_eq!(calc.format_display(), "-5");
+        calc.chs();
+        assert_eq!(calc.format_display(), "5");
+        calc.enter_value(0);
+        calc.chs();
+        assert_eq!(calc.x, 0);
+
+        calc.set_complement_mode(ComplementMode::OnesComplement);
+        calc.enter_value(5);
+        calc.chs();
+        assert_eq!(calc.x, 0xFA);
+        assert_eq!(calc.format_display(), "-5");
+        calc.enter_value(0);
+        calc.chs();
+        assert_eq!(calc.format_display(), "-0");
+
+        // CHS re-enables stack lift after ENTER
+        calc.enter_value(3);
+        calc.enter();
+        calc.chs();
+        calc.enter_value(7);
+        assert_eq!(calc.y, 0xFC);
+    }
+
+    #[test]
+    fn test_checkpoint_verify() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["DEC", "10", "ENTER", "5", "CHECKPOINT start"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        assert_eq!(
+            command::execute(&mut calc, &mut storage, "VERIFY start"),
+            Ok(Outcome::Message(vec!["State matches checkpoint START".to_string()]))
+        );
+
+        command::execute(&mut calc, &mut storage, "+").unwrap();
+        let error = command::execute(&mut calc, &mut storage, "verify start").unwrap_err();
+        let lines: Vec<&str> = error.lines().collect();
+        assert_eq!(lines[0], "State differs from checkpoint START:");
+        assert!(lines[2].starts_with("Y") && lines[2].ends_with(" 10                    0"));
+        assert!(lines[3].starts_with("X") && lines[3].ends_with("  5                   15"));
+        assert_eq!(lines.len(), 4);
+
+        assert_eq!(
+            command::execute(&mut calc, &mut storage, "VERIFY nope"),
+            Err("No checkpoint: NOPE".to_string())
+        );
+        assert!(command::execute(&mut calc, &mut storage, "CHECKPOINT  ").is_err());
+
+        // A failed VERIFY stops a script at its line
+        #[cfg(feature = "scripting")]
+        {
+            storage.write("check.rpn", "CHECKPOINT a\n1\n+\nVERIFY a\nCLR\n").unwrap();
+            let error = command::execute(&mut calc, &mut storage, "SOURCE check.rpn").unwrap_err();
+            assert!(error.starts_with("check.rpn: line 4: State differs from checkpoint A:"));
+            assert_eq!(calc.x, 16);
+        }
+    }
+
+    #[test]
+    fn test_word_size_policy() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |calc: &mut Hp16cCpu, line: &str| command::execute(calc, &mut storage, line);
+
+        // Truncate is the default and stays silent
+        run(&mut calc, "1234").unwrap();
+        assert_eq!(run(&mut calc, "WS 8"), Ok(Outcome::Done));
+        assert_eq!(calc.x, 0x34);
+
+        let mut calc = Hp16cCpu::new();
+        run(&mut calc, "WSPOLICY warn").unwrap();
+        run(&mut calc, "1234").unwrap();
+        run(&mut calc, "12").unwrap();
+        assert_eq!(
+            run(&mut calc, "WS 8"),
+            Ok(Outcome::Message(vec!["Warning: set bits truncated in Y".to_string()]))
+        );
+
+        let mut calc = Hp16cCpu::new();
+        run(&mut calc, "WSPOLICY ERROR").unwrap();
+        run(&mut calc, "1234").unwrap();
+        assert_eq!(run(&mut calc, "WS 8"), Err("Word size 8 would lose set bits in X".to_string()));
+        assert_eq!((calc.word_size.bits(), calc.x), (16, 0x1234));
+        run(&mut calc, "WS 13").unwrap();
+        assert_eq!(calc.word_size.bits(), 13);
+
+        // Preserve brings back values that were not changed in between
+        let mut calc = Hp16cCpu::new();
+        calc.word_size_policy = WordSizePolicy::Preserve;
+        calc.enter_value(0x1234);
+        calc.enter_value(0x5678);
+        assert!(run(&mut calc, "WS 8").is_ok());
+        assert_eq!((calc.x, calc.y), (0x78, 0x34));
+        calc.x = 0x11;
+        calc.change_word_size(4).unwrap();
+        calc.change_word_size(16).unwrap();
+        assert_eq!((calc.x, calc.y), (0x11, 0x1234));
+        calc.change_word_size(8).unwrap();
+        calc.y = 0x99;
+        calc.change_word_size(16).unwrap();
+        assert_eq!((calc.x, calc.y), (0x11, 0x99));
+
+        assert!(run(&mut calc, "WSPOLICY SOMETIMES").is_err());
+        assert!(calc.change_word_size(0).is_err());
+        let json = Snapshot::capture(&calc).to_json().unwrap();
+        assert_eq!(Snapshot::from_json(&json).unwrap().word_size_policy, WordSizePolicy::Preserve);
+    }
+
+    #[test]
+    fn test_register_tags() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["FFFF", "TAG 3 crc_seed", "STO CRC_SEED", "CLR STK", "rcl crc_seed"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        assert_eq!(calc.memory[3], 0xFFFF);
+        assert_eq!(calc.x, 0xFFFF);
+        assert_eq!(calc.tags.get(&3).map(String::as_str), Some("crc_seed"));
+
+        let Ok(Outcome::Message(lines)) = command::execute(&mut calc, &mut storage, "MEMVIEW") else {
+            panic!("MEMVIEW lists the registers");
+        };
+        assert_eq!(lines.len(), 101);
+        assert!(lines[3].starts_with("R3 ") && lines[3].ends_with("FFFF  crc_seed"));
+        assert!(lines[4].ends_with(" 0"));
+
+        assert!(command::execute(&mut calc, &mut storage, "TAG 4 CRC_SEED").is_err());
+        assert!(command::execute(&mut calc, &mut storage, "TAG 4 12").is_err());
+        assert!(command::execute(&mut calc, &mut storage, "TAG 101 top").is_err());
+        assert!(command::execute(&mut calc, &mut storage, "RCL nothing").is_err());
+
+        // Tags are part of the saved session
+        let json = Snapshot::capture(&calc).to_json().unwrap();
+        let mut restored = Hp16cCpu::new();
+        Snapshot::from_json(&json).unwrap().restore(&mut restored);
+        assert_eq!(restored.register_for("CRC_SEED"), Some(3));
+
+        command::execute(&mut calc, &mut storage, "TAG 3").unwrap();
+        assert!(calc.tags.is_empty());
+    }
+
+    #[test]
+    fn test_results_bank() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+
+        // Off by default: results are not recorded
+        command::execute(&mut cpu, &mut storage, "1").unwrap();
+        command::execute(&mut cpu, &mut storage, "2").unwrap();
+        command::execute(&mut cpu, &mut storage, "+").unwrap();
+        assert_eq!(cpu.memory[10], 0);
+        assert!(command::execute(&mut cpu, &mut storage, "RESULTS").is_err());
+
+        command::execute(&mut cpu, &mut storage, "RESULTS 13 15").unwrap();
+        for line in ["1", "+", "1", "+", "1", "+", "1", "+", "DROP", "5"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        // Four results in three registers: the first was overwritten
+        assert_eq!(&cpu.memory[13..16], &[7, 5, 6]);
+        match command::execute(&mut cpu, &mut storage, "RESULTS").unwrap() {
+            Outcome::Message(lines) => {
+                assert_eq!(lines.len(), 3);
+                assert!(lines[0].starts_with("R13") && lines[0].ends_with(" 7"));
+                assert!(lines[2].starts_with("R14") && lines[2].ends_with(" 5"));
+            },
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        // The bank survives a save and load
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.results_bank, cpu.results_bank);
+
+        assert!(command::execute(&mut cpu, &mut storage, "RESULTS 15 13").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "RESULTS 10 101").is_err());
+        command::execute(&mut cpu, &mut storage, "RESULTS OFF").unwrap();
+        assert_eq!(cpu.results_bank, None);
+
+        // Registers lost to a larger word size take the bank and tags with them
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["WS 16", "RESULTS 9 15", "TAG 14 high", "TAG 2 low", "WS 128"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(cpu.memory().len(), 12);
+        assert_eq!(cpu.results_bank, Some(cpu::ResultsBank { first: 9, last: 11, next: 9 }));
+        assert_eq!(cpu.tags.keys().collect::<Vec<_>>(), [&2]);
+        assert!(run(&mut cpu, "RESULTS").is_ok());
+        run(&mut cpu, "+").unwrap();
+        run(&mut cpu, "FLOAT 2").unwrap();
+        assert!(run(&mut cpu, "RESULTS").is_ok());
+    }
+
+    #[test]
+    fn test_double_word_operations() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| {
+            for command in line.split(',') {
+                command::execute(cpu, &mut storage, command.trim()).unwrap();
+            }
+        };
+
+        // High word in X, low word in Y; Z and T are kept
+        run(&mut cpu, "HEX, WS 8, 7, ENTER, 9, ENTER, FF, ENTER, FF, DBL*");
+        assert_eq!((cpu.x, cpu.y, cpu.z, cpu.t), (0xFE, 0x01, 0x09, 0x07));
+
+        // 1234 hex = 4660: 4660 / 86 = 54 remainder 16
+        run(&mut cpu, "34, ENTER, 12, ENTER, 56, DBL/");
+        assert_eq!((cpu.x, cpu.overflow), (0x36, false));
+        run(&mut cpu, "34, ENTER, 12, ENTER, 56, DBLR");
+        assert_eq!(cpu.x, 0x10);
+
+        // Quotient too large for a word, and division by zero
+        run(&mut cpu, "0, ENTER, 56, ENTER, 56, DBL/");
+        assert!(cpu.overflow);
+        assert_eq!(cpu.x, 0x56);
+        run(&mut cpu, "1, ENTER, 1, ENTER, 0, DBLR");
+        assert!(cpu.overflow);
+
+        // Signed: -3 * 100 = -300, and -4660 / 86 = -54 remainder -16
+        run(&mut cpu, "2S, DEC, 3, CHS, ENTER, 100, DBL*, HEX");
+        assert_eq!((cpu.x, cpu.y), (0xFE, 0xD4));
+        run(&mut cpu, "CC, ENTER, ED, ENTER, 56, DBL/");
+        assert_eq!(cpu.x, 0xCA);
+        run(&mut cpu, "CC, ENTER, ED, ENTER, 56, DBLR");
+        assert_eq!(cpu.x, 0xF0);
+
+        // 128-bit words: the product spans two full registers
+        run(&mut cpu, "UNSGN, WS 128");
+        cpu.y = u128::MAX;
+        cpu.x = u128::MAX;
+        run(&mut cpu, "DBL*");
+        assert_eq!((cpu.x, cpu.y), (u128::MAX - 1, 1));
+        (cpu.y, cpu.z, cpu.x) = (cpu.x, cpu.y, u128::MAX);
+        run(&mut cpu, "DBL/");
+        assert_eq!((cpu.x, cpu.overflow), (u128::MAX, false));
+    }
+
+    #[test]
+    fn test_bit_field() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let run = |cpu: &mut Hp16cCpu, line: &str| {
+            for command in line.split(',') {
+                command::execute(cpu, &mut MemoryStorage::new(), command.trim()).unwrap();
+            }
+        };
+
+        // Increment the high byte of a packed word
+        run(&mut cpu, "HEX, WS 16, FIELD 15:8, 12FF, ENTER, 1, +");
+        assert_eq!(cpu.x, 0x13FF);
+
+        // The sum wraps within the field; the other bits are kept
+        run(&mut cpu, "FF34, ENTER, 1, +");
+        assert_eq!(cpu.x, 0x0034);
+
+        // Unary operations work on the field of X in place
+        run(&mut cpu, "FIELD 7:4, 1234, ~");
+        assert_eq!(cpu.x, 0x12C4);
+        run(&mut cpu, "FIELD 3:0, AB, SL 1");
+        assert_eq!(cpu.x, 0xA6);
+
+        // Signed overflow uses the field's width
+        run(&mut cpu, "2S, FIELD 15:8, 7F00, ENTER, 1, +");
+        assert_eq!((cpu.x, cpu.overflow), (0x8000, true));
+
+        // Saved with the session; cleared when the word no longer holds it
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.field, cpu.field);
+        assert!(command::execute(&mut cpu, &mut storage, "FIELD 16:0").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "FIELD 3:7").is_err());
+        run(&mut cpu, "WS 8");
+        assert_eq!(cpu.field, None);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        command::execute(&mut cpu, &mut storage, "HEX").unwrap();
+        command::execute(&mut cpu, &mut storage, "WS 8").unwrap();
+
+        command::execute(&mut cpu, &mut storage, "81").unwrap();
+        command::execute(&mut cpu, &mut storage, "RL").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x03, true));
+        command::execute(&mut cpu, &mut storage, "RL").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x06, false));
+
+        command::execute(&mut cpu, &mut storage, "RR").unwrap();
+        command::execute(&mut cpu, &mut storage, "RR").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x81, true));
+        command::execute(&mut cpu, &mut storage, "RR").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0xC0, true));
+
+        // The full 128-bit word rotates too
+        command::execute(&mut cpu, &mut storage, "WS 128").unwrap();
+        cpu.x = 1 << 127;
+        command::execute(&mut cpu, &mut storage, "RL").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (1, true));
+    }
+
+    #[test]
+    fn test_push_values() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        command::execute(&mut cpu, &mut storage, "HEX").unwrap();
+
+        command::execute(&mut cpu, &mut storage, "PUSHN 1 2 3").unwrap();
+        assert_eq!((cpu.x, cpu.y, cpu.z), (3, 2, 1));
+
+        // Bracketed entry; earlier values fall off T
+        match command::execute(&mut cpu, &mut storage, "[de ad be ef 1]").unwrap() {
+            Outcome::Message(lines) => assert!(lines[0].contains("only the last 4")),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        assert_eq!((cpu.x, cpu.y, cpu.z, cpu.t), (0x1, 0xEF, 0xBE, 0xAD));
+
+        // Into consecutive registers, by number or tag
+        command::execute(&mut cpu, &mut storage, "[DE AD BE EF] STO 4").unwrap();
+        assert_eq!(&cpu.memory[4..8], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(cpu.x, 0x1);
+        command::execute(&mut cpu, &mut storage, "TAG 14 vec").unwrap();
+        command::execute(&mut cpu, &mut storage, "PUSHN 7 8 STO vec").unwrap();
+        assert_eq!(&cpu.memory[14..16], &[7, 8]);
+
+        assert!(command::execute(&mut cpu, &mut storage, "PUSHN 1 2 3 STO 99").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "[1 G]").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "[1 2").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "[]").is_err());
+    }
+
+    #[test]
+    fn test_rotate_through_carry() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        command::execute(&mut cpu, &mut storage, "HEX").unwrap();
+        command::execute(&mut cpu, &mut storage, "WS 8").unwrap();
+
+        // Carry is a ninth bit: nine rotations bring the value back
+        command::execute(&mut cpu, &mut storage, "80").unwrap();
+        cpu.carry = false;
+        command::execute(&mut cpu, &mut storage, "RLC").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x00, true));
+        command::execute(&mut cpu, &mut storage, "RLC").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x01, false));
+        for _ in 0..7 {
+            command::execute(&mut cpu, &mut storage, "RLC").unwrap();
+        }
+        assert_eq!((cpu.x, cpu.carry), (0x80, false));
+
+        command::execute(&mut cpu, &mut storage, "1").unwrap();
+        command::execute(&mut cpu, &mut storage, "RRC").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x00, true));
+        command::execute(&mut cpu, &mut storage, "RRC").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x80, false));
+
+        // A 16-bit value in two 8-bit registers shifted left by one
+        cpu.memory[0] = 0x81;
+        cpu.memory[1] = 0x40;
+        for line in ["RCL 0", "SL 1", "STO 0", "RCL 1", "RLC", "STO 1"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.memory[1], cpu.memory[0]), (0x81, 0x02));
+    }
+
+    #[test]
+    fn test_rotate_by_count() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["HEX", "WS 8", "9", "ENTER", "81", "ENTER", "4", "RLN"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        // Y rotated by X, stack dropped
+        assert_eq!((cpu.x, cpu.y, cpu.carry), (0x18, 0x9, false));
+
+        for line in ["3", "RRN"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.x, cpu.carry), (0x03, false));
+        for line in ["1", "RRN"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.x, cpu.carry), (0x81, true));
+
+        // A full rotation keeps the value; zero clears carry
+        for line in ["8", "RLN"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.x, cpu.carry), (0x81, true));
+        for line in ["0", "RLN"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.x, cpu.carry), (0x81, false));
+
+        command::execute(&mut cpu, &mut storage, "9").unwrap();
+        assert!(command::execute(&mut cpu, &mut storage, "RLN").is_err());
+        assert_eq!((cpu.x, cpu.y), (0x9, 0x81));
+    }
+
+    #[test]
+    fn test_register_reduce_and_map() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        command::execute(&mut cpu, &mut storage, "[3 5 6 9] STO 0").unwrap();
+        command::execute(&mut cpu, &mut storage, "PUSHN 9 8").unwrap();
+
+        command::execute(&mut cpu, &mut storage, "SUMR 0-3").unwrap();
+        assert_eq!((cpu.x, cpu.y, cpu.z), (23, 8, 9));
+        command::execute(&mut cpu, &mut storage, "XORR 0-3").unwrap();
+        assert_eq!(cpu.x, 9);
+        command::execute(&mut cpu, &mut storage, "TAG 3 last").unwrap();
+        command::execute(&mut cpu, &mut storage, "REDUCE * 1-last").unwrap();
+        assert_eq!(cpu.x, 270);
+        assert!(command::execute(&mut cpu, &mut storage, "REDUCE NOPE 0-3").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "SUMR 3-0").is_err());
+        assert_eq!(cpu.x, 270);
+
+        // Each register goes through the commands; the stack is kept
+        command::execute(&mut cpu, &mut storage, "MAPR 0-3 1; +; SL 1").unwrap();
+        assert_eq!(&cpu.memory[0..4], &[8, 12, 14, 20]);
+        assert_eq!((cpu.x, cpu.y), (270, 9));
+        let err = command::execute(&mut cpu, &mut storage, "MAPR 0-1 NOPE").unwrap_err();
+        assert!(err.starts_with("R0: NOPE"));
+        assert_eq!(cpu.x, 270);
+    }
+
+    #[test]
+    fn test_min_max_cmp() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| {
+            command::execute(cpu, &mut storage, line).unwrap();
+            cpu.x
+        };
+        run(&mut cpu, "HEX");
+        run(&mut cpu, "WS 8");
+
+        run(&mut cpu, "PUSHN 7 FF 1");
+        assert_eq!(run(&mut cpu, "MAX"), 0xFF);
+        assert_eq!(cpu.y, 7);
+        run(&mut cpu, "PUSHN FF 1");
+        assert_eq!(run(&mut cpu, "MIN"), 1);
+
+        // Signed: FF is -1
+        run(&mut cpu, "2S");
+        run(&mut cpu, "PUSHN FF 1");
+        assert_eq!(run(&mut cpu, "MAX"), 1);
+        run(&mut cpu, "PUSHN FF 1");
+        assert_eq!(run(&mut cpu, "MIN"), 0xFF);
+
+        run(&mut cpu, "PUSHN FF 1");
+        assert_eq!(run(&mut cpu, "CMP"), 0xFF);
+        run(&mut cpu, "PUSHN 1 FF");
+        assert_eq!(run(&mut cpu, "CMP"), 1);
+        run(&mut cpu, "PUSHN 5 5");
+        assert_eq!(run(&mut cpu, "CMP"), 0);
+
+        // 1's complement: negative zero equals zero, and -1 is FE
+        run(&mut cpu, "1S");
+        run(&mut cpu, "PUSHN FF 0");
+        assert_eq!(run(&mut cpu, "CMP"), 0);
+        run(&mut cpu, "PUSHN FE 0");
+        assert_eq!(run(&mut cpu, "CMP"), 0xFE);
+    }
+
+    #[test]
+    fn test_scratch_memory() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "WS 32").unwrap();
+
+        // Little-endian by default, big-endian on request
+        run(&mut cpu, "DEADBEEF").unwrap();
+        run(&mut cpu, "ST32 10").unwrap();
+        run(&mut cpu, "ST32 20 BE").unwrap();
+        assert_eq!(&cpu.scratch.bytes()[0x10..0x14], &[0xEF, 0xBE, 0xAD, 0xDE]);
+        assert_eq!(&cpu.scratch.bytes()[0x20..0x24], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        run(&mut cpu, "LD16 10").unwrap();
+        assert_eq!((cpu.x, cpu.y), (0xBEEF, 0xDEADBEEF));
+        run(&mut cpu, "LD16 20 BE").unwrap();
+        assert_eq!(cpu.x, 0xDEAD);
+
+        // Narrow stores truncate and set overflow, or saturate
+        run(&mut cpu, "1FF").unwrap();
+        run(&mut cpu, "ST8 0").unwrap();
+        assert_eq!((cpu.scratch.bytes()[0], cpu.overflow), (0xFF, true));
+        run(&mut cpu, "ST8 1 SAT").unwrap();
+        run(&mut cpu, "7F").unwrap();
+        run(&mut cpu, "ST8 2").unwrap();
+        assert_eq!((cpu.scratch.bytes()[1], cpu.overflow), (0xFF, false));
+
+        // Signed modes sign-extend loads and clamp to the signed range
+        run(&mut cpu, "2S").unwrap();
+        run(&mut cpu, "LD8 0").unwrap();
+        assert_eq!(cpu.x, 0xFFFFFFFF);
+        run(&mut cpu, "200").unwrap();
+        run(&mut cpu, "ST8 3 SAT").unwrap();
+        assert_eq!(cpu.scratch.bytes()[3], 0x7F);
+
+        // Vectors go in one after another
+        run(&mut cpu, "[1234 5678] ST16 30 BE").unwrap();
+        assert_eq!(&cpu.scratch.bytes()[0x30..0x34], &[0x12, 0x34, 0x56, 0x78]);
+
+        assert!(run(&mut cpu, "LD32 3FD").is_err());
+        assert!(run(&mut cpu, "ST8").is_err());
+        assert!(run(&mut cpu, "LD8 0 SAT").is_err());
+        match run(&mut cpu, "SCRATCH 30").unwrap() {
+            Outcome::Message(lines) => assert!(lines[0].starts_with("0030: 12 34 56 78 00")),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        // Saved with the session
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.scratch, cpu.scratch);
+        run(&mut cpu, "SCRATCH CLEAR").unwrap();
+        assert!(Snapshot::capture(&cpu).diff(&snapshot).iter().any(|d| d.field == "Scratch 0030"));
+    }
+
+    #[test]
+    fn test_endian_setting() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "WS 16").unwrap();
+        run(&mut cpu, "1234").unwrap();
+        assert_eq!(cpu.bytes_of_x(), vec![0x34, 0x12]);
+
+        // The setting is the default for scratch accesses; LE/BE override it
+        run(&mut cpu, "ENDIAN BIG").unwrap();
+        assert_eq!(cpu.bytes_of_x(), vec![0x12, 0x34]);
+        run(&mut cpu, "ST16 0").unwrap();
+        run(&mut cpu, "ST16 2 LE").unwrap();
+        assert_eq!(&cpu.scratch.bytes()[0..4], &[0x12, 0x34, 0x34, 0x12]);
+        run(&mut cpu, "LD16 2").unwrap();
+        assert_eq!(cpu.x, 0x3412);
+        assert!(run(&mut cpu, "ENDIAN MIDDLE").is_err());
+
+        match run(&mut cpu, "STATUS").unwrap() {
+            Outcome::Message(lines) => {
+                assert!(lines.iter().any(|line| line.starts_with("Byte order:") && line.ends_with("BIG")));
+                assert!(lines.iter().any(|line| line.starts_with("Word size:   16 bits")));
+            },
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        match run(&mut cpu, "BYTES").unwrap() {
+            Outcome::Message(lines) => assert_eq!(lines[0], "X bytes (BIG): 34 12"),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.endian, cpu.endian);
+    }
+
+    #[test]
+    fn test_signed_division_conventions() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line).unwrap();
+        run(&mut cpu, "WS 8");
+        run(&mut cpu, "DEC");
+
+        // Unsigned: carry reports a remainder
+        run(&mut cpu, "PUSHN 7 2");
+        run(&mut cpu, "/");
+        assert_eq!((cpu.x, cpu.carry), (3, true));
+        run(&mut cpu, "PUSHN 8 2");
+        run(&mut cpu, "/");
+        assert_eq!((cpu.x, cpu.carry), (4, false));
+        run(&mut cpu, "PUSHN 7 2");
+        run(&mut cpu, "RMD");
+        assert_eq!(cpu.x, 1);
+
+        // Quotients truncate toward zero; remainders take the dividend's sign
+        run(&mut cpu, "2S");
+        for (y, x, quotient, remainder) in [(-7, 2, "-3", "-1"), (7, -2, "-3", "1"), (-7, -2, "3", "-1"), (-8, 2, "-4", "0")] {
+            for (op, expected) in [("/", quotient), ("RMD", remainder)] {
+                cpu.enter_value(y as u128);
+                cpu.enter_value(x as u128);
+                run(&mut cpu, op);
+                assert_eq!(cpu.format_display(), expected, "{} {} {}", y, op, x);
+                assert!(!cpu.overflow);
+            }
+        }
+
+        // MIN / -1 overflows; division by zero leaves the stack alone
+        cpu.enter_value(0x80);
+        cpu.enter_value(0xFF);
+        run(&mut cpu, "/");
+        assert!(cpu.overflow);
+        run(&mut cpu, "PUSHN 7 0");
+        run(&mut cpu, "RMD");
+        assert!(cpu.overflow);
+        assert_eq!((cpu.x, cpu.y), (0, 7));
+
+        // 1's complement uses the same convention
+        run(&mut cpu, "1S");
+        cpu.enter_value(0xF8);
+        cpu.enter_value(2);
+        run(&mut cpu, "RMD");
+        assert_eq!((cpu.format_display(), cpu.x), ("-1".to_string(), 0xFE));
+
+        match run(&mut cpu, "STATUS") {
+            Outcome::Message(lines) => assert!(lines.iter().any(|line| line.contains(command::DIVISION_CONVENTION))),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bit_operations() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "WS 8").unwrap();
+
+        run(&mut cpu, "PUSHN 9 10 3").unwrap();
+        run(&mut cpu, "SB").unwrap();
+        assert_eq!((cpu.x, cpu.y), (0x18, 0x9));
+        run(&mut cpu, "4").unwrap();
+        run(&mut cpu, "CB").unwrap();
+        assert_eq!(cpu.x, 0x08);
+
+        // B? keeps the value and reports the bit in carry
+        run(&mut cpu, "3").unwrap();
+        run(&mut cpu, "B?").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x08, true));
+        run(&mut cpu, "2").unwrap();
+        run(&mut cpu, "B?").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x08, false));
+
+        run(&mut cpu, "8").unwrap();
+        assert!(run(&mut cpu, "SB").is_err());
+        assert_eq!((cpu.x, cpu.y), (0x8, 0x8));
+
+        // The CPU methods check the bit number themselves
+        let mut cpu = Hp16cCpu::new();
+        cpu.set_word_size(128);
+        cpu.push(1);
+        cpu.push(200);
+        assert!(cpu.set_bit().is_err());
+        assert!(cpu.clear_bit().is_err());
+        assert!(cpu.test_bit().is_err());
+        assert_eq!((cpu.x(), cpu.y()), (200, 1));
+    }
+
+    #[test]
+    fn test_masks() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "WS 16").unwrap();
+
+        // The size comes from X, which the mask replaces
+        run(&mut cpu, "PUSHN 7 4").unwrap();
+        run(&mut cpu, "MASKR").unwrap();
+        assert_eq!((cpu.x, cpu.y), (0x000F, 0x7));
+        run(&mut cpu, "3").unwrap();
+        run(&mut cpu, "MASKL").unwrap();
+        assert_eq!((cpu.x, cpu.y), (0xE000, 0xF));
+
+        // Or is given with the command, in decimal like SL n
+        run(&mut cpu, "MASKL 16").unwrap();
+        assert_eq!((cpu.x, cpu.y), (0xFFFF, 0xE000));
+        run(&mut cpu, "MASKL 0").unwrap();
+        assert_eq!(cpu.x, 0);
+        assert!(run(&mut cpu, "MASKR 17").is_err());
+
+        run(&mut cpu, "WS 128").unwrap();
+        run(&mut cpu, "MASKL 1").unwrap();
+        assert_eq!(cpu.x, 1 << 127);
+        run(&mut cpu, "MASKL 0").unwrap();
+        assert_eq!(cpu.x, 0);
+        run(&mut cpu, "MASKR 128").unwrap();
+        assert_eq!(cpu.x, u128::MAX);
+    }
+
+    #[test]
+    fn test_random_generators() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+
+        // Xorshift64 from seed 1
+        run(&mut cpu, "WS 64").unwrap();
+        run(&mut cpu, "1").unwrap();
+        run(&mut cpu, "RAND SEED").unwrap();
+        run(&mut cpu, "RAND").unwrap();
+        assert_eq!(cpu.x, 0x40822041);
+
+        // A maximal 16-bit Galois LFSR returns to its seed after 65535 steps
+        run(&mut cpu, "WS 16").unwrap();
+        run(&mut cpu, "RAND LFSR B400").unwrap();
+        run(&mut cpu, "RAND").unwrap();
+        assert_eq!(cpu.x, 0xB400);
+        run(&mut cpu, "LFSR 65534").unwrap();
+        assert_eq!(cpu.x, 1);
+        match run(&mut cpu, "RAND STATE").unwrap() {
+            Outcome::Message(lines) => assert_eq!(lines[0], "LFSR taps B400 (16 bits), state 1"),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        let cancel = cancel::CancelToken::new();
+        cancel.cancel();
+        let result = command::execute_with(&mut cpu, &mut MemoryStorage::new(), "LFSR 18446744073709551615", &cancel);
+        assert_eq!(result, Err("Cancelled after 0 steps".to_string()));
+
+        // Streams are reproducible from a seed, and saved with the session
+        run(&mut cpu, "RAND PCG").unwrap();
+        run(&mut cpu, "2A").unwrap();
+        run(&mut cpu, "RAND SEED").unwrap();
+        let snapshot = Snapshot::capture(&cpu);
+        run(&mut cpu, "RAND").unwrap();
+        let first = cpu.x;
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        command::execute(&mut restored, &mut MemoryStorage::new(), "RAND").unwrap();
+        assert_eq!(restored.x, first);
+
+        assert!(run(&mut cpu, "LFSR 1").is_err());
+        assert!(run(&mut cpu, "RAND LFSR 0").is_err());
+        run(&mut cpu, "RAND XORSHIFT").unwrap();
+        run(&mut cpu, "0").unwrap();
+        assert!(run(&mut cpu, "RAND SEED").is_err());
+    }
+
+    #[test]
+    fn test_bcd() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "WS 16").unwrap();
+        run(&mut cpu, "DEC").unwrap();
+        run(&mut cpu, "1234").unwrap();
+        run(&mut cpu, ">BCD").unwrap();
+        assert_eq!((cpu.x, cpu.overflow), (0x1234, false));
+        run(&mut cpu, "BCD>").unwrap();
+        assert_eq!(cpu.x, 1234);
+
+        // Four digits fit in 16 bits
+        run(&mut cpu, "12345").unwrap();
+        run(&mut cpu, ">BCD").unwrap();
+        assert_eq!((cpu.x, cpu.overflow), (0x2345, true));
+
+        // Decimal carry and borrow
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "PUSHN 0199 0001").unwrap();
+        run(&mut cpu, "BCD+").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x0200, false));
+        run(&mut cpu, "PUSHN 9999 2").unwrap();
+        run(&mut cpu, "BCD+").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x0001, true));
+        run(&mut cpu, "PUSHN 100 1").unwrap();
+        run(&mut cpu, "BCD-").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x0099, false));
+        run(&mut cpu, "PUSHN 1 2").unwrap();
+        run(&mut cpu, "BCD-").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0x9999, true));
+
+        // Nibbles above 9 are rejected and leave the stack alone
+        run(&mut cpu, "PUSHN 12 1A").unwrap();
+        assert!(run(&mut cpu, "BCD>").is_err());
+        assert!(run(&mut cpu, "BCD+").is_err());
+        assert_eq!((cpu.x, cpu.y), (0x1A, 0x12));
+        run(&mut cpu, "WS 3").unwrap();
+        assert!(run(&mut cpu, ">BCD").is_err());
+    }
+
+    #[test]
+    fn test_abs() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line).unwrap();
+        run(&mut cpu, "HEX");
+        run(&mut cpu, "WS 8");
+
+        // Unsigned values are already absolute
+        run(&mut cpu, "FB");
+        run(&mut cpu, "ABS");
+        assert_eq!(cpu.x, 0xFB);
+
+        run(&mut cpu, "2S");
+        run(&mut cpu, "ABS");
+        assert_eq!((cpu.x, cpu.overflow), (0x05, false));
+        run(&mut cpu, "ABS");
+        assert_eq!(cpu.x, 0x05);
+
+        // -128 has no 8-bit positive counterpart
+        run(&mut cpu, "80");
+        run(&mut cpu, "ABS");
+        assert_eq!((cpu.x, cpu.overflow), (0x80, true));
+
+        // 1's complement: -5 is FA, and negative zero becomes zero
+        run(&mut cpu, "1S");
+        run(&mut cpu, "FA");
+        run(&mut cpu, "ABS");
+        assert_eq!((cpu.x, cpu.overflow), (0x05, false));
+        run(&mut cpu, "FF");
+        run(&mut cpu, "ABS");
+        assert_eq!(cpu.x, 0);
+    }
+
+    #[test]
+    fn test_checksums() {
+        use checksum::{fletcher16, fletcher32, internet};
+        use scratch::Endian;
+
+        // RFC 1071's example words sum to DDF2
+        assert_eq!(internet(&[0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7]), 0x220D);
+        assert_eq!(internet(&[0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7, 0x22, 0x0D]), 0);
+        assert_eq!(fletcher16(b"abcde"), 0xC8F0);
+        assert_eq!(fletcher16(b"abcdef"), 0x2057);
+        assert_eq!(fletcher32(b"abcde", Endian::Little), 0xF04F_C729);
+        assert_eq!(fletcher32(b"abcdef", Endian::Little), 0x5650_2D2A);
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "CKSUM F16 [61 62 63 64 65]").unwrap();
+        assert_eq!(cpu.x, 0xC8F0);
+
+        // An IPv4 header with its checksum field zeroed, built in scratch
+        run(&mut cpu, "[4500 0073 0000 4000 4011 0000 C0A8 0001 C0A8 00C7] ST16 100 BE").unwrap();
+        run(&mut cpu, "CKSUM IP 100 20").unwrap();
+        assert_eq!(cpu.x, 0xB861);
+
+        assert!(run(&mut cpu, "CKSUM F16 [100]").is_err());
+        assert!(run(&mut cpu, "CKSUM IP 3F0 20").is_err());
+        assert!(run(&mut cpu, "CKSUM CRC [1]").is_err());
+    }
+
+    #[test]
+    fn test_decode_encode_scratch() {
+        use scratch::{decode_base64, encode_base64};
+
+        for (bytes, text) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy")] {
+            assert_eq!(encode_base64(bytes), text);
+            assert_eq!(decode_base64(text).unwrap(), bytes);
+        }
+        assert_eq!(decode_base64("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(decode_base64("-_8").unwrap(), [0xFB, 0xFF]);
+        assert!(decode_base64("Z").is_err());
+        assert!(decode_base64("Zm9v!").is_err());
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "WS 32").unwrap();
+        assert_eq!(
+            run(&mut cpu, "decode b64 10 3q2+7w==").unwrap(),
+            Outcome::Message(vec!["Decoded 4 bytes at 10-13".to_string()])
+        );
+        run(&mut cpu, "LD32 10 BE").unwrap();
+        assert_eq!(cpu.x, 0xDEADBEEF);
+        run(&mut cpu, "DECODE HEX 20 01:02 0a").unwrap();
+        assert_eq!(cpu.scratch.read(0x20, 3).unwrap(), [1, 2, 0x0A]);
+        assert_eq!(
+            run(&mut cpu, "ENCODE B64 10 4").unwrap(),
+            Outcome::Message(vec!["3q2+7w==".to_string()])
+        );
+        assert_eq!(
+            run(&mut cpu, "ENCODE HEX 20 3").unwrap(),
+            Outcome::Message(vec!["01020A".to_string()])
+        );
+
+        assert!(run(&mut cpu, "DECODE HEX 0 123").is_err());
+        assert!(run(&mut cpu, "DECODE HEX 3FF 0102").is_err());
+        assert!(run(&mut cpu, "ENCODE HEX 3F0 17").is_err());
+    }
+
+    #[test]
+    fn test_utf8() {
+        use scratch::decode_utf8;
+
+        assert_eq!(decode_utf8(&[0x41, 0xFF]).unwrap(), ('A', 1));
+        assert_eq!(decode_utf8(&[0xE2, 0x82, 0xAC]).unwrap(), ('€', 3));
+        assert_eq!(decode_utf8(&[0xF0, 0x9F, 0x98, 0x80]).unwrap(), ('😀', 4));
+        assert!(decode_utf8(&[0x80]).is_err());
+        assert!(decode_utf8(&[0xC0, 0x80]).is_err()); // overlong NUL
+        assert!(decode_utf8(&[0xED, 0xA0, 0x80]).is_err()); // surrogate
+        assert!(decode_utf8(&[0xE2, 0x82]).is_err());
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "E9").unwrap();
+        assert_eq!(run(&mut cpu, "UTF8").unwrap(), Outcome::Message(vec!["U+00E9 'é': C3 A9".to_string()]));
+        run(&mut cpu, "D800").unwrap();
+        assert!(run(&mut cpu, "UTF8").is_err());
+
+        run(&mut cpu, "DECODE HEX 10 E2 82 AC").unwrap();
+        assert_eq!(
+            run(&mut cpu, "UTF8 10").unwrap(),
+            Outcome::Message(vec!["U+20AC '€' from 3 bytes: E2 82 AC".to_string()])
+        );
+        assert_eq!(cpu.x, 0x20AC);
+        assert!(run(&mut cpu, "UTF8 11").is_err());
+        run(&mut cpu, "DECODE HEX 3FF C3").unwrap();
+        assert!(run(&mut cpu, "UTF8 3FF").is_err());
+    }
+
+    #[test]
+    fn test_register_arithmetic() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["HEX", "WS 8", "[F0 20 33] STO 3", "1", "2"] {
+            run(&mut cpu, line).unwrap();
+        }
+        run(&mut cpu, "RADD 3 4").unwrap();
+        assert_eq!(cpu.memory[3], 0x10);
+        run(&mut cpu, "RXOR 4 5").unwrap();
+        assert_eq!(cpu.memory[4], 0x13);
+        // The stack is left alone, sources are unchanged
+        assert_eq!((cpu.x, cpu.y, cpu.memory[5]), (2, 1, 0x33));
+
+        run(&mut cpu, "TAG 3 acc").unwrap();
+        run(&mut cpu, "RADD acc 5").unwrap();
+        assert_eq!(cpu.memory[3], 0x43);
+
+        assert!(run(&mut cpu, "RADD 3").is_err());
+        assert!(run(&mut cpu, "RXOR 3 203").is_err());
+        assert!(run(&mut cpu, "SUMR 0-203").is_err());
+    }
+
+    #[test]
+    fn test_user_flags() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "SF 2").unwrap();
+        assert_eq!(cpu.user_flags, [false, false, true, false]);
+        assert_eq!(run(&mut cpu, "F? 2").unwrap(), Outcome::Message(vec!["Flag 2 is set".to_string()]));
+        assert!(display::render_lines(&cpu)[4].contains("Flags: 2"));
+
+        // Flags 4 and 5 are carry and overflow
+        run(&mut cpu, "SF 4").unwrap();
+        run(&mut cpu, "SF 5").unwrap();
+        assert!(cpu.carry && cpu.overflow);
+        run(&mut cpu, "CF 4").unwrap();
+        assert_eq!(cpu.flags(), [false, false, true, false, false, true]);
+        assert!(run(&mut cpu, "SF 6").is_err());
+        assert!(cpu.test_flag(6).is_err());
+
+        // Flags are saved with the session and usable as script conditions
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.user_flags, cpu.user_flags);
+        #[cfg(feature = "scripting")]
+        {
+            let mut scripts = MemoryStorage::new();
+            scripts.write("flags.rpn", "IF F? 2\n  7\nELSE\n  9\nEND\n").unwrap();
+            command::execute(&mut restored, &mut scripts, "SOURCE flags.rpn").unwrap();
+            assert_eq!(restored.x, 7);
+        }
+        run(&mut cpu, "CF 2").unwrap();
+        assert_eq!(Snapshot::capture(&cpu).diff(&snapshot)[0].field, "Flag 2");
+    }
+
+    #[test]
+    fn test_auto_enter_chains() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "DEC").unwrap();
+
+        // Off by default: a chain is not a command
+        assert!(run(&mut cpu, "10 + 5").is_err());
+
+        run(&mut cpu, "AUTOENTER ON").unwrap();
+        run(&mut cpu, "3").unwrap();
+        run(&mut cpu, "10 + 5 * 2").unwrap();
+        assert_eq!((cpu.x, cpu.y), (30, 3));
+        // Starting with an operator continues from X
+        run(&mut cpu, "- 6").unwrap();
+        assert_eq!((cpu.x, cpu.y), (24, 3));
+        run(&mut cpu, "RESULTS ON").unwrap();
+        run(&mut cpu, "/ 4").unwrap();
+        assert_eq!((cpu.x, cpu.memory[10]), (6, 6));
+
+        // Ordinary commands are unaffected
+        run(&mut cpu, "SL 1").unwrap();
+        assert_eq!(cpu.x, 12);
+        assert!(run(&mut cpu, "10 +").is_err());
+        run(&mut cpu, "/ 0").unwrap();
+        assert!(cpu.overflow);
+    }
+
+    #[test]
+    fn test_overflow_flag() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line).unwrap();
+        let mut check = |cpu: &mut Hp16cCpu, lines: &[&str], overflow: bool| {
+            for line in lines {
+                run(cpu, line);
+            }
+            assert_eq!(cpu.overflow, overflow, "after {:?}", lines);
+        };
+        check(&mut cpu, &["HEX", "WS 8"], false);
+
+        // Unsigned results outside 0-FF, and results that fit clear the flag
+        check(&mut cpu, &["FF", "ENTER", "1", "+"], true);
+        check(&mut cpu, &["FE", "ENTER", "1", "+"], false);
+        check(&mut cpu, &["1", "ENTER", "2", "-"], true);
+        check(&mut cpu, &["2", "ENTER", "1", "-"], false);
+        check(&mut cpu, &["10", "ENTER", "10", "*"], true);
+        check(&mut cpu, &["F", "ENTER", "11", "*"], false);
+        check(&mut cpu, &["1", "ENTER", "0", "/"], true);
+        check(&mut cpu, &["9", "ENTER", "2", "/"], false);
+        check(&mut cpu, &["81", "SL 1"], true);
+        check(&mut cpu, &["41", "SL 1"], false);
+        check(&mut cpu, &["5", "CHS"], false);
+
+        // Signed: CHS of the most negative value, and shifts into the sign
+        check(&mut cpu, &["2S", "80", "CHS"], true);
+        assert_eq!(cpu.x, 0x80);
+        check(&mut cpu, &["81", "CHS"], false);
+        check(&mut cpu, &["40", "SL 1"], true);
+        check(&mut cpu, &["C0", "SL 1"], false);
+        check(&mut cpu, &["A0", "SL 1"], true);
+        check(&mut cpu, &["FF", "SL 7"], false);
+        check(&mut cpu, &["80", "SR 1"], false);
+        check(&mut cpu, &["80", "ABS"], true);
+        check(&mut cpu, &["UNSGN", "80", "ABS"], false);
+        check(&mut cpu, &["1S", "80", "CHS"], false);
+    }
+
+    #[test]
+    fn test_carry_at_word_sizes() {
+        for word_size in [4u8, 8, 16, 32, 64, 128] {
+            let max = if word_size == 128 { u128::MAX } else { (1u128 << word_size) - 1 };
+            let top = 1u128 << (word_size - 1);
+            let mut cpu = Hp16cCpu::new();
+            cpu.set_word_size(word_size);
+            let binary = |cpu: &mut Hp16cCpu, y: u128, x: u128, op: fn(&mut Hp16cCpu)| {
+                (cpu.y, cpu.x) = (y, x);
+                op(cpu);
+                (cpu.x, cpu.carry)
+            };
+
+            // Addition carries out of the word, not out of 128 bits
+            assert_eq!(binary(&mut cpu, max, 1, Hp16cCpu::add), (0, true), "WS {}", word_size);
+            assert_eq!(binary(&mut cpu, max - 1, 1, Hp16cCpu::add), (max, false), "WS {}", word_size);
+            assert_eq!(binary(&mut cpu, top, top, Hp16cCpu::add), (0, true), "WS {}", word_size);
+            // Subtraction borrows
+            assert_eq!(binary(&mut cpu, 0, 1, Hp16cCpu::subtract), (max, true), "WS {}", word_size);
+            assert_eq!(binary(&mut cpu, 1, 1, Hp16cCpu::subtract), (0, false), "WS {}", word_size);
+
+            // Shifts: carry is the last bit shifted out
+            cpu.x = top | 1;
+            cpu.shift_left(1);
+            assert_eq!((cpu.x, cpu.carry), (2, true), "WS {}", word_size);
+            cpu.shift_left(word_size);
+            assert_eq!((cpu.x, cpu.carry), (0, false), "WS {}", word_size);
+            cpu.x = top | 1;
+            cpu.shift_right(1);
+            assert_eq!((cpu.x, cpu.carry), (top >> 1, true), "WS {}", word_size);
+            cpu.x = top;
+            cpu.shift_right(word_size);
+            assert_eq!((cpu.x, cpu.carry), (0, true), "WS {}", word_size);
+            cpu.shift_right(0);
+            assert!(!cpu.carry);
+
+            // Rotates: carry is the bit that wrapped around
+            cpu.x = top;
+            cpu.rotate_left();
+            assert_eq!((cpu.x, cpu.carry), (1, true), "WS {}", word_size);
+            cpu.rotate_right();
+            assert_eq!((cpu.x, cpu.carry), (top, true), "WS {}", word_size);
+            cpu.carry = false;
+            cpu.rotate_left_carry();
+            assert_eq!((cpu.x, cpu.carry), (0, true), "WS {}", word_size);
+            cpu.rotate_right_carry();
+            assert_eq!((cpu.x, cpu.carry), (top, false), "WS {}", word_size);
+        }
+
+        // 1's complement keeps its end-around carry; multiplication leaves carry alone
+        let mut cpu = Hp16cCpu::new();
+        cpu.set_word_size(8);
+        cpu.set_complement_mode(ComplementMode::OnesComplement);
+        (cpu.y, cpu.x, cpu.carry) = (0x05, 0xFE, false);
+        cpu.add();
+        assert_eq!((cpu.x, cpu.carry), (0x04, true));
+        cpu.set_complement_mode(ComplementMode::Unsigned);
+        (cpu.y, cpu.x) = (0x80, 0x04);
+        cpu.multiply();
+        assert_eq!((cpu.x, cpu.carry, cpu.overflow), (0x00, true, true));
+    }
+
+    #[test]
+    fn test_shift_counts() {
+        // Every count from 0 to 128 at several word sizes, against the
+        // definition: bits move out of the word, carry is the last one out
+        for word_size in [1u8, 7, 8, 16, 63, 64, 127, 128] {
+            let mask = u128::MAX >> (128 - word_size as u32);
+            let value = 0xA5A5_A5A5_A5A5_A5A5_A5A5_A5A5_A5A5_A5A5 & mask;
+            let bit = |n: u32| n < 128 && value >> n & 1 != 0;
+            for count in 0..=128u8 {
+                let n = count as u32;
+                let mut cpu = Hp16cCpu::new();
+                cpu.set_word_size(word_size);
+
+                cpu.x = value;
+                cpu.shift_left(count);
+                let expected = value.checked_shl(n).unwrap_or(0) & mask;
+                let carry = n >= 1 && n <= word_size as u32 && bit(word_size as u32 - n);
+                assert_eq!((cpu.x, cpu.carry), (expected, carry), "WS {} SL {}", word_size, count);
+
+                cpu.x = value;
+                cpu.shift_right(count);
+                let expected = value.checked_shr(n).unwrap_or(0);
+                let carry = n >= 1 && bit(n - 1);
+                assert_eq!((cpu.x, cpu.carry), (expected, carry), "WS {} SR {}", word_size, count);
+            }
+        }
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "WS 128").unwrap();
+        run(&mut cpu, "1").unwrap();
+        run(&mut cpu, "SL 128").unwrap();
+        assert_eq!((cpu.x, cpu.carry, cpu.overflow), (0, true, true));
+        run(&mut cpu, "1").unwrap();
+        run(&mut cpu, "SL 127").unwrap();
+        assert_eq!(cpu.x, 1 << 127);
+        run(&mut cpu, "SR 128").unwrap();
+        assert_eq!((cpu.x, cpu.carry), (0, true));
+        assert_eq!(run(&mut cpu, "SL 129"), Err("Shift count must be 0-128".to_string()));
+    }
+
+    #[test]
+    fn test_usage_summary() {
+        let mut usage = usage::UsageSummary::new();
+        // Hex is the default base: DEC and CB are commands, FF is a number
+        for (line, error) in [("ff", None), ("DEC", None), ("cb 3", None), ("10", None), ("FOO", Some("Unknown command: FOO"))] {
+            usage.record(line, Base::Hex, error);
+        }
+        usage.record("  ", Base::Hex, None);
+        assert_eq!(usage.total_commands, 5);
+        assert_eq!(usage.commands["[number]"], 2);
+        assert_eq!(usage.commands["DEC"], 1);
+        assert_eq!(usage.commands["CB"], 1);
+        assert_eq!(usage.errors.len(), 1);
+        assert_eq!(usage.errors[0].input, "FOO");
+        assert_eq!(usage.lines()[0], "Commands: 5  Errors: 1");
+        assert_eq!(usage.lines()[1], "  [number]     2");
+
+        let json = usage.to_json().unwrap();
+        assert_eq!(usage::UsageSummary::from_json(&json).unwrap(), usage);
+    }
+
+    #[test]
+    fn test_float_mode() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        // Entering FLOAT takes Y as the mantissa and X as the exponent of 2
+        for line in ["2S", "DEC", "3", "ENTER", "2", "FLOAT 4"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.word_size.bits(), cpu.format_display(), cpu.y), (64, "12.0000".to_string(), 0));
+        for line in ["1.5", "+", "2E-1", "/", "CHS"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(cpu.format_display(), "-67.5000");
+        for line in ["ABS", "1E20", "*"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(cpu.format_display(), "6.7500e21");
+        assert_eq!(run(&mut cpu, "SL 1"), Err("SL is not available in FLOAT mode".to_string()));
+        assert!(run(&mut cpu, "WS 16").is_err());
+        assert!(run(&mut cpu, "INF").is_err());
+
+        // Division by zero leaves the stack alone
+        run(&mut cpu, "0").unwrap();
+        run(&mut cpu, "/").unwrap();
+        assert!(cpu.overflow);
+
+        // Leaving FLOAT splits X into a 32-bit mantissa in Y and an
+        // exponent in X, in a 56-bit 2's complement word
+        run(&mut cpu, "-0.75").unwrap();
+        run(&mut cpu, "HEX").unwrap();
+        assert_eq!((cpu.float_digits, cpu.word_size.bits(), cpu.complement_mode), (None, 56, ComplementMode::TwosComplement));
+        assert_eq!((cpu.signed_value(cpu.y), cpu.signed_value(cpu.x), cpu.z), (-0xC000_0000, -32, 0));
+        run(&mut cpu, "FLOAT 2").unwrap();
+        assert_eq!(cpu.format_display(), "-0.75");
+        assert!(!cpu.overflow);
+
+        assert_eq!(cpu::float_parts(1.0), (1 << 31, -31));
+        assert_eq!(cpu::float_parts(0.0), (0, 0));
+        assert_eq!(cpu::float_parts(f64::MIN_POSITIVE / 4.0), (1 << 31, -1055));
+
+        // Exponents beyond the float range clamp and set overflow
+        for line in ["DEC", "1", "ENTER", "5000", "FLOAT 0"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert!(cpu.overflow);
+        assert_eq!(Hp16cCpu::float_value(cpu.x), f64::MAX);
+
+        let snapshot = snapshot::Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        restored.set_float(3).unwrap();
+        snapshot.restore(&mut restored);
+        assert_eq!((restored.float_digits, restored.x), (Some(0), cpu.x));
+        assert_eq!(run(&mut cpu, "FLOAT 10"), Err("FLOAT digits must be 0-9".to_string()));
+    }
+
+    #[test]
+    fn test_notifier() {
+        let mut notifier = notify::Notifier::new();
+        assert_eq!(notifier.describe(), "Bell: off  Notifier: none");
+        assert_eq!(notifier.notify("nothing to do"), Ok(()));
+        notifier.command = vec!["hp16c-no-such-notifier".to_string(), "-u".to_string()];
+        assert_eq!(notifier.describe(), "Bell: off  Notifier: hp16c-no-such-notifier -u");
+        assert!(notifier.notify("job done").unwrap_err().starts_with("Could not run notifier hp16c-no-such-notifier"));
+
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["CHECKPOINT A", "1"] {
+            command::execute(&mut calc, &mut storage, line).unwrap();
+        }
+        let error = command::execute(&mut calc, &mut storage, "VERIFY A").unwrap_err();
+        assert!(command::is_verify_failure(&error));
+        assert!(!command::is_verify_failure("No checkpoint: B"));
+
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.set_restricted(true);
+        assert_eq!(dispatcher.check_policy("notify notify-send"), Err("NOTIFY is disabled in restricted mode".to_string()));
+    }
+
+    /// Every combination of the crate's features builds, tests included.
+    /// It runs cargo once per combination, so it only runs when asked:
+    /// `cargo test -- --ignored`. CI covers the same with `cargo hack`.
+    #[test]
+    #[ignore]
+    fn test_feature_combinations() {
+        const FEATURES: &[&str] =
+            &["repl", "scripting", "rom", "faceplate", "sled", "compat", "metrics", "controller", "compiler"];
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        // A target directory of its own, as the running test holds the main one
+        let target = concat!(env!("CARGO_MANIFEST_DIR"), "/target/feature-matrix");
+        for combination in 0..1u32 << FEATURES.len() {
+            let features: Vec<&str> = FEATURES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| combination >> i & 1 == 1)
+                .map(|(_, feature)| *feature)
+                .collect();
+            let status = std::process::Command::new(&cargo)
+                .args(["check", "--quiet", "--all-targets", "--no-default-features"])
+                .args(["--manifest-path", manifest, "--target-dir", target])
+                .arg(format!("--features={}", features.join(",")))
+                .status()
+                .unwrap();
+            assert!(status.success(), "build failed with features {:?}", features);
+        }
+    }
+
+    #[test]
+    fn test_cpu_builder() {
+        let mut cpu = Hp16cCpu::builder()
+            .word_size(32)
+            .base(Base::Decimal)
+            .complement(ComplementMode::TwosComplement)
+            .registers(32)
+            .build()
+            .unwrap();
+        assert_eq!((cpu.word_size.bits(), cpu.base, cpu.complement_mode), (32, Base::Decimal, ComplementMode::TwosComplement));
+        // 50 four-byte registers fit in the memory pool
+        assert_eq!(cpu.memory.len(), 50);
+        let defaults = Hp16cCpu::builder().build().unwrap();
+        assert_eq!((defaults.word_size, defaults.base, defaults.memory.len()), (WordSize::default(), Base::Hex, 101));
+        assert!(Hp16cCpu::builder().word_size(0).build().is_err());
+        assert!(Hp16cCpu::builder().word_size(129).build().is_err());
+        assert!(Hp16cCpu::builder().word_size(32).registers(51).build().is_err());
+        assert!(Hp16cCpu::builder().program_steps(204).build().is_err());
+
+        // Register commands follow the built count
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "7").unwrap();
+        run(&mut cpu, "STO 49").unwrap();
+        assert_eq!(cpu.memory[49], 7);
+        assert!(run(&mut cpu, "STO 50").is_err());
+        run(&mut cpu, "RESULTS 20 49").unwrap();
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!((restored.memory.len(), restored.memory[49]), (50, 7));
+
+        // 196 program steps leave 7 bytes, not enough for a 16-byte register
+        let mut small = Hp16cCpu::builder().word_size(128).program_steps(196).build().unwrap();
+        assert_eq!(small.available_registers(), 0);
+        assert!(command::execute(&mut small, &mut MemoryStorage::new(), "RESULTS ON").is_err());
+    }
+
+    #[test]
+    fn test_binary_windows() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "WS 32").unwrap();
+        run(&mut cpu, "12345").unwrap();
+        run(&mut cpu, "BIN").unwrap();
+        // 1 0010 0011 0100 0101 takes three windows
+        assert_eq!(cpu.window_count(), 3);
+        assert_eq!(cpu.get_stack_display()[3], "X: .01000101");
+        run(&mut cpu, "<").unwrap();
+        assert_eq!(cpu.get_stack_display()[3], "X: .00100011.");
+        run(&mut cpu, "<").unwrap();
+        run(&mut cpu, "<").unwrap();
+        assert_eq!((cpu.window, cpu.get_stack_display()[3].as_str()), (2, "X: 1."));
+        // Shorter registers show what they have in the window
+        assert_eq!(cpu.get_stack_display()[2], "Y: .");
+        run(&mut cpu, ">").unwrap();
+        assert_eq!(cpu.window, 1);
+        let frame = display::render_lines(&cpu);
+        assert!(frame.iter().all(|line| line.chars().count() == frame[0].chars().count()));
+
+        // Any other command returns to the lowest window
+        run(&mut cpu, "ENTER").unwrap();
+        assert_eq!(cpu.window, 0);
+        run(&mut cpu, ">").unwrap();
+        assert_eq!(cpu.window, 0);
+        run(&mut cpu, "HEX").unwrap();
+        run(&mut cpu, "<").unwrap();
+        assert_eq!((cpu.window, cpu.get_stack_display()[3].as_str()), (0, "X: 12345"));
+    }
+
+    #[test]
+    fn test_cpu_accessors() {
+        let mut cpu = Hp16cCpu::builder().word_size(8).build().unwrap();
+        cpu.set_x(0x1FF);
+        cpu.set_y(0x42);
+        cpu.set_z(0x100);
+        cpu.set_t(7);
+        assert_eq!((cpu.x(), cpu.y(), cpu.z(), cpu.t()), (0xFF, 0x42, 0, 7));
+        cpu.set_word_size(0);
+        assert_eq!((cpu.base(), cpu.word_size().bits()), (Base::Hex, 8));
+        cpu.set_word_size(4);
+        assert_eq!((cpu.x(), cpu.y()), (0xF, 0x2));
+        cpu.clear_stack();
+        assert_eq!((cpu.x(), cpu.y(), cpu.z(), cpu.t()), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_leading_zeros_flag() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "WS 12").unwrap();
+        run(&mut cpu, "3F").unwrap();
+        assert_eq!(cpu.get_stack_display()[3], "X: 3F");
+        run(&mut cpu, "SF 3").unwrap();
+        assert!(cpu.leading_zeros());
+        assert_eq!(cpu.get_stack_display()[3], "X: 03F");
+        assert_eq!(cpu.get_stack_display()[2], "Y: 000");
+        run(&mut cpu, "OCT").unwrap();
+        assert_eq!(cpu.format_display(), "0077");
+        run(&mut cpu, "DEC").unwrap();
+        assert_eq!(cpu.format_display(), "63");
+        // Binary pads to 12 digits, so X takes two windows
+        run(&mut cpu, "BIN").unwrap();
+        assert_eq!(cpu.window_count(), 2);
+        assert_eq!(cpu.get_stack_display()[3], "X: .00111111");
+        run(&mut cpu, "<").unwrap();
+        assert_eq!(cpu.get_stack_display()[3], "X: 0000.");
+        run(&mut cpu, "CF 3").unwrap();
+        assert_eq!(cpu.format_display(), "111111");
+    }
+
+    #[test]
+    fn test_display_impls() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "3F").unwrap();
+        run(&mut cpu, "ENTER").unwrap();
+        run(&mut cpu, "2").unwrap();
+        assert_eq!(cpu.stack_view().to_string(), "X: 2  Y: 3F  Z: 0  T: 0");
+        assert_eq!(format!("{:#}", cpu.stack_view()), "T: 0\nZ: 0\nY: 3F\nX: 2");
+        assert_eq!(
+            cpu.stack_view().base(Base::Binary).leading_zeros(true).to_string(),
+            "X: 0000000000000010  Y: 0000000000111111  Z: 0000000000000000  T: 0000000000000000"
+        );
+        assert_eq!(cpu.to_string(), "X: 2  Y: 3F  Z: 0  T: 0  [HEX WS 16 UNSGN]");
+        assert_eq!(format!("{:#}", cpu), display::render_lines(&cpu).join("\n"));
+
+        run(&mut cpu, "DEC").unwrap();
+        run(&mut cpu, "2S").unwrap();
+        run(&mut cpu, "CHS").unwrap();
+        run(&mut cpu, "SF 4").unwrap();
+        let snapshot = Snapshot::capture(&cpu);
+        assert_eq!(snapshot.to_string(), "X: -2  Y: 63  Z: 0  T: 0  [DEC WS 16 2'S CARRY]");
+        assert_eq!(snapshot.to_string(), cpu.to_string());
+
+        run(&mut cpu, "FLOAT 2").unwrap();
+        assert!(cpu.to_string().contains("[FLOAT 2 WS 64 2'S"));
+    }
+
+    #[test]
+    fn test_stack_and_memory_access() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["1", "ENTER", "2", "ENTER", "3", "STO 2", "ENTER", "4"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(cpu.stack_iter().collect::<Vec<_>>(), vec![4, 3, 2, 1]);
+        assert_eq!(cpu.stack_iter().max(), Some(4));
+        assert_eq!((cpu[StackRegister::X], cpu[StackRegister::T]), (4, 1));
+        assert_eq!(cpu[2], 3);
+        assert_eq!(cpu.memory().len(), 101);
+        assert_eq!(cpu.memory().iter().filter(|value| **value != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_float_keeps_registers() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        // 29 registers fit at 56 bits but only 25 at 64
+        for line in ["WS 56", "DEC", "1234", "STO 27", "CLX", "FLOAT 2"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.available_registers(), cpu.memory()[27]), (29, 1234));
+        for line in ["HEX", "RCL 27"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.word_size().bits(), cpu.x()), (56, 1234));
+
+        // From a narrow word size, registers stay until FLOAT is left
+        for line in ["WS 16", "7", "STO 90", "FLOAT 2"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.available_registers(), cpu.memory()[90]), (101, 7));
+        let mut restored = Hp16cCpu::new();
+        Snapshot::capture(&cpu).restore(&mut restored);
+        assert_eq!((restored.available_registers(), restored.memory()[90]), (101, 7));
+    }
+
+    #[test]
+    fn test_memory_pool() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        // 203 bytes of registers of one byte per started 8 bits
+        assert_eq!(cpu.available_registers(), 101);
+        run(&mut cpu, "WS 8").unwrap();
+        assert_eq!(cpu.memory().len(), 203);
+        run(&mut cpu, "WS 64").unwrap();
+        assert_eq!(cpu.memory().len(), 25);
+        assert_eq!(cpu::register_bytes(64), 8);
+
+        run(&mut cpu, "5").unwrap();
+        run(&mut cpu, "STO 24").unwrap();
+        run(&mut cpu, "STO 0").unwrap();
+        // Program memory is taken seven steps at a time, from the top
+        cpu.allocate_program(1).unwrap();
+        assert_eq!((cpu.program_bytes(), cpu.available_registers()), (7, 24));
+        let error = run(&mut cpu, "STO 24").unwrap_err();
+        assert!(error.contains("R24 does not exist"), "{}", error);
+        assert_eq!(cpu[0], 5);
+        cpu.allocate_program(8).unwrap();
+        assert_eq!((cpu.program_bytes(), cpu.available_registers()), (14, 23));
+        assert!(cpu.allocate_program(204).is_err());
+        assert_eq!(cpu.program_steps(), 8);
+
+        // Program memory is part of the saved session
+        let snapshot = Snapshot::from_json(&Snapshot::capture(&cpu).to_json().unwrap()).unwrap();
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!((restored.program_steps(), restored.memory().len(), restored[0]), (8, 23, 5));
+
+        // Sessions saved with 16 registers get the rest of the pool
+        let mut old = Snapshot::capture(&Hp16cCpu::new());
+        old.memory.truncate(16);
+        old.program_steps = 0;
+        old.restore(&mut restored);
+        assert_eq!(restored.memory().len(), 101);
+    }
+
+    #[test]
+    fn test_file_argument_completion() {
+        assert!(help::completes_file("LOAD wo"));
+        assert!(help::completes_file("save "));
+        assert!(help::completes_file("DIFF mine.json yo"));
+        assert!(help::completes_file("TAPE SAVE out"));
+        assert!(help::completes_file("source setup"));
+        // Still completing the command itself, or a command without files
+        assert!(!help::completes_file("LOAD"));
+        assert!(!help::completes_file("TAPE "));
+        assert!(!help::completes_file("STO 1"));
+        assert!(!help::completes_file("SAVEX a"));
+
+        let keywords: Vec<String> = help::COMMANDS
+            .iter()
+            .filter(|entry| entry.argument == help::Argument::File)
+            .map(|entry| entry.keyword())
+            .collect();
+        assert!(keywords.contains(&"TRAIL SAVE".to_string()));
+        let sto = help::COMMANDS.iter().find(|entry| entry.command == "STO [n]").unwrap();
+        assert_eq!((sto.keyword().as_str(), sto.argument), ("STO", help::Argument::None));
+    }
+
+    #[test]
+    fn test_index_register() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["5", "STO I", "2A", "STO (i)", "RCL I", "RCL (I)"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.i(), cpu[5]), (5, 0x2A));
+        assert_eq!(cpu.stack_iter().take(3).collect::<Vec<_>>(), vec![0x2A, 5, 0x2A]);
+
+        // Signed modes use the magnitude of I
+        run(&mut cpu, "2S").unwrap();
+        cpu.set_i(0xFFFD);
+        assert_eq!(cpu.indirect_register(), Ok(3));
+
+        // A register that does not exist is an error and leaves the stack
+        cpu.set_i(101);
+        let stack: Vec<u128> = cpu.stack_iter().collect();
+        let error = run(&mut cpu, "RCL (i)").unwrap_err();
+        assert!(error.contains("R101 does not exist"), "{}", error);
+        assert!(run(&mut cpu, "STO (i)").is_err());
+        assert_eq!(cpu.stack_iter().collect::<Vec<_>>(), stack);
+
+        // I follows the word size and is saved with the session
+        run(&mut cpu, "WS 4").unwrap();
+        assert_eq!(cpu.i(), 5);
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.i(), 5);
+        assert!(run(&mut cpu, "TAG 3 i").is_err());
+    }
+
+    #[test]
+    fn test_project_folder() {
+        use project::Project;
+
+        let root = std::env::temp_dir().join(format!("hp16c_project_{}", std::process::id()));
+        let nested = root.join("firmware").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert!(Project::find(&nested).is_none());
+
+        // The nearest .rpn_rust/ folder applies, from any directory below it
+        std::fs::create_dir_all(root.join(project::PROJECT_DIR)).unwrap();
+        let mut found = Project::find(&nested).unwrap();
+        assert_eq!(found.dir(), root.join(project::PROJECT_DIR));
+
+        let mut cpu = Hp16cCpu::new();
+        cpu.set_x(0x1234);
+        found.save_session(&cpu).unwrap();
+        std::fs::write(found.path(project::REGISTERS_FILE), "# UART block\n0 uart_base\n1 uart_ctrl\n").unwrap();
+        let mut loaded = Hp16cCpu::new();
+        assert!(found.load(&mut loaded).is_empty());
+        assert_eq!(loaded.x(), 0x1234);
+        assert_eq!(loaded.register_for("UART_CTRL"), Some(1));
+
+        std::fs::write(found.path(project::REGISTERS_FILE), "0\n").unwrap();
+        let warnings = found.load(&mut Hp16cCpu::new());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("line 1"), "{}", warnings[0]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut dispatcher = Dispatcher::new();
+        let output = dispatcher.execute_batch(&mut cpu, &mut storage, "DEC; 6; ENTER\n7; *; AUTOENTER").unwrap();
+        assert_eq!((cpu.x(), output), (42, vec!["Auto-ENTER is off".to_string()]));
+
+        // The state threads through JSON between runs
+        let json = Snapshot::capture(&cpu).to_json().unwrap();
+        let mut next = Hp16cCpu::new();
+        Snapshot::from_json(&json).unwrap().restore(&mut next);
+        dispatcher.execute_batch(&mut next, &mut storage, "1;+").unwrap();
+        assert_eq!(next.format_display(), "43");
+
+        // The first error stops the batch and names its command
+        let error = dispatcher.execute_batch(&mut next, &mut storage, "2; BOGUS; 3").unwrap_err();
+        assert!(error.starts_with("BOGUS: "), "{}", error);
+        assert_eq!(next.x(), 2);
+        dispatcher.execute_batch(&mut next, &mut storage, "5; QUIT; 6").unwrap();
+        assert_eq!(next.x(), 5);
+
+        dispatcher.set_restricted(true);
+        assert!(dispatcher.execute_batch(&mut next, &mut storage, "SAVE a.json").is_err());
+    }
+
+    #[test]
+    fn test_index_exchange() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["5", "STO I", "9", "X<>(i)"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.x(), cpu[5]), (0, 9));
+        run(&mut cpu, "X<>(I)").unwrap();
+        assert_eq!((cpu.x(), cpu[5]), (9, 0));
+        run(&mut cpu, "x<>i").unwrap();
+        assert_eq!((cpu.x(), cpu.i()), (5, 9));
+        // The next number lifts the exchanged value
+        run(&mut cpu, "1").unwrap();
+        assert_eq!((cpu.x(), cpu.y()), (1, 5));
+
+        cpu.set_i(200);
+        assert!(run(&mut cpu, "X<>(i)").is_err());
+        assert_eq!(cpu.x(), 1);
+    }
+
+    #[test]
+    fn test_register_names() {
+        use crate::cpu::{parse_register, register_name};
+
+        assert_eq!(parse_register("a"), Some(10));
+        assert_eq!(parse_register(".3"), Some(19));
+        assert_eq!(parse_register(".F"), Some(31));
+        assert_eq!(parse_register("40"), Some(40));
+        for name in ["G", ".", ".10", "AB", ""] {
+            assert_eq!(parse_register(name), None, "{}", name);
+        }
+        assert_eq!((0..32).map(register_name).filter(|name| parse_register(name).is_none()).count(), 0);
+        assert_eq!((register_name(15), register_name(16), register_name(32)), ("F".to_string(), ".0".to_string(), "32".to_string()));
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        // A hex digit after STO is a register, not a number, even in HEX
+        for line in ["HEX", "7", "STO A", "9", "sto .3", "CLR STK", "RCL 10"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu.x(), cpu[10], cpu[19]), (7, 7, 9));
+        assert!(run(&mut cpu, "TAG .3 sum").is_ok());
+        assert_eq!(cpu.register_for("SUM"), Some(19));
+        assert!(run(&mut cpu, "TAG 4 b").is_err());
+
+        let Ok(Outcome::Message(lines)) = run(&mut cpu, "MEMVIEW") else {
+            panic!("MEMVIEW lists the registers");
+        };
+        assert!(lines[10].starts_with("RA ") && lines[10].ends_with(" 7"));
+        assert!(lines[19].starts_with("R.3 ") && lines[19].ends_with(" 9  sum"));
+        assert!(lines[32].starts_with("R32 "));
+
+        cpu.set_word_size(64);
+        assert_eq!(run(&mut cpu, "STO .F").unwrap_err(), "R31 does not exist: 25 registers fit at word size 64");
+    }
+
+    #[test]
+    fn test_store_arithmetic() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["DEC", "10", "STO 3", "7", "8", "STO+ 3", "sto- 3", "STO× 3"] {
+            run(&mut cpu, line).unwrap();
+        }
+        // R3 = (10 + 8 - 8) × 8, and the stack is untouched
+        assert_eq!((cpu[3], cpu.x(), cpu.y()), (80, 8, 7));
+        run(&mut cpu, "STO/ 3").unwrap();
+        assert_eq!(cpu[3], 10);
+        run(&mut cpu, "3").unwrap();
+        run(&mut cpu, "STO÷ 3").unwrap();
+        assert_eq!((cpu[3], cpu.carry, cpu.overflow), (3, true, false));
+
+        // Flags follow the operation on the stack
+        for line in ["HEX", "FFFF", "STO 4", "2", "STO+ 4"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu[4], cpu.carry, cpu.overflow), (1, true, true));
+
+        // Dividing by zero keeps the register
+        run(&mut cpu, "0").unwrap();
+        run(&mut cpu, "STO/ 4").unwrap();
+        assert_eq!((cpu[4], cpu.overflow), (1, true));
+
+        // Register names, tags and I work as for STO
+        for line in ["TAG 5 total", "4", "STO+ TOTAL", "STO+ A", "5", "STO I", "1", "STO+ (i)"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!((cpu[5], cpu[10]), (5, 4));
+        assert!(run(&mut cpu, "STO+ 500").is_err());
+        assert!(run(&mut cpu, "STO%").is_err());
+    }
+
+    #[test]
+    fn test_base_and_word_size_types() {
+        assert_eq!(Base::try_from(8), Ok(Base::Octal));
+        assert!(Base::try_from(7).is_err());
+        assert_eq!(u8::from(Base::Binary), 2);
+        assert_eq!(format!("[{:>4}]", Base::Hex), "[ HEX]");
+        assert_eq!((Base::Decimal.name(), Base::Octal.digits(), Base::Binary.suffix()), ("decimal", "0-7", 'b'));
+
+        assert_eq!(WordSize::try_from(128).map(|size| size.bits()), Ok(128));
+        assert!(WordSize::try_from(0).is_err());
+        assert!(WordSize::try_from(129).is_err());
+        assert_eq!(format!("{:3}", WordSize::default()), " 16");
+
+        // Snapshots keep the numbers in JSON and refuse invalid ones
+        let mut cpu = Hp16cCpu::builder().word_size(12).base(Base::Binary).build().unwrap();
+        let json = Snapshot::capture(&cpu).to_json().unwrap();
+        assert!(json.contains("\"word_size\": 12") && json.contains("\"base\": 2"));
+        cpu.set_base(Base::Hex);
+        Snapshot::from_json(&json).unwrap().restore(&mut cpu);
+        assert_eq!((cpu.base(), cpu.word_size().bits()), (Base::Binary, 12));
+        assert!(Snapshot::from_json(&json.replace("\"base\": 2", "\"base\": 3")).is_err());
+        assert!(Snapshot::from_json(&json.replace("\"word_size\": 12", "\"word_size\": 200")).is_err());
+    }
+
+    #[test]
+    fn test_memory_status() {
+        let mut cpu = Hp16cCpu::new();
+        let status = cpu.memory_status();
+        assert_eq!((status.free_steps, status.registers, status.unused_bytes), (0, 101, 1));
+        assert_eq!(status.to_string(), "P-0 r-101");
+
+        // Ten steps take two blocks of seven, leaving four free
+        cpu.allocate_program(10).unwrap();
+        cpu.set_word_size(64);
+        let status = cpu.memory_status();
+        assert_eq!((status.free_steps, status.registers, status.register_bytes), (4, 23, 8));
+        assert_eq!(crate::cpu::MEMORY_BYTES, 14 + 23 * 8 + status.unused_bytes);
+
+        let mut storage = MemoryStorage::new();
+        let Ok(Outcome::Message(lines)) = command::execute(&mut cpu, &mut storage, "mem") else {
+            panic!("MEM reports the memory pool");
+        };
+        assert_eq!(lines[0], "P-4 r-23");
+        let expected = "Program steps: 0 (0 bytes, 14 allocated)  Registers: 23 (8 bytes each)  Unused bytes: 5";
+        assert_eq!(lines[1], expected);
+
+        // STO and RCL of .0-.F take two bytes, and numbers one per digit
+        let mut cpu = Hp16cCpu::new();
+        for line in ["P/R", "LBL A", "STO .3", "123", "RCL 5", "P/R"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.program().len(), cpu.program().bytes(), cpu.program_steps()), (4, 7, 7));
+        let Ok(Outcome::Message(lines)) = command::execute(&mut cpu, &mut storage, "MEM") else {
+            panic!("MEM reports the memory pool");
+        };
+        assert_eq!(lines[0], "P-0 r-98");
+        assert!(lines[1].starts_with("Program steps: 4 (7 bytes, 7 allocated)"), "{}", lines[1]);
+        // Deleting a two-byte step gives both back
+        for line in ["P/R", "GTO .002", "DEL", "P/R"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!((cpu.program().bytes(), cpu.program_steps()), (5, 5));
+        let costs = [("STO .F", 2), ("RCL 31", 2), ("STO 15", 1), ("FF", 2), ("1.5", 3), ("DEC", 1), ("CB", 1)];
+        for (step, bytes) in costs {
+            assert_eq!(program::step_bytes(step), bytes, "{}", step);
+        }
+        assert!(cpu.load_program(program::Program::from(vec!["STO .1".to_string(); 102])).is_err());
+    }
+
+    #[test]
+    fn test_window_follow() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["BIN", "WINDOW AUTO", "1"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(cpu.window, 0);
+        // Shifting the bit up moves the window along with it
+        for _ in 0..8 {
+            run(&mut cpu, "SL 1").unwrap();
+        }
+        assert_eq!((cpu.x(), cpu.window), (0x100, 1));
+        assert!(cpu.get_stack_display()[3].ends_with(" 1."));
+
+        // Scrolling overrides the window until the next command
+        run(&mut cpu, ">").unwrap();
+        assert_eq!(cpu.window, 0);
+        run(&mut cpu, "SL 1").unwrap();
+        assert_eq!(cpu.window, 1);
+
+        // The mode is saved with the session
+        let json = Snapshot::capture(&cpu).to_json().unwrap();
+        let mut restored = Hp16cCpu::new();
+        Snapshot::from_json(&json).unwrap().restore(&mut restored);
+        assert!(restored.window_follow);
+
+        run(&mut cpu, "WINDOW MANUAL").unwrap();
+        run(&mut cpu, "SL 1").unwrap();
+        assert_eq!(cpu.window, 0);
+    }
+
+    #[test]
+    fn test_clear_commands() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["1", "ENTER", "2", "STO 4", "STO I", "TAG 4 four", "3"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert!(run(&mut cpu, "CLR").is_err());
+        assert_eq!(cpu.x(), 3);
+
+        // CLX clears only X and the next number replaces it
+        run(&mut cpu, "CLX").unwrap();
+        run(&mut cpu, "5").unwrap();
+        assert_eq!((cpu.x(), cpu.y(), cpu.z()), (5, 2, 1));
+
+        run(&mut cpu, "CLEAR REG").unwrap();
+        assert_eq!((cpu[4], cpu.i(), cpu.x()), (0, 0, 5));
+        assert_eq!(cpu.register_for("FOUR"), Some(4));
+
+        run(&mut cpu, "clr stk").unwrap();
+        assert_eq!((cpu.x(), cpu.y(), cpu.z(), cpu.t()), (0, 0, 0, 0));
+
+        cpu.allocate_program(20).unwrap();
+        assert_eq!(cpu.available_registers(), 91);
+        run(&mut cpu, "CLEAR PRGM").unwrap();
+        assert_eq!((cpu.program_steps(), cpu.available_registers(), cpu.memory().len()), (0, 101, 101));
+    }
+
+    #[test]
+    fn test_flag_sparkline() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut history = History::new(100);
+        for line in ["FFFF", "1", "+", "CF 4", "2", "-"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+            history.record(Snapshot::capture(&cpu));
+        }
+        let flags = history.flags(4);
+        assert_eq!(flags, [(true, true), (false, true), (false, true), (true, true)]);
+        assert_eq!(display::sparkline(flags.iter().map(|f| f.0)), "█▁▁█");
+
+        let lines = display::render_lines_with_flags(&cpu, &flags);
+        assert_eq!(lines.len(), display::render_lines(&cpu).len() + 1);
+        assert!(lines[5].contains("C: █▁▁█  V: ████"), "{}", lines[5]);
+        // The box stays rectangular with the wide block characters
+        assert!(lines.iter().all(|line| line.chars().count() == lines[0].chars().count()));
+
+        // Viewing an earlier state ends the strip there
+        history.back(2);
+        assert_eq!(history.flags(3), [(false, false), (true, true), (false, true)]);
+    }
+
+    #[test]
+    fn test_program() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "P/R").unwrap();
+        for line in ["LBL A", "2", "*", "RTN", "LBL B", "GSB A", "GSB A", "1", "+", "R/S", "5", "+"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert!(run(&mut cpu, "GTO G").is_err());
+        assert_eq!((cpu.program().len(), cpu.pc, cpu.x()), (12, 12, 0));
+        assert_eq!(cpu.program().step(6), Some("GSB A"));
+        assert_eq!(cpu.program_steps(), 12);
+        assert_eq!(cpu.available_registers(), 101 - 7);
+
+        // DEL removes the current step; GTO .nnn moves without recording
+        run(&mut cpu, "GTO .002").unwrap();
+        run(&mut cpu, "DEL").unwrap();
+        run(&mut cpu, "3").unwrap();
+        assert_eq!((cpu.program().step(2), cpu.program().len()), (Some("3"), 12));
+        run(&mut cpu, "P/R").unwrap();
+        assert!(!cpu.program_mode);
+
+        // GSB B calls A twice, stops at R/S and R/S continues from there
+        run(&mut cpu, "4").unwrap();
+        run(&mut cpu, "GSB B").unwrap();
+        assert_eq!((cpu.x(), cpu.pc), (0x25, 11));
+        run(&mut cpu, "R/S").unwrap();
+        assert_eq!((cpu.x(), cpu.pc), (0x2A, 0));
+        run(&mut cpu, "GTO B").unwrap();
+        assert_eq!(cpu.pc, 5);
+        assert!(run(&mut cpu, "LBL 1").is_err());
+        assert_eq!(run(&mut cpu, "GSB 9"), Err("No LBL 9 in program memory".to_string()));
+
+        // Errors name the line; subroutines nest four deep
+        let mut looping = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["P/R", "LBL 1", "GSB 1", "P/R"] {
+            command::execute(&mut looping, &mut storage, line).unwrap();
+        }
+        assert_eq!(
+            command::execute(&mut looping, &mut storage, "GSB 1"),
+            Err("Line 002: subroutines nested more than 4 deep".to_string())
+        );
+
+        let snapshot = Snapshot::capture(&cpu);
+        let mut restored = Hp16cCpu::new();
+        snapshot.restore(&mut restored);
+        assert_eq!(restored.program(), cpu.program());
+        assert_eq!(restored.program_steps(), 12);
+        cpu.clear_program();
+        assert!(cpu.program().is_empty());
+        assert_eq!(snapshot.diff(&Snapshot::capture(&cpu)).iter().filter(|d| d.field.starts_with("Line")).count(), 12);
+    }
+
+    #[test]
+    fn test_restricted_program() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        // A program from a state file, with a step that cannot be keyed in
+        let steps = ["LBL A", "SAVE out.json", "RTN"].map(String::from);
+        calc.set_program(program::Program::from(steps.to_vec()));
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.set_restricted(true);
+        for line in ["GSB A", "R/S", "MAPR 0-1 GSB A"] {
+            let result = dispatcher.execute(&mut calc, &mut storage, line);
+            assert!(result.unwrap_err().contains("SAVE is disabled in restricted mode"), "{}", line);
+            dispatcher.execute(&mut calc, &mut storage, "GTO A").unwrap();
+        }
+        assert!(!storage.exists("out.json").unwrap());
+
+        dispatcher.set_restricted(false);
+        dispatcher.execute(&mut calc, &mut storage, "P/R").unwrap();
+        for line in ["NOTIFY notify-send", "source setup.rpn", "MAPR 0-1 SAVE x"] {
+            let result = dispatcher.execute(&mut calc, &mut storage, line);
+            assert!(result.unwrap_err().contains("cannot be a program step"), "{}", line);
+        }
+        assert_eq!(calc.program().len(), 3);
+    }
+
+    #[test]
+    fn test_test_instructions() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        let shown = |outcome: Result<command::Outcome, String>| match outcome {
+            Ok(command::Outcome::Message(lines)) => lines.join(" "),
+            other => panic!("expected a message, got {:?}", other),
+        };
+        run(&mut cpu, "6").unwrap();
+        run(&mut cpu, "5").unwrap();
+        assert_eq!(shown(run(&mut cpu, "X<=Y?")), "TRUE");
+        assert_eq!(shown(run(&mut cpu, "x≤y?")), "TRUE");
+        assert_eq!(shown(run(&mut cpu, "X>Y?")), "SKIP");
+        assert_eq!(shown(run(&mut cpu, "X≠Y?")), "TRUE");
+        assert_eq!(shown(run(&mut cpu, "X = Y?")), "SKIP");
+        assert_eq!((cpu.x(), cpu.y()), (5, 6));
+
+        // X<0? depends on the complement mode; 1's complement -0 equals 0
+        run(&mut cpu, "FFFF").unwrap();
+        assert_eq!(shown(run(&mut cpu, "X<0?")), "SKIP");
+        run(&mut cpu, "1S").unwrap();
+        assert_eq!(shown(run(&mut cpu, "X=0?")), "TRUE");
+        run(&mut cpu, "2S").unwrap();
+        assert_eq!(shown(run(&mut cpu, "X<0?")), "TRUE");
+        assert_eq!(shown(run(&mut cpu, "X>0?")), "SKIP");
+        run(&mut cpu, "UNSGN").unwrap();
+
+        // In a program a false test skips the next step
+        for line in ["P/R", "LBL A", "X<=Y?", "-", "3", "B?", "GTO 1", "1", "RTN", "LBL 1", "2", "P/R"] {
+            run(&mut cpu, line).unwrap();
+        }
+        run(&mut cpu, "CLR STK").unwrap();
+        run(&mut cpu, "9").unwrap();
+        run(&mut cpu, "ENTER").unwrap();
+        run(&mut cpu, "1").unwrap();
+        run(&mut cpu, "GSB A").unwrap();
+        // 9 - 1 = 8 has bit 3 set, so GTO 1 runs and pushes 2
+        assert_eq!((cpu.x(), cpu.y(), cpu.carry), (2, 8, true));
+
+        run(&mut cpu, "8").unwrap();
+        run(&mut cpu, "ENTER").unwrap();
+        run(&mut cpu, "9").unwrap();
+        run(&mut cpu, "GSB A").unwrap();
+        // 9 > 8 skips the -, and bit 3 of 9 is set
+        assert_eq!((cpu.x(), cpu.y()), (2, 9));
+
+        run(&mut cpu, "4").unwrap();
+        run(&mut cpu, "ENTER").unwrap();
+        run(&mut cpu, "5").unwrap();
+        run(&mut cpu, "GSB A").unwrap();
+        // Bit 3 of 5 is clear, so the GTO is skipped and 1 is pushed
+        assert_eq!((cpu.x(), cpu.y()), (1, 5));
+
+        run(&mut cpu, "FLOAT 2").unwrap();
+        assert!(run(&mut cpu, "B?").is_err());
+        assert_eq!(program::Test::parse("F? 5"), Some(program::Test::Flag(5)));
+        assert_eq!(program::Test::parse("F? 6"), None);
+    }
+
+    #[test]
+    fn test_plugins() {
+        use plugin::Plugin;
+        use std::sync::{Arc, Mutex};
+
+        /// Handles PING and logs what every line returned.
+        struct Recorder {
+            log: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl Plugin for Recorder {
+            fn name(&self) -> &str {
+                "recorder"
+            }
+
+            fn on_command(&mut self, cpu: &mut Hp16cCpu, line: &str) -> Option<Result<Outcome, String>> {
+                (line == "PING").then(|| Ok(Outcome::Message(vec![format!("PONG {}", cpu.x())])))
+            }
+
+            fn on_result(&mut self, _cpu: &Hp16cCpu, line: &str, outcome: &Result<Outcome, String>) {
+                self.log.lock().unwrap().push(format!("{} {}", line, outcome.is_ok()));
+            }
+
+            fn extra_commands(&self) -> Vec<String> {
+                vec!["PING".to_string()]
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.set_restricted(true);
+        dispatcher.add_plugin(Recorder { log: Arc::clone(&log) });
+        assert_eq!(dispatcher.plugins().names(), ["recorder"]);
+        assert_eq!(dispatcher.plugins().commands(), ["PING"]);
+
+        dispatcher.execute(&mut cpu, &mut storage, "7").unwrap();
+        let outcome = dispatcher.execute(&mut cpu, &mut storage, "PING").unwrap();
+        assert_eq!(outcome, Outcome::Message(vec!["PONG 7".to_string()]));
+        assert!(dispatcher.execute(&mut cpu, &mut storage, "NOPE").is_err());
+        // Lines refused by the policy never reach the plugins
+        assert!(dispatcher.execute(&mut cpu, &mut storage, "SAVE x").is_err());
+        assert_eq!(*log.lock().unwrap(), ["7 true", "PING true", "NOPE false"]);
+
+        #[cfg(feature = "metrics")]
+        {
+            let mut dispatcher = Dispatcher::new();
+            dispatcher.add_plugin(metrics::Metrics::new());
+            for line in ["1", "2", "+", "NOPE"] {
+                let _ = dispatcher.execute(&mut cpu, &mut storage, line);
+            }
+            let Ok(Outcome::Message(lines)) = dispatcher.execute(&mut cpu, &mut storage, "metrics") else {
+                panic!("METRICS shows its counts");
+            };
+            assert!(lines[0].starts_with("Commands: 4  Errors: 1"));
+        }
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        run(&mut cpu, "TAG 3 seed").unwrap();
+        run(&mut cpu, "P/R").unwrap();
+        let steps = ["LBL A", "WS 32", "WS 128", "MIN", "STO .F", "STO 40", "RCL seed", "SL 1", "SL 4", "FFFF", "X<=Y?"];
+        for line in steps {
+            run(&mut cpu, line).unwrap();
+        }
+        run(&mut cpu, "10000000000000000").unwrap();
+        let output = match run(&mut cpu, "CHECK") {
+            Ok(command::Outcome::Message(lines)) => lines,
+            other => panic!("expected a message, got {:?}", other),
+        };
+        assert_eq!(
+            output,
+            [
+                "003- WS 128: word size 128 is above the HP-16C's 64 bits",
+                "004- MIN: not an HP-16C instruction",
+                "006- STO 40: R40 can only be reached through I on an HP-16C",
+                "007- RCL seed: register tags are an emulator extension",
+                "009- SL 4: the HP-16C shifts one bit at a time",
+                "012- 10000000000000000: needs more than 64 bits",
+            ]
+        );
+
+        // Strict mode refuses those steps as they are keyed
+        run(&mut cpu, "STRICT ON").unwrap();
+        assert!(cpu.strict);
+        assert_eq!(run(&mut cpu, "MAX"), Err("MAX: not an HP-16C instruction (strict mode)".to_string()));
+        assert!(run(&mut cpu, "WS 65").is_err());
+        run(&mut cpu, "WS 64").unwrap();
+        assert_eq!(cpu.program().len(), 13);
+        run(&mut cpu, "P/R").unwrap();
+
+        run(&mut cpu, "CLEAR PRGM").unwrap();
+        assert_eq!(run(&mut cpu, "CHECK"), Ok(command::Outcome::Message(vec!["Program memory is empty".to_string()])));
+        run(&mut cpu, "WS 128").unwrap();
+        assert_eq!(
+            run(&mut cpu, "CHECK"),
+            Ok(command::Outcome::Message(vec!["Word size 128 is above the HP-16C's 64 bits".to_string()]))
+        );
+        assert_eq!(program::extension("FLOAT 4", Base::Hex), None);
+        assert_eq!(program::extension("1010", Base::Binary), None);
+        assert_eq!(program::extension("MASKL", Base::Hex), None);
+        assert_eq!(program::extension("maskr 8", Base::Hex), Some("the HP-16C takes the mask size from X".to_string()));
+    }
+
+    #[test]
+    fn test_power_on_state() {
+        // The defaults the HP-16C documents for a continuous memory reset
+        let check = |cpu: &Hp16cCpu| {
+            assert_eq!((cpu.word_size(), cpu.base()), (WordSize::default(), Base::Hex));
+            assert_eq!(cpu.word_size().bits(), 16);
+            assert_eq!(cpu.complement_mode, ComplementMode::TwosComplement);
+            assert_eq!(cpu.flags(), [false; 6]);
+            assert_eq!(cpu.stack_iter().collect::<Vec<_>>(), [0, 0, 0, 0]);
+            assert_eq!((cpu.i(), cpu.memory().len(), cpu.program().len()), (0, 101, 0));
+            assert!(cpu.memory().iter().all(|value| *value == 0));
+            assert_eq!(cpu.memory_status().to_string(), "P-0 r-101");
+            assert_eq!((cpu.float_digits, cpu.window, cpu.pc), (None, 0, 0));
+            assert!(cpu.stack_lift);
+        };
+        check(&Hp16cCpu::power_on());
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["WS 32", "1S", "SF 0", "FFFF", "FFFF", "+", "STO 5", "STO I", "TAG 5 five", "PRINT X", "DEC"] {
+            run(&mut cpu, line).unwrap();
+        }
+        for line in ["P/R", "LBL A", "P/R", "BATTERY LOW"] {
+            run(&mut cpu, line).unwrap();
+        }
+        assert_eq!(
+            run(&mut cpu, "PRESET"),
+            Ok(command::Outcome::Message(vec!["Pr Error (continuous memory reset)".to_string()]))
+        );
+        check(&cpu);
+        assert!(cpu.tags.is_empty());
+        // The printer tape and the battery are not part of memory
+        assert!(!cpu.printer.contents().is_empty());
+        assert!(cpu.low_battery);
+        assert!(display::render_lines(&cpu)[3].contains("2'S  *"));
+        run(&mut cpu, "BATTERY OK").unwrap();
+        assert!(!cpu.low_battery);
+    }
+
+    #[test]
+    fn test_single_step() {
+        use program::keycode;
+        assert_eq!(keycode("lbl a").as_deref(), Some("43 22 A"));
+        assert_eq!(keycode("STO .3").as_deref(), Some("44 48 3"));
+        assert_eq!(keycode("F? 3").as_deref(), Some("43 6 3"));
+        assert_eq!(keycode("X<=Y?").as_deref(), Some("43 1"));
+        assert_eq!(keycode("SL 1").as_deref(), Some("42 A"));
+        assert_eq!((keycode("FF"), keycode("WS 128")), (None, None));
+
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        let steps = ["LBL A", "2", "+", "GSB B", "RTN", "LBL B", "3", "*", "RTN"];
+        run(&mut cpu, "P/R").unwrap();
+        for step in steps {
+            run(&mut cpu, step).unwrap();
+        }
+        run(&mut cpu, "P/R").unwrap();
+        run(&mut cpu, "5").unwrap();
+        run(&mut cpu, "GTO A").unwrap();
+
+        let Ok(command::Outcome::Message(lines)) = run(&mut cpu, "SST") else {
+            panic!("SST shows the step");
+        };
+        assert_eq!(lines[0], "001- 43 22 A  LBL A");
+        assert_eq!(lines[1..], cpu.get_stack_display());
+        run(&mut cpu, "SST").unwrap();
+        let Ok(command::Outcome::Message(lines)) = run(&mut cpu, "SST") else {
+            panic!("SST shows the step");
+        };
+        assert_eq!((lines[0].as_str(), lines[4].as_str()), ("003- 40       +", "X: 7"));
+        // GSB enters the subroutine and RTN comes back after it
+        run(&mut cpu, "SST").unwrap();
+        assert_eq!(cpu.pc, 6);
+        for _ in 0..4 {
+            run(&mut cpu, "SST").unwrap();
+        }
+        assert_eq!((cpu.x(), cpu.pc), (0x15, 5));
+        run(&mut cpu, "SST").unwrap();
+        assert_eq!(cpu.pc, 0);
+
+        assert_eq!(run(&mut cpu, "BST"), Ok(command::Outcome::Message(vec!["009- 43 21    RTN".to_string()])));
+        run(&mut cpu, "P/R").unwrap();
+        assert_eq!(run(&mut cpu, "SST"), Ok(command::Outcome::Message(vec!["000-".to_string()])));
+        assert_eq!(run(&mut cpu, "SST"), Ok(command::Outcome::Message(vec!["001- 43 22 A  LBL A".to_string()])));
+        assert_eq!(cpu.program().len(), steps.len());
+        run(&mut cpu, "P/R").unwrap();
+
+        // R/S finishes a subroutine entered by single steps
+        run(&mut cpu, "CLX").unwrap();
+        run(&mut cpu, "GTO A").unwrap();
+        for _ in 0..6 {
+            run(&mut cpu, "SST").unwrap();
+        }
+        assert_eq!(cpu.pc, 8);
+        run(&mut cpu, "R/S").unwrap();
+        assert_eq!((cpu.x(), cpu.pc), (6, 0));
+    }
+
+    #[test]
+    fn test_keypad() {
+        use keypad::{KeyConfig, Keypad, Rollover};
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut keypad = Keypad::new();
+        let tap_all = |keypad: &mut Keypad, keys: &[u8]| -> Vec<String> {
+            keys.iter().flat_map(|key| keypad.tap(*key, start).unwrap()).collect()
+        };
+        // 1 2 + then f A, STO . 3, g LBL A and g x≤y
+        assert_eq!(tap_all(&mut keypad, &[1, 2, 40]), ["12", "+"]);
+        assert_eq!(tap_all(&mut keypad, &[42, 11, 44, 48, 3, 43, 22, 11, 43, 1]), ["SL 1", "STO .3", "LBL A", "X<=Y?"]);
+        // A second shift replaces the first; backspace cancels a prefix,
+        // then digits, then clears X
+        assert_eq!(tap_all(&mut keypad, &[42, 43, 35, 35]), ["CLX"]);
+        assert_eq!(tap_all(&mut keypad, &[44, 35, 15, 7, 35]), Vec::<String>::new());
+        assert_eq!(keypad.entry(), "E");
+        assert_eq!(keypad.flush().as_deref(), Some("E"));
+        assert_eq!(keypad.tap(44, start), Ok(vec![]));
+        assert_eq!(keypad.pending(), "44");
+        assert_eq!(keypad.tap(40, start), Err("Invalid key sequence 44 40".to_string()));
+        assert_eq!(keypad.pending(), "");
+        assert!(keypad.tap(47, start).is_err());
+
+        // Under lockout a key pressed while another is held is dropped
+        keypad.press(1, ms(0)).unwrap();
+        assert_eq!(keypad.press(2, ms(10)), Ok(vec![]));
+        keypad.release(1);
+        keypad.press(3, ms(20)).unwrap();
+        keypad.release(3);
+        assert_eq!(keypad.flush().as_deref(), Some("13"));
+        let config = KeyConfig { rollover: Rollover::Queue, ..KeyConfig::default() };
+        let mut keypad = Keypad::with_config(config);
+        keypad.press(1, ms(0)).unwrap();
+        keypad.press(2, ms(10)).unwrap();
+        assert_eq!(keypad.flush().as_deref(), Some("12"));
+
+        // A prefix left too long is dropped, and bounces are ignored
+        keypad.config.prefix_timeout = Some(Duration::from_millis(500));
+        keypad.config.debounce = Duration::from_millis(30);
+        keypad.tap(43, ms(100)).unwrap();
+        assert_eq!(keypad.tap(4, ms(700)), Ok(vec![]));
+        assert_eq!(keypad.tap(4, ms(710)), Ok(vec![]));
+        assert_eq!(keypad.flush().as_deref(), Some("4"));
+        keypad.tap(43, ms(800)).unwrap();
+        assert_eq!(keypad.tap(4, ms(1200)), Ok(vec![]));
+        assert_eq!(keypad.tap(2, ms(1300)), Ok(vec!["SF 2".to_string()]));
+    }
+
+    #[cfg(feature = "controller")]
+    #[test]
+    fn test_controller() {
+        use controller::{joystick_event, Control, Controller, ControllerEvent, ControllerMap, MidiParser};
+        use keypad::Keypad;
+        use std::time::Instant;
+
+        let map = ControllerMap::parse("# pads\nnote 60 = 1\nnote 61 = 40\ncc 64 = 43\nbutton 0 = 36\n").unwrap();
+        assert_eq!(map.key_for(Control::Note(61)), Some(40));
+        assert!(ControllerMap::parse("note 60 = 41").is_err());
+        assert!(ControllerMap::parse("pedal 1 = 36").is_err());
+
+        // Note on, the same note on by running status, a clock tick, then
+        // note on with velocity 0 for off
+        let mut midi = MidiParser::new();
+        let events: Vec<ControllerEvent> =
+            [0x90, 60, 100, 60, 0xF8, 0, 0xB0, 64, 127].iter().filter_map(|byte| midi.feed(*byte)).collect();
+        let note = |pressed| ControllerEvent { control: Control::Note(60), pressed };
+        let pedal = ControllerEvent { control: Control::ControlChange(64), pressed: true };
+        assert_eq!(events, [note(true), note(false), pedal]);
+
+        let button = |value: u8, kind: u8| joystick_event(&[0, 0, 0, 0, value, 0, kind, 0]);
+        let enter = ControllerEvent { control: Control::Button(0), pressed: true };
+        assert_eq!(button(1, 0x01), Some(enter));
+        // Axis movement and the initial state are not key presses
+        assert_eq!((button(1, 0x02), button(1, 0x81)), (None, None));
+
+        let mut controller = Controller::new(map, Keypad::new());
+        let now = Instant::now();
+        let mut lines = Vec::new();
+        for event in [note(true), note(false), enter, ControllerEvent { pressed: false, ..enter }] {
+            lines.extend(controller.handle(event, now).unwrap());
+        }
+        assert_eq!(lines, ["1", "ENTER"]);
+        // Under lockout the + note is dropped while ENTER is held
+        controller.handle(enter, now).unwrap();
+        let plus = ControllerEvent { control: Control::Note(61), pressed: true };
+        assert_eq!(controller.handle(plus, now), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_program_listing() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let mut run = |cpu: &mut Hp16cCpu, line: &str| command::execute(cpu, &mut storage, line);
+        for line in ["P/R", "LBL A", "STO .3", "F? 3", "X<=Y?", "FF", "MIN", "RTN"] {
+            run(&mut cpu, line).unwrap();
+        }
+        let listing = [
+            "000-",
+            "001- 43 22 A  LBL A",
+            "002- 44 48 3  STO .3",
+            "003- 43 6 3   F? 3",
+            "004- 43 1     X<=Y?",
+            "005-          FF",
+            "006-          MIN",
+            "007- 43 21    RTN",
+        ];
+        // Listing runs in program mode rather than being stored
+        let expected = Ok(command::Outcome::Message(listing.map(String::from).to_vec()));
+        assert_eq!(run(&mut cpu, "prgm list"), expected);
+        assert_eq!(cpu.program().len(), 7);
+    }
+
+    #[test]
+    fn test_program_export() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["P/R", "LBL A", "|", "RTN", "ANNOTATE 1 Entry: X OR Y", "ANNOTATE 3 Done", "P/R"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        // ANNOTATE runs in program mode, and comments follow their steps
+        assert_eq!(cpu.program().len(), 3);
+        cpu.go_to_line(1).unwrap();
+        cpu.insert_step("CLX").unwrap();
+        assert_eq!((cpu.program().comment(1), cpu.program().comment(4)), (Some("Entry: X OR Y"), Some("Done")));
+        cpu.go_to_line(2).unwrap();
+        cpu.delete_step().unwrap();
+        command::execute(&mut cpu, &mut storage, "annotate 3").unwrap();
+        assert_eq!(cpu.program().comments().len(), 1);
+        assert_eq!(command::execute(&mut cpu, &mut storage, "ANNOTATE 9 x"), Err("No step on line 009".to_string()));
+
+        command::execute(&mut cpu, &mut storage, "EXPORT MD Prog.md").unwrap();
+        let expected = "| Line | Keys | Step | Comment |\n|-----:|------|------|---------|\n\
+            | 001 | 43 22 A | LBL A | Entry: X OR Y |\n| 002 | 42 40 | \\| |  |\n| 003 | 43 21 | RTN |  |\n";
+        assert_eq!(storage.read("Prog.md").unwrap().as_deref(), Some(expected));
+
+        // Comments are saved with the session
+        let mut restored = Hp16cCpu::new();
+        Snapshot::capture(&cpu).restore(&mut restored);
+        assert_eq!(restored.program(), cpu.program());
+        cpu.clear_program();
+        assert!(command::execute(&mut cpu, &mut storage, "EXPORT MD Prog.md").is_err());
+    }
+
+    #[test]
+    fn test_program_import() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        let text = "# Count X down to zero\n001- LBL A\n  g x=0? ; GTO B   # done\n1; -; GTO A\nLBL B; x<>y; R↓\nRTN\n";
+        storage.write("count.txt", text).unwrap();
+        let result = command::execute(&mut cpu, &mut storage, "IMPORT count.txt");
+        assert_eq!(result, Ok(Outcome::Message(vec!["10 steps imported from count.txt".to_string()])));
+        let steps = ["LBL A", "X=0?", "GTO B", "1", "-", "GTO A", "LBL B", "SWAP", "RV", "RTN"];
+        assert_eq!(cpu.program().steps(), steps.map(String::from));
+        for line in ["3", "GSB A"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!(cpu.x(), 0);
+
+        // Errors give the line and column, and leave program memory alone
+        for (text, error) in [
+            ("LBL A\nRCL 1;  FOO", "Line 2, column 9: Unknown command or invalid number: FOO"),
+            ("LBL A; DEC; 1A", "Line 1, column 13: Invalid digit 'A' in decimal (valid digits: 0-9)"),
+            ("LBL G", "Line 1, column 1: Invalid label G (0-9, A-F)"),
+            ("LBL A\n  GSB C", "Line 2, column 3: GSB C has no LBL C to go to"),
+            ("LBL A; save x.json", "Line 1, column 8: SAVE X.JSON cannot be a program step"),
+            ("# nothing", "No program steps to import"),
+        ] {
+            storage.write("bad.txt", text).unwrap();
+            assert_eq!(command::execute(&mut cpu, &mut storage, "IMPORT bad.txt"), Err(error.to_string()), "{}", text);
+        }
+        assert_eq!(cpu.program().len(), 10);
+        assert!(command::execute(&mut cpu, &mut storage, "IMPORT missing.txt").is_err());
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn test_compile() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        cpu.set_base(Base::Decimal);
+        // Sum of 1 to n, and the larger of two numbers through a subroutine
+        let source = "input n\ntotal = 0  # running sum\nwhile n > 0 {\n  total = total + n; n = n - 1\n}\n\
+            return total\n";
+        storage.write("sum.txt", source).unwrap();
+        let result = command::execute(&mut cpu, &mut storage, "COMPILE sum.txt").unwrap();
+        let lines = ["22 steps compiled from sum.txt", "n in R0", "total in R1"];
+        assert_eq!(result, Outcome::Message(lines.map(String::from).to_vec()));
+        let steps = [
+            "LBL A", "STO 0", "0", "STO 1", "LBL 0", "0", "RCL 0", "X<=Y?", "GTO 1", "RCL 1", "RCL 0", "+", "STO 1",
+            "RCL 0", "1", "-", "STO 0", "GTO 0", "LBL 1",
+        ];
+        assert_eq!(cpu.program().steps()[..19], steps.map(String::from));
+        for line in ["10", "GSB A"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!(cpu.x(), 55);
+
+        let source = "input a, b\ncall larger\nreturn m\nsub larger {\n  if a >= b { m = a } else { m = b }\n}\n";
+        storage.write("max.txt", source).unwrap();
+        command::execute(&mut cpu, &mut storage, "COMPILE max.txt").unwrap();
+        for (a, b) in [(3, 9), (9, 3), (4, 4)] {
+            for line in [a.to_string(), "ENTER".to_string(), b.to_string(), "GSB A".to_string()] {
+                command::execute(&mut cpu, &mut storage, &line).unwrap();
+            }
+            assert_eq!(cpu.x(), a.max(b), "{} {}", a, b);
+        }
+
+        // Errors give the line, and leave program memory alone
+        let program = cpu.program().clone();
+        for (source, error) in [
+            ("x = y + 1", "Line 1: y is never set"),
+            ("x = 1 +", "Line 1: expected a number, a variable or '('"),
+            (
+                "x = 1 + (2 * (3 + (4 - 5)))",
+                "Line 1: expression needs 5 stack levels and the HP-16C has 4; set part of it to a variable first",
+            ),
+            ("call f\nsub f { call f }", "Line 2: sub f calls itself; the HP-16C cannot recurse"),
+            ("call a\nsub a { call b }\nsub b { call c }\nsub c { call d }\nsub d { call e }\nsub e { x = 1 }",
+                "Line 1: call a nests subroutines 5 deep; the HP-16C returns from 4"),
+            ("if 1 < 2 {\nx = 1", "Line 2: expected '}'"),
+        ] {
+            storage.write("bad.txt", source).unwrap();
+            let result = command::execute(&mut cpu, &mut storage, "COMPILE bad.txt");
+            assert_eq!(result, Err(error.to_string()), "{}", source);
+        }
+        assert_eq!(*cpu.program(), program);
+        command::execute(&mut cpu, &mut storage, "FLOAT 4").unwrap();
+        assert!(command::execute(&mut cpu, &mut storage, "COMPILE sum.txt").is_err());
+    }
+
+    #[cfg(feature = "compiler")]
+    #[test]
+    fn test_decompile() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        assert!(command::execute(&mut cpu, &mut storage, "DECOMPILE").is_err());
+        cpu.set_base(Base::Decimal);
+        let source = "input n\ntotal = 0\nwhile n > 0 {\n  total = total + n; n = n - 1\n}\n\
+            if total >= 100 { total = 100 } else { total = 0 }\nreturn total\n";
+        storage.write("sum.txt", source).unwrap();
+        command::execute(&mut cpu, &mut storage, "COMPILE sum.txt").unwrap();
+        let decompiled = |cpu: &mut Hp16cCpu, storage: &mut MemoryStorage| {
+            match command::execute(cpu, storage, "DECOMPILE") {
+                Ok(Outcome::Message(lines)) => lines.join("\n"),
+                other => panic!("{:?}", other),
+            }
+        };
+        let expected = "label A:\nR0 = x\npush 0\nR1 = x\nloop {\n    push 0\n    push R0\n\
+            \x20   if x <= y {\n        break\n    }\n\
+            \x20   push R1\n    push R0\n    +\n    R1 = x\n    push R0\n    push 1\n    -\n    R0 = x\n}\n\
+            push R1\npush 100\nif x <= y {\n    push 100\n    R1 = x\n} else {\n    push 0\n    R1 = x\n}\n\
+            push R1\nreturn\nreturn";
+        assert_eq!(decompiled(&mut cpu, &mut storage), expected);
+
+        // A published-style listing: a skip over one step, and a GTO that
+        // loops back while a test holds
+        let listing = "LBL B; X=0?; GTO 1; 1; -; GSB C; LBL 1; RTN\nLBL C; X<0?; CHS; LBL 2; 2; /; X>Y?; GTO 2; RTN";
+        storage.write("listing.txt", listing).unwrap();
+        command::execute(&mut cpu, &mut storage, "IMPORT listing.txt").unwrap();
+        let expected = "label B:\nif x != 0 {\n    push 1\n    -\n    call C\n}\nreturn\nlabel C:\n\
+            if x < 0 {\n    CHS\n}\nloop {\n    push 2\n    /\n    if x <= y {\n        break\n    }\n}\nreturn";
+        assert_eq!(decompiled(&mut cpu, &mut storage), expected);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        assert!(command::execute(&mut cpu, &mut storage, "OPTIMIZE").is_err());
+        let listing = "LBL A; X=0?; GTO 1; ENTER; CLX; 5; +; GTO 2; 7; 8\n\
+            LBL 1; GTO 3; LBL 3; GTO 2; LBL 2; CLX; CLX; X=0?; CLX; CLX; RTN; RTN";
+        storage.write("listing.txt", listing).unwrap();
+        command::execute(&mut cpu, &mut storage, "IMPORT listing.txt").unwrap();
+        command::execute(&mut cpu, &mut storage, "ANNOTATE 15 Done").unwrap();
+        let original = cpu.clone();
+        let result = command::execute(&mut cpu, &mut storage, "OPTIMIZE").unwrap();
+        let lines = [
+            "003- GTO 1: now GTO 2, as LBL 1 only goes on there",
+            "005- CLX: unneeded between ENTER and a number",
+            "009- 7: unreachable",
+            "010- 8: unreachable",
+            "011- LBL 1: unreachable",
+            "012- GTO 3: unreachable",
+            "013- LBL 3: unreachable",
+            "014- GTO 2: unreachable",
+            "017- CLX: repeats the CLX before it",
+            "022- RTN: unreachable",
+            "Saved 9 bytes: the program takes 13 of 203 bytes",
+        ];
+        assert_eq!(result, Outcome::Message(lines.map(String::from).to_vec()));
+        let steps = ["LBL A", "X=0?", "GTO 2", "ENTER", "5", "+", "GTO 2", "LBL 2", "CLX", "X=0?", "CLX", "CLX", "RTN"];
+        assert_eq!(cpu.program().steps(), steps.map(String::from));
+        assert_eq!(cpu.program().comment(8), Some("Done"));
+        // The freed bytes go back to the registers
+        assert_eq!(cpu.program_steps(), 13);
+        assert!(cpu.available_registers() > original.available_registers());
+        let result = command::execute(&mut cpu, &mut storage, "OPTIMIZE");
+        assert_eq!(result, Ok(Outcome::Message(vec!["Nothing to optimize".to_string()])));
+
+        // The program runs as before
+        for x in ["0", "3"] {
+            let (mut before, mut after) = (original.clone(), cpu.clone());
+            for calculator in [&mut before, &mut after] {
+                for line in ["9", "ENTER", x, "GSB A"] {
+                    command::execute(calculator, &mut storage, line).unwrap();
+                }
+            }
+            assert_eq!(after.stack_iter().collect::<Vec<_>>(), before.stack_iter().collect::<Vec<_>>(), "{}", x);
+        }
+    }
+
+    #[test]
+    fn test_keystroke_export() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["P/R", "LBL A", "STO .3", "12", "+", "X<=Y?", "RTN", "ANNOTATE 4 Add 12", "P/R"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        let result = command::execute(&mut cpu, &mut storage, "EXPORT KEYS keys.txt");
+        assert_eq!(result, Ok(Outcome::Message(vec!["Keystrokes exported to keys.txt".to_string()])));
+        let expected = [
+            "[ ]      g R/S          43 31     P/R: program mode, from line 000",
+            "[ ] 001  g GTO A        43 22 A   LBL A",
+            "[ ] 002  STO · 3        44 48 3   STO .3",
+            "[ ] 003  1              1         1",
+            "[ ] 004  2              2         2",
+            "[ ] 005  +              40        +  # Add 12",
+            "[ ] 006  g 1            43 1      X<=Y?",
+            "[ ] 007  g GSB          43 21     RTN",
+            "[ ]      g R/S          43 31     P/R: back to run mode",
+        ];
+        assert_eq!(storage.read("keys.txt").unwrap(), Some(expected.join("\n") + "\n"));
+
+        // Steps the HP-16C has no keys for stop the export
+        command::execute(&mut cpu, &mut storage, "P/R").unwrap();
+        command::execute(&mut cpu, &mut storage, "MIN").unwrap();
+        let error = command::execute(&mut cpu, &mut storage, "EXPORT KEYS keys.txt").unwrap_err();
+        assert_eq!(error, "Line 007: MIN has no HP-16C keys; CHECK lists the steps to change");
+    }
+
+    #[test]
+    fn test_panes() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["7", "STO 1", "TAG 1 count", "P/R", "LBL A", "PRINT X", "RTN", "P/R", "GSB A", "P/R", "SST"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        assert_eq!(Pane::parse(" Program"), Some(Pane::Program));
+        assert_eq!(Pane::parse("trace"), None);
+        assert_eq!(Pane::Tape.next(), Pane::Stack);
+
+        let lines = panes::render(&cpu, Pane::default());
+        // The focused pane has the double border
+        assert!(lines[0].starts_with("╔ stack ═"));
+        assert!(lines[0].contains("┌ program ─"));
+        assert!(lines.iter().any(|line| line.contains("║ X: 7")));
+        assert!(lines.iter().any(|line| line.contains("│ R1   7  count")));
+        assert!(lines.iter().any(|line| line.contains("C: 0  V: 0  PRGM 001")));
+        // The current step is marked
+        assert!(lines.iter().any(|line| line.contains("▶ 001- 43 22 A  LBL A")));
+        assert!(lines.iter().any(|line| line.contains("│   002-          PRINT X")));
+        assert!(lines.iter().any(|line| line.contains("7 h")));
+
+        let lines = panes::render(&cpu, Pane::Program);
+        assert!(lines[0].starts_with("┌ stack ─"));
+        assert!(lines[0].contains("╔ program ═"));
+        assert!(lines.iter().any(|line| line.contains("║ ▶ 001-")));
+    }
+
+    #[test]
+    fn test_non_ascii_commands() {
+        let mut cpu = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        for line in ["P/R", "LBL A", "RTN", "P/R"] {
+            command::execute(&mut cpu, &mut storage, line).unwrap();
+        }
+        // Letters that upper-case to ASCII of another length are no command
+        for line in [
+            "ſave out.txt",
+            "Dıﬀ a b",
+            "EXPORT KEYſ out.txt",
+            "ımport out.txt",
+            "compıle out.txt",
+            "TAPE ſAVE out.txt",
+            "ſOURCE out.txt",
+        ] {
+            let result = command::execute(&mut cpu, &mut storage, line);
+            assert!(result.unwrap_err().starts_with("Unknown command"), "{}", line);
+        }
+        assert!(!storage.exists("out.txt").unwrap());
+
+        // Arguments are taken as typed
+        command::execute(&mut cpu, &mut storage, "TAG 1 ſeed").unwrap();
+        command::execute(&mut cpu, &mut storage, "ANNOTATE 1 Größe").unwrap();
+        assert_eq!((cpu.tags()[&1].as_str(), cpu.program().comment(1)), ("ſeed", Some("Größe")));
+        command::execute(&mut cpu, &mut storage, "SAVE ſession.json").unwrap();
+        command::execute(&mut cpu, &mut storage, "LOAD ſession.json").unwrap();
+        command::execute(&mut cpu, &mut storage, "EXPORT MD prögram.md").unwrap();
+        command::execute(&mut cpu, &mut storage, "EXPORT KEYS kéys.txt").unwrap();
+        command::execute(&mut cpu, &mut storage, "TAPE SAVE tāpe.txt").unwrap();
+        storage.write("ımport.txt", "LBL B\nRTN").unwrap();
+        command::execute(&mut cpu, &mut storage, "IMPORT ımport.txt").unwrap();
+        assert!(command::execute(&mut cpu, &mut storage, "MAPR 0-1 ſave x").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "SOURCE ſcript.rpn").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "DIFF ſession.json prögram.md").is_err());
+        assert!(command::execute(&mut cpu, &mut storage, "DECODE ſ").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "scripting")]
+    fn test_restricted_config() {
+        let mut calc = Hp16cCpu::new();
+        let mut storage = MemoryStorage::new();
+        storage.write("kept.json", "{}").unwrap();
+        let context = Context { restricted: true, ..Context::default() };
+
+        // The config sets the calculator up, but its file commands are refused
+        storage.write("config.rpn", "DEC\n42\nSAVE out.json\n").unwrap();
+        let result = command::apply_config(&mut calc, &mut storage, "config.rpn", &context);
+        assert!(result.unwrap_err().contains("restricted mode"));
+        assert_eq!(calc.x, 42);
+        assert!(!storage.exists("out.json").unwrap());
+
+        storage.write("config.rpn", "LOAD kept.json\n").unwrap();
+        let result = command::apply_config(&mut calc, &mut storage, "config.rpn", &context);
+        assert!(result.unwrap_err().contains("restricted mode"));
+        assert_eq!(calc.x, 42);
+
+        // Unrestricted, the same config runs in full
+        storage.write("config.rpn", "DEC\n42\nSAVE out.json\n").unwrap();
+        command::apply_config(&mut calc, &mut storage, "config.rpn", &Context::default()).unwrap();
+        assert!(storage.exists("out.json").unwrap());
+    }
+}
@@ -1,20 +1,87 @@
-mod rom;
-mod cpu;
-
-use cpu::Hp16cCpu;
+use hp16c_rpn::autosave::Autosave;
+use hp16c_rpn::cancel::CancelToken;
+use hp16c_rpn::command::{self, Dispatcher, Outcome};
+use hp16c_rpn::constants;
+#[cfg(feature = "controller")]
+use hp16c_rpn::controller::{joystick_event, Controller, ControllerMap, MidiParser, JOYSTICK_EVENT_SIZE};
+use hp16c_rpn::cpu::{register_name, Base, Hp16cCpu, WordSize, NAMED_REGISTERS};
+use hp16c_rpn::demo::{self, DemoStep};
+use hp16c_rpn::display;
+use hp16c_rpn::help;
+use hp16c_rpn::history::History;
+use hp16c_rpn::jobs::Jobs;
+use hp16c_rpn::keymap::{Key, KeyChord, Keymap};
+#[cfg(feature = "controller")]
+use hp16c_rpn::keypad::Keypad;
+#[cfg(feature = "metrics")]
+use hp16c_rpn::metrics::Metrics;
+use hp16c_rpn::notify::Notifier;
+use hp16c_rpn::pager::Pager;
+use hp16c_rpn::panes::{self, Pane};
+use hp16c_rpn::project::{self, Project};
+use hp16c_rpn::snapshot::Snapshot;
+use hp16c_rpn::storage::{FileStorage, MemoryStorage, Storage};
+use hp16c_rpn::trail::PaperTrail;
+use hp16c_rpn::usage::UsageSummary;
 use rustyline::error::ReadlineError;
-use rustyline::{Editor, Result};
-use rustyline::completion::{Completer, Pair};
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, EventHandler, RepeatCount};
+use rustyline::{Editor, KeyCode, KeyEvent, Modifiers, Result};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
 use rustyline::validate::Validator;
 use rustyline::{Context, Helper};
 use std::borrow::Cow;
 use std::collections::HashSet;
-use std::io;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Number of commands between automatic session saves
+const AUTOSAVE_INTERVAL: usize = 5;
+
+// Number of past states kept for BACK/FORWARD
+const HISTORY_LIMIT: usize = 1000;
+
+// How often FG checks whether a background job has finished
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Optional key bindings loaded at startup
+const KEYMAP_FILE: &str = "hp16c_keymap.txt";
+
+// Optional script of commands run at startup, e.g. to enable RESULTS
+const CONFIG_FILE: &str = "hp16c_config.rpn";
+
+// Line editor history, unless a project keeps its own
+const HISTORY_FILE: &str = "hp16c_history.txt";
 
 struct Hp16cHelper {
     completer: Hp16cCompleter,
+    // Calculator settings that affect how input is checked and previewed
+    input: Arc<InputContext>,
+}
+
+/// Base and word size shared with the line editor.
+struct InputContext {
+    settings: Mutex<(Base, WordSize)>,
+}
+
+impl InputContext {
+    fn new(calc: &Hp16cCpu) -> Self {
+        InputContext {
+            settings: Mutex::new((calc.base(), calc.word_size())),
+        }
+    }
+
+    fn update(&self, calc: &Hp16cCpu) {
+        *self.settings.lock().unwrap() = (calc.base(), calc.word_size());
+    }
+
+    fn settings(&self) -> (Base, WordSize) {
+        *self.settings.lock().unwrap()
+    }
 }
 
 impl Helper for Hp16cHelper {}
@@ -35,12 +102,34 @@ impl Completer for Hp16cHelper {
 impl Hinter for Hp16cHelper {
     type Hint = String;
     
-    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
-        None
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        // Preview a number in all bases before it is entered
+        if pos < line.len() {
+            return None;
+        }
+        let (base, word_size) = self.input.settings();
+        command::number_preview(&line.trim().to_uppercase(), base, word_size).map(|preview| format!("   {}", preview))
     }
 }
 
 impl Highlighter for Hp16cHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        // Show a digit that is invalid in the current base in red. ASCII
+        // upper case keeps the offsets valid for `line`
+        let (base, _) = self.input.settings();
+        match command::invalid_digit(&line.to_ascii_uppercase(), base) {
+            Some((index, digit)) => {
+                let end = index + digit.len_utf8();
+                Cow::Owned(format!("{}\x1b[1;31m{}\x1b[0m{}", &line[..index], &line[index..end], &line[end..]))
+            }
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+
     fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
         &'s self,
         prompt: &'p str,
@@ -62,6 +151,10 @@ impl Validator for Hp16cHelper {}
 
 struct Hp16cCompleter {
     commands: HashSet<String>,
+    // Completions that change during the session, such as user constants
+    session_commands: Arc<Mutex<Vec<String>>>,
+    // Arguments of commands that take files, such as SAVE and SOURCE
+    filenames: FilenameCompleter,
 }
 
 impl Hp16cCompleter {
@@ -70,41 +163,192 @@ impl Hp16cCompleter {
         
         // Basic commands
         commands.insert("HELP".to_string());
+        commands.insert("HELP TASKS".to_string());
         commands.insert("QUIT".to_string());
-        commands.insert("CLEAR".to_string());
-        commands.insert("CLR".to_string());
+        for clear in ["STK", "REG", "PRGM"] {
+            commands.insert(format!("CLR {}", clear));
+            commands.insert(format!("CLEAR {}", clear));
+        }
+        for reset in ["PRESET", "BATTERY LOW", "BATTERY OK"] {
+            commands.insert(reset.to_string());
+        }
+        commands.insert("SAVE".to_string());
+        commands.insert("LOAD".to_string());
+        commands.insert("DIFF".to_string());
+        commands.insert("DEMO".to_string());
+        commands.insert("SOURCE".to_string());
+        commands.insert("CHECKPOINT".to_string());
+        commands.insert("VERIFY".to_string());
+        commands.insert("BACK".to_string());
+        commands.insert("FORWARD".to_string());
+        commands.insert("RESUME".to_string());
+        commands.insert("PRINT X".to_string());
+        commands.insert("TAPE".to_string());
+        commands.insert("TAPE CLEAR".to_string());
+        commands.insert("TAPE SAVE".to_string());
+        commands.insert("TRAIL".to_string());
+        commands.insert("TRAIL ON".to_string());
+        commands.insert("JOBS".to_string());
+        commands.insert("FG".to_string());
+        commands.insert("KILL".to_string());
+        for panes in ["PANES ON", "PANES OFF", "PANES NEXT"] {
+            commands.insert(panes.to_string());
+        }
+        for pane in Pane::ALL {
+            commands.insert(format!("PANES FOCUS {}", pane.name().to_uppercase()));
+        }
+        commands.insert("TIMER ON".to_string());
+        commands.insert("TIMER OFF".to_string());
+        commands.insert("SPARKLINE ON".to_string());
+        commands.insert("SPARKLINE OFF".to_string());
+        commands.insert("USAGE".to_string());
+        for notify in ["BELL ON", "BELL OFF", "NOTIFY", "NOTIFY OFF"] {
+            commands.insert(notify.to_string());
+        }
+        commands.insert("TRAIL OFF".to_string());
+        commands.insert("TRAIL CLEAR".to_string());
+        commands.insert("TRAIL SAVE".to_string());
         
         // Stack operations
         commands.insert("ENTER".to_string());
+        commands.insert("CLX".to_string());
         commands.insert("DROP".to_string());
         commands.insert("SWAP".to_string());
         commands.insert("RV".to_string());
         commands.insert("R^".to_string());
+        commands.insert("CHS".to_string());
+        commands.insert("ABS".to_string());
+        commands.insert("RMD".to_string());
+        commands.insert(">BCD".to_string());
+        commands.insert("BCD>".to_string());
+        commands.insert("BCD+".to_string());
+        commands.insert("BCD-".to_string());
+        commands.insert("MIN".to_string());
+        commands.insert("MAX".to_string());
+        commands.insert("CMP".to_string());
+        commands.insert("DBL*".to_string());
+        commands.insert("DBL/".to_string());
+        commands.insert("DBLR".to_string());
+        commands.insert("PUSHN".to_string());
+        commands.insert("FIELD".to_string());
+        commands.insert("FIELD OFF".to_string());
         
         // Number bases
         commands.insert("HEX".to_string());
         commands.insert("DEC".to_string());
         commands.insert("OCT".to_string());
         commands.insert("BIN".to_string());
+        commands.insert("<".to_string());
+        commands.insert(">".to_string());
+        for window in ["WINDOW", "WINDOW AUTO", "WINDOW MANUAL"] {
+            commands.insert(window.to_string());
+        }
+        commands.insert("FLOAT".to_string());
+        
+        // Complement modes
+        commands.insert("UNSGN".to_string());
+        commands.insert("1S".to_string());
+        commands.insert("2S".to_string());
         
         // Memory operations (with space for parameter)
-        for i in 0..16 {
-            commands.insert(format!("STO {}", i));
-            commands.insert(format!("RCL {}", i));
+        for name in (0..NAMED_REGISTERS).map(register_name) {
+            commands.insert(format!("STO {}", name));
+            commands.insert(format!("RCL {}", name));
+        }
+        for register in ["I", "(I)"] {
+            commands.insert(format!("STO {}", register));
+            commands.insert(format!("RCL {}", register));
+            commands.insert(format!("X<>{}", register));
         }
         
         // Word size operations (common sizes)
         for size in [1, 2, 4, 8, 16, 32, 64, 128] {
             commands.insert(format!("WS {}", size));
         }
+        for policy in ["TRUNCATE", "WARN", "PRESERVE", "ERROR"] {
+            commands.insert(format!("WSPOLICY {}", policy));
+        }
         
         // Shift operations (common shift amounts)
         for shift in 1..=8 {
             commands.insert(format!("SL {}", shift));
             commands.insert(format!("SR {}", shift));
         }
+        commands.insert("MASKL".to_string());
+        commands.insert("MASKR".to_string());
+        commands.insert("SB".to_string());
+        commands.insert("CB".to_string());
+        commands.insert("B?".to_string());
+        commands.insert("RL".to_string());
+        commands.insert("RR".to_string());
+        commands.insert("RLN".to_string());
+        commands.insert("RRN".to_string());
+        commands.insert("RLC".to_string());
+        commands.insert("RRC".to_string());
         
-        Self { commands }
+        // Constants
+        commands.insert("CONST".to_string());
+        commands.insert("MEMVIEW".to_string());
+        commands.insert("SUMR".to_string());
+        commands.insert("XORR".to_string());
+        commands.insert("REDUCE".to_string());
+        commands.insert("MAPR".to_string());
+        commands.insert("RADD".to_string());
+        commands.insert("RXOR".to_string());
+        for operation in ["+", "-", "*", "/"] {
+            commands.insert(format!("STO{}", operation));
+        }
+        for width in [8, 16, 32, 64] {
+            commands.insert(format!("ST{}", width));
+            commands.insert(format!("LD{}", width));
+        }
+        commands.insert("SCRATCH".to_string());
+        commands.insert("SCRATCH CLEAR".to_string());
+        for codec in ["DECODE HEX", "DECODE B64", "ENCODE HEX", "ENCODE B64"] {
+            commands.insert(codec.to_string());
+        }
+        commands.insert("UTF8".to_string());
+        for checksum in ["CKSUM IP", "CKSUM F16", "CKSUM F32"] {
+            commands.insert(checksum.to_string());
+        }
+        for random in ["RAND", "RAND XORSHIFT", "RAND PCG", "RAND LFSR", "RAND SEED", "RAND STATE", "LFSR"] {
+            commands.insert(random.to_string());
+        }
+        commands.insert("ENDIAN".to_string());
+        commands.insert("ENDIAN LITTLE".to_string());
+        commands.insert("ENDIAN BIG".to_string());
+        commands.insert("BYTES".to_string());
+        commands.insert("STATUS".to_string());
+        commands.insert("MEM".to_string());
+        let programs = [
+            "P/R", "LBL", "GTO", "GSB", "RTN", "R/S", "SST", "BST", "DEL", "PRGM", "PRGM LIST", "CHECK", "STRICT ON",
+            "STRICT OFF", "IMPORT", "COMPILE", "DECOMPILE", "OPTIMIZE", "ANNOTATE", "EXPORT MD", "EXPORT KEYS",
+        ];
+        for program in programs {
+            commands.insert(program.to_string());
+        }
+        for test in ["X=Y?", "X!=Y?", "X<=Y?", "X>Y?", "X=0?", "X!=0?", "X<0?", "X>0?"] {
+            commands.insert(test.to_string());
+        }
+        for auto_enter in ["AUTOENTER", "AUTOENTER ON", "AUTOENTER OFF"] {
+            commands.insert(auto_enter.to_string());
+        }
+        for flag in ["SF", "CF", "F?"] {
+            commands.insert(flag.to_string());
+        }
+        commands.insert("RESULTS".to_string());
+        commands.insert("RESULTS ON".to_string());
+        commands.insert("RESULTS OFF".to_string());
+        commands.insert("TAG".to_string());
+        for constant in constants::BUILTIN {
+            commands.insert(format!("CONST {}", constant.name));
+        }
+
+        Self {
+            commands,
+            session_commands: Arc::new(Mutex::new(Vec::new())),
+            filenames: FilenameCompleter::new(),
+        }
     }
 }
 
@@ -115,18 +359,22 @@ impl Completer for Hp16cCompleter {
         &self,
         line: &str,
         pos: usize,
-        _ctx: &Context<'_>,
+        ctx: &Context<'_>,
     ) -> Result<(usize, Vec<Pair>)> {
-        let line_upper = line.to_uppercase();
+        if help::completes_file(&line[..pos]) {
+            return self.filenames.complete(line, pos, ctx);
+        }
         let mut matches = Vec::new();
         
-        // Find the start of the current word
-        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
-        let word = &line_upper[start..pos];
+        // Complete the whole line so arguments such as "STO 1" match too.
+        // Cut before upper-casing, which can change the length of the text
+        let start = 0;
+        let word = line[start..pos].to_uppercase();
         
         // Find matching commands
-        for command in &self.commands {
-            if command.starts_with(word) {
+        let session_commands = self.session_commands.lock().unwrap();
+        for command in self.commands.iter().chain(session_commands.iter()) {
+            if command.starts_with(&word) {
                 matches.push(Pair {
                     display: command.clone(),
                     replacement: command.clone(),
@@ -141,40 +389,739 @@ impl Completer for Hp16cCompleter {
     }
 }
 
+/// Interactive session state that lives outside the calculator itself.
+struct Repl {
+    calculator: Hp16cCpu,
+    dispatcher: Dispatcher,
+    storage: FileStorage,
+    autosave: Autosave<FileStorage>,
+    trail: PaperTrail,
+    trail_pane: bool,
+    // Show the stack, registers, program and tape side by side, and which
+    // of them has the focus
+    panes: bool,
+    focus: Pane,
+    // Show recent carry and overflow under the flags, from the history
+    sparkline: bool,
+    // Show how long each command took below the display
+    timer: bool,
+    history: History,
+    jobs: Jobs,
+    // Commands and errors of this session, when --usage asked for them
+    usage: Option<UsageSummary>,
+    // Bell and notifier for finished jobs and failed VERIFY
+    notifier: Notifier,
+    session_commands: Arc<Mutex<Vec<String>>>,
+    input: Arc<InputContext>,
+}
+
+impl Repl {
+    /// Run one command line. Returns false when the user quits.
+    fn run_line(&mut self, line: &str) -> bool {
+        // ASCII only, so arguments can be cut from `line` at the same offsets
+        let input = line.to_ascii_uppercase();
+
+        // The REPL runs some file commands itself, so apply the policy first
+        if let Err(message) = self.dispatcher.check_policy(line) {
+            if let Some(usage) = &mut self.usage {
+                usage.record(line, self.calculator.base(), Some(&message));
+            }
+            println!("{}", message);
+            return true;
+        }
+
+        if let Some(handled) = self.run_history_command(&input) {
+            return handled;
+        }
+        if let Some(handled) = self.run_job_command(line, &input) {
+            return handled;
+        }
+
+        match input.as_str() {
+            "HELP" | "H" | "?" => {
+                page(help_lines());
+                return true;
+            },
+            "HELP TASKS" => {
+                println!();
+                page(help::task_index());
+                return true;
+            },
+            "DEMO" => {
+                let cancel = self.dispatcher.cancel_token();
+                cancel.reset();
+                run_demo(&cancel);
+                return true;
+            },
+            "TRAIL" => {
+                println!();
+                page(PaperTrail::render(self.trail.entries()));
+                return true;
+            },
+            "TRAIL ON" => {
+                self.trail_pane = true;
+                return true;
+            },
+            "TRAIL OFF" => {
+                self.trail_pane = false;
+                return true;
+            },
+            "TRAIL CLEAR" => {
+                self.trail.clear();
+                return true;
+            },
+            "PANES ON" => {
+                self.panes = true;
+                return true;
+            },
+            "PANES OFF" => {
+                self.panes = false;
+                return true;
+            },
+            "PANES NEXT" => {
+                self.panes = true;
+                self.focus = self.focus.next();
+                return true;
+            },
+            "TIMER ON" => {
+                self.timer = true;
+                return true;
+            },
+            "TIMER OFF" => {
+                self.timer = false;
+                return true;
+            },
+            "SPARKLINE ON" => {
+                self.sparkline = true;
+                return true;
+            },
+            "SPARKLINE OFF" => {
+                self.sparkline = false;
+                return true;
+            },
+            "BELL ON" | "BELL OFF" => {
+                self.notifier.bell = input == "BELL ON";
+                self.jobs.set_notifier(self.notifier.clone());
+                return true;
+            },
+            "NOTIFY" => {
+                println!("{}", self.notifier.describe());
+                return true;
+            },
+            "NOTIFY OFF" => {
+                self.notifier.command.clear();
+                self.jobs.set_notifier(self.notifier.clone());
+                return true;
+            },
+            "USAGE" => {
+                match &self.usage {
+                    Some(usage) => {
+                        println!();
+                        page(usage.lines());
+                    },
+                    None => println!("Usage summary is off; start with --usage FILE to record one"),
+                }
+                return true;
+            },
+            _ => {
+                if let Some(query) = input.strip_prefix("HELP ?").or_else(|| input.strip_prefix('?')) {
+                    println!();
+                    page(help::search_results(query));
+                    return true;
+                }
+                if input.starts_with("NOTIFY ") {
+                    // The program and its arguments keep their case
+                    self.notifier.command = line[7..].split_whitespace().map(String::from).collect();
+                    self.jobs.set_notifier(self.notifier.clone());
+                    return true;
+                }
+                if let Some(name) = input.strip_prefix("PANES FOCUS ") {
+                    match Pane::parse(name) {
+                        Some(pane) => {
+                            self.panes = true;
+                            self.focus = pane;
+                        },
+                        None => println!("No {} pane; the panes are stack, registers, program and tape", name.trim()),
+                    }
+                    return true;
+                }
+                if input.starts_with("TRAIL SAVE ") {
+                    let name = line[11..].trim();
+                    match self.storage.write(name, &self.trail.contents()) {
+                        Ok(()) => println!("Paper trail saved to {}", name),
+                        Err(e) => println!("Could not save paper trail: {}", e),
+                    }
+                    return true;
+                }
+            }
+        }
+
+        let outcome = self.dispatcher.execute(&mut self.calculator, &mut self.storage, line);
+        self.finish_command(&input, outcome)
+    }
+
+    /// Show a command's outcome and record the resulting state. Returns
+    /// false when the user quits.
+    fn finish_command(&mut self, input: &str, outcome: std::result::Result<Outcome, String>) -> bool {
+        if let Some(usage) = &mut self.usage {
+            usage.record(input, self.calculator.base(), outcome.as_ref().err().map(String::as_str));
+        }
+        match outcome {
+            Ok(Outcome::Quit) => return false,
+            Ok(Outcome::Message(lines)) => {
+                println!();
+                page(lines);
+            },
+            Ok(Outcome::Done) => {},
+            Err(message) => {
+                println!("{}", message);
+                if command::is_verify_failure(&message) {
+                    if let Err(e) = self.notifier.notify(&format!("VERIFY failed: {}", input)) {
+                        println!("{}", e);
+                    }
+                }
+            },
+        }
+
+        self.trail.record(input, self.calculator.format_display());
+        self.history.record(Snapshot::capture(&self.calculator));
+        self.input.update(&self.calculator);
+        let calc = &self.calculator;
+        let constants = calc.constants().user().keys().map(|name| format!("CONST {}", name));
+        let tags = calc.tags().values().flat_map(|label| {
+            let label = label.to_uppercase();
+            [format!("RCL {}", label), format!("STO {}", label)]
+        });
+        *self.session_commands.lock().unwrap() = constants.chain(tags).collect();
+        if let Err(e) = self.autosave.record(&self.calculator) {
+            eprintln!("Warning: Autosave failed: {}", e);
+        }
+        true
+    }
+
+    /// Handle background jobs: `command &` starts one on a copy of the
+    /// calculator, JOBS lists them, FG waits for one and takes over its
+    /// state (or just its X if the calculator changed meanwhile), and KILL
+    /// stops one and discards it.
+    fn run_job_command(&mut self, line: &str, input: &str) -> Option<bool> {
+        if let Some(command) = line.strip_suffix(" &") {
+            let command = command.trim();
+            let id = self.jobs.spawn(&self.dispatcher, &self.calculator, self.storage.clone(), command);
+            println!("[{}] started: {}", id, command);
+            return Some(true);
+        }
+
+        let mut words = input.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let id = match (command, words.next().map(|n| n.parse::<usize>())) {
+            (_, None) => self.jobs.latest(),
+            ("FG" | "KILL", Some(Ok(id))) => Some(id),
+            ("FG" | "KILL", Some(Err(_))) => {
+                println!("Invalid job number");
+                return Some(true);
+            },
+            _ => None,
+        };
+
+        match command {
+            "JOBS" => {
+                let jobs = self.jobs.list();
+                if jobs.is_empty() {
+                    println!("No background jobs");
+                }
+                for job in jobs {
+                    let state = if job.finished { "Done" } else { "Running" };
+                    println!("[{}] {:8} {}", job.id, state, job.command);
+                }
+            },
+            "FG" => {
+                let Some(id) = id else {
+                    println!("No background jobs");
+                    return Some(true);
+                };
+                // Ctrl-C while waiting stops the job but keeps its progress
+                let cancel = self.dispatcher.cancel_token();
+                cancel.reset();
+                while self.jobs.is_finished(id) == Some(false) {
+                    if cancel.is_cancelled() {
+                        self.jobs.kill(id);
+                    }
+                    thread::sleep(JOB_POLL_INTERVAL);
+                }
+                match self.jobs.wait(id) {
+                    Ok(result) => {
+                        match result.merge_into(&mut self.calculator) {
+                            Ok(true) => {},
+                            Ok(false) => println!("[{}] The calculator changed meanwhile; only its X was entered", id),
+                            Err(message) => println!("[{}] {}", id, message),
+                        }
+                        return Some(self.finish_command(&format!("FG {}", id), result.outcome));
+                    },
+                    Err(message) => println!("{}", message),
+                }
+            },
+            "KILL" => {
+                let Some(id) = id else {
+                    println!("No background jobs");
+                    return Some(true);
+                };
+                if self.jobs.kill(id) {
+                    let _ = self.jobs.wait(id);
+                    println!("[{}] killed", id);
+                } else {
+                    println!("No such job: {}", id);
+                }
+            },
+            _ => return None,
+        }
+        Some(true)
+    }
+
+    /// Handle BACK/FORWARD/RESUME, and keep other commands from running
+    /// while a past state is on display.
+    fn run_history_command(&mut self, input: &str) -> Option<bool> {
+        let mut words = input.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let count = match (command, words.next()) {
+            ("BACK" | "FORWARD", Some(n)) => match n.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => {
+                    println!("Invalid step count");
+                    return Some(true);
+                }
+            },
+            _ => 1,
+        };
+
+        match command {
+            "BACK" => {
+                if self.history.back(count).is_none() {
+                    println!("Already at the oldest recorded state");
+                }
+            },
+            "FORWARD" => {
+                self.history.forward(count);
+            },
+            "RESUME" => {
+                self.history.resume();
+            },
+            "QUIT" | "Q" | "EXIT" | "HELP" | "H" => return None,
+            _ if command.starts_with('?') => return None,
+            _ if self.history.viewing().is_some() => {
+                println!("Viewing history; use BACK, FORWARD or RESUME");
+            },
+            _ => return None,
+        }
+        Some(true)
+    }
+
+    fn display(&self) {
+        let flags = if self.sparkline { self.history.flags(SPARKLINE_LENGTH) } else { Vec::new() };
+        match self.history.viewing() {
+            Some(snapshot) => {
+                let mut past = Hp16cCpu::new();
+                snapshot.restore(&mut past);
+                self.display_calculator(&past, &flags);
+                println!(
+                    "\x1b[1;33m  VIEWING HISTORY: {} step(s) back — RESUME to return\x1b[0m",
+                    self.history.steps_back()
+                );
+            },
+            None => self.display_calculator(&self.calculator, &flags),
+        }
+        if self.timer {
+            if let Some(elapsed) = self.dispatcher.last_elapsed() {
+                println!("  Elapsed: {:.3?}", elapsed);
+            }
+        }
+        if self.trail_pane {
+            display_trail_pane(&self.trail);
+        }
+    }
+
+    fn display_calculator(&self, calc: &Hp16cCpu, flags: &[(bool, bool)]) {
+        if self.panes {
+            println!();
+            println!("{}", panes::render(calc, self.focus).join("\n"));
+        } else {
+            display_calculator(calc, flags);
+        }
+    }
+}
+
+/// Key binding handler that submits the typed line and queues a command.
+struct BoundCommand {
+    command: String,
+    pending: Arc<Mutex<Option<String>>>,
+}
+
+impl ConditionalEventHandler for BoundCommand {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, _ctx: &EventContext) -> Option<Cmd> {
+        *self.pending.lock().unwrap() = Some(self.command.clone());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+fn key_event(chord: KeyChord) -> KeyEvent {
+    let code = match chord.key {
+        Key::Char(c) => KeyCode::Char(c),
+        Key::F(n) => KeyCode::F(n),
+        Key::PageUp => KeyCode::PageUp,
+        Key::PageDown => KeyCode::PageDown,
+        Key::Home => KeyCode::Home,
+        Key::End => KeyCode::End,
+        Key::Insert => KeyCode::Insert,
+        Key::Delete => KeyCode::Delete,
+    };
+    let mut modifiers = Modifiers::NONE;
+    if chord.ctrl {
+        modifiers |= Modifiers::CTRL;
+    }
+    if chord.alt {
+        modifiers |= Modifiers::ALT;
+    }
+    KeyEvent(code, modifiers)
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("Usage: hp16c [--restricted] [--usage FILE]");
+    eprintln!("       hp16c [--restricted] [--state-in FILE] [--state-out FILE] -e COMMANDS...");
+    #[cfg(feature = "controller")]
+    eprintln!("       hp16c [--restricted] --midi|--gamepad DEVICE MAP");
+    std::process::exit(2);
+}
+
+/// Run `-e` commands without the line editor, for scripts and automation:
+/// start from the `--state-in` snapshot (a missing file is a fresh
+/// calculator), print the commands' output and X, and write the state to
+/// `--state-out`. Nothing is written if a command fails. Returns the exit
+/// status.
+fn run_batch(commands: &[String], state_in: Option<&str>, state_out: Option<&str>, restricted: bool) -> i32 {
+    let mut calculator = Hp16cCpu::new();
+    let mut storage = FileStorage::new(".");
+    if let Some(file) = state_in {
+        match storage.load_snapshot(file) {
+            Ok(Some(snapshot)) => snapshot.restore(&mut calculator),
+            Ok(None) => {},
+            Err(e) => {
+                eprintln!("Could not read state {}: {}", file, e);
+                return 1;
+            },
+        }
+    }
+
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.set_restricted(restricted);
+    for text in commands {
+        match dispatcher.execute_batch(&mut calculator, &mut storage, text) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            },
+        }
+    }
+    println!("{}", calculator.format_display());
+
+    if let Some(file) = state_out {
+        if let Err(e) = storage.save_snapshot(file, &Snapshot::capture(&calculator)) {
+            eprintln!("Could not write state {}: {}", file, e);
+            return 1;
+        }
+    }
+    0
+}
+
+/// The kind of input device `--midi` and `--gamepad` read.
+#[cfg(feature = "controller")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    // A raw MIDI byte stream, such as /dev/snd/midiC1D0
+    Midi,
+    // The Linux joystick interface, such as /dev/input/js0
+    Gamepad,
+}
+
+/// Drive the calculator from a MIDI or gamepad device alone, for kiosk
+/// builds: each control the map binds plays its key on a keypad, and the
+/// display is drawn again after every key. Returns the exit status when
+/// the device closes or QUIT is keyed.
+#[cfg(feature = "controller")]
+fn run_controller(device: Device, path: &str, map_file: &str, restricted: bool) -> i32 {
+    use std::fs::File;
+    use std::io::Read;
+    use std::time::Instant;
+
+    let map = match ControllerMap::load_from_file(map_file) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Could not load controller map {}: {}", map_file, e);
+            return 1;
+        },
+    };
+    let mut input = match File::open(path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", path, e);
+            return 1;
+        },
+    };
+    let mut controller = Controller::new(map, Keypad::new());
+    let mut calculator = Hp16cCpu::new();
+    let mut storage = FileStorage::new(".");
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.set_restricted(restricted);
+    let mut midi = MidiParser::new();
+    let mut messages = Vec::new();
+
+    loop {
+        // Clear the screen and draw the display with the keys so far
+        print!("\x1b[2J\x1b[H");
+        display_calculator(&calculator, &[]);
+        println!("{} {}", controller.keypad.pending(), controller.keypad.entry());
+        messages.iter().for_each(|line| println!("{}", line));
+        let _ = io::stdout().flush();
+
+        let event = match device {
+            Device::Midi => {
+                let mut byte = [0u8];
+                match input.read(&mut byte) {
+                    Ok(0) => return 0,
+                    Ok(_) => midi.feed(byte[0]),
+                    Err(e) => {
+                        eprintln!("Could not read {}: {}", path, e);
+                        return 1;
+                    },
+                }
+            },
+            Device::Gamepad => {
+                let mut event = [0u8; JOYSTICK_EVENT_SIZE];
+                match input.read_exact(&mut event) {
+                    Ok(()) => joystick_event(&event),
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return 0,
+                    Err(e) => {
+                        eprintln!("Could not read {}: {}", path, e);
+                        return 1;
+                    },
+                }
+            },
+        };
+        let Some(event) = event else {
+            continue;
+        };
+        let lines = match controller.handle(event, Instant::now()) {
+            Ok(lines) => lines,
+            Err(e) => {
+                messages = vec![e];
+                continue;
+            },
+        };
+        for line in lines {
+            messages = match dispatcher.execute(&mut calculator, &mut storage, &line) {
+                Ok(Outcome::Quit) => return 0,
+                Ok(Outcome::Message(lines)) => lines,
+                Ok(Outcome::Done) => Vec::new(),
+                Err(e) => vec![e],
+            };
+        }
+    }
+}
+
 fn main() {
+    let mut restricted = false;
+    let mut usage_file = None;
+    let (mut state_in, mut state_out) = (None, None);
+    #[cfg(feature = "controller")]
+    let mut controller = None;
+    let mut commands = Vec::new();
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--restricted" => restricted = true,
+            "--usage" => match arguments.next() {
+                Some(file) => usage_file = Some(file),
+                None => exit_with_usage(),
+            },
+            "--state-in" => match arguments.next() {
+                Some(file) => state_in = Some(file),
+                None => exit_with_usage(),
+            },
+            "--state-out" => match arguments.next() {
+                Some(file) => state_out = Some(file),
+                None => exit_with_usage(),
+            },
+            "-e" => match arguments.next() {
+                Some(text) => commands.push(text),
+                None => exit_with_usage(),
+            },
+            #[cfg(feature = "controller")]
+            "--midi" | "--gamepad" => match (arguments.next(), arguments.next()) {
+                (Some(path), Some(map)) => {
+                    let device = if argument == "--midi" { Device::Midi } else { Device::Gamepad };
+                    controller = Some((device, path, map));
+                },
+                _ => exit_with_usage(),
+            },
+            _ => exit_with_usage(),
+        }
+    }
+    if !commands.is_empty() {
+        std::process::exit(run_batch(&commands, state_in.as_deref(), state_out.as_deref(), restricted));
+    }
+    if state_in.is_some() || state_out.is_some() {
+        exit_with_usage();
+    }
+    #[cfg(feature = "controller")]
+    if let Some((device, path, map)) = controller {
+        std::process::exit(run_controller(device, &path, &map, restricted));
+    }
+
     let mut calculator = Hp16cCpu::new();
+    let storage = FileStorage::new(".");
     
     // Load ROM data
+    #[cfg(feature = "rom")]
     if let Err(e) = calculator.load_rom("16c.obj") {
         eprintln!("Warning: Could not load ROM file: {}", e);
         eprintln!("Continuing without ROM data...");
     }
 
+    // Apply settings from the config file if one exists, under the same
+    // restrictions as typed commands
+    if let Ok(true) = storage.exists(CONFIG_FILE) {
+        let mut config_storage = storage.clone();
+        let context = command::Context { restricted, ..command::Context::default() };
+        if let Err(e) = command::apply_config(&mut calculator, &mut config_storage, CONFIG_FILE, &context) {
+            eprintln!("Warning: Could not apply {}: {}", CONFIG_FILE, e);
+        }
+    }
+
+    // A .rpn_rust/ folder here or above brings its own session and settings.
+    // Loading one reads and runs files, so restricted mode leaves it alone
+    let mut project = if restricted {
+        None
+    } else {
+        env::current_dir().ok().and_then(|dir| Project::find(&dir))
+    };
+    if let Some(project) = &project {
+        for warning in project.load(&mut calculator) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    let project_file = |file: &str, default: &str| match &project {
+        Some(project) => project.path(file),
+        None => default.into(),
+    };
+    let history_file = project_file(project::HISTORY_FILE, HISTORY_FILE);
+    let mut keymap_file = project_file(project::KEYMAP_FILE, KEYMAP_FILE);
+    if !keymap_file.exists() {
+        keymap_file = KEYMAP_FILE.into();
+    }
+
     println!("HP-16C RPN Calculator Emulator");
     println!("==============================");
     println!("Type HELP for detailed command information, or QUIT to exit.");
     println!("Use TAB for command completion.");
+    if restricted {
+        println!("Restricted mode: file and script commands are disabled.");
+    }
+    if let Some(file) = &usage_file {
+        println!("Recording a usage summary to {} (type USAGE to see it).", file);
+    }
+    if let Some(project) = &project {
+        println!("Project: {}", project.dir().display());
+    }
     println!();
 
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.set_restricted(restricted);
+    #[cfg(feature = "metrics")]
+    dispatcher.add_plugin(Metrics::new());
+
     // Set up rustyline with completion
+    let mut completer = Hp16cCompleter::new();
+    completer.commands.extend(dispatcher.plugins().commands());
+    let session_commands = Arc::clone(&completer.session_commands);
+    let input = Arc::new(InputContext::new(&calculator));
     let h = Hp16cHelper {
-        completer: Hp16cCompleter::new(),
+        completer,
+        input: Arc::clone(&input),
     };
     
     let mut rl: Editor<Hp16cHelper, _> = Editor::new().unwrap();
     rl.set_helper(Some(h));
     
     // Load history if available
-    let _ = rl.load_history("hp16c_history.txt");
+    let _ = rl.load_history(&history_file);
 
-    loop {
-        display_calculator(&calculator);
+    // Bind keys from the keymap file if one exists
+    let pending = Arc::new(Mutex::new(None));
+    match Keymap::load_from_file(&keymap_file.to_string_lossy()) {
+        Ok(keymap) => {
+            for (chord, command) in keymap.bindings() {
+                let handler = BoundCommand {
+                    command: command.clone(),
+                    pending: Arc::clone(&pending),
+                };
+                rl.bind_sequence(key_event(*chord), EventHandler::Conditional(Box::new(handler)));
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => eprintln!("Warning: Could not load keymap {}: {}", keymap_file.display(), e),
+    }
+
+    // Offer to restore the autosaved session after an unclean exit
+    let mut autosave = Autosave::new(storage.clone(), AUTOSAVE_INTERVAL);
+    match autosave.start() {
+        Ok(Some(snapshot)) => {
+            println!("The previous session did not exit cleanly.");
+            if let Ok(answer) = rl.readline("Restore autosaved session? [y/N] ") {
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    snapshot.restore(&mut calculator);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: Autosave unavailable: {}", e),
+    }
+
+    let mut repl = Repl {
+        calculator,
+        dispatcher,
+        storage,
+        autosave,
+        trail: PaperTrail::new(),
+        trail_pane: false,
+        panes: false,
+        focus: Pane::default(),
+        sparkline: true,
+        timer: false,
+        history: History::new(HISTORY_LIMIT),
+        jobs: Jobs::new(),
+        usage: usage_file.as_ref().map(|_| UsageSummary::new()),
+        notifier: Notifier::new(),
+        session_commands,
+        input,
+    };
+
+    repl.history.record(Snapshot::capture(&repl.calculator));
+    repl.input.update(&repl.calculator);
+
+    // Ctrl-C while a command runs stops it and returns to the prompt; at the
+    // prompt itself the line editor handles Ctrl-C
+    let cancel = repl.dispatcher.cancel_token();
+    if let Err(e) = ctrlc::set_handler(move || cancel.cancel()) {
+        eprintln!("Warning: Ctrl-C handler unavailable: {}", e);
+    }
+
+    'repl: loop {
+        repl.display();
         
         let readline = rl.readline("> ");
-        let input = match readline {
+        let line = match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str()).unwrap();
-                line.trim().to_uppercase()
+                line.trim().to_string()
             }
             Err(ReadlineError::Interrupted) => {
                 println!("^C");
@@ -189,327 +1136,131 @@ fn main() {
                 continue;
             }
         };
-        
-        if input.is_empty() {
-            continue;
-        }
-        
-        match input.as_str() {
-            "QUIT" | "Q" => break,
-            "HELP" | "H" | "?" => {
-                show_help();
-                continue;
-            },
-            "CLR" | "CLEAR" => {
-                calculator.x = 0;
-                calculator.y = 0;
-                calculator.z = 0;
-                calculator.t = 0;
-            },
-            "ENTER" => {
-                calculator.push(calculator.x);
-            },
-            "DROP" => {
-                calculator.drop();
-            },
-            "SWAP" => {
-                calculator.swap_xy();
-            },
-            "RV" => {
-                calculator.roll_down();
-            },
-            "R^" => {
-                calculator.roll_up();
-            },
-            "+" => {
-                calculator.add();
-            },
-            "-" => {
-                calculator.subtract();
-            },
-            "*" => {
-                calculator.multiply();
-            },
-            "/" => {
-                calculator.divide();
-            },
-            "&" => {
-                calculator.and();
-            },
-            "|" => {
-                calculator.or();
-            },
-            "^" => {
-                calculator.xor();
-            },
-            "~" => {
-                calculator.not();
-            },
-            "BIN" => {
-                calculator.set_base(2);
-            },
-            "OCT" => {
-                calculator.set_base(8);
-            },
-            "DEC" => {
-                calculator.set_base(10);
-            },
-            "HEX" => {
-                calculator.set_base(16);
-            },
-            _ => {
-                // Check for memory operations
-                if input.starts_with("STO ") {
-                    if let Ok(reg) = input[4..].parse::<usize>() {
-                        calculator.store(reg);
-                    } else {
-                        println!("Invalid register number");
-                    }
-                } else if input.starts_with("RCL ") {
-                    if let Ok(reg) = input[4..].parse::<usize>() {
-                        calculator.recall(reg);
-                    } else {
-                        println!("Invalid register number");
-                    }
-                } else if input.starts_with("WS ") {
-                    if let Ok(size) = input[3..].parse::<u8>() {
-                        calculator.set_word_size(size);
-                    } else {
-                        println!("Invalid word size (1-128)");
-                    }
-                } else if input.starts_with("SL ") {
-                    if let Ok(positions) = input[3..].parse::<u8>() {
-                        calculator.shift_left(positions);
-                    } else {
-                        println!("Invalid shift count");
-                    }
-                } else if input.starts_with("SR ") {
-                    if let Ok(positions) = input[3..].parse::<u8>() {
-                        calculator.shift_right(positions);
-                    } else {
-                        println!("Invalid shift count");
-                    }
-                } else {
-                    // Try to parse as number in current base
-                    let parsed_value = match calculator.base {
-                        2 => u128::from_str_radix(&input, 2),
-                        8 => u128::from_str_radix(&input, 8),
-                        10 => input.parse::<u128>(),
-                        16 => u128::from_str_radix(&input, 16),
-                        _ => u128::from_str_radix(&input, 16),
-                    };
-                    
-                    match parsed_value {
-                        Ok(value) => {
-                            calculator.push(value);
-                        },
-                        Err(_) => {
-                            println!("Unknown command or invalid number: {}", input);
-                        }
-                    }
-                }
+
+        // A bound key runs its command after whatever was typed before it
+        let bound = pending.lock().unwrap().take();
+        for line in [Some(line), bound].into_iter().flatten() {
+            if !line.is_empty() && !repl.run_line(&line) {
+                break 'repl;
             }
         }
     }
     
     // Save history
-    let _ = rl.save_history("hp16c_history.txt");
+    let _ = rl.save_history(&history_file);
+    let _ = repl.autosave.finish();
+    if let Some(project) = &mut project {
+        if let Err(e) = project.save_session(&repl.calculator) {
+            eprintln!("Warning: Could not save the project session: {}", e);
+        }
+    }
+    if let (Some(file), Some(usage)) = (&usage_file, &repl.usage) {
+        if let Err(e) = usage.to_json().and_then(|json| repl.storage.write(file, &json)) {
+            eprintln!("Warning: Could not write usage summary {}: {}", file, e);
+        }
+    }
     println!("Goodbye!");
 }
 
-fn display_calculator(calc: &Hp16cCpu) {
+// Pacing of the demo: per typed character and after each step
+const DEMO_KEY_DELAY: Duration = Duration::from_millis(80);
+const DEMO_STEP_DELAY: Duration = Duration::from_millis(1200);
+
+/// Replay the bundled demo on a fresh calculator, typing each command visibly.
+fn run_demo(cancel: &CancelToken) {
+    let mut calculator = Hp16cCpu::new();
+    let mut storage = MemoryStorage::new();
+
+    for step in demo::steps(demo::SCRIPT) {
+        if cancel.is_cancelled() {
+            println!();
+            println!("Demo cancelled; your calculator state is unchanged.");
+            return;
+        }
+        match step {
+            DemoStep::Say(text) => {
+                println!();
+                println!("\x1b[1;36m{}\x1b[0m", text);
+                thread::sleep(DEMO_STEP_DELAY);
+            },
+            DemoStep::Key(line) => {
+                print!("> ");
+                for c in line.chars() {
+                    print!("{}", c);
+                    let _ = io::stdout().flush();
+                    thread::sleep(DEMO_KEY_DELAY);
+                }
+                println!();
+                if let Err(message) = command::execute(&mut calculator, &mut storage, line) {
+                    println!("{}", message);
+                }
+                display_calculator(&calculator, &[]);
+                thread::sleep(DEMO_STEP_DELAY);
+            },
+        }
+    }
+    println!();
+    println!("Demo finished; your calculator state is unchanged.");
+}
+
+fn display_calculator(calc: &Hp16cCpu, flags: &[(bool, bool)]) {
     println!();
-    
-    // Calculate the required width based on the longest stack display
-    let stack = calc.get_stack_display();
-    let title = "HP-16C Calculator";
-    let status_line = format!("Base: {:2}  Word Size: {:2}", calc.base, calc.word_size);
-    let flags_line = format!("Carry: {}  Overflow: {}", 
-                            if calc.carry { "1" } else { "0" },
-                            if calc.overflow { "1" } else { "0" });
-    
-    // Find the maximum width needed
-    let mut max_width = title.len().max(status_line.len()).max(flags_line.len());
-    for line in &stack {
-        max_width = max_width.max(line.len());
+    println!("{}", display::render_lines_with_flags(calc, flags).join("\n"));
+}
+
+// Number of commands the carry and overflow sparkline covers
+const SPARKLINE_LENGTH: usize = 16;
+
+// Number of paper trail entries shown below the display
+const TRAIL_PANE_LINES: usize = 6;
+
+fn display_trail_pane(trail: &PaperTrail) {
+    println!("  ── paper trail ──");
+    for line in PaperTrail::render(trail.recent(TRAIL_PANE_LINES)) {
+        println!("  {}", line);
     }
-    
-    // Ensure minimum width and add padding for borders
-    let display_width = max_width.max(29) + 2; // +2 for left and right padding
-    
-    // Create border strings
-    let top_border = format!("┌{}┐", "─".repeat(display_width));
-    let mid_border = format!("├{}┤", "─".repeat(display_width));
-    let bottom_border = format!("└{}┘", "─".repeat(display_width));
-    
-    // Display the calculator
-    println!("{}", top_border);
-    println!("│ {:width$} │", title, width = display_width - 2);
-    println!("{}", mid_border);
-    println!("│ {:width$} │", status_line, width = display_width - 2);
-    println!("│ {:width$} │", flags_line, width = display_width - 2);
-    println!("{}", mid_border);
-    
-    for line in &stack {
-        println!("│ {:width$} │", line, width = display_width - 2);
+}
+
+// Screen height used when the terminal does not report one in LINES
+const DEFAULT_SCREEN_LINES: usize = 24;
+
+/// Print output a screen at a time. Short output, and output that is not
+/// going to a terminal, is printed in one go.
+fn page(lines: Vec<String>) {
+    let height = env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_SCREEN_LINES);
+    // Leave room for the status line
+    let height = height.saturating_sub(1).max(1);
+    if lines.len() <= height || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        for line in lines {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    let mut pager = Pager::new(lines, height);
+    loop {
+        for line in pager.screen() {
+            println!("{}", line);
+        }
+        print!("\x1b[7m{}\x1b[0m ", pager.status());
+        let _ = io::stdout().flush();
+
+        // The answer is read as a line, so every key in the status line is
+        // followed by Enter; a bare Enter (or space Enter) pages on
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return;
+        }
+        let key = answer.trim_end_matches(['\r', '\n']).chars().next().unwrap_or(' ');
+        if !pager.key(key) {
+            return;
+        }
     }
-    
-    println!("{}", bottom_border);
 }
 
-fn show_help() {
-    println!();
-    println!("═══════════════════════════════════════════════════════════════════════");
-    println!("                          HP-16C CALCULATOR HELP");
-    println!("═══════════════════════════════════════════════════════════════════════");
-    println!();
-    
-    println!("📋 BASIC USAGE:");
-    println!("  • Enter numbers in the current base and press ENTER to push to stack");
-    println!("  • Operations consume stack values (RPN - Reverse Polish Notation)");
-    println!("  • Use TAB key for command completion while typing");
-    println!("  • Example: To calculate 10 + 5: type '10', 'ENTER', '5', '+'");
-    println!();
-    
-    println!("🔢 NUMBER ENTRY:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  [number]   Enter number in current base   FF (hex), 255 (dec)");
-    println!("  ENTER      Push X to stack (duplicate)    10 ENTER → stack: [10,10]");
-    println!();
-    println!("  Example sequence:");
-    println!("    • Type 'A' → X register shows A (10 in hex)");
-    println!("    • Type 'ENTER' → Push A to Y, X still shows A");
-    println!("    • Type '5' → X shows 5, Y shows A");
-    println!();
-    
-    println!("🧮 ARITHMETIC OPERATIONS:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  +          Add Y + X                      10 ENTER 5 + → 15");
-    println!("  -          Subtract Y - X                 10 ENTER 3 - → 7");
-    println!("  *          Multiply Y × X                 6 ENTER 7 * → 42");
-    println!("  /          Divide Y ÷ X                   20 ENTER 4 / → 5");
-    println!();
-    println!("  Example: Calculate (15 + 25) × 2:");
-    println!("    15 ENTER 25 + 2 * → Result: 80");
-    println!();
-    
-    println!("🔧 BITWISE OPERATIONS:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  &          Bitwise AND of Y & X           F0 ENTER 0F & → 0");
-    println!("  |          Bitwise OR of Y | X            F0 ENTER 0F | → FF");
-    println!("  ^          Bitwise XOR of Y ^ X           FF ENTER AA ^ → 55");
-    println!("  ~          Bitwise NOT of X               FF ~ → 0 (in 8-bit mode)");
-    println!();
-    println!("  Example: Mask lower 4 bits of FF:");
-    println!("    FF ENTER 0F & → Result: 0F");
-    println!();
-    
-    println!("↕️  STACK MANIPULATION:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  DROP       Remove X, lift stack up        [4,3,2,1] DROP → [3,2,1,1]");
-    println!("  SWAP       Exchange X and Y               [4,3,2,1] SWAP → [3,4,2,1]");
-    println!("  RV         Roll stack down               [4,3,2,1] RV → [3,2,1,4]");
-    println!("  R^         Roll stack up                 [4,3,2,1] R^ → [1,4,3,2]");
-    println!();
-    println!("  Note: Stack format shown as [T,Z,Y,X] where X is display register");
-    println!();
-    
-    println!("🔢 NUMBER BASE CONVERSION:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  HEX        Switch to hexadecimal         255 HEX → displays as FF");
-    println!("  DEC        Switch to decimal             FF DEC → displays as 255");
-    println!("  OCT        Switch to octal               255 OCT → displays as 377");
-    println!("  BIN        Switch to binary              255 BIN → displays as 11111111");
-    println!();
-    println!("  Example: Convert hex FF to decimal:");
-    println!("    FF → shows FF, then DEC → shows 255");
-    println!();
-    
-    println!("📏 WORD SIZE CONTROL:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  WS [n]     Set word size (1-128 bits)    WS 8 → 8-bit arithmetic");
-    println!();
-    println!("  Example: Set 4-bit mode and see overflow:");
-    println!("    WS 4 → 4-bit mode");
-    println!("    10 → shows 0 (10 masked to 4 bits)");
-    println!("    F → shows F (15, max for 4 bits)");
-    println!();
-    
-    println!("🔄 SHIFT OPERATIONS:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  SL [n]     Shift left n positions        5 SL 1 → A (5<<1 = 10)");
-    println!("  SR [n]     Shift right n positions       A SR 1 → 5 (10>>1 = 5)");
-    println!();
-    println!("  Example: Multiply by 4 using shifts:");
-    println!("    7 SL 2 → 1C (7 shifted left 2 = 7×4 = 28)");
-    println!();
-    
-    println!("💾 MEMORY OPERATIONS:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  STO [n]    Store X in register n (0-15)  42 STO 5 → saves 42 to R5");
-    println!("  RCL [n]    Recall register n to stack    RCL 5 → pushes R5 to stack");
-    println!();
-    println!("  Example: Store intermediate result:");
-    println!("    10 ENTER 5 + STO 1 → store 15 in R1");
-    println!("    20 ENTER 3 * → calculate 60");
-    println!("    RCL 1 + → add stored 15, result: 75");
-    println!();
-    
-    println!("🧹 UTILITY COMMANDS:");
-    println!("  Command    Description                    Example");
-    println!("  ─────────  ──────────────────────────────  ───────────────────────");
-    println!("  CLR        Clear all stack registers     CLR → all registers = 0");
-    println!("  HELP       Show this help (also H, ?)    HELP → shows this screen");
-    println!("  QUIT       Exit calculator (also Q)      QUIT → exits program");
-    println!("  TAB        Auto-complete commands         HE<TAB> → completes to HELP");
-    println!();
-    
-    println!("📊 CALCULATOR DISPLAY:");
-    println!("  • T, Z, Y, X: The four-level RPN stack");
-    println!("  • Base: Current number base (2, 8, 10, or 16)");
-    println!("  • Word Size: Current bit width (1-64)");
-    println!("  • Carry: Set when arithmetic operation carries/borrows");
-    println!("  • Overflow: Set when result exceeds word size");
-    println!();
-    
-    println!("💡 SAMPLE CALCULATIONS:");
-    println!();
-    println!("  1. Convert 255 to different bases:");
-    println!("     255 DEC → shows 255");
-    println!("     HEX → shows FF");
-    println!("     OCT → shows 377");
-    println!("     BIN → shows 11111111");
-    println!();
-    
-    println!("  2. Calculate percentage using bitwise (what % of FF is 80?):");
-    println!("     80 ENTER FF / 100 * → shows percentage");
-    println!();
-    
-    println!("  3. Check if a number is power of 2:");
-    println!("     8 ENTER 8 ENTER 1 - & → result 0 means power of 2");
-    println!();
-    
-    println!("  4. Extract lower nibble (4 bits):");
-    println!("     A5 ENTER F & → result: 5");
-    println!();
-    
-    println!("  5. Set specific bit (set bit 3 in value 10):");
-    println!("     10 ENTER 1 3 SL | → result: 18 (10 | 8)");
-    println!();
-    
-    println!("═══════════════════════════════════════════════════════════════════════");
-    println!("Press any key to continue...");
-    println!();
-    
-    // Wait for user input
-    let mut dummy = String::new();
-    let _ = io::stdin().read_line(&mut dummy);
-}
\ No newline at end of file
+fn help_lines() -> Vec<String> {
+    help::TEXT.lines().map(String::from).collect()
+}
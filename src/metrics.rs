@@ -0,0 +1,80 @@
+use crate::command::Outcome;
+use crate::cpu::Hp16cCpu;
+use crate::plugin::Plugin;
+use std::time::{Duration, Instant};
+
+/// Plugin counting the commands of a session and timing them, shown by
+/// METRICS and restarted by METRICS RESET.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    commands: usize,
+    errors: usize,
+    total: Duration,
+    slowest: Option<(String, Duration)>,
+    // When the command now running started
+    started: Option<Instant>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn commands(&self) -> usize {
+        self.commands
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Lines for the METRICS command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Commands: {}  Errors: {}  Total time: {:.3?}",
+            self.commands, self.errors, self.total
+        )];
+        if let Some((line, elapsed)) = &self.slowest {
+            lines.push(format!("Slowest: {} ({:.3?})", line, elapsed));
+        }
+        lines
+    }
+}
+
+impl Plugin for Metrics {
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    fn on_command(&mut self, _cpu: &mut Hp16cCpu, line: &str) -> Option<Result<Outcome, String>> {
+        match line.trim().to_uppercase().as_str() {
+            "METRICS" => Some(Ok(Outcome::Message(self.lines()))),
+            "METRICS RESET" => {
+                *self = Metrics::new();
+                Some(Ok(Outcome::Done))
+            },
+            _ => {
+                self.started = Some(Instant::now());
+                None
+            },
+        }
+    }
+
+    fn on_result(&mut self, _cpu: &Hp16cCpu, line: &str, outcome: &Result<Outcome, String>) {
+        // METRICS itself is not counted
+        let Some(started) = self.started.take() else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        self.commands += 1;
+        self.errors += outcome.is_err() as usize;
+        self.total += elapsed;
+        if self.slowest.as_ref().is_none_or(|(_, slowest)| elapsed > *slowest) {
+            self.slowest = Some((line.trim().to_string(), elapsed));
+        }
+    }
+
+    fn extra_commands(&self) -> Vec<String> {
+        vec!["METRICS".to_string(), "METRICS RESET".to_string()]
+    }
+}
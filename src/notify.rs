@@ -0,0 +1,47 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// How to get the user's attention when a background job finishes or a
+/// VERIFY fails: a terminal bell, a notifier program, or both.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Notifier {
+    pub bell: bool,
+    /// Program and arguments, run with the message as the last argument.
+    pub command: Vec<String>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ring the bell and start the notifier program without waiting for
+    /// it. Fails if the program cannot be started.
+    pub fn notify(&self, message: &str) -> Result<(), String> {
+        if self.bell {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        let Some((program, arguments)) = self.command.split_first() else {
+            return Ok(());
+        };
+        let mut child = Command::new(program)
+            .args(arguments)
+            .arg(message)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not run notifier {}: {}", program, e))?;
+        // Reap the notifier when it exits
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    /// The settings, for the NOTIFY command.
+    pub fn describe(&self) -> String {
+        let command = if self.command.is_empty() { "none".to_string() } else { self.command.join(" ") };
+        format!("Bell: {}  Notifier: {}", if self.bell { "on" } else { "off" }, command)
+    }
+}
@@ -0,0 +1,159 @@
+use crate::program::{Control, Program, Test};
+use std::collections::BTreeSet;
+
+/// What OPTIMIZE did to a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimized {
+    pub program: Program,
+    /// Each change, as `005- step: what happened`, by the line it was on
+    pub changes: Vec<String>,
+    /// Bytes of program memory no longer needed
+    pub saved: usize,
+}
+
+/// OPTIMIZE: `program` with steps that change nothing taken out. A GTO to
+/// a label whose next step is another GTO goes straight to the end of the
+/// chain; steps no GTO, GSB or earlier step can reach are removed; and a
+/// CLX straight after CLX, or between ENTER and a number (which replaces
+/// X anyway), is dropped. The program starts at line 001 and at its
+/// labels A-F, which are run from the keyboard; labels 0-9 nothing in the
+/// program goes to are taken as unused. A step a test could skip is never
+/// removed, so the test goes on skipping the same one. Comments stay with
+/// their steps.
+pub fn optimize(program: &Program) -> Optimized {
+    // Each step with the line it was on
+    let mut steps: Vec<(usize, String)> =
+        program.steps().iter().enumerate().map(|(index, step)| (index + 1, step.clone())).collect();
+    let mut threaded = Vec::new();
+    let mut result = program.clone();
+    for index in 0..steps.len() {
+        if let Some((label, target)) = thread(&steps, index) {
+            let (line, step) = &mut steps[index];
+            let change = format!("{:03}- {}: now GTO {:X}, as LBL {:X} only goes on there", line, step, target, label);
+            threaded.push((*line, change));
+            *step = format!("GTO {:X}", target);
+            result.replace(*line, step);
+        }
+    }
+    let mut changes = Vec::new();
+    let mut removed = BTreeSet::new();
+    loop {
+        let unreachable = unreachable(&steps);
+        let mut redundant = redundant(&steps);
+        redundant.retain(|(index, _)| !unreachable.contains(index));
+        if unreachable.is_empty() && redundant.is_empty() {
+            break;
+        }
+        for (index, reason) in unreachable.iter().map(|index| (*index, "unreachable".to_string())).chain(redundant) {
+            // Reported as the step was written, before any threading
+            let line = steps[index].0;
+            changes.push(format!("{:03}- {}: {}", line, program.step(line).unwrap_or_default(), reason));
+            removed.insert(line);
+        }
+        steps.retain(|(line, _)| !removed.contains(line));
+    }
+    // A GTO threaded and then found unreachable is only reported once
+    changes.extend(threaded.into_iter().filter(|(line, _)| !removed.contains(line)).map(|(_, change)| change));
+    changes.sort();
+    for line in removed.iter().rev() {
+        result.delete(*line);
+    }
+    Optimized {
+        saved: program.bytes() - result.bytes(),
+        program: result,
+        changes,
+    }
+}
+
+fn control(steps: &[(usize, String)], index: usize) -> Option<Control> {
+    steps.get(index).and_then(|(_, step)| Control::parse(step).ok().flatten())
+}
+
+fn is_test(steps: &[(usize, String)], index: usize) -> bool {
+    steps.get(index).is_some_and(|(_, step)| Test::parse(step).is_some())
+}
+
+// Where LBL `label` is; a GTO goes to the first one
+fn label_index(steps: &[(usize, String)], label: u8) -> Option<usize> {
+    (0..steps.len()).find(|index| control(steps, *index) == Some(Control::Label(label)))
+}
+
+// For a GTO at `index` to a label that only leads on to other GTOs, that
+// label and the one at the end of the chain
+fn thread(steps: &[(usize, String)], index: usize) -> Option<(u8, u8)> {
+    let Some(Control::Goto(first)) = control(steps, index) else {
+        return None;
+    };
+    let mut seen = vec![first];
+    let mut label = first;
+    loop {
+        let mut next = label_index(steps, label)?;
+        while let Some(Control::Label(_)) = control(steps, next) {
+            next += 1;
+        }
+        match control(steps, next) {
+            Some(Control::Goto(target)) if !seen.contains(&target) => {
+                seen.push(target);
+                label = target;
+            },
+            // A GTO looping back into the chain runs for ever either way
+            _ => break,
+        }
+    }
+    (label != first).then_some((first, label))
+}
+
+// Steps nothing reaches from line 001, labels A-F or the labels the
+// program goes to
+fn unreachable(steps: &[(usize, String)]) -> Vec<usize> {
+    let mut gone_to = BTreeSet::new();
+    for index in 0..steps.len() {
+        if let Some(Control::Goto(label) | Control::Gosub(label)) = control(steps, index) {
+            gone_to.insert(label);
+        }
+    }
+    let is_entry = |index: usize| match control(steps, index) {
+        Some(Control::Label(label)) => label >= 10 || gone_to.contains(&label),
+        _ => index == 0,
+    };
+    let mut pending: Vec<usize> = (0..steps.len()).filter(|index| is_entry(*index)).collect();
+    let mut reached = vec![false; steps.len()];
+    while let Some(index) = pending.pop() {
+        if index >= steps.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+        match control(steps, index) {
+            // A GTO to a missing label stops the program with an error
+            Some(Control::Goto(label)) => pending.extend(label_index(steps, label)),
+            Some(Control::Gosub(label)) => {
+                pending.extend(label_index(steps, label));
+                pending.push(index + 1);
+            },
+            Some(Control::Return) => {},
+            _ if is_test(steps, index) => pending.extend([index + 1, index + 2]),
+            _ => pending.push(index + 1),
+        }
+    }
+    (0..steps.len()).filter(|index| !reached[*index]).collect()
+}
+
+// Steps that change nothing after the step before them, with why
+fn redundant(steps: &[(usize, String)]) -> Vec<(usize, String)> {
+    let mut found: Vec<(usize, String)> = Vec::new();
+    for index in 1..steps.len() {
+        // A test skipping the step before would leave this one to run
+        if (index >= 2 && is_test(steps, index - 2)) || found.iter().any(|(before, _)| *before == index - 1) {
+            continue;
+        }
+        let step = |offset: usize| steps.get(index + offset - 1).map_or("", |(_, step)| step.as_str());
+        let is_number = step(2).starts_with(|c: char| c.is_ascii_digit());
+        let reason = match (step(0), step(1)) {
+            ("CLX", "CLX") => "repeats the CLX before it",
+            ("ENTER", "CLX") if is_number => "unneeded between ENTER and a number",
+            _ => continue,
+        };
+        found.push((index, reason.to_string()));
+    }
+    found
+}
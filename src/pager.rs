@@ -0,0 +1,52 @@
+/// Splits long output into screens. The front-end shows `screen()` and
+/// `status()`, then passes each key the user presses to `key()` until it
+/// returns false.
+#[derive(Debug, Clone)]
+pub struct Pager {
+    lines: Vec<String>,
+    height: usize,
+    top: usize,
+}
+
+impl Pager {
+    /// `height` is the number of output lines per screen.
+    pub fn new(lines: Vec<String>, height: usize) -> Self {
+        Pager {
+            lines,
+            height: height.max(1),
+            top: 0,
+        }
+    }
+
+    pub fn screen(&self) -> &[String] {
+        let end = (self.top + self.height).min(self.lines.len());
+        &self.lines[self.top..end]
+    }
+
+    pub fn status(&self) -> String {
+        let end = (self.top + self.height).min(self.lines.len());
+        format!(
+            "-- lines {}-{} of {} -- Enter: next page, b Enter: back, q Enter: quit",
+            self.top + 1,
+            end,
+            self.lines.len()
+        )
+    }
+
+    /// Handle a key: space or Enter for the next page, `b` for the previous
+    /// one, `q` to stop. Returns false once paging is over.
+    pub fn key(&mut self, key: char) -> bool {
+        match key.to_ascii_lowercase() {
+            ' ' | '\n' | 'f' => {
+                if self.top + self.height >= self.lines.len() {
+                    return false;
+                }
+                self.top += self.height;
+            },
+            'b' => self.top = self.top.saturating_sub(self.height),
+            'q' => return false,
+            _ => {},
+        }
+        true
+    }
+}
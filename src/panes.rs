@@ -0,0 +1,152 @@
+use crate::cpu::{register_name, Hp16cCpu};
+use crate::display::StackView;
+
+// Rows of content in a pane, and in the pane with the focus
+const PANE_ROWS: usize = 4;
+const FOCUSED_ROWS: usize = 10;
+
+// Narrowest a column of panes gets, so short content still lines up
+const MIN_WIDTH: usize = 30;
+
+/// One of the windows of the multi-pane display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pane {
+    #[default]
+    Stack,
+    Registers,
+    Program,
+    Tape,
+}
+
+impl Pane {
+    pub const ALL: [Pane; 4] = [Pane::Stack, Pane::Registers, Pane::Program, Pane::Tape];
+
+    /// The pane called `name`, as PANES FOCUS takes it. Case is ignored.
+    pub fn parse(name: &str) -> Option<Pane> {
+        Self::ALL.into_iter().find(|pane| pane.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pane::Stack => "stack",
+            Pane::Registers => "registers",
+            Pane::Program => "program",
+            Pane::Tape => "tape",
+        }
+    }
+
+    /// The pane the focus moves on to, back to the stack after the tape.
+    pub fn next(&self) -> Pane {
+        let index = Self::ALL.iter().position(|pane| pane == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// The multi-pane display: the stack above the registers on the left, and
+/// the program around the current step above the printer tape on the
+/// right. The pane with the `focus` has a double border and more rows;
+/// the others show what fits in a few. The current program step is marked
+/// `▶`.
+pub fn render(calc: &Hp16cCpu, focus: Pane) -> Vec<String> {
+    let rows = |pane: Pane| if pane == focus { FOCUSED_ROWS } else { PANE_ROWS };
+    let registers = register_lines(calc, rows(Pane::Registers));
+    let program = program_lines(calc, rows(Pane::Program));
+    let left = column(&[(Pane::Stack, stack_lines(calc)), (Pane::Registers, registers)], focus);
+    let right = column(&[(Pane::Program, program), (Pane::Tape, tape_lines(calc, rows(Pane::Tape)))], focus);
+    let left_width = left.first().map_or(0, |line| line.chars().count());
+    (0..left.len().max(right.len()))
+        .map(|index| {
+            let left = left.get(index).map_or("", String::as_str);
+            let right = right.get(index).map_or("", String::as_str);
+            format!("{:width$} {}", left, right, width = left_width).trim_end().to_string()
+        })
+        .collect()
+}
+
+// Panes drawn one above the other at the width of the widest
+fn column(panes: &[(Pane, Vec<String>)], focus: Pane) -> Vec<String> {
+    let width = panes
+        .iter()
+        .flat_map(|(pane, lines)| lines.iter().map(String::as_str).chain([pane.name()]))
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(MIN_WIDTH);
+    panes
+        .iter()
+        .flat_map(|(pane, lines)| draw(pane.name(), lines, width, *pane == focus))
+        .collect()
+}
+
+// A box around `lines` with `title` in its top border
+fn draw(title: &str, lines: &[String], width: usize, focused: bool) -> Vec<String> {
+    let (top, bottom, side, rule) = if focused { ("╔", "╚", "║", "═") } else { ("┌", "└", "│", "─") };
+    let (top_end, bottom_end) = if focused { ("╗", "╝") } else { ("┐", "┘") };
+    let title = format!(" {} ", title);
+    let mut boxed = vec![format!("{}{}{}{}", top, title, rule.repeat(width + 2 - title.chars().count()), top_end)];
+    boxed.extend(lines.iter().map(|line| format!("{} {:width$} {}", side, line, side, width = width)));
+    boxed.push(format!("{}{}{}", bottom, rule.repeat(width + 2), bottom_end));
+    boxed
+}
+
+// T down to X, then the flags
+fn stack_lines(calc: &Hp16cCpu) -> Vec<String> {
+    let mut lines: Vec<String> = format!("{:#}", StackView::new(calc)).lines().map(String::from).collect();
+    let mut flags = format!("C: {}  V: {}", u8::from(calc.carry), u8::from(calc.overflow));
+    if calc.program_mode {
+        flags.push_str(&format!("  PRGM {:03}", calc.pc));
+    }
+    lines.push(flags);
+    lines
+}
+
+// Registers that hold something or have a tag, in order
+fn register_lines(calc: &Hp16cCpu, rows: usize) -> Vec<String> {
+    let used: Vec<String> = calc
+        .memory()
+        .iter()
+        .enumerate()
+        .filter(|(register, value)| **value != 0 || calc.tags().contains_key(register))
+        .map(|(register, value)| {
+            let tag = calc.tags().get(&register).map(String::as_str).unwrap_or("");
+            format!("R{:<3} {}  {}", register_name(register), calc.format_value(*value), tag).trim_end().to_string()
+        })
+        .collect();
+    if used.is_empty() {
+        return vec!["(all registers are 0)".to_string()];
+    }
+    fit(used, rows)
+}
+
+// Lines of program memory around the current one, which is marked
+fn program_lines(calc: &Hp16cCpu, rows: usize) -> Vec<String> {
+    let program = calc.program();
+    let current = calc.pc as usize;
+    let first = current.saturating_sub(rows / 2).min((program.len() + 1).saturating_sub(rows));
+    (first..=program.len())
+        .take(rows)
+        .map(|line| {
+            let marker = if line == current { "▶" } else { " " };
+            format!("{} {}", marker, program.keycode_line(line))
+        })
+        .collect()
+}
+
+// The end of the printer tape
+fn tape_lines(calc: &Hp16cCpu, rows: usize) -> Vec<String> {
+    let tape = calc.printer().tape();
+    if tape.is_empty() {
+        return vec!["(printer tape is empty)".to_string()];
+    }
+    tape[tape.len().saturating_sub(rows)..].to_vec()
+}
+
+// At most `rows` of `lines`, the last saying how many more there are
+fn fit(mut lines: Vec<String>, rows: usize) -> Vec<String> {
+    if lines.len() > rows {
+        let more = lines.len() - rows + 1;
+        lines.truncate(rows - 1);
+        lines.push(format!("(+{} more)", more));
+    }
+    lines
+}
@@ -0,0 +1,78 @@
+use crate::command::Outcome;
+use crate::cpu::Hp16cCpu;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A compiled-in extension to the read-eval loop, added to a
+/// [`Dispatcher`](crate::command::Dispatcher) with `add_plugin`. The hooks
+/// see every command line the dispatcher accepts, after the input policy
+/// and before the calculator, so integrations such as metrics or a
+/// tutorial need nothing from the front-end. Each hook does nothing unless
+/// overridden.
+pub trait Plugin: Send {
+    /// Short name, for listings.
+    fn name(&self) -> &str;
+
+    /// Called with each command line before it runs. Returning an outcome
+    /// handles the line here and the calculator never sees it; this is how
+    /// a plugin provides its extra commands.
+    fn on_command(&mut self, _cpu: &mut Hp16cCpu, _line: &str) -> Option<Result<Outcome, String>> {
+        None
+    }
+
+    /// Called after each command line with what it returned, including
+    /// lines a plugin handled.
+    fn on_result(&mut self, _cpu: &Hp16cCpu, _line: &str, _outcome: &Result<Outcome, String>) {}
+
+    /// The commands `on_command` handles, for tab completion.
+    fn extra_commands(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The plugins of one dispatcher, called in the order they were added.
+/// Clones share the same plugins.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    plugins: Vec<Arc<Mutex<dyn Plugin>>>,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl Plugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, plugin: impl Plugin + 'static) {
+        self.plugins.push(Arc::new(Mutex::new(plugin)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.plugins.iter().map(|plugin| plugin.lock().unwrap().name().to_string()).collect()
+    }
+
+    /// The extra commands of every plugin.
+    pub fn commands(&self) -> Vec<String> {
+        self.plugins.iter().flat_map(|plugin| plugin.lock().unwrap().extra_commands()).collect()
+    }
+
+    /// Offer `line` to each plugin in turn; the first to handle it wins.
+    pub fn on_command(&self, cpu: &mut Hp16cCpu, line: &str) -> Option<Result<Outcome, String>> {
+        self.plugins.iter().find_map(|plugin| plugin.lock().unwrap().on_command(cpu, line))
+    }
+
+    pub fn on_result(&self, cpu: &Hp16cCpu, line: &str, outcome: &Result<Outcome, String>) {
+        for plugin in &self.plugins {
+            plugin.lock().unwrap().on_result(cpu, line, outcome);
+        }
+    }
+}
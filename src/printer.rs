@@ -0,0 +1,32 @@
+/// Virtual printer: an append-only paper tape of formatted output lines.
+#[derive(Debug, Clone, Default)]
+pub struct Printer {
+    tape: Vec<String>,
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.tape.push(line.into());
+    }
+
+    pub fn tape(&self) -> &[String] {
+        &self.tape
+    }
+
+    pub fn clear(&mut self) {
+        self.tape.clear();
+    }
+
+    /// The whole tape as text, one printed line per line.
+    pub fn contents(&self) -> String {
+        let mut text = self.tape.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+}
@@ -0,0 +1,695 @@
+use crate::cancel::CancelToken;
+use crate::command::{parse_number, Outcome};
+use crate::cpu::{parse_register, register_name, Base, Hp16cCpu, MAX_FLOAT_DIGITS, NAMED_REGISTERS};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Subroutine levels GSB can nest, as on the HP-16C.
+pub const RETURN_STACK_DEPTH: usize = 4;
+
+// Guard against programs that never reach RTN or R/S
+const MAX_STEPS_RUN: usize = 1_000_000;
+
+/// Widest word of the HP-16C. Wider words are an emulator extension.
+pub const HP16C_MAX_WORD_SIZE: u8 = 64;
+
+/// Commands without arguments that the HP-16C has, as this calculator
+/// spells them. Tests and the control steps are checked separately.
+const HP16C_COMMANDS: &[&str] = &[
+    "ENTER", "CLX", "+", "-", "*", "/", "RMD", "CHS", "ABS", "DBL*", "DBL/", "DBLR", "&", "|", "^", "~", "SB",
+    "CB", "MASKL", "MASKR", "RL", "RR", "RLC", "RRC", "RLN", "RRN", "SWAP", "RV", "R^", "1S", "2S", "UNSGN", "HEX",
+    "DEC", "OCT", "BIN", "STO I", "RCL I", "STO (I)", "RCL (I)", "X<>I", "X<>(I)", "CLR REG", "CLEAR REG", "<",
+    ">", "MEM", "STATUS",
+];
+
+/// Why `step` would not run on a real HP-16C, such as `not an HP-16C
+/// instruction`, or None if it would.
+/// Numbers are read in `base`, the base the program is meant to run in.
+pub fn extension(step: &str, base: Base) -> Option<String> {
+    let step = step.trim().to_uppercase();
+    if let Some(value) = parse_number(&step, base) {
+        return (value > u64::MAX as u128).then(|| format!("needs more than {} bits", HP16C_MAX_WORD_SIZE));
+    }
+    if HP16C_COMMANDS.contains(&step.as_str()) || Test::parse(&step).is_some() {
+        return None;
+    }
+    if let Ok(Some(_)) = Control::parse(&step) {
+        return None;
+    }
+    let not_hp16c = || Some("not an HP-16C instruction".to_string());
+    let Some((word, argument)) = step.split_once(' ') else {
+        return not_hp16c();
+    };
+    let number = argument.parse::<u8>().ok();
+    match word {
+        "WS" => match number {
+            Some(bits) if bits > HP16C_MAX_WORD_SIZE => {
+                Some(format!("word size {} is above the HP-16C's {} bits", bits, HP16C_MAX_WORD_SIZE))
+            },
+            Some(_) => None,
+            None => not_hp16c(),
+        },
+        "STO" | "RCL" => match parse_register(argument) {
+            Some(register) if register < NAMED_REGISTERS => None,
+            Some(register) => Some(format!("R{} can only be reached through I on an HP-16C", register)),
+            None => Some("register tags are an emulator extension".to_string()),
+        },
+        "SL" | "SR" => match number {
+            Some(1) => None,
+            _ => Some("the HP-16C shifts one bit at a time".to_string()),
+        },
+        // The keys take the bit count from X, not from the step
+        "MASKL" | "MASKR" => Some("the HP-16C takes the mask size from X".to_string()),
+        "SF" | "CF" => number.filter(|flag| *flag <= 5).is_none().then(not_hp16c).flatten(),
+        "FLOAT" => number.filter(|digits| *digits <= MAX_FLOAT_DIGITS).is_none().then(not_hp16c).flatten(),
+        _ => not_hp16c(),
+    }
+}
+
+/// The HP-16C's key codes for the steps that take no argument: the row and
+/// column of each key, after 42 for the gold f key or 43 for the blue g key.
+/// The digit keys are shown as the digit itself.
+const KEYCODES: &[(&str, &str)] = &[
+    ("/", "10"), ("*", "20"), ("-", "30"), ("+", "40"), ("R/S", "31"), ("RV", "33"), ("SWAP", "34"),
+    ("ENTER", "36"), ("CHS", "49"), ("HEX", "23"), ("DEC", "24"), ("OCT", "25"), ("BIN", "26"),
+    ("SL 1", "42 A"), ("SR 1", "42 B"), ("RL", "42 C"), ("RR", "42 D"), ("RLN", "42 E"), ("RRN", "42 F"),
+    ("RMD", "42 9"), ("^", "42 10"), ("X<>(I)", "42 21"), ("X<>I", "42 22"), ("SB", "42 4"), ("CB", "42 5"),
+    ("&", "42 20"), ("CLR REG", "42 34"), ("CLEAR REG", "42 34"), ("1S", "42 1"), ("2S", "42 2"),
+    ("UNSGN", "42 3"), ("~", "42 30"), ("MEM", "42 0"), ("STATUS", "42 48"), ("|", "42 40"), ("RLC", "43 C"),
+    ("RRC", "43 D"), ("ABS", "43 8"), ("DBLR", "43 9"), ("DBL/", "43 10"), ("RTN", "43 21"), ("DBL*", "43 20"),
+    ("R^", "43 33"), ("CLX", "43 35"), ("<", "43 44"), (">", "43 45"), ("STO (I)", "44 31"), ("STO I", "44 32"),
+    ("RCL (I)", "45 31"), ("RCL I", "45 32"),
+];
+
+/// The keys an HP-16C shows for `step`, such as `43 22 A` for `LBL A`, or
+/// None for steps it has no single key sequence for, such as numbers of
+/// more than one digit or emulator extensions.
+pub fn keycode(step: &str) -> Option<String> {
+    let step = step.trim().to_uppercase();
+    if let Some(test) = Test::parse(&step) {
+        return Some(test.keycode());
+    }
+    if let Some((_, code)) = KEYCODES.iter().find(|(name, _)| *name == step) {
+        return Some(code.to_string());
+    }
+    if step.len() == 1 && step.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(step.to_string());
+    }
+    let (word, argument) = step.split_once(' ')?;
+    let prefix = match word {
+        "LBL" => "43 22",
+        "GTO" => "22",
+        "GSB" => "21",
+        "STO" => "44",
+        "RCL" => "45",
+        "SF" => "43 4",
+        "CF" => "43 5",
+        "FLOAT" => "42 45",
+        _ => return None,
+    };
+    let argument = match word {
+        "STO" | "RCL" => match parse_register(argument)? {
+            register @ 0..=15 => format!("{:X}", register),
+            register @ 16..=31 => format!("48 {:X}", register - 16),
+            _ => return None,
+        },
+        "SF" | "CF" => argument.parse::<u8>().ok().filter(|flag| *flag <= 5)?.to_string(),
+        _ if argument.len() == 1 && argument.chars().all(|c| c.is_ascii_hexdigit()) => argument.to_string(),
+        _ => return None,
+    };
+    Some(format!("{} {}", prefix, argument))
+}
+
+/// Bytes `step` takes in HP-16C program memory. Most instructions take
+/// one; `STO` and `RCL` of registers .0-.F take two, and a number takes one
+/// per key, as each digit is a step of its own on the calculator.
+pub fn step_bytes(step: &str) -> usize {
+    let step = step.trim().to_uppercase();
+    // Commands such as DEC and CB are spelled with hex digits too
+    let is_number = !HP16C_COMMANDS.contains(&step.as_str())
+        && step.starts_with(|c: char| c.is_ascii_hexdigit())
+        && step.chars().all(|c| c.is_ascii_hexdigit() || c == '.' || c == '-');
+    if is_number {
+        return step.len();
+    }
+    match step.split_once(' ') {
+        Some(("STO" | "RCL", register)) => match parse_register(register) {
+            Some(16..NAMED_REGISTERS) => 2,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+/// The legend of the HP-16C key with code `code`, such as `g` for 43 or
+/// `STO` for 44. Digit keys are their digit.
+pub fn key_legend(code: &str) -> Option<&'static str> {
+    let legend = match code {
+        "10" => "÷",
+        "20" => "×",
+        "30" => "−",
+        "40" => "+",
+        "21" => "GSB",
+        "22" => "GTO",
+        "23" => "HEX",
+        "24" => "DEC",
+        "25" => "OCT",
+        "26" => "BIN",
+        "31" => "R/S",
+        "32" => "SST",
+        "33" => "R↓",
+        "34" => "x≷y",
+        "35" => "BSP",
+        "36" => "ENTER",
+        "42" => "f",
+        "43" => "g",
+        "44" => "STO",
+        "45" => "RCL",
+        "48" => "·",
+        "49" => "CHS",
+        _ => return None,
+    };
+    Some(legend)
+}
+
+// A number step keyed one digit (or point) at a time
+fn is_keyed_number(step: &str) -> bool {
+    !HP16C_COMMANDS.contains(&step) && step.chars().all(|c| c.is_ascii_hexdigit() || c == '.')
+}
+
+// Where a program goes after a step
+enum Flow {
+    Next(usize),
+    // R/S, resuming on the line given
+    Stop(usize),
+    // A RTN outside any subroutine, or QUIT
+    End,
+}
+
+// An error on a program line
+fn at(line: usize, message: String) -> String {
+    format!("Line {:03}: {}", line, message)
+}
+
+/// Every step the keyboard can key, with its key codes, such as
+/// `("43 22 A", "LBL A")`: the inverse of [`keycode`].
+pub fn keyed_steps() -> Vec<(String, String)> {
+    let hex = |count: u8| (0..count).map(|digit| format!("{:X}", digit));
+    let mut steps: Vec<String> = KEYCODES.iter().map(|(step, _)| step.to_string()).collect();
+    steps.extend(hex(16));
+    steps.extend(["X=Y?", "X!=Y?", "X<=Y?", "X>Y?", "X=0?", "X!=0?", "X<0?", "X>0?", "B?"].map(String::from));
+    for word in ["LBL", "GTO", "GSB"] {
+        steps.extend(hex(16).map(|label| format!("{} {}", word, label)));
+    }
+    for word in ["STO", "RCL"] {
+        steps.extend((0..NAMED_REGISTERS).map(|register| format!("{} {}", word, register_name(register))));
+    }
+    for word in ["SF", "CF", "F?"] {
+        steps.extend((0..=5).map(|flag| format!("{} {}", word, flag)));
+    }
+    steps.extend(hex(10).map(|digits| format!("FLOAT {}", digits)));
+    steps.into_iter().filter_map(|step| Some((keycode(&step)?, step))).collect()
+}
+
+/// A label for LBL, GTO and GSB: 0-9 or A-F.
+pub fn parse_label(text: &str) -> Option<u8> {
+    let mut chars = text.trim().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+/// A step that changes where a program goes next rather than the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Label(u8),
+    Goto(u8),
+    Gosub(u8),
+    Return,
+    RunStop,
+}
+
+impl Control {
+    /// The control step `step` is, or None for a calculator command. A
+    /// LBL, GTO or GSB with a bad label is an error.
+    pub fn parse(step: &str) -> Result<Option<Self>, String> {
+        let step = step.trim().to_uppercase();
+        match step.as_str() {
+            "RTN" => return Ok(Some(Control::Return)),
+            "R/S" => return Ok(Some(Control::RunStop)),
+            _ => {},
+        }
+        let Some((word, label)) = step.split_once(' ') else {
+            return match step.as_str() {
+                "LBL" | "GTO" | "GSB" => Err(format!("{} needs a label (0-9, A-F)", step)),
+                _ => Ok(None),
+            };
+        };
+        let control: fn(u8) -> Control = match word {
+            "LBL" => Control::Label,
+            "GTO" => Control::Goto,
+            "GSB" => Control::Gosub,
+            _ => return Ok(None),
+        };
+        let label = parse_label(label).ok_or_else(|| format!("Invalid label {} (0-9, A-F)", label.trim()))?;
+        Ok(Some(control(label)))
+    }
+}
+
+/// A test instruction. Typed, it shows TRUE or SKIP; in a program the next
+/// step is skipped when the test is false.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    XEqualsY,
+    XNotEqualY,
+    XLessOrEqualY,
+    XGreaterThanY,
+    XEqualsZero,
+    XNotZero,
+    XLessThanZero,
+    XGreaterThanZero,
+    /// `B?`: bit X of Y is set. Drops the bit number and sets carry.
+    Bit,
+    /// `F? n`: flag n (0-5) is set
+    Flag(u8),
+}
+
+impl Test {
+    /// The test `step` is, as on the keyboard (`X<=Y?`, `x≠0?`, `B?`, `F? 3`),
+    /// or None.
+    pub fn parse(step: &str) -> Option<Self> {
+        let step = step.trim().to_uppercase().replace(' ', "");
+        let test = match step.as_str() {
+            "X=Y?" => Test::XEqualsY,
+            "X≠Y?" | "X!=Y?" => Test::XNotEqualY,
+            "X≤Y?" | "X<=Y?" => Test::XLessOrEqualY,
+            "X>Y?" => Test::XGreaterThanY,
+            "X=0?" => Test::XEqualsZero,
+            "X≠0?" | "X!=0?" => Test::XNotZero,
+            "X<0?" => Test::XLessThanZero,
+            "X>0?" => Test::XGreaterThanZero,
+            "B?" => Test::Bit,
+            other => {
+                let number = other.strip_prefix("F?")?.parse::<u8>().ok().filter(|number| *number <= 5)?;
+                Test::Flag(number)
+            },
+        };
+        Some(test)
+    }
+
+    /// The keys an HP-16C shows for the test, such as `43 1` for x≤y.
+    pub fn keycode(&self) -> String {
+        let code = match self {
+            Test::XEqualsY => "43 49",
+            Test::XNotEqualY => "43 0",
+            Test::XLessOrEqualY => "43 1",
+            Test::XGreaterThanY => "43 3",
+            Test::XEqualsZero => "43 40",
+            Test::XNotZero => "43 48",
+            Test::XLessThanZero => "43 2",
+            Test::XGreaterThanZero => "43 30",
+            Test::Bit => "42 6",
+            Test::Flag(number) => return format!("43 6 {}", number),
+        };
+        code.to_string()
+    }
+
+    /// Whether the test holds. Comparisons follow the complement mode, or
+    /// compare the decimal values in FLOAT mode.
+    pub fn evaluate(&self, cpu: &mut Hp16cCpu) -> Result<bool, String> {
+        let compare = |cpu: &Hp16cCpu, a: u128, b: u128| {
+            if cpu.is_float() {
+                let (a, b) = (Hp16cCpu::float_value(a), Hp16cCpu::float_value(b));
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            } else {
+                cpu.compare(a, b)
+            }
+        };
+        let zero = if cpu.is_float() { 0f64.to_bits() as u128 } else { 0 };
+        let held = match self {
+            Test::XEqualsY => compare(cpu, cpu.x(), cpu.y()).is_eq(),
+            Test::XNotEqualY => compare(cpu, cpu.x(), cpu.y()).is_ne(),
+            Test::XLessOrEqualY => compare(cpu, cpu.x(), cpu.y()).is_le(),
+            Test::XGreaterThanY => compare(cpu, cpu.x(), cpu.y()).is_gt(),
+            Test::XEqualsZero => compare(cpu, cpu.x(), zero).is_eq(),
+            Test::XNotZero => compare(cpu, cpu.x(), zero).is_ne(),
+            Test::XLessThanZero => compare(cpu, cpu.x(), zero).is_lt(),
+            Test::XGreaterThanZero => compare(cpu, cpu.x(), zero).is_gt(),
+            Test::Bit => {
+                if cpu.is_float() {
+                    return Err("B? is not available in FLOAT mode".to_string());
+                }
+                cpu.bit_test()?
+            },
+            Test::Flag(number) => cpu.test_flag(*number)?,
+        };
+        Ok(held)
+    }
+}
+
+/// Program memory: keystroke lines entered in program mode, numbered from
+/// 001. Line 000 is the top of memory and holds no step.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    steps: Vec<String>,
+    // Comments set with ANNOTATE, by line; they move with their steps
+    comments: BTreeMap<usize, String>,
+}
+
+impl From<Vec<String>> for Program {
+    fn from(steps: Vec<String>) -> Self {
+        Program {
+            steps,
+            comments: BTreeMap::new(),
+        }
+    }
+}
+
+// A table cell: pipes would end the cell early
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn steps(&self) -> &[String] {
+        &self.steps
+    }
+
+    /// The step on `line`, counting from 1.
+    pub fn step(&self, line: usize) -> Option<&str> {
+        line.checked_sub(1).and_then(|index| self.steps.get(index)).map(String::as_str)
+    }
+
+    /// Insert `step` after `line` and return its line number.
+    pub fn insert(&mut self, line: usize, step: &str) -> usize {
+        let line = line.min(self.steps.len());
+        self.steps.insert(line, step.to_string());
+        self.comments = std::mem::take(&mut self.comments)
+            .into_iter()
+            .map(|(commented, text)| (if commented > line { commented + 1 } else { commented }, text))
+            .collect();
+        line + 1
+    }
+
+    /// Remove the step on `line`, with its comment. Returns false if there
+    /// is none.
+    pub fn delete(&mut self, line: usize) -> bool {
+        if line == 0 || line > self.steps.len() {
+            return false;
+        }
+        self.steps.remove(line - 1);
+        self.comments.remove(&line);
+        self.comments = std::mem::take(&mut self.comments)
+            .into_iter()
+            .map(|(commented, text)| (if commented > line { commented - 1 } else { commented }, text))
+            .collect();
+        true
+    }
+
+    /// Put `step` in place of the one on `line`, keeping its comment.
+    /// Returns false if there is none.
+    pub fn replace(&mut self, line: usize, step: &str) -> bool {
+        match line.checked_sub(1).and_then(|index| self.steps.get_mut(index)) {
+            Some(old) => {
+                *old = step.to_string();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Bytes of program memory the steps take on the HP-16C.
+    pub fn bytes(&self) -> usize {
+        self.steps.iter().map(|step| step_bytes(step)).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.comments.clear();
+    }
+
+    /// ANNOTATE: attach `text` to the step on `line`, or remove its comment
+    /// if `text` is empty.
+    pub fn annotate(&mut self, line: usize, text: &str) -> Result<(), String> {
+        if self.step(line).is_none() {
+            return Err(format!("No step on line {:03}", line));
+        }
+        match text.trim() {
+            "" => self.comments.remove(&line),
+            text => self.comments.insert(line, text.to_string()),
+        };
+        Ok(())
+    }
+
+    /// The comment on `line`, if it has one.
+    pub fn comment(&self, line: usize) -> Option<&str> {
+        self.comments.get(&line).map(String::as_str)
+    }
+
+    pub fn comments(&self) -> &BTreeMap<usize, String> {
+        &self.comments
+    }
+
+    /// This program with `comments`, for restoring a session. Comments on
+    /// lines past the end are dropped.
+    pub fn with_comments(mut self, comments: BTreeMap<usize, String>) -> Self {
+        self.comments = comments.into_iter().filter(|(line, _)| self.step(*line).is_some()).collect();
+        self
+    }
+
+    /// EXPORT MD: the program as a Markdown table of line numbers, key
+    /// codes, steps and comments, laid out like a published listing.
+    pub fn markdown(&self) -> String {
+        let mut text = String::from("| Line | Keys | Step | Comment |\n|-----:|------|------|---------|\n");
+        for (index, step) in self.steps.iter().enumerate() {
+            let line = index + 1;
+            text.push_str(&format!(
+                "| {:03} | {} | {} | {} |\n",
+                line,
+                keycode(step).unwrap_or_default(),
+                markdown_cell(step),
+                markdown_cell(self.comment(line).unwrap_or_default())
+            ));
+        }
+        text
+    }
+
+    /// EXPORT KEYS: a checklist for keying the program into a physical
+    /// HP-16C, one line per calculator line with a box to tick, the keys
+    /// to press by their legends with f and g spelled out, their key codes
+    /// and the step. A number is keyed digit by digit, a line each, as on
+    /// the calculator. A step with no HP-16C keys is an error.
+    pub fn keystrokes(&self) -> Result<Vec<String>, String> {
+        let row = |line: String, codes: &str, step: &str| {
+            let keys: Vec<&str> = codes.split(' ').map(|code| key_legend(code).unwrap_or(code)).collect();
+            format!("[ ] {:<4} {:<14} {:<9} {}", line, keys.join(" "), codes, step)
+        };
+        let mut lines = vec![row(String::new(), "43 31", "P/R: program mode, from line 000")];
+        let mut line = 0;
+        for (index, step) in self.steps.iter().enumerate() {
+            let keyed = match keycode(step) {
+                Some(codes) => vec![(codes, step.to_string())],
+                None if is_keyed_number(step) => step
+                    .chars()
+                    .map(|c| (if c == '.' { "48".to_string() } else { c.to_string() }, c.to_string()))
+                    .collect(),
+                None => {
+                    let message = format!("{} has no HP-16C keys; CHECK lists the steps to change", step);
+                    return Err(format!("Line {:03}: {}", index + 1, message));
+                },
+            };
+            for (codes, keyed_step) in keyed {
+                line += 1;
+                lines.push(row(format!("{:03}", line), &codes, &keyed_step));
+            }
+            if let Some(comment) = self.comment(index + 1) {
+                let last = lines.last_mut().expect("a line was just added");
+                last.push_str(&format!("  # {}", comment));
+            }
+        }
+        lines.push(row(String::new(), "43 31", "P/R: back to run mode"));
+        Ok(lines)
+    }
+
+    /// The line of `LBL label`, the first one if there are several.
+    pub fn find_label(&self, label: u8) -> Option<usize> {
+        (1..=self.steps.len()).find(|line| {
+            matches!(Control::parse(&self.steps[line - 1]), Ok(Some(Control::Label(found))) if found == label)
+        })
+    }
+
+    /// CHECK: each step that would not run on an HP-16C, as `005- step:
+    /// reason`, reading numbers in `base`.
+    pub fn check(&self, base: Base) -> Vec<String> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter_map(|(index, step)| Some(format!("{:03}- {}: {}", index + 1, step, extension(step, base)?)))
+            .collect()
+    }
+
+    /// The listing shown by PRGM: `000-` then one `001- LBL A` line per
+    /// step.
+    pub fn listing(&self) -> Vec<String> {
+        let mut lines = vec!["000-".to_string()];
+        lines.extend(self.steps.iter().enumerate().map(|(index, step)| format!("{:03}- {}", index + 1, step)));
+        lines
+    }
+
+    /// The listing shown by PRGM LIST: each line with its key codes and
+    /// then the step, `005- 43 22 A  LBL A`, to compare with published
+    /// HP-16C programs.
+    pub fn keycode_listing(&self) -> Vec<String> {
+        (0..=self.steps.len()).map(|line| self.keycode_line(line)).collect()
+    }
+
+    /// `line` as SST and BST show it, the step's keys then the step:
+    /// `005- 43 22 A  LBL A`. The keys are left blank for steps without
+    /// any, and line 000 is `000-`.
+    pub fn keycode_line(&self, line: usize) -> String {
+        match self.step(line) {
+            Some(step) => format!("{:03}- {:<7}  {}", line, keycode(step).unwrap_or_default(), step),
+            None => format!("{:03}-", line),
+        }
+    }
+
+    /// Run from `line`, passing each calculator command to `execute`, until
+    /// R/S, a RTN outside any subroutine or the end of memory. A false test
+    /// skips the step after it. The
+    /// calculator's program counter is left on the line to resume from, or
+    /// on the failing line after an error. Messages produced by commands
+    /// are collected.
+    pub fn run<F>(
+        &self,
+        cpu: &mut Hp16cCpu,
+        line: usize,
+        cancel: &CancelToken,
+        execute: &mut F,
+    ) -> Result<Vec<String>, String>
+    where
+        F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+    {
+        let mut messages = Vec::new();
+        // Subroutines entered by single steps before the run
+        let mut returns = std::mem::take(&mut cpu.returns);
+        // Line 000 holds no step, so a run from the top starts on 001
+        let mut line = line.max(1);
+        let mut steps_run = 0;
+
+        while self.step(line).is_some() {
+            cpu.pc = line as u16;
+            if cancel.is_cancelled() {
+                return Err(at(line, format!("cancelled after {} steps", steps_run)));
+            }
+            steps_run += 1;
+            if steps_run > MAX_STEPS_RUN {
+                return Err(at(line, format!("program did not stop after {} steps", MAX_STEPS_RUN)));
+            }
+            line = match self.run_step(cpu, line, &mut returns, &mut messages, execute)? {
+                Flow::Next(next) => next,
+                Flow::Stop(next) => {
+                    cpu.pc = next as u16;
+                    cpu.returns = returns;
+                    return Ok(messages);
+                },
+                Flow::End => break,
+            };
+        }
+        cpu.pc = 0;
+        Ok(messages)
+    }
+
+    /// SST in run mode: run the step on the current line, from 001 at the
+    /// top, and move to the line the program would go to next. GSB enters
+    /// the subroutine, remembering where RTN goes back to; a RTN outside
+    /// any subroutine, or stepping off the end, goes back to 000. Returns
+    /// the messages the step produced.
+    pub fn single_step<F>(&self, cpu: &mut Hp16cCpu, execute: &mut F) -> Result<Vec<String>, String>
+    where
+        F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+    {
+        if self.is_empty() {
+            return Err("Program memory is empty".to_string());
+        }
+        let line = (cpu.pc as usize).max(1);
+        cpu.pc = line as u16;
+        let mut messages = Vec::new();
+        let mut returns = std::mem::take(&mut cpu.returns);
+        let next = match self.run_step(cpu, line, &mut returns, &mut messages, execute)? {
+            Flow::Next(next) | Flow::Stop(next) if next <= self.len() => next,
+            _ => 0,
+        };
+        if next == 0 {
+            returns.clear();
+        }
+        cpu.pc = next as u16;
+        cpu.returns = returns;
+        Ok(messages)
+    }
+
+    // Run the step on `line`, which must exist, and say where to go next
+    fn run_step<F>(
+        &self,
+        cpu: &mut Hp16cCpu,
+        line: usize,
+        returns: &mut Vec<usize>,
+        messages: &mut Vec<String>,
+        execute: &mut F,
+    ) -> Result<Flow, String>
+    where
+        F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+    {
+        let step = self.step(line).unwrap_or_default();
+        let flow = match Control::parse(step).map_err(|e| at(line, e))? {
+            Some(Control::Label(_)) => Flow::Next(line + 1),
+            Some(Control::Goto(label)) => Flow::Next(self.label_line(label).map_err(|e| at(line, e))?),
+            Some(Control::Gosub(label)) => {
+                if returns.len() >= RETURN_STACK_DEPTH {
+                    return Err(at(line, format!("subroutines nested more than {} deep", RETURN_STACK_DEPTH)));
+                }
+                let target = self.label_line(label).map_err(|e| at(line, e))?;
+                returns.push(line + 1);
+                Flow::Next(target)
+            },
+            Some(Control::Return) => match returns.pop() {
+                Some(next) => Flow::Next(next),
+                None => Flow::End,
+            },
+            Some(Control::RunStop) => Flow::Stop(if line < self.len() { line + 1 } else { 0 }),
+            None => match Test::parse(step) {
+                Some(test) => {
+                    let held = test.evaluate(cpu).map_err(|e| at(line, e))?;
+                    Flow::Next(if held { line + 1 } else { line + 2 })
+                },
+                None => match execute(cpu, step) {
+                    Ok(Outcome::Message(lines)) => {
+                        messages.extend(lines);
+                        Flow::Next(line + 1)
+                    },
+                    Ok(Outcome::Done) => Flow::Next(line + 1),
+                    Ok(Outcome::Quit) => Flow::End,
+                    Err(message) => return Err(at(line, message)),
+                },
+            },
+        };
+        Ok(flow)
+    }
+
+    /// The line of `LBL label`, or the error a GTO or GSB to it shows.
+    pub fn label_line(&self, label: u8) -> Result<usize, String> {
+        self.find_label(label).ok_or_else(|| format!("No LBL {:X} in program memory", label))
+    }
+}
@@ -0,0 +1,107 @@
+use crate::command;
+use crate::cpu::{parse_register, Hp16cCpu};
+use crate::snapshot::Snapshot;
+use crate::storage::{FileStorage, Storage};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Folder that gives a directory tree its own calculator environment.
+pub const PROJECT_DIR: &str = ".rpn_rust";
+
+/// Script of commands run at startup.
+pub const CONFIG_FILE: &str = "config.rpn";
+/// Key bindings, in the keymap format.
+pub const KEYMAP_FILE: &str = "keymap.txt";
+/// Register labels, one `register label` pair per line.
+pub const REGISTERS_FILE: &str = "registers.txt";
+/// The session, restored at startup and saved on exit.
+pub const SESSION_FILE: &str = "session.json";
+/// Line editor history.
+pub const HISTORY_FILE: &str = "history.txt";
+
+/// A `.rpn_rust/` folder found at startup. Like direnv with `.envrc`, the
+/// nearest one in the working directory or a parent applies.
+#[derive(Debug, Clone)]
+pub struct Project {
+    dir: PathBuf,
+    storage: FileStorage,
+}
+
+impl Project {
+    /// The project `start` is in, if any.
+    pub fn find(start: &Path) -> Option<Self> {
+        let dir = start.ancestors().map(|dir| dir.join(PROJECT_DIR)).find(|dir| dir.is_dir())?;
+        Some(Project {
+            storage: FileStorage::new(&dir),
+            dir,
+        })
+    }
+
+    /// The `.rpn_rust/` folder itself.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of one of the project's files.
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    /// Set up `cpu` from the project's saved session, register labels and
+    /// config script, in that order, so the config has the last word.
+    /// Returns a warning for each file that could not be applied.
+    pub fn load(&self, cpu: &mut Hp16cCpu) -> Vec<String> {
+        let mut storage = self.storage.clone();
+        load_project(&mut storage, cpu)
+    }
+
+    /// Save the session for the next start in this project.
+    pub fn save_session(&mut self, cpu: &Hp16cCpu) -> io::Result<()> {
+        self.storage.save_snapshot(SESSION_FILE, &Snapshot::capture(cpu))
+    }
+}
+
+/// [`Project::load`] from any storage holding the project's files.
+pub fn load_project(storage: &mut dyn Storage, cpu: &mut Hp16cCpu) -> Vec<String> {
+    let mut warnings = Vec::new();
+    match storage.load_snapshot(SESSION_FILE) {
+        Ok(Some(snapshot)) => snapshot.restore(cpu),
+        Ok(None) => {},
+        Err(e) => warnings.push(format!("Could not restore {}: {}", SESSION_FILE, e)),
+    }
+    match storage.read(REGISTERS_FILE) {
+        Ok(Some(text)) => {
+            if let Err(e) = apply_register_map(cpu, &text) {
+                warnings.push(format!("Could not apply {}: {}", REGISTERS_FILE, e));
+            }
+        },
+        Ok(None) => {},
+        Err(e) => warnings.push(format!("Could not read {}: {}", REGISTERS_FILE, e)),
+    }
+    if let Ok(true) = storage.exists(CONFIG_FILE) {
+        if let Err(e) = command::execute(cpu, storage, &format!("SOURCE {}", CONFIG_FILE)) {
+            warnings.push(format!("Could not apply {}: {}", CONFIG_FILE, e));
+        }
+    }
+    warnings
+}
+
+/// Label registers from `register label` lines, as TAG would. Blank lines
+/// and lines starting with `#` are skipped. Returns the number of labels.
+pub fn apply_register_map(cpu: &mut Hp16cCpu, text: &str) -> Result<usize, String> {
+    let mut count = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (register, label) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("line {}: expected REGISTER LABEL", number + 1))?;
+        let register =
+            parse_register(register).ok_or_else(|| format!("line {}: invalid register {}", number + 1, register))?;
+        cpu.tag(register, label.trim()).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        count += 1;
+    }
+    Ok(count)
+}
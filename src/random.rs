@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+/// Seed used until the user sets one, so streams are reproducible.
+const DEFAULT_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// PCG32 multiplier and default stream.
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+const PCG_INCREMENT: u64 = 1442695040888963407;
+
+/// The pseudo-random generator behind RAND. Its state is visible and
+/// saved with the session, so a stream can be reproduced exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Generator {
+    /// Galois LFSR shifting right: when the bit shifted out is 1 the
+    /// state is XORed with `taps`. The highest tap sets the register width.
+    Lfsr { taps: u128, state: u128 },
+    /// Marsaglia's xorshift64 with shifts 13, 7, 17.
+    Xorshift { state: u64 },
+    /// PCG32 (XSH RR) with the default stream.
+    Pcg { state: u64 },
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator::Xorshift { state: DEFAULT_SEED }
+    }
+}
+
+impl Generator {
+    /// An LFSR with the given taps, starting from state 1.
+    pub fn lfsr(taps: u128) -> Result<Self, String> {
+        if taps == 0 {
+            return Err("LFSR taps must not be zero".to_string());
+        }
+        Ok(Generator::Lfsr { taps, state: 1 })
+    }
+
+    pub fn xorshift() -> Self {
+        Generator::Xorshift { state: DEFAULT_SEED }
+    }
+
+    pub fn pcg() -> Self {
+        let mut generator = Generator::Pcg { state: 0 };
+        generator.seed(DEFAULT_SEED as u128).expect("PCG accepts any seed");
+        generator
+    }
+
+    /// Bits produced by one step.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Generator::Lfsr { taps, .. } => 128 - taps.leading_zeros(),
+            Generator::Xorshift { .. } => 64,
+            Generator::Pcg { .. } => 32,
+        }
+    }
+
+    /// Restart from `seed`. LFSR and xorshift states must not be zero, as
+    /// they would never leave it.
+    pub fn seed(&mut self, seed: u128) -> Result<(), String> {
+        match self {
+            Generator::Lfsr { taps, state } => {
+                let width = 128 - taps.leading_zeros();
+                let seed = if width == 128 { seed } else { seed & ((1u128 << width) - 1) };
+                if seed == 0 {
+                    return Err("LFSR seed must not be zero".to_string());
+                }
+                *state = seed;
+            },
+            Generator::Xorshift { state } => {
+                if seed as u64 == 0 {
+                    return Err("Xorshift seed must not be zero".to_string());
+                }
+                *state = seed as u64;
+            },
+            Generator::Pcg { state } => {
+                // As the reference pcg32_srandom: step from zero, add the
+                // seed, step again
+                *state = PCG_INCREMENT.wrapping_add(seed as u64);
+                *state = state.wrapping_mul(PCG_MULTIPLIER).wrapping_add(PCG_INCREMENT);
+            },
+        }
+        Ok(())
+    }
+
+    /// Advance one step and return its output.
+    pub fn step(&mut self) -> u128 {
+        match self {
+            Generator::Lfsr { taps, state } => {
+                let out = *state & 1;
+                *state >>= 1;
+                if out == 1 {
+                    *state ^= *taps;
+                }
+                *state
+            },
+            Generator::Xorshift { state } => {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                *state as u128
+            },
+            Generator::Pcg { state } => {
+                let old = *state;
+                *state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(PCG_INCREMENT);
+                let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+                xorshifted.rotate_right((old >> 59) as u32) as u128
+            },
+        }
+    }
+
+    /// A value of `bits` bits: the outputs of as many steps as needed,
+    /// concatenated. An LFSR returns its state after one step.
+    pub fn next_word(&mut self, bits: u32) -> u128 {
+        if let Generator::Lfsr { .. } = self {
+            return self.step();
+        }
+        let step = self.bits();
+        let mut value = 0u128;
+        let mut filled = 0;
+        while filled < bits {
+            value = value.checked_shl(step).unwrap_or(0) | self.step();
+            filled += step;
+        }
+        value
+    }
+
+    /// Generator, taps and state for RAND STATE.
+    pub fn describe(&self) -> String {
+        match self {
+            Generator::Lfsr { taps, state } => {
+                format!("LFSR taps {:X} ({} bits), state {:X}", taps, self.bits(), state)
+            },
+            Generator::Xorshift { state } => format!("XORSHIFT state {:X}", state),
+            Generator::Pcg { state } => format!("PCG state {:X}", state),
+        }
+    }
+}
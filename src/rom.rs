@@ -7,6 +7,12 @@ pub struct Rom {
     data: HashMap<u16, u16>,
 }
 
+impl Default for Rom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Rom {
     pub fn new() -> Self {
         Rom {
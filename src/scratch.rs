@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+/// Bytes of scratch memory.
+pub const SCRATCH_SIZE: usize = 1024;
+
+/// Byte order used to lay multi-byte values out in scratch memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Endian::Little => "LITTLE",
+            Endian::Big => "BIG",
+        }
+    }
+}
+
+/// Byte-addressed memory modelled on a target's RAM, for building and
+/// inspecting memory images with narrow loads and stores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scratch {
+    bytes: Vec<u8>,
+}
+
+impl Default for Scratch {
+    fn default() -> Self {
+        Scratch {
+            bytes: vec![0; SCRATCH_SIZE],
+        }
+    }
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.fill(0);
+    }
+
+    /// The bytes from `address` to `address + width`, or an error naming
+    /// the valid address range.
+    fn range(&self, address: u128, width: usize) -> Result<std::ops::Range<usize>, String> {
+        let start = usize::try_from(address).ok().filter(|start| start + width <= self.bytes.len());
+        match start {
+            Some(start) => Ok(start..start + width),
+            None => Err(format!(
+                "Address {:X} is outside scratch memory (0-{:X} for {} bytes)",
+                address,
+                self.bytes.len() - width,
+                width
+            )),
+        }
+    }
+
+    /// The `length` bytes at `address`.
+    pub fn read(&self, address: u128, length: usize) -> Result<&[u8], String> {
+        let range = self.range(address, length)?;
+        Ok(&self.bytes[range])
+    }
+
+    /// Copy `bytes` in at `address`.
+    pub fn write(&mut self, address: u128, bytes: &[u8]) -> Result<(), String> {
+        let range = self.range(address, bytes.len())?;
+        self.bytes[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Write the low `width` bytes of `value` at `address`.
+    pub fn store(&mut self, address: u128, width: usize, value: u128, endian: Endian) -> Result<(), String> {
+        let range = self.range(address, width)?;
+        let little = value.to_le_bytes();
+        let cells = &mut self.bytes[range];
+        for (i, cell) in cells.iter_mut().enumerate() {
+            *cell = match endian {
+                Endian::Little => little[i],
+                Endian::Big => little[width - 1 - i],
+            };
+        }
+        Ok(())
+    }
+
+    /// Read `width` bytes at `address` as an unsigned value.
+    pub fn load(&self, address: u128, width: usize, endian: Endian) -> Result<u128, String> {
+        let range = self.range(address, width)?;
+        let cells = &self.bytes[range];
+        let mut little = [0u8; 16];
+        for (i, cell) in cells.iter().enumerate() {
+            match endian {
+                Endian::Little => little[i] = *cell,
+                Endian::Big => little[width - 1 - i] = *cell,
+            }
+        }
+        Ok(u128::from_le_bytes(little))
+    }
+
+    /// Hex dump of `rows` lines of 16 bytes from `address`, which is
+    /// rounded down to a row boundary.
+    pub fn dump(&self, address: usize, rows: usize) -> Vec<String> {
+        let start = (address / 16 * 16).min(self.bytes.len() - 16);
+        self.bytes[start..]
+            .chunks(16)
+            .take(rows)
+            .enumerate()
+            .map(|(row, bytes)| format!("{:04X}: {}", start + row * 16, hex_bytes(bytes)))
+            .collect()
+    }
+
+    /// Contents as hex, without the trailing zero bytes, for saving.
+    pub fn to_hex(&self) -> String {
+        let used = self.bytes.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+        self.bytes[..used].iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    /// Load contents saved by [`Scratch::to_hex`]. Invalid text is ignored.
+    pub fn set_hex(&mut self, text: &str) {
+        self.clear();
+        let bytes = text.as_bytes().chunks(2).map(|pair| {
+            std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        });
+        for (cell, byte) in self.bytes.iter_mut().zip(bytes) {
+            *cell = byte.unwrap_or(0);
+        }
+    }
+}
+
+/// Bytes as space-separated hex pairs.
+pub fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// The character encoded at the start of `bytes` and its length in bytes.
+/// Overlong forms, surrogates and truncated sequences are rejected.
+pub fn decode_utf8(bytes: &[u8]) -> Result<(char, usize), String> {
+    let lead = *bytes.first().ok_or("No bytes to decode")?;
+    let length = match lead.leading_ones() {
+        0 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        _ => return Err(format!("{:02X} does not start a UTF-8 sequence", lead)),
+    };
+    let sequence = bytes.get(..length).ok_or("Truncated UTF-8 sequence")?;
+    match std::str::from_utf8(sequence) {
+        Ok(text) => Ok((text.chars().next().unwrap_or_default(), length)),
+        Err(_) => Err(format!("Invalid UTF-8 sequence: {}", hex_bytes(sequence))),
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Bytes of a hex string. Whitespace and `:` between pairs are ignored.
+pub fn decode_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("Hex string has an odd number of digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("Invalid hex byte: {}", pair))
+        })
+        .collect()
+}
+
+/// Bytes of a base64 string, in the standard or URL-safe alphabet.
+/// Padding is optional and whitespace is ignored.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches(|c: char| c == '=' || c.is_whitespace()).chars() {
+        let value = match c {
+            '-' => 62,
+            '_' => 63,
+            c if c.is_whitespace() => continue,
+            c => BASE64_ALPHABET
+                .iter()
+                .position(|letter| *letter as char == c)
+                .ok_or_else(|| format!("Invalid base64 character: {}", c))?,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    if count >= 6 {
+        return Err("Base64 string is truncated".to_string());
+    }
+    Ok(bytes)
+}
+
+/// Standard base64 with `=` padding.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for group in bytes.chunks(3) {
+        let bits = group.iter().fold(0u32, |bits, byte| (bits << 8) | *byte as u32) << (8 * (3 - group.len()));
+        for i in 0..4 {
+            if i <= group.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
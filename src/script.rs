@@ -0,0 +1,378 @@
+use crate::cancel::CancelToken;
+use crate::command::Outcome;
+use crate::cpu::Hp16cCpu;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Guard against scripts that never leave a WHILE loop
+const MAX_LOOP_ITERATIONS: usize = 1_000_000;
+
+/// A flag or stack test evaluated by IF and WHILE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Carry,
+    Overflow,
+    XZero,
+    XEqualsY,
+    XLessThanY,
+    XGreaterThanY,
+    /// `F? n`: user flag n (0-5) is set
+    Flag(u8),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_uppercase();
+        if let Some(rest) = text.strip_prefix("NOT ") {
+            return Some(Condition::Not(Box::new(Self::parse(rest)?)));
+        }
+        let negated = |c| Some(Condition::Not(Box::new(c)));
+        match text.replace(' ', "").as_str() {
+            "CARRY" => Some(Condition::Carry),
+            "OVERFLOW" => Some(Condition::Overflow),
+            "X=0" => Some(Condition::XZero),
+            "X!=0" => negated(Condition::XZero),
+            "X=Y" => Some(Condition::XEqualsY),
+            "X!=Y" => negated(Condition::XEqualsY),
+            "X<Y" => Some(Condition::XLessThanY),
+            "X>Y" => Some(Condition::XGreaterThanY),
+            other => other
+                .strip_prefix("F?")
+                .and_then(|number| number.parse::<u8>().ok())
+                .filter(|number| *number <= 5)
+                .map(Condition::Flag),
+        }
+    }
+
+    pub fn evaluate(&self, cpu: &Hp16cCpu) -> bool {
+        match self {
+            Condition::Carry => cpu.carry,
+            Condition::Overflow => cpu.overflow,
+            Condition::XZero => cpu.x == 0,
+            Condition::XEqualsY => cpu.x == cpu.y,
+            Condition::XLessThanY => cpu.compare(cpu.x, cpu.y).is_lt(),
+            Condition::XGreaterThanY => cpu.compare(cpu.x, cpu.y).is_gt(),
+            Condition::Flag(number) => cpu.test_flag(*number).unwrap_or(false),
+            Condition::Not(inner) => !inner.evaluate(cpu),
+        }
+    }
+}
+
+/// The script file and line a statement came from, for error messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    source: Rc<str>,
+    line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.source.is_empty() {
+            write!(f, "line {}", self.line)
+        } else {
+            write!(f, "{}: line {}", self.source, self.line)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+    Command { at: Location, text: String },
+    If { condition: Condition, then: Vec<Statement>, otherwise: Vec<Statement> },
+    While { at: Location, condition: Condition, body: Vec<Statement> },
+    Repeat { count: usize, body: Vec<Statement> },
+}
+
+/// A parsed batch script: calculator commands, one per line, with
+/// `IF cond` / `ELSE` / `END`, `WHILE cond` / `END` and `REPEAT n` / `END`
+/// blocks. Lines starting with `#` are comments, and `INCLUDE file` splices
+/// in another script when loaded through [`Script::load`].
+///
+/// Conditions are `CARRY`, `OVERFLOW`, `X=0`, `X!=0`, `X=Y`, `X!=Y`, `X<Y`,
+/// `X>Y` and `F? n`, optionally prefixed with `NOT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    body: Vec<Statement>,
+}
+
+/// Where a block being parsed was opened, so errors can point at it.
+enum Block {
+    Top,
+    If { line: usize, condition: Condition, then: Option<Vec<Statement>> },
+    While { line: usize, condition: Condition },
+    Repeat { line: usize, count: usize },
+}
+
+/// Directories searched for scripts named by SOURCE and INCLUDE.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchPath {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    /// Environment variable listing extra script directories, separated
+    /// like `PATH`.
+    pub const ENV_VAR: &'static str = "HP16C_PATH";
+
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        SearchPath { dirs }
+    }
+
+    pub fn from_env() -> Self {
+        match env::var_os(Self::ENV_VAR) {
+            Some(value) => SearchPath::new(env::split_paths(&value).collect()),
+            None => SearchPath::default(),
+        }
+    }
+
+    /// Names to try for `name`, in order: next to the including script,
+    /// as given, then in each search directory.
+    fn candidates(&self, name: &str, including: Option<&str>) -> Vec<String> {
+        let mut candidates = Vec::new();
+        if let Some(dir) = including.and_then(|key| Path::new(key).parent()) {
+            if !dir.as_os_str().is_empty() && Path::new(name).is_relative() {
+                candidates.push(dir.join(name).to_string_lossy().into_owned());
+            }
+        }
+        candidates.push(name.to_string());
+        for dir in &self.dirs {
+            candidates.push(dir.join(name).to_string_lossy().into_owned());
+        }
+        candidates
+    }
+}
+
+type Includer<'a> = dyn FnMut(&str) -> Result<Vec<Statement>, String> + 'a;
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut no_includes = |_: &str| Err("INCLUDE is only available in script files".to_string());
+        Self::parse_with(text, "", &mut no_includes)
+    }
+
+    /// Load a script and everything it includes. `read` returns the text
+    /// stored under a name, or None if there is none.
+    pub fn load<F>(name: &str, search: &SearchPath, read: &mut F) -> Result<Self, String>
+    where
+        F: FnMut(&str) -> Result<Option<String>, String>,
+    {
+        let mut chain = Vec::new();
+        let body = load_body(name, search, read, &mut chain)?;
+        Ok(Script { body })
+    }
+
+    fn parse_with(text: &str, source: &str, include: &mut Includer) -> Result<Self, String> {
+        let source: Rc<str> = Rc::from(source);
+        let mut stack: Vec<(Block, Vec<Statement>)> = vec![(Block::Top, Vec::new())];
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let text = raw.trim();
+
+            // Skip comments and empty lines
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let upper = text.to_uppercase();
+            let (keyword, rest) = match upper.split_once(char::is_whitespace) {
+                Some((keyword, rest)) => (keyword, rest.trim()),
+                None => (upper.as_str(), ""),
+            };
+            let condition = || {
+                Condition::parse(rest).ok_or_else(|| format!("line {}: unknown condition '{}'", line, rest))
+            };
+
+            match keyword {
+                "IF" => stack.push((Block::If { line, condition: condition()?, then: None }, Vec::new())),
+                "WHILE" => stack.push((Block::While { line, condition: condition()? }, Vec::new())),
+                "REPEAT" => {
+                    let count = rest
+                        .parse::<usize>()
+                        .map_err(|_| format!("line {}: REPEAT needs a count", line))?;
+                    stack.push((Block::Repeat { line, count }, Vec::new()));
+                },
+                "INCLUDE" => {
+                    let name = text[7..].trim();
+                    if name.is_empty() {
+                        return Err(format!("line {}: INCLUDE needs a file name", line));
+                    }
+                    let included = include(name).map_err(|e| format!("line {}: {}", line, e))?;
+                    stack.last_mut().expect("top level block").1.extend(included);
+                },
+                "ELSE" => match stack.last_mut() {
+                    Some((Block::If { then, .. }, body)) if then.is_none() => {
+                        *then = Some(std::mem::take(body));
+                    },
+                    _ => return Err(format!("line {}: ELSE without IF", line)),
+                },
+                "END" => {
+                    let statement = match stack.pop() {
+                        Some((Block::If { condition, then, .. }, body)) => match then {
+                            Some(then) => Statement::If { condition, then, otherwise: body },
+                            None => Statement::If { condition, then: body, otherwise: Vec::new() },
+                        },
+                        Some((Block::While { line, condition }, body)) => Statement::While {
+                            at: Location { source: Rc::clone(&source), line },
+                            condition,
+                            body,
+                        },
+                        Some((Block::Repeat { count, .. }, body)) => Statement::Repeat { count, body },
+                        Some((Block::Top, _)) | None => return Err(format!("line {}: END without a block", line)),
+                    };
+                    stack.last_mut().expect("top level block").1.push(statement);
+                },
+                _ => stack.last_mut().expect("top level block").1.push(Statement::Command {
+                    at: Location { source: Rc::clone(&source), line },
+                    text: text.to_string(),
+                }),
+            }
+        }
+
+        match stack.pop() {
+            Some((Block::Top, body)) => Ok(Script { body }),
+            Some((Block::If { line, .. }, _))
+            | Some((Block::While { line, .. }, _))
+            | Some((Block::Repeat { line, .. }, _)) => Err(format!("line {}: block is missing END", line)),
+            None => unreachable!("top level block is never popped early"),
+        }
+    }
+
+    /// Run the script, passing each command to `execute`. Messages produced
+    /// by commands are collected; the first failing command stops the run.
+    pub fn run<F>(&self, cpu: &mut Hp16cCpu, execute: &mut F) -> Result<Vec<String>, String>
+    where
+        F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+    {
+        self.run_cancellable(cpu, &CancelToken::new(), execute)
+    }
+
+    /// Like [`Script::run`], but stops with an error reporting how far the
+    /// script got once `cancel` is set.
+    pub fn run_cancellable<F>(
+        &self,
+        cpu: &mut Hp16cCpu,
+        cancel: &CancelToken,
+        execute: &mut F,
+    ) -> Result<Vec<String>, String>
+    where
+        F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+    {
+        let mut progress = Progress {
+            cancel,
+            commands: 0,
+            messages: Vec::new(),
+        };
+        run_block(&self.body, cpu, execute, &mut progress)?;
+        Ok(progress.messages)
+    }
+}
+
+/// State carried through a script run.
+struct Progress<'a> {
+    cancel: &'a CancelToken,
+    // Commands run so far, for reporting where a cancelled run stopped
+    commands: usize,
+    messages: Vec<String>,
+}
+
+impl Progress<'_> {
+    fn check(&self, at: &Location) -> Result<(), String> {
+        if self.cancel.is_cancelled() {
+            return Err(format!("{}: cancelled after {} commands", at, self.commands));
+        }
+        Ok(())
+    }
+}
+
+fn load_body<F>(
+    name: &str,
+    search: &SearchPath,
+    read: &mut F,
+    chain: &mut Vec<String>,
+) -> Result<Vec<Statement>, String>
+where
+    F: FnMut(&str) -> Result<Option<String>, String>,
+{
+    let mut found = None;
+    for candidate in search.candidates(name, chain.last().map(String::as_str)) {
+        if let Some(text) = read(&candidate)? {
+            found = Some((candidate, text));
+            break;
+        }
+    }
+    let (key, text) = found.ok_or_else(|| format!("No such script: {}", name))?;
+
+    if chain.contains(&key) {
+        return Err(format!("INCLUDE cycle: {} -> {}", chain.join(" -> "), key));
+    }
+    chain.push(key.clone());
+    let mut include = |included: &str| load_body(included, search, read, chain);
+    let script = Script::parse_with(&text, &key, &mut include);
+    chain.pop();
+
+    script.map(|script| script.body).map_err(|e| format!("{}: {}", key, e))
+}
+
+/// Returns false once the script asked to quit.
+fn run_block<F>(
+    body: &[Statement],
+    cpu: &mut Hp16cCpu,
+    execute: &mut F,
+    progress: &mut Progress,
+) -> Result<bool, String>
+where
+    F: FnMut(&mut Hp16cCpu, &str) -> Result<Outcome, String>,
+{
+    for statement in body {
+        let keep_going = match statement {
+            Statement::Command { at, text } => {
+                progress.check(at)?;
+                progress.commands += 1;
+                match execute(cpu, text) {
+                    Ok(Outcome::Done) => true,
+                    Ok(Outcome::Message(lines)) => {
+                        progress.messages.extend(lines);
+                        true
+                    },
+                    Ok(Outcome::Quit) => false,
+                    Err(message) => return Err(format!("{}: {}", at, message)),
+                }
+            },
+            Statement::If { condition, then, otherwise } => {
+                let branch = if condition.evaluate(cpu) { then } else { otherwise };
+                run_block(branch, cpu, execute, progress)?
+            },
+            Statement::While { at, condition, body } => {
+                let mut iterations = 0;
+                let mut keep_going = true;
+                while keep_going && condition.evaluate(cpu) {
+                    progress.check(at)?;
+                    iterations += 1;
+                    if iterations > MAX_LOOP_ITERATIONS {
+                        return Err(format!("{}: WHILE loop did not finish", at));
+                    }
+                    keep_going = run_block(body, cpu, execute, progress)?;
+                }
+                keep_going
+            },
+            Statement::Repeat { count, body } => {
+                let mut keep_going = true;
+                for _ in 0..*count {
+                    keep_going = run_block(body, cpu, execute, progress)?;
+                    if !keep_going {
+                        break;
+                    }
+                }
+                keep_going
+            },
+        };
+        if !keep_going {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,107 @@
+use crate::program::{Control, Test};
+
+/// Spellings from published listings and other calculators, and the step
+/// this calculator knows them as.
+const ALIASES: &[(&str, &str)] = &[
+    ("X<>Y", "SWAP"),
+    ("X⇄Y", "SWAP"),
+    ("R↓", "RV"),
+    ("RDN", "RV"),
+    ("R↑", "R^"),
+    ("RUP", "R^"),
+    ("×", "*"),
+    ("÷", "/"),
+    ("AND", "&"),
+    ("OR", "|"),
+    ("XOR", "^"),
+    ("NOT", "~"),
+    ("SL", "SL 1"),
+    ("SR", "SR 1"),
+];
+
+/// A step of a program written in shorthand, with the line and column it
+/// starts at, both from 1, for errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceStep {
+    pub line: usize,
+    pub column: usize,
+    pub step: String,
+}
+
+impl SourceStep {
+    /// `message` about this step, saying where it is.
+    pub fn error(&self, message: impl std::fmt::Display) -> String {
+        format!("Line {}, column {}: {}", self.line, self.column, message)
+    }
+}
+
+/// Split a program written in an editor into steps, such as
+/// `LBL A; RCL 1; x<>y; GTO A`. Steps are separated by `;` or new lines,
+/// and `#` comments out the rest of a line. Line numbers copied from a
+/// listing (`001-`) and the f and g prefixes are skipped, and the usual
+/// spellings of published listings (`x<>y`, `R↓`, `XOR`) become this
+/// calculator's. A LBL, GTO or GSB with a bad label, or a GTO or GSB to a
+/// label the program lacks, is an error saying where it is.
+pub fn parse(text: &str) -> Result<Vec<SourceStep>, String> {
+    let mut steps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        let mut offset = 0;
+        for part in code.split(';') {
+            let start = offset + part.len() - part.trim_start().len();
+            offset += part.len() + 1;
+            let step = normalize(part);
+            if step.is_empty() {
+                continue;
+            }
+            let step = SourceStep {
+                line: index + 1,
+                column: code[..start].chars().count() + 1,
+                step,
+            };
+            Control::parse(&step.step).map_err(|e| step.error(e))?;
+            steps.push(step);
+        }
+    }
+
+    let labels: Vec<u8> = steps
+        .iter()
+        .filter_map(|source| match Control::parse(&source.step) {
+            Ok(Some(Control::Label(label))) => Some(label),
+            _ => None,
+        })
+        .collect();
+    for source in &steps {
+        if let Ok(Some(Control::Goto(label) | Control::Gosub(label))) = Control::parse(&source.step) {
+            if !labels.contains(&label) {
+                return Err(source.error(format!("{} has no LBL {:X} to go to", source.step, label)));
+            }
+        }
+    }
+    Ok(steps)
+}
+
+// One step as this calculator spells it: upper case, single spaces,
+// without a listing's line number or a shift key
+fn normalize(text: &str) -> String {
+    let mut words: Vec<String> = text.split_whitespace().map(str::to_uppercase).collect();
+    if words.first().is_some_and(|word| is_line_number(word)) {
+        words.remove(0);
+    }
+    if words.len() > 1 && matches!(words[0].as_str(), "F" | "G") {
+        words.remove(0);
+    }
+    let step = words.join(" ");
+    if Test::parse(&step).is_some() {
+        return step;
+    }
+    match ALIASES.iter().find(|(alias, _)| *alias == step) {
+        Some((_, known)) => known.to_string(),
+        None => step,
+    }
+}
+
+// `001-` or `12-`, as listings number their lines
+fn is_line_number(word: &str) -> bool {
+    word.strip_suffix('-').is_some_and(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+}
@@ -0,0 +1,231 @@
+use crate::cpu::{Base, BitField, ComplementMode, Hp16cCpu, ResultsBank, WordSize, WordSizePolicy, MEMORY_BYTES};
+use crate::program::Program;
+use crate::random::Generator;
+use crate::scratch::{hex_bytes, Endian, Scratch};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+/// A serializable copy of the user-visible calculator state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub x: u128,
+    pub y: u128,
+    pub z: u128,
+    pub t: u128,
+    pub word_size: WordSize,
+    pub base: Base,
+    pub carry: bool,
+    pub overflow: bool,
+    #[serde(default)]
+    pub user_flags: [bool; 4],
+    #[serde(default)]
+    pub complement_mode: ComplementMode,
+    #[serde(default)]
+    pub word_size_policy: WordSizePolicy,
+    pub memory: Vec<u128>,
+    #[serde(default)]
+    pub program_steps: usize,
+    /// Program memory, one step per entry
+    #[serde(default)]
+    pub program: Vec<String>,
+    /// Comments on program lines, set with ANNOTATE
+    #[serde(default)]
+    pub comments: BTreeMap<usize, String>,
+    #[serde(default)]
+    pub i: u128,
+    #[serde(default)]
+    pub constants: BTreeMap<String, u128>,
+    #[serde(default)]
+    pub tags: BTreeMap<usize, String>,
+    #[serde(default)]
+    pub results_bank: Option<ResultsBank>,
+    #[serde(default)]
+    pub field: Option<BitField>,
+    #[serde(default)]
+    pub endian: Endian,
+    #[serde(default)]
+    pub random: Generator,
+    #[serde(default)]
+    pub auto_enter: bool,
+    #[serde(default)]
+    pub window_follow: bool,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub float_digits: Option<u8>,
+    /// Word size the registers are laid out at in FLOAT mode
+    #[serde(default)]
+    pub float_layout: Option<WordSize>,
+    /// Scratch memory as hex, without trailing zero bytes
+    #[serde(default)]
+    pub scratch: String,
+}
+
+/// Formats as the calculator it restores to: the stack and modes with
+/// `{}`, the display box with `{:#}`.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cpu = Hp16cCpu::new();
+        self.restore(&mut cpu);
+        fmt::Display::fmt(&cpu, f)
+    }
+}
+
+/// One field that differs between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &Hp16cCpu) -> Self {
+        Snapshot {
+            x: cpu.x,
+            y: cpu.y,
+            z: cpu.z,
+            t: cpu.t,
+            word_size: cpu.word_size,
+            base: cpu.base,
+            carry: cpu.carry,
+            overflow: cpu.overflow,
+            user_flags: cpu.user_flags,
+            complement_mode: cpu.complement_mode,
+            word_size_policy: cpu.word_size_policy,
+            memory: cpu.memory.to_vec(),
+            program_steps: cpu.program_steps,
+            program: cpu.program.steps().to_vec(),
+            comments: cpu.program.comments().clone(),
+            i: cpu.i,
+            constants: cpu.constants.user().clone(),
+            tags: cpu.tags.clone(),
+            results_bank: cpu.results_bank,
+            field: cpu.field,
+            endian: cpu.endian,
+            random: cpu.random,
+            auto_enter: cpu.auto_enter,
+            window_follow: cpu.window_follow,
+            strict: cpu.strict,
+            float_digits: cpu.float_digits,
+            float_layout: cpu.float_layout,
+            scratch: cpu.scratch.to_hex(),
+        }
+    }
+
+    pub fn restore(&self, cpu: &mut Hp16cCpu) {
+        // The stack is replaced, so skip the conversion out of FLOAT mode
+        cpu.float_digits = None;
+        cpu.program_steps = self.program_steps.min(MEMORY_BYTES);
+        cpu.set_program(Program::from(self.program.clone()).with_comments(self.comments.clone()));
+        cpu.float_layout = self.float_layout;
+        cpu.set_word_size(self.word_size.bits());
+        cpu.set_base(self.base);
+        cpu.float_digits = self.float_digits;
+        cpu.x = self.x;
+        cpu.y = self.y;
+        cpu.z = self.z;
+        cpu.t = self.t;
+        cpu.i = self.i;
+        cpu.carry = self.carry;
+        cpu.overflow = self.overflow;
+        cpu.user_flags = self.user_flags;
+        cpu.set_complement_mode(self.complement_mode);
+        cpu.word_size_policy = self.word_size_policy;
+        cpu.memory = self.memory.clone();
+        cpu.resize_memory();
+        cpu.constants.set_user(self.constants.clone());
+        cpu.tags = self.tags.clone();
+        cpu.results_bank = self.results_bank;
+        cpu.field = self.field;
+        cpu.endian = self.endian;
+        cpu.random = self.random;
+        cpu.auto_enter = self.auto_enter;
+        cpu.window_follow = self.window_follow;
+        cpu.strict = self.strict;
+        cpu.scratch.set_hex(&self.scratch);
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Compare against another snapshot. Register and memory values are
+    /// formatted in this snapshot's number base.
+    pub fn diff(&self, other: &Snapshot) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        let mut compare = |field: String, left: String, right: String| {
+            if left != right {
+                differences.push(Difference { field, left, right });
+            }
+        };
+        let value = |v: u128| format_in_base(v, self.base);
+
+        compare("Word size".to_string(), self.word_size.to_string(), other.word_size.to_string());
+        compare("Base".to_string(), self.base.to_string(), other.base.to_string());
+        compare(
+            "Mode".to_string(),
+            format!("{:?}", self.complement_mode),
+            format!("{:?}", other.complement_mode),
+        );
+        let float = |digits: Option<u8>| digits.map_or("off".to_string(), |digits| digits.to_string());
+        compare("Float".to_string(), float(self.float_digits), float(other.float_digits));
+        compare("Byte order".to_string(), self.endian.label().to_string(), other.endian.label().to_string());
+        compare("Carry".to_string(), flag(self.carry), flag(other.carry));
+        compare("Overflow".to_string(), flag(self.overflow), flag(other.overflow));
+        for (number, (left, right)) in self.user_flags.iter().zip(other.user_flags).enumerate() {
+            compare(format!("Flag {}", number), flag(*left), flag(right));
+        }
+        for (name, left, right) in [
+            ("T", self.t, other.t),
+            ("Z", self.z, other.z),
+            ("Y", self.y, other.y),
+            ("X", self.x, other.x),
+            ("I", self.i, other.i),
+        ] {
+            compare(name.to_string(), value(left), value(right));
+        }
+
+        let cells = self.memory.len().max(other.memory.len());
+        for i in 0..cells {
+            let left = self.memory.get(i).copied().unwrap_or(0);
+            let right = other.memory.get(i).copied().unwrap_or(0);
+            compare(format!("R{}", i), value(left), value(right));
+        }
+
+        let lines = self.program.len().max(other.program.len());
+        for line in 0..lines {
+            let step = |program: &[String]| program.get(line).cloned().unwrap_or_default();
+            compare(format!("Line {:03}", line + 1), step(&self.program), step(&other.program));
+        }
+
+        // Scratch memory, one row of 16 bytes at a time
+        let (mut left, mut right) = (Scratch::new(), Scratch::new());
+        left.set_hex(&self.scratch);
+        right.set_hex(&other.scratch);
+        for (row, (a, b)) in left.bytes().chunks(16).zip(right.bytes().chunks(16)).enumerate() {
+            compare(format!("Scratch {:04X}", row * 16), hex_bytes(a), hex_bytes(b));
+        }
+
+        differences
+    }
+}
+
+fn flag(value: bool) -> String {
+    if value { "1" } else { "0" }.to_string()
+}
+
+fn format_in_base(value: u128, base: Base) -> String {
+    match base {
+        Base::Binary => format!("{:b}", value),
+        Base::Octal => format!("{:o}", value),
+        Base::Decimal => format!("{}", value),
+        Base::Hex => format!("{:X}", value),
+    }
+}
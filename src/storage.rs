@@ -0,0 +1,136 @@
+use crate::snapshot::Snapshot;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Key/value persistence used for sessions and other saved state.
+///
+/// Front-ends that cannot touch the filesystem (WASM, servers) supply their
+/// own implementation instead of the file-backed default.
+pub trait Storage {
+    fn read(&self, key: &str) -> io::Result<Option<String>>;
+    fn write(&mut self, key: &str, contents: &str) -> io::Result<()>;
+    fn remove(&mut self, key: &str) -> io::Result<()>;
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.read(key)?.is_some())
+    }
+
+    fn save_snapshot(&mut self, key: &str, snapshot: &Snapshot) -> io::Result<()> {
+        self.write(key, &snapshot.to_json()?)
+    }
+
+    fn load_snapshot(&self, key: &str) -> io::Result<Option<Snapshot>> {
+        match self.read(key)? {
+            Some(text) => Ok(Some(Snapshot::from_json(&text)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Stores each key as a file below a root directory.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileStorage { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.path(key)) {
+            Ok(text) => Ok(Some(text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, key: &str, contents: &str) -> io::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Volatile storage, useful for tests and sandboxed front-ends.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    entries: HashMap<String, String>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> io::Result<Option<String>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn write(&mut self, key: &str, contents: &str) -> io::Result<()> {
+        self.entries.insert(key.to_string(), contents.to_string());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.entries.remove(key);
+        Ok(())
+    }
+}
+
+/// Embedded database storage backed by sled.
+#[cfg(feature = "sled")]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledStorage {
+    pub fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let db = sled::open(path).map_err(io::Error::other)?;
+        Ok(SledStorage { db })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Storage for SledStorage {
+    fn read(&self, key: &str) -> io::Result<Option<String>> {
+        match self.db.get(key).map_err(io::Error::other)? {
+            Some(bytes) => String::from_utf8(bytes.to_vec())
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    fn write(&mut self, key: &str, contents: &str) -> io::Result<()> {
+        self.db.insert(key, contents.as_bytes()).map_err(io::Error::other)?;
+        self.db.flush().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.db.remove(key).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
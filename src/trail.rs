@@ -0,0 +1,55 @@
+/// One line of the paper trail: what was typed and the resulting X.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrailEntry {
+    pub input: String,
+    pub result: String,
+}
+
+/// Adding-machine style record of every input and its result.
+#[derive(Debug, Clone, Default)]
+pub struct PaperTrail {
+    entries: Vec<TrailEntry>,
+}
+
+impl PaperTrail {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, input: impl Into<String>, result: impl Into<String>) {
+        self.entries.push(TrailEntry {
+            input: input.into(),
+            result: result.into(),
+        });
+    }
+
+    pub fn entries(&self) -> &[TrailEntry] {
+        &self.entries
+    }
+
+    /// The most recent `count` entries, oldest first.
+    pub fn recent(&self, count: usize) -> &[TrailEntry] {
+        &self.entries[self.entries.len().saturating_sub(count)..]
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Render entries as aligned "input  result" lines.
+    pub fn render(entries: &[TrailEntry]) -> Vec<String> {
+        let width = entries.iter().map(|e| e.input.len()).max().unwrap_or(0);
+        entries
+            .iter()
+            .map(|e| format!("{:width$}  = {}", e.input, e.result, width = width))
+            .collect()
+    }
+
+    pub fn contents(&self) -> String {
+        let mut text = Self::render(&self.entries).join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+}
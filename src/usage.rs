@@ -0,0 +1,84 @@
+use crate::command::parse_number;
+use crate::cpu::Base;
+use crate::help;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Name recorded for a number typed on its own.
+const NUMBER_ENTRY: &str = "[number]";
+
+/// A command that failed and the message it showed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageError {
+    pub input: String,
+    pub message: String,
+}
+
+/// What a session used: how often each command ran and which inputs
+/// failed. Kept only when asked for and written as local JSON, so an
+/// instructor can collect it from a student's machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub total_commands: usize,
+    pub commands: BTreeMap<String, usize>,
+    pub errors: Vec<UsageError>,
+}
+
+impl UsageSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one input line, typed in `base`, and keep its error if it
+    /// failed.
+    pub fn record(&mut self, input: &str, base: Base, error: Option<&str>) {
+        let input = input.trim().to_uppercase();
+        if input.is_empty() {
+            return;
+        }
+        self.total_commands += 1;
+        *self.commands.entry(command_name(&input, base)).or_insert(0) += 1;
+        if let Some(message) = error {
+            self.errors.push(UsageError {
+                input,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    /// Summary lines for the USAGE command, most used commands first.
+    pub fn lines(&self) -> Vec<String> {
+        let mut counts: Vec<_> = self.commands.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let mut lines = vec![format!("Commands: {}  Errors: {}", self.total_commands, self.errors.len())];
+        lines.extend(counts.into_iter().map(|(name, count)| format!("  {:12} {}", name, count)));
+        if !self.errors.is_empty() {
+            lines.push("Errors:".to_string());
+            lines.extend(self.errors.iter().map(|e| format!("  {:12} {}", e.input, e.message)));
+        }
+        lines
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+/// The command word of `input`. Command names come before numbers, so
+/// DEC or CB in hex count as commands, as they run.
+fn command_name(input: &str, base: Base) -> String {
+    let word = input.split_whitespace().next().unwrap_or("");
+    let known = help::COMMANDS
+        .iter()
+        .any(|entry| entry.command.split_whitespace().next() == Some(word));
+    if !known && parse_number(input, base).is_some() {
+        NUMBER_ENTRY.to_string()
+    } else {
+        word.to_string()
+    }
+}